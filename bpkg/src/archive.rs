@@ -0,0 +1,88 @@
+//! Package archives: a zstd-compressed tar of a package directory
+//! (`bpkg.toml` plus the `root/` payload), named `<name>-<version>.bpkg`.
+
+use crate::manifest::Manifest;
+use crate::source::{Source, MANIFEST_FILE, PAYLOAD_DIR};
+use anyhow::{bail, Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+pub const EXTENSION: &str = "bpkg";
+
+/// Pack the package directory `dir` into `out_dir`, returning the archive
+/// path. Entries are sorted and ownership/mtimes zeroed so the same tree
+/// always produces the same bytes.
+pub fn pack(dir: &Path, out_dir: &Path) -> Result<PathBuf> {
+    let source = Source::from_dir(dir)?;
+    let m = &source.manifest;
+    std::fs::create_dir_all(out_dir)
+        .with_context(|| format!("failed creating {}", out_dir.display()))?;
+    let out = out_dir.join(format!("{}-{}.{EXTENSION}", m.name, m.version));
+
+    let file = File::create(&out).with_context(|| format!("failed creating {}", out.display()))?;
+    let encoder = zstd::Encoder::new(file, 19)?;
+    let mut tar = tar::Builder::new(encoder);
+    tar.mode(tar::HeaderMode::Deterministic);
+    tar.follow_symlinks(false);
+    // The manifest goes in with its file list filled in from the payload.
+    let text = m.to_toml()?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(text.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    tar.append_data(&mut header, MANIFEST_FILE, text.as_bytes())?;
+    if let Some(payload) = &source.payload {
+        for f in &m.files {
+            tar.append_path_with_name(payload.join(f), Path::new(PAYLOAD_DIR).join(f))
+                .with_context(|| format!("failed adding {f}"))?;
+        }
+    }
+    tar.into_inner()?.finish()?;
+    Ok(out)
+}
+
+/// Read just the manifest out of an archive.
+pub fn read_manifest(archive: &Path) -> Result<Manifest> {
+    let mut tar = open(archive)?;
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        if entry.path()?.as_os_str() == MANIFEST_FILE {
+            let mut text = String::new();
+            entry.read_to_string(&mut text)?;
+            return Manifest::parse(&text)
+                .with_context(|| format!("invalid manifest in {}", archive.display()));
+        }
+    }
+    bail!("{} has no {MANIFEST_FILE}", archive.display())
+}
+
+/// Extract an archive into `dest` (replacing it) and load it as a package.
+pub fn unpack(archive: &Path, dest: &Path) -> Result<Source> {
+    match std::fs::remove_dir_all(dest) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    std::fs::create_dir_all(dest)?;
+    // `unpack` refuses entries that would land outside `dest`.
+    open(archive)?
+        .unpack(dest)
+        .with_context(|| format!("failed extracting {}", archive.display()))?;
+    Source::from_dir(dest)
+}
+
+fn open(archive: &Path) -> Result<tar::Archive<zstd::Decoder<'static, std::io::BufReader<File>>>> {
+    let file =
+        File::open(archive).with_context(|| format!("failed opening {}", archive.display()))?;
+    Ok(tar::Archive::new(zstd::Decoder::new(file)?))
+}
+
+/// Hex SHA-256 of a file.
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file =
+        File::open(path).with_context(|| format!("failed opening {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
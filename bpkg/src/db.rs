@@ -0,0 +1,142 @@
+use crate::manifest::Manifest;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// An installed package as recorded under `<db>/installed/<name>.toml`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Installed {
+    pub package: Manifest,
+    pub installed_at: u64,
+    /// Requested by the user rather than pulled in as a dependency.
+    pub explicit: bool,
+    /// SHA-256 of the archive it was installed from, which tells builds of
+    /// the same version apart.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+/// The installed-package database.
+pub struct Database {
+    dir: PathBuf,
+}
+
+impl Database {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn record_path(&self, name: &str) -> PathBuf {
+        self.dir.join("installed").join(format!("{name}.toml"))
+    }
+
+    /// Take the exclusive database lock; released when the guard drops.
+    /// Another bpkg (or munin-core) holding it makes this fail fast.
+    pub fn lock(&self) -> Result<File> {
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("failed creating {}", self.dir.display()))?;
+        let file = File::create(self.dir.join("lock"))?;
+        match file.try_lock() {
+            Ok(()) => Ok(file),
+            Err(std::fs::TryLockError::WouldBlock) => {
+                anyhow::bail!(
+                    "another bpkg transaction is running ({})",
+                    self.dir.display()
+                )
+            }
+            Err(std::fs::TryLockError::Error(e)) => Err(e.into()),
+        }
+    }
+
+    /// Version requirements packages are pinned to, from `<db>/pins.toml`.
+    /// A hold is a pin to `=<installed version>`.
+    pub fn pins(&self) -> Result<BTreeMap<String, String>> {
+        #[derive(Deserialize)]
+        struct PinFile {
+            #[serde(default)]
+            pins: BTreeMap<String, String>,
+        }
+        let path = self.dir.join("pins.toml");
+        match std::fs::read_to_string(&path) {
+            Ok(text) => Ok(toml::from_str::<PinFile>(&text)
+                .with_context(|| format!("invalid {}", path.display()))?
+                .pins),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(e).with_context(|| format!("failed reading {}", path.display())),
+        }
+    }
+
+    pub fn set_pins(&self, pins: &BTreeMap<String, String>) -> Result<()> {
+        #[derive(Serialize)]
+        struct PinFile<'a> {
+            pins: &'a BTreeMap<String, String>,
+        }
+        write_atomic(
+            &self.dir.join("pins.toml"),
+            toml::to_string(&PinFile { pins })?.as_bytes(),
+        )
+    }
+
+    /// Which installed package owns each file.
+    pub fn owners(&self) -> Result<HashMap<String, String>> {
+        let mut out = HashMap::new();
+        for record in self.list()? {
+            for f in &record.package.files {
+                out.insert(f.clone(), record.package.name.clone());
+            }
+        }
+        Ok(out)
+    }
+
+    pub fn get(&self, name: &str) -> Result<Option<Installed>> {
+        let path = self.record_path(name);
+        match std::fs::read_to_string(&path) {
+            Ok(text) => {
+                Ok(Some(toml::from_str(&text).with_context(|| {
+                    format!("corrupt record {}", path.display())
+                })?))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("failed reading {}", path.display())),
+        }
+    }
+
+    pub fn list(&self) -> Result<Vec<Installed>> {
+        let mut out = Vec::new();
+        let dir = self.dir.join("installed");
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(e) => e,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(out),
+            Err(e) => return Err(e).with_context(|| format!("failed reading {}", dir.display())),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "toml") {
+                let text = std::fs::read_to_string(&path)?;
+                out.push(
+                    toml::from_str(&text)
+                        .with_context(|| format!("corrupt record {}", path.display()))?,
+                );
+            }
+        }
+        out.sort_by(|a: &Installed, b| a.package.name.cmp(&b.package.name));
+        Ok(out)
+    }
+}
+
+/// Write via a temp file and rename so readers never see a partial record.
+pub(crate) fn write_atomic(path: &Path, bytes: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("failed creating {}", parent.display()))?;
+    }
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, bytes).with_context(|| format!("failed writing {}", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("failed replacing {}", path.display()))
+}
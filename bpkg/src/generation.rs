@@ -0,0 +1,128 @@
+//! Numbered generations: the installed set after every transaction, with
+//! the archive each package came from, so any recent state can be restored.
+//!
+//! Generation files live at `<db>/generations/<n>.toml` and are written
+//! through the install transaction itself. Archives are kept in
+//! `<db>/cache/` for as long as a retained generation refers to them.
+
+use crate::db::Installed;
+use crate::manifest::Manifest;
+use crate::{Change, DB_DIR};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// Generations kept before the oldest (and archives only they use) go.
+pub const MAX_GENERATIONS: usize = 20;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Generation {
+    pub id: u64,
+    pub created_at: u64,
+    /// What produced it, e.g. "install htop" or "rollback to 3".
+    pub summary: String,
+    #[serde(default)]
+    pub changes: Vec<Change>,
+    #[serde(default)]
+    pub packages: Vec<GenPackage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GenPackage {
+    pub package: Manifest,
+    pub explicit: bool,
+    /// Archive file name in `<db>/cache/`; `None` for packages with no
+    /// payload or installed before generations were kept.
+    pub archive: Option<String>,
+    pub sha256: Option<String>,
+}
+
+impl GenPackage {
+    pub fn from_installed(record: &Installed) -> Self {
+        Self {
+            package: record.package.clone(),
+            explicit: record.explicit,
+            archive: None,
+            sha256: record.sha256.clone(),
+        }
+    }
+}
+
+pub struct Generations {
+    dir: PathBuf,
+}
+
+impl Generations {
+    pub fn new(db_dir: &Path) -> Self {
+        Self {
+            dir: db_dir.join("generations"),
+        }
+    }
+
+    /// Path of generation `id` relative to the install root, for writing it
+    /// through a transaction.
+    pub fn rel_path(id: u64) -> String {
+        format!("{DB_DIR}/generations/{id}.toml")
+    }
+
+    /// All kept generations, oldest first.
+    pub fn list(&self) -> Result<Vec<Generation>> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(e) => e,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut out: Vec<Generation> = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "toml") {
+                let text = std::fs::read_to_string(&path)?;
+                out.push(
+                    toml::from_str(&text)
+                        .with_context(|| format!("corrupt generation {}", path.display()))?,
+                );
+            }
+        }
+        out.sort_by_key(|g| g.id);
+        Ok(out)
+    }
+
+    pub fn get(&self, id: u64) -> Result<Option<Generation>> {
+        Ok(self.list()?.into_iter().find(|g| g.id == id))
+    }
+
+    pub fn latest(&self) -> Result<Option<Generation>> {
+        Ok(self.list()?.pop())
+    }
+
+    /// Drop generations beyond [`MAX_GENERATIONS`], then cached archives no
+    /// remaining generation refers to.
+    pub fn prune(&self, cache_dir: &Path) -> Result<()> {
+        let mut all = self.list()?;
+        if all.len() > MAX_GENERATIONS {
+            for old in all.drain(..all.len() - MAX_GENERATIONS) {
+                std::fs::remove_file(self.dir.join(format!("{}.toml", old.id))).ok();
+            }
+        }
+        let keep: HashSet<&str> = all
+            .iter()
+            .flat_map(|g| g.packages.iter().filter_map(|p| p.archive.as_deref()))
+            .collect();
+        let Ok(entries) = std::fs::read_dir(cache_dir) else {
+            return Ok(());
+        };
+        for entry in entries {
+            let path = entry?.path();
+            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+            if path
+                .extension()
+                .is_some_and(|e| e == crate::archive::EXTENSION)
+                && !keep.contains(name)
+            {
+                std::fs::remove_file(&path).ok();
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,1229 @@
+//! bpkg library API, shared by the `bpkg` CLI and munin-core's package tools.
+
+pub mod archive;
+pub mod db;
+pub mod generation;
+pub mod manifest;
+pub mod recipe;
+pub mod repo;
+pub mod resolve;
+pub mod source;
+pub mod transaction;
+
+use anyhow::{bail, Context, Result};
+use db::{Database, Installed};
+use generation::{GenPackage, Generation, Generations};
+use manifest::Manifest;
+use resolve::{Dep, Step};
+use serde::{Deserialize, Serialize};
+use source::Source;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use transaction::{Content, Txn};
+
+/// Installed-package database, relative to the install root.
+pub const DB_DIR: &str = "var/lib/bpkg";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PackageSummary {
+    pub name: String,
+    pub version: String,
+    pub description: String,
+    pub installed: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PackageInfo {
+    /// Newest available manifest, if any source carries the package.
+    pub available: Option<Manifest>,
+    pub installed: Option<Installed>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Install,
+    Upgrade,
+    Downgrade,
+    Remove,
+}
+
+/// One package changed by a transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Change {
+    pub name: String,
+    pub kind: ChangeKind,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    /// Why a package the user did not name is in the plan.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Transaction {
+    pub changes: Vec<Change>,
+}
+
+/// A resolved set of changes; print `transaction` to show it, then
+/// [`Bpkg::apply`] it.
+#[derive(Debug, Clone)]
+pub struct Plan {
+    pub transaction: Transaction,
+    installs: Vec<Step>,
+    /// Recorded with the generation the plan produces.
+    summary: String,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InstallOptions {
+    /// Overwrite files on disk that no package owns.
+    pub force: bool,
+}
+
+/// Stops [`Bpkg::apply_cancellable`] before its transaction commits; it then
+/// rolls back. Once the commit has begun the transaction can't be cancelled.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicU8>);
+
+const OPEN: u8 = 0;
+const CANCELLED: u8 = 1;
+const COMMITTING: u8 = 2;
+
+impl CancelToken {
+    /// Cancel the transaction. `false` means it is already committing and
+    /// will finish.
+    pub fn cancel(&self) -> bool {
+        match self
+            .0
+            .compare_exchange(OPEN, CANCELLED, Ordering::SeqCst, Ordering::SeqCst)
+        {
+            Ok(_) => true,
+            Err(state) => state == CANCELLED,
+        }
+    }
+
+    fn check(&self) -> Result<()> {
+        if self.0.load(Ordering::SeqCst) == CANCELLED {
+            bail!("the transaction was cancelled");
+        }
+        Ok(())
+    }
+
+    /// Claim the commit, unless the transaction was cancelled first. After
+    /// this, `cancel` refuses; callers doing their own irreversible work
+    /// claim it just before starting.
+    pub fn commit(&self) -> Result<()> {
+        match self
+            .0
+            .compare_exchange(OPEN, COMMITTING, Ordering::SeqCst, Ordering::SeqCst)
+        {
+            Ok(_) | Err(COMMITTING) => Ok(()),
+            Err(_) => bail!("the transaction was cancelled"),
+        }
+    }
+}
+
+/// A bpkg installation rooted at `root` (`/` on a device).
+pub struct Bpkg {
+    root: PathBuf,
+    db: Database,
+    generations: Generations,
+}
+
+impl Bpkg {
+    pub fn open(root: impl Into<PathBuf>) -> Self {
+        let root = root.into();
+        let db = Database::new(root.join(DB_DIR));
+        let generations = Generations::new(db.dir());
+        Self {
+            root,
+            db,
+            generations,
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Manifests offered for install: packages in the configured repositories'
+    /// indexes, plus `<db>/available/*.toml` and package directories placed there.
+    pub fn available(&self) -> Result<Vec<Manifest>> {
+        Ok(self.sources()?.into_iter().map(|s| s.manifest).collect())
+    }
+
+    fn sources(&self) -> Result<Vec<Source>> {
+        let dir = self.db.dir().join("available");
+        let mut out = repo::sources(&self.root, self.db.dir())?;
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(e) => e.collect::<std::io::Result<Vec<_>>>()?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        for entry in entries {
+            let path = entry.path();
+            if path.join(source::MANIFEST_FILE).is_file() {
+                out.push(Source::from_dir(&path)?);
+            } else if path.extension().is_some_and(|e| e == "toml") {
+                out.push(Source::from_manifest(&path)?);
+            }
+        }
+        // Newest first within a name, so lookups by name find the latest.
+        out.sort_by_cached_key(|s| {
+            (
+                s.manifest.name.clone(),
+                std::cmp::Reverse(resolve::parse_version(&s.manifest.version).ok()),
+            )
+        });
+        Ok(out)
+    }
+
+    pub fn installed(&self) -> Result<Vec<Installed>> {
+        self.db.list()
+    }
+
+    /// Available or installed packages whose name or description contains
+    /// `query` (case-insensitive).
+    pub fn search(&self, query: &str) -> Result<Vec<PackageSummary>> {
+        let q = query.to_lowercase();
+        let installed = self.installed()?;
+        let mut out: Vec<PackageSummary> = Vec::new();
+        let candidates = self
+            .available()?
+            .into_iter()
+            .chain(installed.iter().map(|i| i.package.clone()));
+        for m in candidates {
+            if out.iter().any(|s| s.name == m.name) {
+                continue;
+            }
+            if m.name.to_lowercase().contains(&q) || m.description.to_lowercase().contains(&q) {
+                out.push(PackageSummary {
+                    installed: installed
+                        .iter()
+                        .find(|i| i.package.name == m.name)
+                        .map(|i| i.package.version.clone()),
+                    name: m.name,
+                    version: m.version,
+                    description: m.description,
+                });
+            }
+        }
+        Ok(out)
+    }
+
+    pub fn info(&self, name: &str) -> Result<Option<PackageInfo>> {
+        let available = self.available()?.into_iter().find(|m| m.name == name);
+        let installed = self.db.get(name)?;
+        if available.is_none() && installed.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(PackageInfo {
+            available,
+            installed,
+        }))
+    }
+
+    /// The installed package that owns `path` (absolute or root-relative).
+    pub fn owner(&self, path: &str) -> Result<Option<String>> {
+        Ok(self.db.owners()?.remove(path.trim_start_matches('/')))
+    }
+
+    pub fn install(&self, targets: &[String]) -> Result<Transaction> {
+        self.install_with(targets, InstallOptions::default())
+    }
+
+    pub fn install_with(&self, targets: &[String], opts: InstallOptions) -> Result<Transaction> {
+        let plan = self.plan_install(targets)?;
+        self.apply(&plan, opts)
+    }
+
+    pub fn remove(&self, names: &[String]) -> Result<Transaction> {
+        let plan = self.plan_remove(names)?;
+        self.apply(&plan, InstallOptions::default())
+    }
+
+    /// Resolve what installing `targets` would change, without touching
+    /// anything. Targets are package names with an optional version
+    /// requirement (`htop`, `htop>=3`) or paths to package directories.
+    pub fn plan_install(&self, targets: &[String]) -> Result<Plan> {
+        self.plan_install_from(targets, true)
+    }
+
+    /// Like [`Bpkg::plan_install`], but targets are only ever package names
+    /// resolved from the configured sources, never local paths.
+    pub fn plan_install_names(&self, names: &[String]) -> Result<Plan> {
+        self.plan_install_from(names, false)
+    }
+
+    fn plan_install_from(&self, targets: &[String], local_paths: bool) -> Result<Plan> {
+        let mut universe = Vec::new();
+        let mut requests = Vec::new();
+        for target in targets {
+            if !local_paths {
+                requests.push(Dep::parse(target)?);
+                continue;
+            }
+            let dir = Path::new(target);
+            let is_archive =
+                dir.is_file() && dir.extension().is_some_and(|e| e == archive::EXTENSION);
+            if is_archive || dir.join(source::MANIFEST_FILE).is_file() {
+                let src = if is_archive {
+                    local_archive(dir)?
+                } else {
+                    Source::from_dir(dir)?
+                };
+                requests.push(Dep::parse(&format!(
+                    "{} ={}",
+                    src.manifest.name,
+                    resolve::parse_version(&src.manifest.version)?
+                ))?);
+                // Local directories win over repository copies of the same version.
+                universe.push(src);
+            } else {
+                requests.push(Dep::parse(target)?);
+            }
+        }
+        universe.extend(self.sources()?);
+
+        let installed = self.installed()?;
+        let resolution =
+            resolve::resolve(&universe, &installed, &requests, &self.pin_reqs()?, true)
+                .with_context(|| format!("cannot install {}", targets.join(" ")))?;
+        let removes = resolution
+            .removes
+            .into_iter()
+            .map(|(name, why)| (name, Some(why)))
+            .collect::<Vec<_>>();
+        plan_from(
+            &installed,
+            &removes,
+            resolution.installs,
+            format!("install {}", targets.join(" ")),
+        )
+    }
+
+    /// Plan moving installed packages (all, or just `names`) to the newest
+    /// versions their pins allow, pulling in whatever those need.
+    pub fn plan_upgrade(&self, names: &[String]) -> Result<Plan> {
+        let installed = self.installed()?;
+        for name in names {
+            if !installed.iter().any(|i| &i.package.name == name) {
+                bail!("package {name} is not installed");
+            }
+        }
+        let sources = self.sources()?;
+        let pins = self.pin_reqs()?;
+        let mut requests = Vec::new();
+        for record in &installed {
+            let name = &record.package.name;
+            if !names.is_empty() && !names.contains(name) {
+                continue;
+            }
+            let current = resolve::parse_version(&record.package.version)?;
+            // Sources are sorted newest first within a name.
+            for s in sources.iter().filter(|s| &s.manifest.name == name) {
+                let version = resolve::parse_version(&s.manifest.version)?;
+                if pins.get(name).is_some_and(|pin| !pin.matches(&version)) {
+                    continue;
+                }
+                if version > current {
+                    requests.push(Dep::parse(&format!("{name} ={version}"))?);
+                }
+                break;
+            }
+        }
+        let resolution = resolve::resolve(&sources, &installed, &requests, &pins, false)
+            .context("cannot upgrade")?;
+        let removes = resolution
+            .removes
+            .into_iter()
+            .map(|(name, why)| (name, Some(why)))
+            .collect::<Vec<_>>();
+        let summary = match names {
+            [] => "upgrade".to_string(),
+            _ => format!("upgrade {}", names.join(" ")),
+        };
+        plan_from(&installed, &removes, resolution.installs, summary)
+    }
+
+    /// Plan restoring the installed set recorded in generation `id`, from
+    /// the archives kept for it.
+    pub fn plan_rollback(&self, id: u64) -> Result<Plan> {
+        let generation = self
+            .generations
+            .get(id)?
+            .with_context(|| format!("no generation {id} (see bpkg history)"))?;
+        let installed = self.installed()?;
+        let removes: Vec<(String, Option<String>)> = installed
+            .iter()
+            .filter(|i| {
+                !generation
+                    .packages
+                    .iter()
+                    .any(|p| p.package.name == i.package.name)
+            })
+            .map(|i| (i.package.name.clone(), None))
+            .collect();
+
+        let cache = self.db.dir().join("cache");
+        let mut installs = Vec::new();
+        for p in &generation.packages {
+            let m = &p.package;
+            // The same version may be a different build: only the archive's
+            // hash says the installed package is the one recorded.
+            if installed.iter().any(|i| {
+                i.package.name == m.name && i.package.version == m.version && i.sha256 == p.sha256
+            }) {
+                continue;
+            }
+            let remote = match (&p.archive, &p.sha256) {
+                (Some(file), Some(sha256)) if cache.join(file).is_file() => Some(source::Remote {
+                    url: format!("file://{}", cache.join(file).display()),
+                    sha256: sha256.clone(),
+                    size: cache.join(file).metadata()?.len(),
+                }),
+                _ if m.files.is_empty() => None,
+                _ => bail!(
+                    "the archive for {} {} is no longer kept; cannot roll back to generation {id}",
+                    m.name,
+                    m.version
+                ),
+            };
+            installs.push(Step {
+                source: Source {
+                    manifest: m.clone(),
+                    payload: None,
+                    remote,
+                },
+                explicit: p.explicit,
+                reason: None,
+            });
+        }
+        plan_from(&installed, &removes, installs, format!("rollback to {id}"))
+    }
+
+    /// Installed-set snapshots, oldest first.
+    pub fn generations(&self) -> Result<Vec<Generation>> {
+        self.generations.list()
+    }
+
+    pub fn rollback(&self, id: u64) -> Result<Transaction> {
+        let plan = self.plan_rollback(id)?;
+        self.apply(&plan, InstallOptions::default())
+    }
+
+    /// Pinned packages and their version requirements.
+    pub fn pins(&self) -> Result<BTreeMap<String, String>> {
+        self.db.pins()
+    }
+
+    /// Pin `name` to `req`, or hold it at its installed version when `req`
+    /// is `None`.
+    pub fn pin(&self, name: &str, req: Option<&str>) -> Result<String> {
+        let _lock = self.begin()?;
+        let req = match req {
+            Some(r) => {
+                semver::VersionReq::parse(r)
+                    .with_context(|| format!("bad version requirement {r:?}"))?;
+                r.to_string()
+            }
+            None => {
+                let record = self
+                    .db
+                    .get(name)?
+                    .with_context(|| format!("package {name} is not installed"))?;
+                format!("={}", resolve::parse_version(&record.package.version)?)
+            }
+        };
+        let mut pins = self.db.pins()?;
+        pins.insert(name.to_string(), req.clone());
+        self.db.set_pins(&pins)?;
+        Ok(req)
+    }
+
+    pub fn unpin(&self, name: &str) -> Result<bool> {
+        let _lock = self.begin()?;
+        let mut pins = self.db.pins()?;
+        let found = pins.remove(name).is_some();
+        self.db.set_pins(&pins)?;
+        Ok(found)
+    }
+
+    fn pin_reqs(&self) -> Result<BTreeMap<String, semver::VersionReq>> {
+        self.db
+            .pins()?
+            .into_iter()
+            .map(|(name, req)| {
+                let parsed = semver::VersionReq::parse(&req)
+                    .with_context(|| format!("{name} has a bad pin {req:?}"))?;
+                Ok((name, parsed))
+            })
+            .collect()
+    }
+
+    /// Plan removing `names`, refusing if a remaining package needs them.
+    pub fn plan_remove(&self, names: &[String]) -> Result<Plan> {
+        let installed = self.installed()?;
+        let mut tx = Transaction::default();
+        for name in names {
+            let Some(record) = installed.iter().find(|i| &i.package.name == name) else {
+                bail!("package {name} is not installed");
+            };
+            tx.changes.push(Change {
+                name: name.clone(),
+                kind: ChangeKind::Remove,
+                from: Some(record.package.version.clone()),
+                to: None,
+                reason: None,
+            });
+        }
+        resolve::check_removal(&installed, names)
+            .with_context(|| format!("cannot remove {}", names.join(" ")))?;
+        Ok(Plan {
+            transaction: tx,
+            installs: Vec::new(),
+            summary: format!("remove {}", names.join(" ")),
+        })
+    }
+
+    /// Carry out a plan. Either every change lands or the tree is left as it
+    /// was; a plan made against a database that has since changed is refused.
+    pub fn apply(&self, plan: &Plan, opts: InstallOptions) -> Result<Transaction> {
+        self.apply_cancellable(plan, opts, &CancelToken::default())
+    }
+
+    /// [`Bpkg::apply`], stopping between downloads and steps once `cancel`
+    /// is cancelled.
+    pub fn apply_cancellable(
+        &self,
+        plan: &Plan,
+        opts: InstallOptions,
+        cancel: &CancelToken,
+    ) -> Result<Transaction> {
+        let _lock = self.begin()?;
+        let mut current = HashMap::new();
+        for c in &plan.transaction.changes {
+            let record = self.db.get(&c.name)?;
+            if record.as_ref().map(|r| &r.package.version) != c.from.as_ref() {
+                bail!("the package database changed since this plan was made; plan again");
+            }
+            current.insert(c.name.as_str(), record);
+        }
+        let removed: Vec<&Installed> = plan
+            .transaction
+            .changes
+            .iter()
+            .filter(|c| c.kind == ChangeKind::Remove)
+            .filter_map(|c| current[c.name.as_str()].as_ref())
+            .collect();
+        let fetched = self.fetch_payloads(&plan.installs, cancel)?;
+        let result = self
+            .check_files(&fetched.steps, &current, opts)
+            .and_then(|()| self.next_generation(plan, &fetched))
+            .and_then(|generation| {
+                self.run_transaction(&removed, &fetched.steps, &current, &generation, cancel)
+            });
+        for dir in &fetched.scratch {
+            std::fs::remove_dir_all(dir).ok();
+        }
+        result?;
+        Ok(plan.transaction.clone())
+    }
+
+    /// The generation `plan` produces: the last one (or the installed set,
+    /// before generations were kept) with the plan's changes applied.
+    fn next_generation(&self, plan: &Plan, fetched: &Fetched) -> Result<Generation> {
+        let previous = self.generations.latest()?;
+        let mut packages: BTreeMap<String, GenPackage> = BTreeMap::new();
+        for record in self.installed()? {
+            let kept = previous.as_ref().and_then(|g| {
+                g.packages.iter().find(|p| {
+                    p.package.name == record.package.name
+                        && p.package.version == record.package.version
+                        && (record.sha256.is_none() || p.sha256 == record.sha256)
+                })
+            });
+            let entry = match kept {
+                Some(p) => GenPackage {
+                    explicit: record.explicit,
+                    ..p.clone()
+                },
+                None => GenPackage::from_installed(&record),
+            };
+            packages.insert(record.package.name.clone(), entry);
+        }
+        for c in &plan.transaction.changes {
+            if c.kind == ChangeKind::Remove {
+                packages.remove(&c.name);
+            }
+        }
+        for step in &fetched.steps {
+            let m = &step.source.manifest;
+            let archive = fetched.archives.get(&m.name);
+            packages.insert(
+                m.name.clone(),
+                GenPackage {
+                    package: m.clone(),
+                    explicit: step.explicit,
+                    archive: archive.map(|a| a.0.clone()),
+                    sha256: archive.map(|a| a.1.clone()),
+                },
+            );
+        }
+        Ok(Generation {
+            id: previous.map_or(1, |g| g.id + 1),
+            created_at: now_secs(),
+            summary: plan.summary.clone(),
+            changes: plan.transaction.changes.clone(),
+            packages: packages.into_values().collect(),
+        })
+    }
+
+    fn run_transaction(
+        &self,
+        removed: &[&Installed],
+        installs: &[Step],
+        current: &HashMap<&str, Option<Installed>>,
+        generation: &Generation,
+        cancel: &CancelToken,
+    ) -> Result<()> {
+        let mut txn = Txn::begin(&self.root, self.db.dir())?;
+        let result = self.apply_remove(&mut txn, removed).and_then(|()| {
+            for step in installs {
+                cancel.check()?;
+                let name = step.source.manifest.name.as_str();
+                let sha256 = generation
+                    .packages
+                    .iter()
+                    .find(|p| p.package.name == name)
+                    .and_then(|p| p.sha256.clone());
+                self.apply_install(&mut txn, step, current[name].as_ref(), sha256)?;
+            }
+            txn.write(
+                &Generations::rel_path(generation.id),
+                Content::Bytes(toml::to_string_pretty(generation)?.as_bytes()),
+            )?;
+            cancel.commit()
+        });
+        match result {
+            Ok(()) => {
+                txn.commit()?;
+                for record in removed {
+                    for f in &record.package.files {
+                        self.prune_dirs(f);
+                    }
+                }
+                // Housekeeping: the transaction is in, so a failure here
+                // must not report it as failed.
+                if let Err(e) = self.generations.prune(&self.db.dir().join("cache")) {
+                    log::warn!("failed pruning old generations: {e:#}");
+                }
+                // Services restart only now that the new files are in for good.
+                let failed: Vec<String> = installs
+                    .iter()
+                    .filter_map(|step| {
+                        let m = &step.source.manifest;
+                        let old = current[m.name.as_str()]
+                            .as_ref()
+                            .map(|c| c.package.version.as_str());
+                        self.run_script("post_commit", &m.scripts.post_commit, m, old)
+                            .err()
+                            .map(|e| format!("{e:#}"))
+                    })
+                    .collect();
+                if !failed.is_empty() {
+                    bail!("the transaction was applied, but {}", failed.join("; "));
+                }
+                Ok(())
+            }
+            Err(e) => {
+                txn.rollback()?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Download and unpack archives for steps that come from a repository
+    /// (or a local `.bpkg`), and keep an archive of every payload in the
+    /// cache so later generations can be rolled back to.
+    fn fetch_payloads(&self, steps: &[Step], cancel: &CancelToken) -> Result<Fetched> {
+        let cache = self.db.dir().join("cache");
+        let mut fetched = Fetched::default();
+        for step in steps {
+            cancel.check()?;
+            let m = &step.source.manifest;
+            let Some(remote) = &step.source.remote else {
+                if let Some(dir) = step.source.payload.as_ref().and_then(|p| p.parent()) {
+                    // Packed aside first: the plain `<name>-<version>` name
+                    // may belong to another build.
+                    let packed = archive::pack(dir, &cache.join("incoming"))?;
+                    let sha256 = archive::sha256_file(&packed)?;
+                    let name = repo::cache_name(&file_name(&packed), &sha256);
+                    std::fs::rename(&packed, cache.join(&name))?;
+                    fetched.archives.insert(m.name.clone(), (name, sha256));
+                }
+                fetched.steps.push(step.clone());
+                continue;
+            };
+            let archive = repo::download(remote, &cache)?;
+            fetched
+                .archives
+                .insert(m.name.clone(), (file_name(&archive), remote.sha256.clone()));
+            let dir = cache.join(format!("{}-{}", m.name, m.version));
+            fetched.scratch.push(dir.clone());
+            let source = archive::unpack(&archive, &dir)?;
+            if source.manifest.name != m.name || source.manifest.version != m.version {
+                bail!(
+                    "{} contains {} {}, expected {} {}",
+                    remote.url,
+                    source.manifest.name,
+                    source.manifest.version,
+                    m.name,
+                    m.version
+                );
+            }
+            fetched.steps.push(Step {
+                source,
+                ..step.clone()
+            });
+        }
+        Ok(fetched)
+    }
+
+    /// Refuse files owned by a package that stays, claimed twice by the plan,
+    /// or present on disk without an owner (unless forced).
+    fn check_files(
+        &self,
+        installs: &[Step],
+        current: &HashMap<&str, Option<Installed>>,
+        opts: InstallOptions,
+    ) -> Result<()> {
+        let mut owners = self.db.owners()?;
+        // Files of packages the plan removes or replaces are up for grabs.
+        for record in current.values().flatten() {
+            for f in &record.package.files {
+                owners.remove(f);
+            }
+        }
+        let mut claimed: HashMap<&str, &str> = HashMap::new();
+        for step in installs {
+            let m = &step.source.manifest;
+            if step.source.payload.is_none() && !m.files.is_empty() {
+                bail!("{} has no payload to install", m.name);
+            }
+            let previous = current[m.name.as_str()].as_ref();
+            for f in &m.files {
+                if let Some(other) = claimed.insert(f, &m.name) {
+                    bail!("{} and {other} both contain /{f}", m.name);
+                }
+                if let Some(owner) = owners.get(f) {
+                    bail!("{} conflicts with {owner}: both contain /{f}", m.name);
+                }
+                let was_ours = previous.is_some_and(|p| p.package.files.contains(f));
+                if !opts.force && !was_ours && self.root.join(f).symlink_metadata().is_ok() {
+                    bail!("/{f} exists and is not owned by any package (use --force)");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_install(
+        &self,
+        txn: &mut Txn,
+        step: &Step,
+        current: Option<&Installed>,
+        sha256: Option<String>,
+    ) -> Result<()> {
+        let m = &step.source.manifest;
+        let old = current.map(|c| c.package.version.as_str());
+        self.run_script("pre_install", &m.scripts.pre_install, m, old)?;
+        if let Some(payload) = &step.source.payload {
+            for f in &m.files {
+                let from = payload.join(f);
+                if from.symlink_metadata()?.is_symlink() {
+                    txn.write(f, Content::Symlink(&std::fs::read_link(&from)?))?;
+                } else {
+                    txn.write(f, Content::Copy(&from))?;
+                }
+            }
+        }
+        if let Some(current) = current {
+            for f in &current.package.files {
+                if !m.files.contains(f) {
+                    txn.remove(f)?;
+                }
+            }
+        }
+        let record = Installed {
+            package: m.clone(),
+            installed_at: now_secs(),
+            explicit: step.explicit,
+            sha256,
+        };
+        txn.write(
+            &record_rel(&m.name),
+            Content::Bytes(toml::to_string_pretty(&record)?.as_bytes()),
+        )?;
+        self.run_script("post_install", &m.scripts.post_install, m, old)
+    }
+
+    fn apply_remove(&self, txn: &mut Txn, records: &[&Installed]) -> Result<()> {
+        for record in records {
+            let m = &record.package;
+            self.run_script("pre_remove", &m.scripts.pre_remove, m, Some(&m.version))?;
+            for f in &m.files {
+                txn.remove(f)?;
+            }
+            txn.remove(&record_rel(&m.name))?;
+            self.run_script("post_remove", &m.scripts.post_remove, m, Some(&m.version))?;
+        }
+        Ok(())
+    }
+
+    /// Fetch and verify the configured repositories' indexes.
+    pub fn update(&self) -> Result<Vec<(String, usize)>> {
+        let _lock = self.begin()?;
+        repo::update(&self.root, self.db.dir())
+    }
+
+    /// Lock the database and finish rolling back anything a crash left behind.
+    fn begin(&self) -> Result<std::fs::File> {
+        let lock = self.db.lock()?;
+        if transaction::recover(&self.root, self.db.dir())? {
+            log::warn!("rolled back an interrupted transaction");
+        }
+        Ok(lock)
+    }
+
+    fn run_script(
+        &self,
+        stage: &str,
+        script: &Option<String>,
+        m: &Manifest,
+        old_version: Option<&str>,
+    ) -> Result<()> {
+        let Some(script) = script else {
+            return Ok(());
+        };
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(script)
+            .current_dir(&self.root)
+            .env("BPKG_ROOT", &self.root)
+            .env("BPKG_PACKAGE", &m.name)
+            .env("BPKG_VERSION", &m.version)
+            .env("BPKG_OLD_VERSION", old_version.unwrap_or(""))
+            .status()
+            .with_context(|| format!("failed running {} {stage}", m.name))?;
+        if !status.success() {
+            bail!("{} {stage} script failed ({status})", m.name);
+        }
+        Ok(())
+    }
+
+    /// Remove directories a package's files left empty, stopping at the root.
+    fn prune_dirs(&self, file: &str) {
+        let mut dir = Path::new(file).parent();
+        while let Some(d) = dir.filter(|d| !d.as_os_str().is_empty()) {
+            if std::fs::remove_dir(self.root.join(d)).is_err() {
+                break;
+            }
+            dir = d.parent();
+        }
+    }
+}
+
+/// Steps ready to install, with each payload's cached archive (file name and
+/// SHA-256, by package) and the scratch directories to delete afterwards.
+#[derive(Default)]
+struct Fetched {
+    steps: Vec<Step>,
+    archives: HashMap<String, (String, String)>,
+    scratch: Vec<PathBuf>,
+}
+
+/// Build a plan from resolved removes (with optional reasons) and installs,
+/// classifying each install against what is installed now.
+fn plan_from(
+    installed: &[Installed],
+    removes: &[(String, Option<String>)],
+    installs: Vec<Step>,
+    summary: String,
+) -> Result<Plan> {
+    let mut tx = Transaction::default();
+    for (name, reason) in removes {
+        let from = installed.iter().find(|i| &i.package.name == name);
+        tx.changes.push(Change {
+            name: name.clone(),
+            kind: ChangeKind::Remove,
+            from: from.map(|i| i.package.version.clone()),
+            to: None,
+            reason: reason.clone(),
+        });
+    }
+    for step in &installs {
+        let m = &step.source.manifest;
+        let current = installed.iter().find(|i| i.package.name == m.name);
+        let kind = match current {
+            None => ChangeKind::Install,
+            Some(c)
+                if resolve::parse_version(&m.version)?
+                    < resolve::parse_version(&c.package.version)? =>
+            {
+                ChangeKind::Downgrade
+            }
+            Some(_) => ChangeKind::Upgrade,
+        };
+        tx.changes.push(Change {
+            name: m.name.clone(),
+            kind,
+            from: current.map(|i| i.package.version.clone()),
+            to: Some(m.version.clone()),
+            reason: step.reason.clone(),
+        });
+    }
+    Ok(Plan {
+        transaction: tx,
+        installs,
+        summary,
+    })
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// A `.bpkg` file named on the command line, installed like a repository
+/// archive but trusted because the user picked it.
+fn local_archive(path: &Path) -> Result<Source> {
+    let path = path
+        .canonicalize()
+        .with_context(|| format!("failed resolving {}", path.display()))?;
+    Ok(Source {
+        manifest: archive::read_manifest(&path)?,
+        payload: None,
+        remote: Some(source::Remote {
+            url: format!("file://{}", path.display()),
+            sha256: archive::sha256_file(&path)?,
+            size: path.metadata()?.len(),
+        }),
+    })
+}
+
+/// Database record path relative to the install root, so record writes go
+/// through the same transaction as the files.
+fn record_rel(name: &str) -> String {
+    format!("{DB_DIR}/installed/{name}.toml")
+}
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn root_with(manifests: &[&str]) -> PathBuf {
+        let root =
+            std::env::temp_dir().join(format!("bpkg-test-{}-{}", std::process::id(), now_nanos()));
+        let avail = root.join(DB_DIR).join("available");
+        std::fs::create_dir_all(&avail).unwrap();
+        for (i, m) in manifests.iter().enumerate() {
+            std::fs::write(avail.join(format!("{i}.toml")), m).unwrap();
+        }
+        root
+    }
+
+    fn now_nanos() -> u128 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos()
+    }
+
+    #[test]
+    fn install_search_and_remove() {
+        let root = root_with(&[
+            "[package]\nname = \"htop\"\nversion = \"3.3.0\"\ndescription = \"Process viewer\"\ndepends = [\"ncurses\"]\n",
+            "[package]\nname = \"ncurses\"\nversion = \"6.4\"\n",
+        ]);
+        let pkg = Bpkg::open(&root);
+
+        assert!(pkg.install(&["htop>=4".into()]).is_err());
+        let plan = pkg.plan_install(&["htop".into()]).unwrap();
+        assert!(pkg.installed().unwrap().is_empty());
+        let tx = pkg.apply(&plan, InstallOptions::default()).unwrap();
+        assert_eq!(tx.changes.len(), 2);
+        assert_eq!(
+            tx.changes[0].reason.as_deref(),
+            Some("required by htop 3.3.0")
+        );
+        assert_eq!(tx.changes[1].to.as_deref(), Some("3.3.0"));
+        assert!(!pkg.installed().unwrap()[1].explicit);
+
+        let found = pkg.search("viewer").unwrap();
+        assert_eq!(found[0].installed.as_deref(), Some("3.3.0"));
+
+        assert!(pkg.remove(&["ncurses".into()]).is_err());
+        let tx = pkg.remove(&["htop".into()]).unwrap();
+        assert_eq!(tx.changes[0].kind, ChangeKind::Remove);
+        assert!(pkg.info("htop").unwrap().unwrap().installed.is_none());
+        std::fs::remove_dir_all(root).ok();
+    }
+
+    /// Write a package directory with `files` (path, contents) under `root/`.
+    fn package_dir(root: &Path, manifest: &str, files: &[(&str, &str)]) -> String {
+        let dir = root.join(format!("pkg-{}", now_nanos()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(source::MANIFEST_FILE), manifest).unwrap();
+        for (path, body) in files {
+            let p = dir.join(source::PAYLOAD_DIR).join(path);
+            std::fs::create_dir_all(p.parent().unwrap()).unwrap();
+            std::fs::write(p, body).unwrap();
+        }
+        dir.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn installs_files_and_detects_conflicts() {
+        let root = root_with(&[]);
+        let pkg = Bpkg::open(&root);
+        let a = package_dir(
+            &root,
+            "[package]\nname = \"a\"\nversion = \"1\"\n",
+            &[("usr/bin/a", "A"), ("usr/share/a/data", "D")],
+        );
+        let b = package_dir(
+            &root,
+            "[package]\nname = \"b\"\nversion = \"1\"\n",
+            &[("usr/bin/a", "B")],
+        );
+
+        pkg.install(&[a]).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(root.join("usr/bin/a")).unwrap(),
+            "A"
+        );
+        let err = pkg.install(std::slice::from_ref(&b)).unwrap_err();
+        assert!(err.to_string().contains("conflicts with a"), "{err}");
+
+        std::fs::write(root.join("usr/bin/stray"), "x").unwrap();
+        let c = package_dir(
+            &root,
+            "[package]\nname = \"c\"\nversion = \"1\"\n",
+            &[("usr/bin/stray", "C")],
+        );
+        assert!(pkg.install(std::slice::from_ref(&c)).is_err());
+        pkg.install_with(&[c], InstallOptions { force: true })
+            .unwrap();
+
+        pkg.remove(&["a".into()]).unwrap();
+        assert!(!root.join("usr/bin/a").exists());
+        assert!(!root.join("usr/share/a").exists());
+        assert!(root.join("usr/bin/stray").exists());
+        std::fs::remove_dir_all(root).ok();
+    }
+
+    #[test]
+    fn failed_script_rolls_back_install() {
+        let root = root_with(&[]);
+        let pkg = Bpkg::open(&root);
+        std::fs::create_dir_all(root.join("etc")).unwrap();
+        std::fs::write(root.join("etc/app.conf"), "old").unwrap();
+        let a = package_dir(
+            &root,
+            "[package]\nname = \"app\"\nversion = \"1\"\n\n[scripts]\npost_install = \"exit 3\"\n",
+            &[("etc/app.conf", "new"), ("opt/app/bin", "B")],
+        );
+
+        let err = pkg
+            .install_with(&[a], InstallOptions { force: true })
+            .unwrap_err();
+        assert!(err.to_string().contains("post_install"), "{err}");
+        assert_eq!(
+            std::fs::read_to_string(root.join("etc/app.conf")).unwrap(),
+            "old"
+        );
+        assert!(!root.join("opt").exists());
+        assert!(pkg.installed().unwrap().is_empty());
+        assert!(!root.join(DB_DIR).join("txn").exists());
+        std::fs::remove_dir_all(root).ok();
+    }
+
+    #[test]
+    fn post_commit_runs_once_the_transaction_is_in() {
+        let root = root_with(&[]);
+        let pkg = Bpkg::open(&root);
+        let a = package_dir(
+            &root,
+            "[package]\nname = \"app\"\nversion = \"1\"\n\n[scripts]\npost_commit = \"test ! -d var/lib/bpkg/txn && cat opt/app/bin > started\"\n",
+            &[("opt/app/bin", "B")],
+        );
+        pkg.install(&[a]).unwrap();
+        assert_eq!(std::fs::read_to_string(root.join("started")).unwrap(), "B");
+
+        let b = package_dir(
+            &root,
+            "[package]\nname = \"app\"\nversion = \"2\"\n\n[scripts]\npost_commit = \"exit 1\"\n",
+            &[("opt/app/bin", "C")],
+        );
+        let err = pkg.install(&[b]).unwrap_err();
+        assert!(err.to_string().contains("applied"), "{err}");
+        assert_eq!(pkg.installed().unwrap()[0].package.version, "2");
+        std::fs::remove_dir_all(root).ok();
+    }
+
+    #[test]
+    fn cancelled_transactions_roll_back() {
+        let root = root_with(&[]);
+        let pkg = Bpkg::open(&root);
+        let a = package_dir(
+            &root,
+            "[package]\nname = \"app\"\nversion = \"1\"\n",
+            &[("opt/app/bin", "B")],
+        );
+        assert!(pkg.plan_install_names(std::slice::from_ref(&a)).is_err());
+
+        let plan = pkg.plan_install(&[a]).unwrap();
+        let cancel = CancelToken::default();
+        assert!(cancel.cancel());
+        let err = pkg
+            .apply_cancellable(&plan, InstallOptions::default(), &cancel)
+            .unwrap_err();
+        assert!(err.to_string().contains("cancelled"), "{err}");
+        assert!(pkg.installed().unwrap().is_empty());
+        assert!(!root.join("opt").exists());
+
+        // Past the commit the transaction finishes regardless.
+        let cancel = CancelToken::default();
+        pkg.apply_cancellable(&plan, InstallOptions::default(), &cancel)
+            .unwrap();
+        assert!(!cancel.cancel());
+        assert_eq!(pkg.installed().unwrap().len(), 1);
+        std::fs::remove_dir_all(root).ok();
+    }
+
+    #[test]
+    fn upgrade_stays_within_pins() {
+        let root = root_with(&[
+            "[package]\nname = \"tool\"\nversion = \"1.0.0\"\n",
+            "[package]\nname = \"tool\"\nversion = \"1.2.0\"\n",
+            "[package]\nname = \"tool\"\nversion = \"2.0.0\"\n",
+        ]);
+        let pkg = Bpkg::open(&root);
+        pkg.install(&["tool=1.0.0".into()]).unwrap();
+        assert_eq!(pkg.pin("tool", Some("<2")).unwrap(), "<2");
+
+        let tx = pkg.apply(&pkg.plan_upgrade(&[]).unwrap(), InstallOptions::default());
+        assert_eq!(tx.unwrap().changes[0].to.as_deref(), Some("1.2.0"));
+        assert!(pkg
+            .plan_upgrade(&[])
+            .unwrap()
+            .transaction
+            .changes
+            .is_empty());
+        assert!(pkg.install(&["tool=2.0.0".into()]).is_err());
+
+        pkg.unpin("tool").unwrap();
+        let plan = pkg.plan_upgrade(&["tool".into()]).unwrap();
+        assert_eq!(plan.transaction.changes[0].kind, ChangeKind::Upgrade);
+        pkg.apply(&plan, InstallOptions::default()).unwrap();
+        assert!(pkg.installed().unwrap()[0].explicit);
+        std::fs::remove_dir_all(root).ok();
+    }
+
+    #[test]
+    fn rollback_tells_builds_of_one_version_apart() {
+        let root = root_with(&[]);
+        let pkg = Bpkg::open(&root);
+        let manifest = "[package]\nname = \"app\"\nversion = \"1.0\"\n";
+        let first = package_dir(&root, manifest, &[("opt/app/bin", "first")]);
+        let second = package_dir(&root, manifest, &[("opt/app/bin", "second")]);
+        pkg.install(&[first]).unwrap();
+        pkg.remove(&["app".into()]).unwrap();
+        pkg.install(&[second]).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(root.join("opt/app/bin")).unwrap(),
+            "second"
+        );
+
+        let cached = std::fs::read_dir(root.join(DB_DIR).join("cache"))
+            .unwrap()
+            .filter(|e| {
+                e.as_ref()
+                    .unwrap()
+                    .path()
+                    .extension()
+                    .is_some_and(|x| x == "bpkg")
+            })
+            .count();
+        assert_eq!(cached, 2);
+        pkg.rollback(1).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(root.join("opt/app/bin")).unwrap(),
+            "first"
+        );
+        std::fs::remove_dir_all(root).ok();
+    }
+
+    #[test]
+    fn rollback_restores_an_earlier_generation() {
+        let root = root_with(&[]);
+        let pkg = Bpkg::open(&root);
+        let v1 = package_dir(
+            &root,
+            "[package]\nname = \"app\"\nversion = \"1.0\"\n",
+            &[("opt/app/bin", "one"), ("opt/app/old", "legacy")],
+        );
+        let v2 = package_dir(
+            &root,
+            "[package]\nname = \"app\"\nversion = \"2.0\"\n",
+            &[("opt/app/bin", "two")],
+        );
+        let extra = package_dir(
+            &root,
+            "[package]\nname = \"extra\"\nversion = \"1\"\n",
+            &[("opt/extra", "x")],
+        );
+        pkg.install(std::slice::from_ref(&v1)).unwrap();
+        std::fs::remove_dir_all(&v1).unwrap();
+        pkg.install(&[v2, extra]).unwrap();
+        assert!(!root.join("opt/app/old").exists());
+
+        let generations = pkg.generations().unwrap();
+        assert_eq!(generations.len(), 2);
+        assert_eq!(generations[1].packages.len(), 2);
+
+        let plan = pkg.plan_rollback(1).unwrap();
+        let kinds: Vec<_> = plan.transaction.changes.iter().map(|c| c.kind).collect();
+        assert_eq!(kinds, [ChangeKind::Remove, ChangeKind::Downgrade]);
+        pkg.apply(&plan, InstallOptions::default()).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(root.join("opt/app/bin")).unwrap(),
+            "one"
+        );
+        assert!(root.join("opt/app/old").exists());
+        assert!(!root.join("opt/extra").exists());
+        let last = pkg.generations().unwrap().pop().unwrap();
+        assert_eq!((last.id, last.summary.as_str()), (3, "rollback to 1"));
+
+        // Generation 2's archives are still cached, so it can come back too.
+        pkg.rollback(2).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(root.join("opt/app/bin")).unwrap(),
+            "two"
+        );
+        std::fs::remove_dir_all(root).ok();
+    }
+}
@@ -1,4 +1,7 @@
+use anyhow::Result;
+use bpkg::{Bpkg, ChangeKind, InstallOptions, Plan};
 use clap::{Parser, Subcommand};
+use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
 #[command(name = "bpkg")]
@@ -6,26 +9,336 @@ use clap::{Parser, Subcommand};
 struct Args {
     #[command(subcommand)]
     command: Commands,
+
+    /// Install root (the database lives under <root>/var/lib/bpkg)
+    #[arg(long, default_value = "/", global = true)]
+    root: PathBuf,
 }
 
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// List installed packages
     List,
-    /// Install a package
-    Install { name: String },
+    /// Install packages by name (optionally `name>=version`) or from package directories
+    Install {
+        names: Vec<String>,
+        /// Overwrite files that exist on disk but belong to no package
+        #[arg(long)]
+        force: bool,
+        /// Show the plan without applying it
+        #[arg(long)]
+        dry_run: bool,
+    },
     /// Remove a package
-    Remove { name: String },
+    Remove {
+        names: Vec<String>,
+        /// Show the plan without applying it
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Upgrade installed packages (all, or the ones named) within their pins
+    Upgrade {
+        names: Vec<String>,
+        /// Show the plan without applying it
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// List the numbered generations, newest last
+    History,
+    /// Restore the installed set of a generation (default: the one before the current)
+    Rollback {
+        generation: Option<u64>,
+        /// Show the plan without applying it
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Pin a package to a version requirement, or list pins
+    Pin {
+        name: Option<String>,
+        /// e.g. `~1.4` or `<2`; defaults to the installed version
+        req: Option<String>,
+    },
+    /// Hold a package at its installed version
+    Hold { name: String },
+    /// Remove a package's pin or hold
+    Unpin { name: String },
     /// Search for packages
     Search { query: String },
+    /// Show details of a package
+    Info { name: String },
+    /// Show which package owns a file
+    Owner { path: String },
+    /// Fetch package indexes from the repositories in etc/bpkg/repos.toml
+    Update,
+    /// Pack a package directory (bpkg.toml + root/) into a .bpkg archive
+    Pack {
+        dir: PathBuf,
+        #[arg(long, default_value = ".")]
+        out: PathBuf,
+    },
+    /// Build a package archive from a recipe
+    Build {
+        recipe: PathBuf,
+        #[arg(long, default_value = ".")]
+        out: PathBuf,
+        /// Package existing build outputs without running the build command
+        #[arg(long)]
+        no_build: bool,
+        /// Appended to the version as `+<n>`; defaults to the current Unix time
+        #[arg(long)]
+        build_number: Option<u64>,
+    },
+    /// Manage a package repository
+    Repo {
+        #[command(subcommand)]
+        command: RepoCommands,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum RepoCommands {
+    /// Write a signed index.toml for the .bpkg archives in a directory
+    Build {
+        dir: PathBuf,
+        /// Signing key created by `bpkg repo keygen`
+        #[arg(long)]
+        key: PathBuf,
+    },
+    /// Create an ed25519 signing key (public key goes to <path>.pub)
+    Keygen { path: PathBuf },
+}
+
+/// Prints what the library logs (recovered or failed rollbacks) to stderr.
+struct StderrLog;
+
+impl log::Log for StderrLog {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Info
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("bpkg: {}", record.args());
+        }
+    }
+
+    fn flush(&self) {}
 }
 
-fn main() {
+fn main() -> Result<()> {
+    log::set_logger(&StderrLog)
+        .map(|()| log::set_max_level(log::LevelFilter::Info))
+        .ok();
     let args = Args::parse();
+    let pkg = Bpkg::open(&args.root);
     match args.command {
-        Commands::List => println!("Listing installed packages..."),
-        Commands::Install { name } => println!("Installing {}...", name),
-        Commands::Remove { name } => println!("Removing {}...", name),
-        Commands::Search { query } => println!("Searching for {}...", query),
+        Commands::List => {
+            for i in pkg.installed()? {
+                println!("{} {}", i.package.name, i.package.version);
+            }
+        }
+        Commands::Install {
+            names,
+            force,
+            dry_run,
+        } => {
+            let plan = pkg.plan_install(&names)?;
+            run_plan(&pkg, &plan, InstallOptions { force }, dry_run)?;
+        }
+        Commands::Remove { names, dry_run } => {
+            let plan = pkg.plan_remove(&names)?;
+            run_plan(&pkg, &plan, InstallOptions::default(), dry_run)?;
+        }
+        Commands::Upgrade { names, dry_run } => {
+            let plan = pkg.plan_upgrade(&names)?;
+            run_plan(&pkg, &plan, InstallOptions::default(), dry_run)?;
+        }
+        Commands::History => {
+            let generations = pkg.generations()?;
+            for g in &generations {
+                let current = if Some(g.id) == generations.last().map(|l| l.id) {
+                    " (current)"
+                } else {
+                    ""
+                };
+                println!(
+                    "{:>4}  {}  {}{current}",
+                    g.id,
+                    format_time(g.created_at),
+                    g.summary
+                );
+            }
+        }
+        Commands::Rollback {
+            generation,
+            dry_run,
+        } => {
+            let id = match generation {
+                Some(id) => id,
+                None => {
+                    let generations = pkg.generations()?;
+                    match generations.len() {
+                        0 | 1 => anyhow::bail!("no earlier generation to roll back to"),
+                        n => generations[n - 2].id,
+                    }
+                }
+            };
+            let plan = pkg.plan_rollback(id)?;
+            run_plan(&pkg, &plan, InstallOptions::default(), dry_run)?;
+        }
+        Commands::Pin {
+            name: Some(name),
+            req,
+        } => {
+            let req = pkg.pin(&name, req.as_deref())?;
+            println!("{name} pinned to {req}");
+        }
+        Commands::Pin { name: None, .. } => {
+            for (name, req) in pkg.pins()? {
+                println!("{name} {req}");
+            }
+        }
+        Commands::Hold { name } => {
+            let req = pkg.pin(&name, None)?;
+            println!("{name} held at {}", req.trim_start_matches('='));
+        }
+        Commands::Unpin { name } => {
+            if !pkg.unpin(&name)? {
+                anyhow::bail!("{name} is not pinned");
+            }
+        }
+        Commands::Search { query } => {
+            for p in pkg.search(&query)? {
+                let mark = match &p.installed {
+                    Some(v) => format!(" [installed {v}]"),
+                    None => String::new(),
+                };
+                println!("{} {}{} - {}", p.name, p.version, mark, p.description);
+            }
+        }
+        Commands::Info { name } => match pkg.info(&name)? {
+            Some(info) => {
+                let m = info
+                    .available
+                    .as_ref()
+                    .or(info.installed.as_ref().map(|i| &i.package))
+                    .expect("info has a manifest");
+                println!("Name:        {}", m.name);
+                if let Some(a) = &info.available {
+                    println!("Available:   {}", a.version);
+                }
+                println!("Description: {}", m.description);
+                println!("Depends:     {}", m.depends.join(", "));
+                match &info.installed {
+                    Some(i) => {
+                        println!("Installed:   {}", i.package.version);
+                        for f in &i.package.files {
+                            println!("  /{f}");
+                        }
+                    }
+                    None => println!("Installed:   no"),
+                }
+            }
+            None => anyhow::bail!("package {name} not found"),
+        },
+        Commands::Owner { path } => match pkg.owner(&path)? {
+            Some(name) => println!("/{} is owned by {name}", path.trim_start_matches('/')),
+            None => anyhow::bail!("no package owns {path}"),
+        },
+        Commands::Update => {
+            for (repo, count) in pkg.update()? {
+                println!("{repo}: {count} packages");
+            }
+        }
+        Commands::Pack { dir, out } => {
+            println!("{}", bpkg::archive::pack(&dir, &out)?.display());
+        }
+        Commands::Build {
+            recipe,
+            out,
+            no_build,
+            build_number,
+        } => {
+            let build_number = build_number.unwrap_or_else(|| {
+                std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs())
+            });
+            println!(
+                "{}",
+                bpkg::recipe::build(&recipe, &out, !no_build, build_number)?.display()
+            );
+        }
+        Commands::Repo { command } => match command {
+            RepoCommands::Build { dir, key } => {
+                let index = bpkg::repo::build(&dir, &key)?;
+                println!(
+                    "indexed {} packages in {}",
+                    index.packages.len(),
+                    dir.display()
+                );
+            }
+            RepoCommands::Keygen { path } => println!("{}", bpkg::repo::keygen(&path)?),
+        },
+    }
+    Ok(())
+}
+
+/// Print the plan, then apply it unless this is a dry run.
+fn run_plan(pkg: &Bpkg, plan: &Plan, opts: InstallOptions, dry_run: bool) -> Result<()> {
+    let changes = &plan.transaction.changes;
+    if changes.is_empty() {
+        println!("Nothing to do.");
+        return Ok(());
     }
+    println!("Plan:");
+    for c in changes {
+        let line = match c.kind {
+            ChangeKind::Install => format!("install {} {}", c.name, c.to.as_deref().unwrap_or("")),
+            ChangeKind::Upgrade => format!(
+                "upgrade {} {} -> {}",
+                c.name,
+                c.from.as_deref().unwrap_or(""),
+                c.to.as_deref().unwrap_or("")
+            ),
+            ChangeKind::Downgrade => format!(
+                "downgrade {} {} -> {}",
+                c.name,
+                c.from.as_deref().unwrap_or(""),
+                c.to.as_deref().unwrap_or("")
+            ),
+            ChangeKind::Remove => format!("remove  {} {}", c.name, c.from.as_deref().unwrap_or("")),
+        };
+        match &c.reason {
+            Some(why) => println!("  {line} ({why})"),
+            None => println!("  {line}"),
+        }
+    }
+    if dry_run {
+        return Ok(());
+    }
+    pkg.apply(plan, opts)?;
+    println!("Applied {} change(s).", changes.len());
+    Ok(())
+}
+
+/// `YYYY-MM-DD HH:MM` (UTC) for a Unix timestamp.
+fn format_time(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+    // Civil-from-days, after Howard Hinnant's algorithm.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}",
+        rem / 3_600,
+        rem % 3_600 / 60
+    )
 }
@@ -0,0 +1,142 @@
+use crate::resolve::{parse_version, valid_name_char, Dep};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Package metadata as written in `bpkg.toml`:
+///
+/// ```toml
+/// [package]
+/// name = "munin-core"
+/// version = "0.1.0"
+/// description = "MuninOS core"
+/// depends = ["munin-brain >= 0.2, <0.3"]
+/// conflicts = ["munin-core-legacy"]
+/// provides = ["munin-agent"]
+/// # Optional; defaults to every file under the package's `root/`.
+/// files = ["usr/bin/munin-core"]
+///
+/// [scripts]
+/// post_install = "systemctl daemon-reload"
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub name: String,
+    pub version: String,
+    #[serde(default)]
+    pub description: String,
+    /// Packages that must be installed first, as `name [semver requirement]`.
+    #[serde(default)]
+    pub depends: Vec<String>,
+    /// Packages that cannot be installed alongside this one, same syntax.
+    #[serde(default)]
+    pub conflicts: Vec<String>,
+    /// Virtual package names this package satisfies, at its own version.
+    #[serde(default)]
+    pub provides: Vec<String>,
+    /// Paths relative to the install root that this package owns.
+    #[serde(default)]
+    pub files: Vec<String>,
+    #[serde(default)]
+    pub scripts: Scripts,
+}
+
+/// Shell snippets run with `sh -c` from the install root. `BPKG_ROOT`,
+/// `BPKG_PACKAGE`, `BPKG_VERSION` and `BPKG_OLD_VERSION` are set.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Scripts {
+    pub pre_install: Option<String>,
+    pub post_install: Option<String>,
+    pub pre_remove: Option<String>,
+    pub post_remove: Option<String>,
+    /// Runs once the transaction that installed or upgraded the package has
+    /// committed, e.g. to restart its services. A failure is reported but
+    /// can't undo the install.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_commit: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ManifestFile {
+    package: Manifest,
+    #[serde(default)]
+    scripts: Option<Scripts>,
+}
+
+impl Manifest {
+    pub fn parse(text: &str) -> Result<Self> {
+        let file: ManifestFile = toml::from_str(text)?;
+        let mut manifest = file.package;
+        if let Some(scripts) = file.scripts {
+            manifest.scripts = scripts;
+        }
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    /// Render as a `bpkg.toml` (scripts nested under `[package.scripts]`).
+    pub fn to_toml(&self) -> Result<String> {
+        #[derive(Serialize)]
+        struct Out<'a> {
+            package: &'a Manifest,
+        }
+        Ok(toml::to_string(&Out { package: self })?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed reading {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("invalid manifest {}", path.display()))
+    }
+
+    fn validate(&self) -> Result<()> {
+        for name in std::iter::once(&self.name).chain(&self.provides) {
+            if name.is_empty() || !name.chars().all(valid_name_char) || name.starts_with('.') {
+                bail!("invalid package name {name:?}");
+            }
+        }
+        parse_version(&self.version).with_context(|| format!("{} has a bad version", self.name))?;
+        for spec in self.depends.iter().chain(&self.conflicts) {
+            Dep::parse(spec).with_context(|| format!("{} has a bad dependency", self.name))?;
+        }
+        for f in &self.files {
+            check_rel_path(f).with_context(|| format!("{} lists a bad file", self.name))?;
+        }
+        Ok(())
+    }
+}
+
+/// Package paths must stay inside the install root.
+pub fn check_rel_path(path: &str) -> Result<()> {
+    let p = Path::new(path);
+    if path.is_empty()
+        || p.is_absolute()
+        || p.components()
+            .any(|c| !matches!(c, std::path::Component::Normal(_)))
+    {
+        bail!("{path:?} is not a plain relative path");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_scripts_and_rejects_escaping_paths() {
+        let m = Manifest::parse(
+            "[package]\nname = \"a\"\nversion = \"1\"\nfiles = [\"usr/bin/a\"]\n\n[scripts]\npost_install = \"true\"\n",
+        )
+        .unwrap();
+        assert_eq!(m.scripts.post_install.as_deref(), Some("true"));
+        assert!(Manifest::parse(
+            "[package]\nname = \"a\"\nversion = \"1\"\nfiles = [\"../etc/shadow\"]\n"
+        )
+        .is_err());
+        assert!(Manifest::parse(
+            "[package]\nname = \"a\"\nversion = \"1\"\nfiles = [\"/etc/shadow\"]\n"
+        )
+        .is_err());
+    }
+}
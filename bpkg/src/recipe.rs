@@ -0,0 +1,328 @@
+//! Build recipes: how to turn a source tree into a `.bpkg` archive.
+//!
+//! ```toml
+//! [package]
+//! name = "munin-core"
+//! # version defaults to the source tree's Cargo.toml, plus `+<build number>`
+//! description = "MuninOS core orchestrator"
+//!
+//! [build]
+//! source = "../../munin-core"
+//! command = "cargo build --release"
+//!
+//! [[install]]
+//! from = "../../munin-core/target/release/munin-core"
+//! to = "opt/muninos/bin/munin-core"
+//!
+//! [[unit]]
+//! from = "../rootfs/overlay/etc/systemd/system/munin-core.service"
+//!
+//! [scripts]
+//! post_install = "echo installed"
+//! ```
+//!
+//! All paths are relative to the recipe file. `install.from` may be a
+//! directory, copied recursively. Units land in `etc/systemd/system/` and are
+//! enabled (and restarted on upgrade when systemd is running) by a generated
+//! `post_commit` hook, so services only restart once the new files are in
+//! place for good; it runs before the recipe's own.
+//!
+//! The build number makes every build a distinct, newer version even when the
+//! source version stays the same, so `bpkg upgrade` and `rollback` can tell
+//! builds apart.
+
+use crate::archive;
+use crate::manifest::{check_rel_path, Manifest, Scripts};
+use crate::source::{MANIFEST_FILE, PAYLOAD_DIR};
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const UNIT_DIR: &str = "etc/systemd/system";
+
+#[derive(Debug, Deserialize)]
+struct Recipe {
+    package: RecipePackage,
+    #[serde(default)]
+    build: Option<BuildStep>,
+    #[serde(default)]
+    install: Vec<InstallItem>,
+    #[serde(default)]
+    unit: Vec<Unit>,
+    #[serde(default)]
+    scripts: Scripts,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecipePackage {
+    name: String,
+    version: Option<String>,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    depends: Vec<String>,
+    #[serde(default)]
+    conflicts: Vec<String>,
+    #[serde(default)]
+    provides: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BuildStep {
+    source: PathBuf,
+    command: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct InstallItem {
+    from: PathBuf,
+    to: String,
+    /// Octal permissions, e.g. "0755"; defaults to the source file's.
+    mode: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Unit {
+    from: PathBuf,
+    #[serde(default = "default_true")]
+    enable: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Build the recipe at `path` into an archive in `out_dir`, versioned
+/// `<version>+<build_number>`. With `run_build` false the build command is
+/// skipped and existing outputs are packaged as they are.
+pub fn build(path: &Path, out_dir: &Path, run_build: bool, build_number: u64) -> Result<PathBuf> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("failed reading {}", path.display()))?;
+    let recipe: Recipe =
+        toml::from_str(&text).with_context(|| format!("invalid recipe {}", path.display()))?;
+    let base = path.parent().unwrap_or(Path::new("."));
+    let source = recipe.build.as_ref().map(|b| base.join(&b.source));
+
+    let version = match (&recipe.package.version, &source) {
+        (Some(v), _) => v.clone(),
+        (None, Some(src)) => cargo_version(src)?,
+        (None, None) => bail!("{} has no version and no source tree", path.display()),
+    };
+    // Build metadata orders numerically, so a later build is an upgrade.
+    let version = match version.split_once('+') {
+        Some(_) => version,
+        None => format!("{version}+{build_number}"),
+    };
+
+    if let (true, Some(step), Some(src)) = (run_build, &recipe.build, &source) {
+        if let Some(command) = &step.command {
+            let status = Command::new("sh")
+                .arg("-c")
+                .arg(command)
+                .current_dir(src)
+                .status()
+                .with_context(|| format!("failed running {command:?}"))?;
+            if !status.success() {
+                bail!("build of {} failed ({status})", recipe.package.name);
+            }
+        }
+    }
+
+    let stage = out_dir.join(format!(".stage-{}", recipe.package.name));
+    match std::fs::remove_dir_all(&stage) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    let result = stage_package(&recipe, base, &version, &stage)
+        .and_then(|()| archive::pack(&stage, out_dir));
+    std::fs::remove_dir_all(&stage).ok();
+    result
+}
+
+fn stage_package(recipe: &Recipe, base: &Path, version: &str, stage: &Path) -> Result<()> {
+    let payload = stage.join(PAYLOAD_DIR);
+    std::fs::create_dir_all(&payload)?;
+    for item in &recipe.install {
+        check_rel_path(&item.to)?;
+        let dest = payload.join(&item.to);
+        copy_tree(&base.join(&item.from), &dest)?;
+        if let Some(mode) = &item.mode {
+            let mode = u32::from_str_radix(mode.trim_start_matches("0o"), 8)
+                .with_context(|| format!("bad mode {mode:?} for {}", item.to))?;
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&dest, std::fs::Permissions::from_mode(mode))?;
+        }
+    }
+    let mut units = Vec::new();
+    for unit in &recipe.unit {
+        let from = base.join(&unit.from);
+        let name = from
+            .file_name()
+            .and_then(|n| n.to_str())
+            .with_context(|| format!("bad unit path {}", unit.from.display()))?
+            .to_string();
+        copy_tree(&from, &payload.join(UNIT_DIR).join(&name))?;
+        if unit.enable {
+            units.push(name);
+        }
+    }
+
+    let p = &recipe.package;
+    let manifest = Manifest {
+        name: p.name.clone(),
+        version: version.to_string(),
+        description: p.description.clone(),
+        depends: p.depends.clone(),
+        conflicts: p.conflicts.clone(),
+        provides: p.provides.clone(),
+        files: Vec::new(),
+        scripts: unit_scripts(&units, &recipe.scripts),
+    };
+    // Round-trip through the parser so the recipe gets manifest validation.
+    let text = manifest.to_toml()?;
+    Manifest::parse(&text)?;
+    std::fs::write(stage.join(MANIFEST_FILE), text)?;
+    Ok(())
+}
+
+/// Prepend unit enable/restart (after commit) and disable/stop hooks to the
+/// recipe's own. Against another root (an image being built) units are only
+/// enabled.
+fn unit_scripts(units: &[String], own: &Scripts) -> Scripts {
+    if units.is_empty() {
+        return own.clone();
+    }
+    let list = units.join(" ");
+    let live = r#"[ "$BPKG_ROOT" = / ] && [ -d /run/systemd/system ]"#;
+    let post_commit = format!(
+        "if {live}; then systemctl daemon-reload; systemctl enable {list}; \
+         if [ -n \"$BPKG_OLD_VERSION\" ]; then systemctl try-restart {list}; else systemctl start {list}; fi; \
+         else systemctl --root=\"$BPKG_ROOT\" enable {list} || true; fi"
+    );
+    let pre_remove = format!(
+        "if {live}; then systemctl disable --now {list}; \
+         else systemctl --root=\"$BPKG_ROOT\" disable {list} || true; fi"
+    );
+    let join = |generated: String, own: &Option<String>| match own {
+        Some(own) => Some(format!("{generated}\n{own}")),
+        None => Some(generated),
+    };
+    Scripts {
+        pre_install: own.pre_install.clone(),
+        post_install: own.post_install.clone(),
+        pre_remove: join(pre_remove, &own.pre_remove),
+        post_remove: own.post_remove.clone(),
+        post_commit: join(post_commit, &own.post_commit),
+    }
+}
+
+/// Copy a file, symlink or directory tree, keeping permissions.
+fn copy_tree(from: &Path, to: &Path) -> Result<()> {
+    let meta = from
+        .symlink_metadata()
+        .with_context(|| format!("{} does not exist (was it built?)", from.display()))?;
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if meta.is_dir() {
+        std::fs::create_dir_all(to)?;
+        for entry in std::fs::read_dir(from)? {
+            let entry = entry?;
+            copy_tree(&entry.path(), &to.join(entry.file_name()))?;
+        }
+    } else if meta.is_symlink() {
+        std::os::unix::fs::symlink(std::fs::read_link(from)?, to)?;
+    } else {
+        std::fs::copy(from, to)
+            .with_context(|| format!("failed copying {} to {}", from.display(), to.display()))?;
+    }
+    Ok(())
+}
+
+/// `[package].version` from a Cargo.toml in `dir`.
+fn cargo_version(dir: &Path) -> Result<String> {
+    let path = dir.join("Cargo.toml");
+    let text = std::fs::read_to_string(&path)
+        .with_context(|| format!("no version in recipe and failed reading {}", path.display()))?;
+    let value: toml::Value = toml::from_str(&text)?;
+    value
+        .get("package")
+        .and_then(|p| p.get("version"))
+        .and_then(|v| v.as_str())
+        .map(str::to_string)
+        .with_context(|| format!("{} has no [package].version", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_installs_and_units_into_an_archive() {
+        let dir = std::env::temp_dir().join(format!("bpkg-recipe-{}", std::process::id()));
+        let src = dir.join("tool");
+        std::fs::create_dir_all(src.join("assets")).unwrap();
+        std::fs::write(
+            src.join("Cargo.toml"),
+            "[package]\nname = \"tool\"\nversion = \"0.4.1\"\n",
+        )
+        .unwrap();
+        std::fs::write(src.join("assets/index.html"), "<html>").unwrap();
+        std::fs::write(dir.join("tool.service"), "[Service]\n").unwrap();
+        std::fs::write(
+            dir.join("tool.toml"),
+            r#"
+[package]
+name = "tool"
+
+[build]
+source = "tool"
+command = "mkdir -p out && printf '#!/bin/sh\n' > out/tool"
+
+[[install]]
+from = "tool/out/tool"
+to = "opt/bin/tool"
+mode = "0755"
+
+[[install]]
+from = "tool/assets"
+to = "opt/ui"
+
+[[unit]]
+from = "tool.service"
+
+[scripts]
+post_install = "echo hello"
+"#,
+        )
+        .unwrap();
+
+        let out = build(&dir.join("tool.toml"), &dir.join("out"), true, 7).unwrap();
+        assert!(out.ends_with("tool-0.4.1+7.bpkg"));
+        let m = archive::read_manifest(&out).unwrap();
+        assert_eq!(
+            m.files,
+            [
+                "etc/systemd/system/tool.service",
+                "opt/bin/tool",
+                "opt/ui/index.html"
+            ]
+        );
+        let activate = m.scripts.post_commit.unwrap();
+        assert!(activate.contains("enable tool.service"), "{activate}");
+        assert!(activate.contains("try-restart tool.service"), "{activate}");
+        assert_eq!(m.scripts.post_install.as_deref(), Some("echo hello"));
+        assert!(
+            crate::resolve::parse_version(&m.version).unwrap()
+                > crate::resolve::parse_version("0.4.1+6").unwrap()
+        );
+        assert!(m.scripts.pre_remove.unwrap().contains("disable"));
+
+        let unpacked = archive::unpack(&out, &dir.join("unpacked")).unwrap();
+        let tool = unpacked.payload.unwrap().join("opt/bin/tool");
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(tool.metadata().unwrap().permissions().mode() & 0o777, 0o755);
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,326 @@
+//! Package repositories: a directory (served over `file://` or `http://`)
+//! holding `.bpkg` archives, an `index.toml` describing them and an
+//! `index.toml.sig` ed25519 signature over the index.
+//!
+//! Repositories are configured in `<root>/etc/bpkg/repos.toml`:
+//!
+//! ```toml
+//! [[repo]]
+//! name = "main"
+//! url = "http://10.0.2.2:8000/repo"
+//! key = "3b6a27bc..."   # hex ed25519 public key
+//! ```
+
+use crate::archive;
+use crate::db::write_atomic;
+use crate::manifest::Manifest;
+use crate::source::{Remote, Source};
+use anyhow::{bail, Context, Result};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Repository configuration, relative to the install root.
+pub const CONFIG_FILE: &str = "etc/bpkg/repos.toml";
+pub const INDEX_FILE: &str = "index.toml";
+pub const SIG_FILE: &str = "index.toml.sig";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RepoConfig {
+    pub name: String,
+    pub url: String,
+    /// Hex ed25519 public key the index must be signed with.
+    pub key: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ConfigFile {
+    #[serde(default)]
+    repo: Vec<RepoConfig>,
+}
+
+pub fn load_config(root: &Path) -> Result<Vec<RepoConfig>> {
+    let path = root.join(CONFIG_FILE);
+    match std::fs::read_to_string(&path) {
+        Ok(text) => Ok(toml::from_str::<ConfigFile>(&text)
+            .with_context(|| format!("invalid {}", path.display()))?
+            .repo),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e).with_context(|| format!("failed reading {}", path.display())),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexEntry {
+    /// Archive file name, relative to the repository URL.
+    pub file: String,
+    pub sha256: String,
+    pub size: u64,
+    pub package: Manifest,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Index {
+    #[serde(default)]
+    pub packages: Vec<IndexEntry>,
+}
+
+/// Create a signing key at `path` (hex seed, mode 0600) and write the
+/// public key next to it as `<path>.pub`. Returns the public key.
+pub fn keygen(path: &Path) -> Result<String> {
+    if path.exists() {
+        bail!("{} already exists", path.display());
+    }
+    let mut seed = [0u8; 32];
+    std::fs::File::open("/dev/urandom")?.read_exact(&mut seed)?;
+    let key = SigningKey::from_bytes(&seed);
+    let public = hex::encode(key.verifying_key().as_bytes());
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(path)
+            .with_context(|| format!("failed creating {}", path.display()))?;
+        std::io::Write::write_all(&mut file, format!("{}\n", hex::encode(seed)).as_bytes())?;
+    }
+    std::fs::write(pub_path(path), format!("{public}\n"))?;
+    Ok(public)
+}
+
+fn pub_path(secret: &Path) -> PathBuf {
+    let mut name = secret.as_os_str().to_owned();
+    name.push(".pub");
+    PathBuf::from(name)
+}
+
+fn load_signing_key(path: &Path) -> Result<SigningKey> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("failed reading {}", path.display()))?;
+    let seed: [u8; 32] = hex::decode(text.trim())
+        .ok()
+        .and_then(|b| b.try_into().ok())
+        .with_context(|| format!("{} is not a hex ed25519 seed", path.display()))?;
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+/// Index every archive in `dir` and sign the index with `key`.
+pub fn build(dir: &Path, key: &Path) -> Result<Index> {
+    let key = load_signing_key(key)?;
+    let mut index = Index::default();
+    for entry in
+        std::fs::read_dir(dir).with_context(|| format!("failed reading {}", dir.display()))?
+    {
+        let path = entry?.path();
+        if path.extension().is_none_or(|e| e != archive::EXTENSION) {
+            continue;
+        }
+        index.packages.push(IndexEntry {
+            file: path.file_name().unwrap().to_string_lossy().into_owned(),
+            sha256: archive::sha256_file(&path)?,
+            size: path.metadata()?.len(),
+            package: archive::read_manifest(&path)?,
+        });
+    }
+    index
+        .packages
+        .sort_by(|a, b| (&a.package.name, &a.file).cmp(&(&b.package.name, &b.file)));
+
+    let text = toml::to_string(&index)?;
+    let sig = key.sign(text.as_bytes());
+    write_atomic(&dir.join(INDEX_FILE), text.as_bytes())?;
+    write_atomic(
+        &dir.join(SIG_FILE),
+        format!("{}\n", hex::encode(sig.to_bytes())).as_bytes(),
+    )?;
+    Ok(index)
+}
+
+/// Check `sig` (hex) over `index` against the repository's public key.
+pub fn verify(repo: &RepoConfig, index: &[u8], sig: &str) -> Result<()> {
+    let key: [u8; 32] = hex::decode(repo.key.trim())
+        .ok()
+        .and_then(|b| b.try_into().ok())
+        .with_context(|| format!("repo {} has a malformed key", repo.name))?;
+    let key = VerifyingKey::from_bytes(&key)
+        .with_context(|| format!("repo {} has an invalid key", repo.name))?;
+    let sig: [u8; 64] = hex::decode(sig.trim())
+        .ok()
+        .and_then(|b| b.try_into().ok())
+        .with_context(|| format!("repo {} has a malformed index signature", repo.name))?;
+    key.verify(index, &Signature::from_bytes(&sig))
+        .with_context(|| format!("repo {} index signature does not verify", repo.name))
+}
+
+/// Fetch every configured repository's index into `<db>/repos/<name>/`,
+/// keeping the old copy if the new one is missing or badly signed.
+/// Returns each repository with its package count.
+pub fn update(root: &Path, db_dir: &Path) -> Result<Vec<(String, usize)>> {
+    let mut out = Vec::new();
+    for repo in load_config(root)? {
+        let base = repo.url.trim_end_matches('/');
+        let index = fetch(&format!("{base}/{INDEX_FILE}"))?;
+        let sig = fetch(&format!("{base}/{SIG_FILE}"))?;
+        verify(&repo, &index, &String::from_utf8_lossy(&sig))?;
+        let parsed: Index = toml::from_str(std::str::from_utf8(&index)?)
+            .with_context(|| format!("repo {} has an invalid index", repo.name))?;
+
+        let dir = db_dir.join("repos").join(&repo.name);
+        write_atomic(&dir.join(SIG_FILE), &sig)?;
+        write_atomic(&dir.join(INDEX_FILE), &index)?;
+        out.push((repo.name, parsed.packages.len()));
+    }
+    Ok(out)
+}
+
+/// Packages from the cached indexes. Each index is checked against its
+/// signature again, so a tampered cache is refused rather than trusted.
+pub fn sources(root: &Path, db_dir: &Path) -> Result<Vec<Source>> {
+    let mut out = Vec::new();
+    for repo in load_config(root)? {
+        let dir = db_dir.join("repos").join(&repo.name);
+        let index = match std::fs::read(dir.join(INDEX_FILE)) {
+            Ok(b) => b,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let sig = std::fs::read_to_string(dir.join(SIG_FILE)).unwrap_or_default();
+        verify(&repo, &index, &sig)?;
+        let index: Index = toml::from_str(std::str::from_utf8(&index)?)?;
+        let base = repo.url.trim_end_matches('/');
+        for entry in index.packages {
+            crate::manifest::check_rel_path(&entry.file)?;
+            out.push(Source {
+                manifest: entry.package,
+                payload: None,
+                remote: Some(Remote {
+                    url: format!("{base}/{}", entry.file),
+                    sha256: entry.sha256,
+                    size: entry.size,
+                }),
+            });
+        }
+    }
+    Ok(out)
+}
+
+/// Download an archive into `cache_dir` and check its size and hash. The
+/// cached file is named by its hash too, so two builds of one version never
+/// overwrite each other.
+pub fn download(remote: &Remote, cache_dir: &Path) -> Result<PathBuf> {
+    let name = remote
+        .url
+        .rsplit('/')
+        .next()
+        .filter(|n| !n.is_empty())
+        .with_context(|| format!("{} has no file name", remote.url))?;
+    let path = cache_dir.join(cache_name(name, &remote.sha256));
+    if path.exists() && archive::sha256_file(&path)? == remote.sha256 {
+        return Ok(path);
+    }
+    let bytes = fetch(&remote.url)?;
+    if bytes.len() as u64 != remote.size {
+        bail!(
+            "{} is {} bytes, index says {}",
+            remote.url,
+            bytes.len(),
+            remote.size
+        );
+    }
+    let digest = hex::encode(Sha256::digest(&bytes));
+    if digest != remote.sha256 {
+        bail!(
+            "{} has SHA-256 {digest}, index says {}",
+            remote.url,
+            remote.sha256
+        );
+    }
+    write_atomic(&path, &bytes)?;
+    Ok(path)
+}
+
+/// Read a `file://` path or GET an `http://` URL.
+fn fetch(url: &str) -> Result<Vec<u8>> {
+    if let Some(path) = url.strip_prefix("file://") {
+        return std::fs::read(path).with_context(|| format!("failed reading {path}"));
+    }
+    if !url.starts_with("http://") {
+        bail!("unsupported repository URL {url} (use file:// or http://)");
+    }
+    let resp = ureq::get(url)
+        .call()
+        .with_context(|| format!("failed fetching {url}"))?;
+    let mut bytes = Vec::new();
+    resp.into_reader().read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// `<stem>-<sha256>.bpkg` for an archive called `file`; names already
+/// carrying the hash (cached archives) are kept.
+pub fn cache_name(file: &str, sha256: &str) -> String {
+    let stem = file
+        .strip_suffix(&format!(".{}", archive::EXTENSION))
+        .unwrap_or(file);
+    if stem.ends_with(sha256) {
+        format!("{stem}.{}", archive::EXTENSION)
+    } else {
+        format!("{stem}-{sha256}.{}", archive::EXTENSION)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signed_index_round_trips_and_rejects_tampering() {
+        let dir = std::env::temp_dir().join(format!("bpkg-repo-{}", std::process::id()));
+        let pkg = dir.join("src");
+        std::fs::create_dir_all(pkg.join("root/usr/bin")).unwrap();
+        std::fs::write(
+            pkg.join("bpkg.toml"),
+            "[package]\nname = \"hi\"\nversion = \"1.0\"\n",
+        )
+        .unwrap();
+        std::fs::write(pkg.join("root/usr/bin/hi"), "echo hi").unwrap();
+        let repo_dir = dir.join("repo");
+        archive::pack(&pkg, &repo_dir).unwrap();
+
+        let public = keygen(&dir.join("key")).unwrap();
+        let index = build(&repo_dir, &dir.join("key")).unwrap();
+        assert_eq!(index.packages[0].file, "hi-1.0.bpkg");
+        assert_eq!(index.packages[0].package.files, ["usr/bin/hi"]);
+
+        let repo = RepoConfig {
+            name: "local".into(),
+            url: format!("file://{}", repo_dir.display()),
+            key: public,
+        };
+        let text = std::fs::read(repo_dir.join(INDEX_FILE)).unwrap();
+        let sig = std::fs::read_to_string(repo_dir.join(SIG_FILE)).unwrap();
+        verify(&repo, &text, &sig).unwrap();
+        let mut forged = text.clone();
+        forged.extend_from_slice(b"\n# extra\n");
+        assert!(verify(&repo, &forged, &sig).is_err());
+
+        let mut remote = Remote {
+            url: format!("{}/hi-1.0.bpkg", repo.url),
+            sha256: index.packages[0].sha256.clone(),
+            size: index.packages[0].size,
+        };
+        let cached = download(&remote, &dir.join("cache")).unwrap();
+        assert_eq!(
+            cached.file_name().unwrap().to_str().unwrap(),
+            format!("hi-1.0-{}.bpkg", remote.sha256)
+        );
+        let unpacked = archive::unpack(&cached, &dir.join("unpacked")).unwrap();
+        assert_eq!(unpacked.manifest.name, "hi");
+        std::fs::remove_file(cached).unwrap();
+        remote.sha256 = "00".repeat(32);
+        assert!(download(&remote, &dir.join("cache")).is_err());
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,540 @@
+//! Dependency resolution over available and installed packages.
+//!
+//! Resolution is greedy: each requirement takes the newest candidate that
+//! satisfies it, and the finished set is then checked as a whole (every
+//! dependency met, no conflicts) so a failure names the packages involved.
+
+use crate::db::Installed;
+use crate::manifest::Manifest;
+use crate::source::Source;
+use anyhow::{bail, Context, Result};
+use semver::{Version, VersionReq};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fmt;
+
+/// A dependency or conflict entry: `name` optionally followed by a semver
+/// requirement, e.g. `ncurses`, `ncurses >= 6.2`, `libfoo ^1.4, <1.9`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dep {
+    pub name: String,
+    pub req: VersionReq,
+}
+
+impl Dep {
+    pub fn parse(spec: &str) -> Result<Self> {
+        let spec = spec.trim();
+        let split = spec
+            .find(|c: char| !valid_name_char(c))
+            .unwrap_or(spec.len());
+        let (name, req) = spec.split_at(split);
+        if name.is_empty() {
+            bail!("{spec:?} does not start with a package name");
+        }
+        let req = req.trim();
+        let req = if req.is_empty() {
+            VersionReq::STAR
+        } else {
+            VersionReq::parse(req)
+                .with_context(|| format!("bad version requirement in {spec:?}"))?
+        };
+        Ok(Self {
+            name: name.to_string(),
+            req,
+        })
+    }
+
+    /// Whether a package called `name` at `version` satisfies this entry.
+    fn matches(&self, name: &str, version: &Version) -> bool {
+        self.name == name && self.req.matches(version)
+    }
+}
+
+impl fmt::Display for Dep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.req == VersionReq::STAR {
+            write!(f, "{}", self.name)
+        } else {
+            write!(f, "{} {}", self.name, self.req)
+        }
+    }
+}
+
+pub(crate) fn valid_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '+')
+}
+
+/// Parse a package version, accepting the short `6.4` and `2` forms as
+/// `6.4.0` and `2.0.0`.
+pub fn parse_version(version: &str) -> Result<Version> {
+    let (core, rest) = match version.find(['-', '+']) {
+        Some(i) => version.split_at(i),
+        None => (version, ""),
+    };
+    let padded = match core.matches('.').count() {
+        0 => format!("{core}.0.0{rest}"),
+        1 => format!("{core}.0{rest}"),
+        _ => version.to_string(),
+    };
+    Version::parse(&padded).with_context(|| format!("{version:?} is not a valid version"))
+}
+
+/// One package in the resolved world.
+#[derive(Debug, Clone)]
+struct Node {
+    manifest: Manifest,
+    version: Version,
+    /// Where to install it from; `None` for an installed package left alone.
+    source: Option<Source>,
+    explicit: bool,
+    /// Why it is in the plan, for packages the user did not ask for.
+    reason: Option<String>,
+}
+
+impl Node {
+    /// Whether this package is, or provides, what `dep` asks for.
+    fn satisfies(&self, dep: &Dep) -> bool {
+        dep.matches(&self.manifest.name, &self.version)
+            || self
+                .manifest
+                .provides
+                .iter()
+                .any(|p| dep.matches(p, &self.version))
+    }
+
+    fn label(&self) -> String {
+        format!("{} {}", self.manifest.name, self.manifest.version)
+    }
+}
+
+/// A package the plan installs or upgrades, in dependency order.
+#[derive(Debug, Clone)]
+pub(crate) struct Step {
+    pub source: Source,
+    pub explicit: bool,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Resolution {
+    pub installs: Vec<Step>,
+    /// Installed packages that must go, with the reason.
+    pub removes: Vec<(String, String)>,
+}
+
+/// Resolve `requests` (dependency specs, or exact versions for local package
+/// directories already in `available`) against the installed set. Packages
+/// in `pins` only move to versions their requirement allows. With `explicit`
+/// the requested packages are marked as user-installed; upgrades keep the
+/// flag they had.
+pub(crate) fn resolve(
+    available: &[Source],
+    installed: &[Installed],
+    requests: &[Dep],
+    pins: &BTreeMap<String, VersionReq>,
+    explicit: bool,
+) -> Result<Resolution> {
+    let mut world: BTreeMap<String, Node> = BTreeMap::new();
+    for i in installed {
+        world.insert(
+            i.package.name.clone(),
+            Node {
+                version: parse_version(&i.package.version)?,
+                manifest: i.package.clone(),
+                source: None,
+                explicit: i.explicit,
+                reason: None,
+            },
+        );
+    }
+    let mut chosen: HashSet<String> = HashSet::new();
+
+    // (requirement, chain of packages that led to it)
+    let mut queue: VecDeque<(Dep, Vec<String>)> =
+        requests.iter().map(|d| (d.clone(), Vec::new())).collect();
+    while let Some((dep, chain)) = queue.pop_front() {
+        if let Some(node) = world.values_mut().find(|n| n.satisfies(&dep)) {
+            if explicit && chain.is_empty() && node.manifest.name == dep.name {
+                node.explicit = true;
+            }
+            continue;
+        }
+
+        let mut candidates: Vec<(&Source, Version)> = Vec::new();
+        for s in available {
+            let version = parse_version(&s.manifest.version)?;
+            if pins
+                .get(&s.manifest.name)
+                .is_some_and(|pin| !pin.matches(&version))
+            {
+                continue;
+            }
+            let node = Node {
+                manifest: s.manifest.clone(),
+                version: version.clone(),
+                source: None,
+                explicit: false,
+                reason: None,
+            };
+            if node.satisfies(&dep) {
+                candidates.push((s, version));
+            }
+        }
+        // Prefer the real package over providers, then the newest version.
+        candidates.sort_by(|a, b| {
+            (b.0.manifest.name == dep.name)
+                .cmp(&(a.0.manifest.name == dep.name))
+                .then(b.1.cmp(&a.1))
+        });
+        let Some((source, version)) = candidates.into_iter().next() else {
+            return Err(unsatisfiable(available, &dep, &chain, pins));
+        };
+
+        let name = source.manifest.name.clone();
+        if chosen.contains(&name) {
+            let picked = &world[&name];
+            bail!(
+                "{}requires {dep}, but {} was already chosen{}",
+                requirer(&chain),
+                picked.label(),
+                picked
+                    .reason
+                    .as_ref()
+                    .map(|r| format!(" ({r})"))
+                    .unwrap_or_default()
+            );
+        }
+        let node = Node {
+            manifest: source.manifest.clone(),
+            version,
+            source: Some(source.clone()),
+            explicit: (explicit && chain.is_empty())
+                || world.get(&name).is_some_and(|n| n.explicit),
+            reason: chain.last().map(|p| format!("required by {p}")),
+        };
+        let mut next = chain.clone();
+        next.push(node.label());
+        for d in &node.manifest.depends {
+            queue.push_back((Dep::parse(d)?, next.clone()));
+        }
+        chosen.insert(name.clone());
+        world.insert(name, node);
+    }
+
+    let mut removes = Vec::new();
+    for name in chosen.iter() {
+        let Some(node) = world.get(name).cloned() else {
+            continue;
+        };
+        for spec in &node.manifest.conflicts {
+            let conflict = Dep::parse(spec)?;
+            let hits: Vec<String> = world
+                .values()
+                .filter(|n| n.manifest.name != node.manifest.name && n.satisfies(&conflict))
+                .map(|n| n.manifest.name.clone())
+                .collect();
+            for hit in hits {
+                if chosen.contains(&hit) {
+                    bail!(
+                        "{} conflicts with {}, and both are needed",
+                        node.label(),
+                        world[&hit].label()
+                    );
+                }
+                if let Some(pin) = pins.get(&hit) {
+                    bail!(
+                        "{} conflicts with {}, which is pinned to {pin}",
+                        node.label(),
+                        world[&hit].label()
+                    );
+                }
+                // An installed package in the way of a new one is replaced;
+                // the check below refuses if anything still needs it.
+                world.remove(&hit);
+                removes.push((hit, format!("conflicts with {}", node.label())));
+            }
+        }
+    }
+    // Installed packages may conflict with the newcomers too.
+    for node in world
+        .values()
+        .filter(|n| !chosen.contains(&n.manifest.name))
+    {
+        for spec in &node.manifest.conflicts {
+            let conflict = Dep::parse(spec)?;
+            if let Some(hit) = world
+                .values()
+                .find(|n| chosen.contains(&n.manifest.name) && n.satisfies(&conflict))
+            {
+                bail!(
+                    "installed {} conflicts with {}; remove it first",
+                    node.label(),
+                    hit.label()
+                );
+            }
+        }
+    }
+
+    check_satisfied(&world, &removes)?;
+    Ok(Resolution {
+        installs: order(&world, &chosen)?,
+        removes,
+    })
+}
+
+/// Every package left in `world` must still have its dependencies met.
+fn check_satisfied(world: &BTreeMap<String, Node>, removes: &[(String, String)]) -> Result<()> {
+    for node in world.values() {
+        for spec in &node.manifest.depends {
+            let dep = Dep::parse(spec)?;
+            if world.values().any(|n| n.satisfies(&dep)) {
+                continue;
+            }
+            let state = match (
+                world.get(&dep.name),
+                removes.iter().find(|r| r.0 == dep.name),
+            ) {
+                (Some(other), _) => format!("the plan leaves {}", other.label()),
+                (None, Some((_, why))) => format!("{} would be removed ({why})", dep.name),
+                (None, None) => format!("{} would be removed", dep.name),
+            };
+            bail!("{} requires {dep}, but {state}", node.label());
+        }
+    }
+    Ok(())
+}
+
+/// Installed packages remaining after removing `names` must stay satisfied.
+pub(crate) fn check_removal(installed: &[Installed], names: &[String]) -> Result<()> {
+    let mut world = BTreeMap::new();
+    for i in installed
+        .iter()
+        .filter(|i| !names.contains(&i.package.name))
+    {
+        world.insert(
+            i.package.name.clone(),
+            Node {
+                version: parse_version(&i.package.version)?,
+                manifest: i.package.clone(),
+                source: None,
+                explicit: i.explicit,
+                reason: None,
+            },
+        );
+    }
+    check_satisfied(&world, &[])
+}
+
+/// Chosen packages with dependencies before dependents; errors on cycles.
+fn order(world: &BTreeMap<String, Node>, chosen: &HashSet<String>) -> Result<Vec<Step>> {
+    fn visit(
+        name: &str,
+        world: &BTreeMap<String, Node>,
+        chosen: &HashSet<String>,
+        stack: &mut Vec<String>,
+        done: &mut HashSet<String>,
+        out: &mut Vec<Step>,
+    ) -> Result<()> {
+        if done.contains(name) {
+            return Ok(());
+        }
+        if let Some(at) = stack.iter().position(|s| s == name) {
+            let mut cycle = stack[at..].to_vec();
+            cycle.push(name.to_string());
+            bail!("dependency cycle: {}", cycle.join(" -> "));
+        }
+        let node = &world[name];
+        stack.push(name.to_string());
+        for spec in &node.manifest.depends {
+            let dep = Dep::parse(spec)?;
+            if let Some(target) = world
+                .values()
+                .find(|n| chosen.contains(&n.manifest.name) && n.satisfies(&dep))
+            {
+                visit(&target.manifest.name, world, chosen, stack, done, out)?;
+            }
+        }
+        stack.pop();
+        done.insert(name.to_string());
+        out.push(Step {
+            source: node.source.clone().expect("chosen packages have a source"),
+            explicit: node.explicit,
+            reason: node.reason.clone(),
+        });
+        Ok(())
+    }
+
+    let mut names: Vec<&String> = chosen.iter().collect();
+    names.sort();
+    let (mut stack, mut done, mut out) = (Vec::new(), HashSet::new(), Vec::new());
+    for name in names {
+        visit(name, world, chosen, &mut stack, &mut done, &mut out)?;
+    }
+    Ok(out)
+}
+
+fn requirer(chain: &[String]) -> String {
+    match chain.last() {
+        Some(p) => format!("{p} "),
+        None => "the request ".to_string(),
+    }
+}
+
+/// Explain why nothing satisfies `dep`.
+fn unsatisfiable(
+    available: &[Source],
+    dep: &Dep,
+    chain: &[String],
+    pins: &BTreeMap<String, VersionReq>,
+) -> anyhow::Error {
+    let versions: Vec<&str> = available
+        .iter()
+        .filter(|s| s.manifest.name == dep.name)
+        .map(|s| s.manifest.version.as_str())
+        .collect();
+    let mut via = if chain.len() > 1 {
+        format!(" (via {})", chain.join(" -> "))
+    } else {
+        String::new()
+    };
+    if let Some(pin) = pins.get(&dep.name) {
+        via.push_str(&format!("; {} is pinned to {pin}", dep.name));
+    }
+    if versions.is_empty() {
+        anyhow::anyhow!(
+            "{}requires {dep}, which no available package provides{via}",
+            requirer(chain)
+        )
+    } else {
+        anyhow::anyhow!(
+            "{}requires {dep}, but only {} {} available{via}",
+            requirer(chain),
+            versions.join(", "),
+            if versions.len() == 1 { "is" } else { "are" }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn src(toml: &str) -> Source {
+        Source {
+            manifest: Manifest::parse(&format!("[package]\n{toml}")).unwrap(),
+            payload: None,
+            remote: None,
+        }
+    }
+
+    fn installed(toml: &str) -> Installed {
+        Installed {
+            package: src(toml).manifest,
+            installed_at: 0,
+            explicit: true,
+            sha256: None,
+        }
+    }
+
+    fn names(r: &Resolution) -> Vec<String> {
+        r.installs
+            .iter()
+            .map(|s| format!("{} {}", s.source.manifest.name, s.source.manifest.version))
+            .collect()
+    }
+
+    fn req(spec: &str) -> Vec<Dep> {
+        vec![Dep::parse(spec).unwrap()]
+    }
+
+    #[test]
+    fn parses_deps_and_short_versions() {
+        let d = Dep::parse("ncurses >= 6.2, <7").unwrap();
+        assert_eq!(d.name, "ncurses");
+        assert!(d.req.matches(&parse_version("6.4").unwrap()));
+        assert!(!d.req.matches(&parse_version("7").unwrap()));
+        assert_eq!(Dep::parse("zlib").unwrap().to_string(), "zlib");
+        assert!(Dep::parse(">= 1").is_err());
+        assert_eq!(parse_version("1.2-rc1").unwrap().to_string(), "1.2.0-rc1");
+    }
+
+    #[test]
+    fn picks_newest_matching_versions_in_dependency_order() {
+        let available = [
+            src("name = \"app\"\nversion = \"2.0.0\"\ndepends = [\"lib >=1.2, <2\"]"),
+            src("name = \"lib\"\nversion = \"1.1.0\""),
+            src("name = \"lib\"\nversion = \"1.4.0\"\ndepends = [\"base\"]"),
+            src("name = \"lib\"\nversion = \"2.0.0\""),
+            src("name = \"base\"\nversion = \"1\""),
+        ];
+        let r = resolve(&available, &[], &req("app"), &BTreeMap::new(), true).unwrap();
+        assert_eq!(names(&r), ["base 1", "lib 1.4.0", "app 2.0.0"]);
+        assert!(r.installs[2].explicit);
+        assert_eq!(
+            r.installs[1].reason.as_deref(),
+            Some("required by app 2.0.0")
+        );
+
+        let err = resolve(&available, &[], &req("app >= 3"), &BTreeMap::new(), true).unwrap_err();
+        assert!(err.to_string().contains("only 2.0.0 is available"), "{err}");
+    }
+
+    #[test]
+    fn upgrade_that_breaks_a_dependent_is_refused() {
+        let available = [src("name = \"lib\"\nversion = \"2.0.0\"")];
+        let world = [
+            installed("name = \"lib\"\nversion = \"1.0.0\""),
+            installed("name = \"old\"\nversion = \"1\"\ndepends = [\"lib ^1\"]"),
+        ];
+        let err =
+            resolve(&available, &world, &req("lib >= 2"), &BTreeMap::new(), true).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "old 1 requires lib ^1, but the plan leaves lib 2.0.0"
+        );
+    }
+
+    #[test]
+    fn provides_and_conflicts_replace_installed_packages() {
+        let available = [
+            src("name = \"mail\"\nversion = \"1\"\ndepends = [\"smtp\"]"),
+            src("name = \"postfix\"\nversion = \"3\"\nprovides = [\"smtp\"]\nconflicts = [\"exim\"]"),
+        ];
+        let world = [installed("name = \"exim\"\nversion = \"4\"")];
+        let r = resolve(&available, &world, &req("mail"), &BTreeMap::new(), true).unwrap();
+        assert_eq!(names(&r), ["postfix 3", "mail 1"]);
+        assert_eq!(r.removes[0].0, "exim");
+
+        let world = [
+            installed("name = \"exim\"\nversion = \"4\""),
+            installed("name = \"cron\"\nversion = \"1\"\ndepends = [\"exim\"]"),
+        ];
+        let err = resolve(&available, &world, &req("mail"), &BTreeMap::new(), true).unwrap_err();
+        assert!(err.to_string().contains("exim would be removed"), "{err}");
+    }
+
+    #[test]
+    fn pins_hold_versions_back() {
+        let available = [
+            src("name = \"brain\"\nversion = \"0.2.0\""),
+            src("name = \"brain\"\nversion = \"0.3.0\""),
+        ];
+        let pins = BTreeMap::from([("brain".to_string(), VersionReq::parse("^0.2").unwrap())]);
+        let r = resolve(&available, &[], &req("brain"), &pins, true).unwrap();
+        assert_eq!(names(&r), ["brain 0.2.0"]);
+        let err = resolve(&available, &[], &req("brain >= 0.3"), &pins, true).unwrap_err();
+        assert!(
+            err.to_string().ends_with("brain is pinned to ^0.2"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn cycles_are_reported() {
+        let available = [
+            src("name = \"a\"\nversion = \"1\"\ndepends = [\"b\"]"),
+            src("name = \"b\"\nversion = \"1\"\ndepends = [\"a\"]"),
+        ];
+        let err = resolve(&available, &[], &req("a"), &BTreeMap::new(), true).unwrap_err();
+        assert_eq!(err.to_string(), "dependency cycle: a -> b -> a");
+    }
+}
@@ -0,0 +1,97 @@
+use crate::manifest::{check_rel_path, Manifest};
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+
+/// Manifest file at the top of a package directory.
+pub const MANIFEST_FILE: &str = "bpkg.toml";
+/// Payload tree inside a package directory, laid out as under the install root.
+pub const PAYLOAD_DIR: &str = "root";
+
+/// Something installable: a manifest plus, for real packages, the directory
+/// its files are copied from or the archive to fetch first.
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub manifest: Manifest,
+    pub payload: Option<PathBuf>,
+    pub remote: Option<Remote>,
+}
+
+/// An archive to fetch and unpack at install time. `sha256` comes from a
+/// signature-checked index (or was computed from a local file).
+#[derive(Debug, Clone)]
+pub struct Remote {
+    pub url: String,
+    pub sha256: String,
+    pub size: u64,
+}
+
+impl Source {
+    /// A bare manifest with no payload (metapackages, test fixtures).
+    pub fn from_manifest(path: &Path) -> Result<Self> {
+        Ok(Self {
+            manifest: Manifest::load(path)?,
+            payload: None,
+            remote: None,
+        })
+    }
+
+    /// A package directory: `bpkg.toml` plus an optional `root/` tree. When
+    /// the manifest lists no files they are taken from the tree; otherwise
+    /// every listed file must be present in it.
+    pub fn from_dir(dir: &Path) -> Result<Self> {
+        let mut manifest = Manifest::load(&dir.join(MANIFEST_FILE))?;
+        let payload = dir.join(PAYLOAD_DIR);
+        if !payload.is_dir() {
+            if !manifest.files.is_empty() {
+                bail!("{} lists files but has no {PAYLOAD_DIR}/", dir.display());
+            }
+            return Ok(Self {
+                manifest,
+                payload: None,
+                remote: None,
+            });
+        }
+
+        if manifest.files.is_empty() {
+            let mut files = Vec::new();
+            walk(&payload, &payload, &mut files)?;
+            files.sort();
+            manifest.files = files;
+        } else {
+            for f in &manifest.files {
+                if payload.join(f).symlink_metadata().is_err() {
+                    bail!("{} lists {f}, which is not in its payload", manifest.name);
+                }
+            }
+        }
+        Ok(Self {
+            manifest,
+            payload: Some(payload),
+            remote: None,
+        })
+    }
+}
+
+/// Regular files and symlinks under `dir`, relative to `base`.
+fn walk(base: &Path, dir: &Path, out: &mut Vec<String>) -> Result<()> {
+    for entry in
+        std::fs::read_dir(dir).with_context(|| format!("failed reading {}", dir.display()))?
+    {
+        let entry = entry?;
+        let path = entry.path();
+        let kind = entry.file_type()?;
+        if kind.is_dir() {
+            walk(base, &path, out)?;
+            continue;
+        }
+        let rel = path
+            .strip_prefix(base)
+            .expect("walk stays under base")
+            .to_str()
+            .with_context(|| format!("{} is not valid UTF-8", path.display()))?
+            .to_string();
+        check_rel_path(&rel)?;
+        out.push(rel);
+    }
+    Ok(())
+}
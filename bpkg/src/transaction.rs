@@ -0,0 +1,248 @@
+//! Journaled file operations under the install root.
+//!
+//! Every change is recorded in `<db>/txn/journal.toml` *before* it happens,
+//! and replaced files are moved aside rather than overwritten. A transaction
+//! that fails, is dropped, or was interrupted by a crash is rolled back by
+//! replaying the journal in reverse.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Op {
+    /// Path relative to the install root.
+    path: String,
+    /// Backup slot holding the previous file, if one existed.
+    backup: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Journal {
+    ops: Vec<Op>,
+}
+
+/// What to put at a path.
+pub enum Content<'a> {
+    /// Copy an existing file (payload), keeping its permissions.
+    Copy(&'a Path),
+    Bytes(&'a [u8]),
+    Symlink(&'a Path),
+}
+
+pub struct Txn {
+    root: PathBuf,
+    dir: PathBuf,
+    journal: Journal,
+    created_dirs: Vec<PathBuf>,
+    done: bool,
+}
+
+impl Txn {
+    /// Start a transaction. Callers must hold the database lock and have run
+    /// [`recover`] first.
+    pub fn begin(root: &Path, db_dir: &Path) -> Result<Self> {
+        let dir = db_dir.join("txn");
+        std::fs::create_dir_all(dir.join("backup"))
+            .with_context(|| format!("failed creating {}", dir.display()))?;
+        let txn = Self {
+            root: root.to_path_buf(),
+            dir,
+            journal: Journal::default(),
+            created_dirs: Vec::new(),
+            done: false,
+        };
+        txn.save()?;
+        Ok(txn)
+    }
+
+    pub fn write(&mut self, rel: &str, content: Content) -> Result<()> {
+        let target = self.root.join(rel);
+        self.ensure_parent(&target)?;
+        self.stash(rel)?;
+        match content {
+            Content::Copy(src) => {
+                std::fs::copy(src, &target).with_context(|| {
+                    format!(
+                        "failed installing {} -> {}",
+                        src.display(),
+                        target.display()
+                    )
+                })?;
+            }
+            Content::Bytes(bytes) => std::fs::write(&target, bytes)
+                .with_context(|| format!("failed writing {}", target.display()))?,
+            Content::Symlink(dest) => std::os::unix::fs::symlink(dest, &target)
+                .with_context(|| format!("failed linking {}", target.display()))?,
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, rel: &str) -> Result<()> {
+        self.stash(rel)
+    }
+
+    /// Record the op, then move any existing file into a backup slot.
+    fn stash(&mut self, rel: &str) -> Result<()> {
+        let target = self.root.join(rel);
+        let exists = target.symlink_metadata().is_ok();
+        let backup = exists.then(|| format!("{}", self.journal.ops.len()));
+        self.journal.ops.push(Op {
+            path: rel.to_string(),
+            backup: backup.clone(),
+        });
+        self.save()?;
+        if let Some(slot) = backup {
+            move_file(&target, &self.dir.join("backup").join(slot))?;
+        }
+        Ok(())
+    }
+
+    fn ensure_parent(&mut self, target: &Path) -> Result<()> {
+        let Some(parent) = target.parent() else {
+            return Ok(());
+        };
+        let mut missing = Vec::new();
+        let mut p = parent;
+        while !p.exists() {
+            missing.push(p.to_path_buf());
+            match p.parent() {
+                Some(up) => p = up,
+                None => break,
+            }
+        }
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("failed creating {}", parent.display()))?;
+        self.created_dirs.extend(missing);
+        Ok(())
+    }
+
+    fn save(&self) -> Result<()> {
+        crate::db::write_atomic(
+            &self.dir.join("journal.toml"),
+            toml::to_string(&self.journal)?.as_bytes(),
+        )
+    }
+
+    pub fn commit(mut self) -> Result<()> {
+        self.done = true;
+        std::fs::remove_dir_all(&self.dir).ok();
+        Ok(())
+    }
+
+    pub fn rollback(mut self) -> Result<()> {
+        self.done = true;
+        undo(&self.root, &self.dir, &self.journal)?;
+        self.remove_created_dirs();
+        Ok(())
+    }
+
+    fn remove_created_dirs(&mut self) {
+        // Deepest first, so parents are empty by the time we reach them.
+        self.created_dirs
+            .sort_by_key(|d| std::cmp::Reverse(d.components().count()));
+        for dir in &self.created_dirs {
+            std::fs::remove_dir(dir).ok();
+        }
+    }
+}
+
+impl Drop for Txn {
+    fn drop(&mut self) {
+        if !self.done {
+            if let Err(e) = undo(&self.root, &self.dir, &self.journal) {
+                log::error!("rollback failed: {e:#}");
+            }
+            self.remove_created_dirs();
+        }
+    }
+}
+
+/// Roll back a transaction left behind by a crash. Returns true if one was found.
+pub fn recover(root: &Path, db_dir: &Path) -> Result<bool> {
+    let dir = db_dir.join("txn");
+    let path = dir.join("journal.toml");
+    let text = match std::fs::read_to_string(&path) {
+        Ok(t) => t,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+    let journal: Journal = toml::from_str(&text)
+        .with_context(|| format!("corrupt transaction journal {}", path.display()))?;
+    undo(root, &dir, &journal)?;
+    Ok(true)
+}
+
+fn undo(root: &Path, dir: &Path, journal: &Journal) -> Result<()> {
+    for op in journal.ops.iter().rev() {
+        let target = root.join(&op.path);
+        let backup = op.backup.as_ref().map(|slot| dir.join("backup").join(slot));
+        // A recorded backup that was never moved means the crash came first
+        // and `target` is still the original.
+        if backup
+            .as_ref()
+            .is_some_and(|b| b.symlink_metadata().is_err())
+        {
+            continue;
+        }
+        match std::fs::remove_file(&target) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(e).with_context(|| format!("failed removing {}", target.display()))
+            }
+            _ => {}
+        }
+        if let Some(backup) = backup {
+            move_file(&backup, &target)?;
+        }
+    }
+    std::fs::remove_dir_all(dir).ok();
+    Ok(())
+}
+
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+    if std::fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    // Different filesystems: copy, then drop the original.
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let meta = from.symlink_metadata()?;
+    if meta.is_symlink() {
+        std::os::unix::fs::symlink(std::fs::read_link(from)?, to)?;
+    } else {
+        std::fs::copy(from, to)
+            .with_context(|| format!("failed moving {} to {}", from.display(), to.display()))?;
+    }
+    std::fs::remove_file(from)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recover_undoes_an_abandoned_journal() {
+        let root = std::env::temp_dir().join(format!("bpkg-txn-{}", std::process::id()));
+        let db = root.join("db");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("keep"), "original").unwrap();
+
+        let mut txn = Txn::begin(&root, &db).unwrap();
+        txn.write("keep", Content::Bytes(b"replaced")).unwrap();
+        txn.write("new", Content::Bytes(b"added")).unwrap();
+        // Simulate a crash: the journal stays on disk, nothing is undone.
+        txn.done = true;
+        drop(txn);
+
+        assert!(recover(&root, &db).unwrap());
+        assert_eq!(
+            std::fs::read_to_string(root.join("keep")).unwrap(),
+            "original"
+        );
+        assert!(!root.join("new").exists());
+        assert!(!recover(&root, &db).unwrap());
+        std::fs::remove_dir_all(root).ok();
+    }
+}
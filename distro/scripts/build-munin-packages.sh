@@ -0,0 +1,35 @@
+#!/usr/bin/env bash
+set -euo pipefail
+
+ROOT="$(cd "$(dirname "${BASH_SOURCE[0]}")/../.." && pwd)"
+OUT="$ROOT/build/packages"
+RECIPES="$ROOT/distro/packages"
+# Optional ed25519 key (from `bpkg repo keygen`) to sign build/packages as a repo
+SIGNING_KEY="${BPKG_SIGNING_KEY:-}"
+# Appended to every package version (`0.1.0+<n>`) so each build upgrades the last
+BUILD_NUMBER="${BPKG_BUILD_NUMBER:-$(date -u +%s)}"
+
+mkdir -p "$OUT"
+
+if ! command -v cargo >/dev/null 2>&1; then
+  echo "[munin-pkg] cargo not found. install Rust toolchain first."
+  exit 1
+fi
+
+echo "[munin-pkg] building host bpkg"
+cargo build --release --manifest-path "$ROOT/bpkg/Cargo.toml"
+BPKG="$ROOT/bpkg/target/release/bpkg"
+
+for recipe in "$RECIPES"/*.toml; do
+  echo "[munin-pkg] building $(basename "$recipe" .toml)"
+  "$BPKG" build "$recipe" --out "$OUT" --build-number "$BUILD_NUMBER"
+done
+
+if [[ -n "$SIGNING_KEY" ]]; then
+  "$BPKG" repo build "$OUT" --key "$SIGNING_KEY"
+else
+  echo "[munin-pkg] BPKG_SIGNING_KEY not set; skipping signed index"
+fi
+
+echo "[munin-pkg] done -> $OUT"
+ls -lh "$OUT"
@@ -89,8 +89,14 @@ $SUDO rsync -a "$OVERLAY/" "$WORK/"
 $SUDO mkdir -p "$WORK/opt/muninos/ui"
 $SUDO rsync -a "$ROOT/munin-ui/" "$WORK/opt/muninos/ui/" || true
 
-# ship compiled Munin binaries when available
-if [[ -d "$ROOT/build/munin-bin" ]]; then
+# install Munin packages when built (build-munin-packages.sh); they own the
+# binaries, wrappers and units so devices can upgrade them with bpkg.
+# --force takes over the copies the overlay already placed.
+BPKG="$ROOT/bpkg/target/release/bpkg"
+if compgen -G "$ROOT/build/packages/*.bpkg" >/dev/null && [[ -x "$BPKG" ]]; then
+  $SUDO "$BPKG" --root "$WORK" install --force "$ROOT"/build/packages/*.bpkg
+# otherwise ship compiled Munin binaries when available
+elif [[ -d "$ROOT/build/munin-bin" ]]; then
   $SUDO mkdir -p "$WORK/opt/muninos/bin"
   $SUDO rsync -a "$ROOT/build/munin-bin/" "$WORK/opt/muninos/bin/"
 fi
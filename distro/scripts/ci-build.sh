@@ -4,8 +4,9 @@ set -euo pipefail
 ROOT="$(cd "$(dirname "${BASH_SOURCE[0]}")/../.." && pwd)"
 cd "$ROOT"
 
-echo "[ci-build] step 1/4: build binaries"
+echo "[ci-build] step 1/4: build binaries and packages"
 bash distro/scripts/build-munin-binaries.sh
+bash distro/scripts/build-munin-packages.sh
 
 echo "[ci-build] step 2/4: build rootfs"
 bash distro/scripts/build-rootfs.sh
@@ -4,6 +4,8 @@ use cpal::traits::{DeviceTrait, HostTrait};
 use reqwest::Client;
 use serde_json::json;
 
+const AGENT_ID: &str = "munin-audio";
+
 #[derive(Parser, Debug)]
 #[command(name = "munin-audio")]
 struct Args {
@@ -13,6 +15,9 @@ struct Args {
     #[arg(long, default_value = "http://127.0.0.1:8790")]
     brain_endpoint: String,
 
+    #[arg(long, default_value = "http://127.0.0.1:8787")]
+    core_endpoint: String,
+
     #[arg(long, default_value = "hey munin")]
     wake_phrase: String,
 
@@ -50,6 +55,34 @@ fn list_devices() {
     }
 }
 
+/// Heartbeat to core, (re-)registering when core doesn't know us.
+async fn keep_registered(c: &Client, core_endpoint: &str, registered: bool) -> Result<()> {
+    let base = core_endpoint.trim_end_matches('/');
+    if registered {
+        let resp = c
+            .post(format!("{base}/v1/agents/heartbeat"))
+            .json(&json!({"id": AGENT_ID}))
+            .send()
+            .await?;
+        if resp.status().is_success() {
+            return Ok(());
+        }
+    }
+
+    let info = json!({
+        "id": AGENT_ID,
+        "capabilities": ["audio.capture", "audio.playback"],
+        "version": env!("CARGO_PKG_VERSION"),
+        "pid": std::process::id(),
+    });
+    c.post(format!("{base}/v1/agents/register"))
+        .json(&info)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
 async fn send_transcript(brain_endpoint: &str, transcript: &str, locale: &str) -> Result<()> {
     let url = format!("{}/v1/decide", brain_endpoint.trim_end_matches('/'));
     let payload = json!({
@@ -75,7 +108,16 @@ async fn main() -> Result<()> {
             tracing::info!("wake phrase: {}", args.wake_phrase);
             tracing::info!("brain endpoint: {}", args.brain_endpoint);
             tracing::info!("note: audio-driver streaming loop scaffold is active; full DSP/VAD in next iteration");
+            let client = Client::new();
+            let mut registered = false;
             loop {
+                registered = match keep_registered(&client, &args.core_endpoint, registered).await {
+                    Ok(()) => true,
+                    Err(e) => {
+                        tracing::warn!("core registration failed: {}", e);
+                        false
+                    }
+                };
                 tokio::time::sleep(std::time::Duration::from_secs(5)).await;
             }
         }
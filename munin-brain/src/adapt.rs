@@ -0,0 +1,344 @@
+//! Dynamic tier adaptation: read memory, pressure-stall (PSI), temperature
+//! and power state, and step the served tier down under pressure and back up
+//! once conditions have stayed calm for a while.
+//!
+//! The pressure and calm thresholds are apart and recovery needs a hold
+//! time, so a machine hovering around one threshold does not flap between
+//! tiers.
+
+use crate::host::ModelHost;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// What the sensors said on one reading. `None` where the kernel or
+/// hardware does not expose a value.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Conditions {
+    pub mem_total_mb: u64,
+    pub mem_available_mb: u64,
+    /// `some avg10` from /proc/pressure/memory: % of time tasks stalled on memory.
+    pub memory_psi: Option<f64>,
+    /// Hottest thermal zone, in °C.
+    pub cpu_temp_c: Option<f64>,
+    pub on_battery: bool,
+    pub battery_percent: Option<u8>,
+}
+
+impl Conditions {
+    fn available_ratio(&self) -> f64 {
+        if self.mem_total_mb == 0 {
+            return 1.0;
+        }
+        self.mem_available_mb as f64 / self.mem_total_mb as f64
+    }
+}
+
+/// Where to read `/proc` and `/sys` from; tests point these at fixtures.
+pub struct Sensors {
+    proc: PathBuf,
+    sys: PathBuf,
+}
+
+impl Default for Sensors {
+    fn default() -> Self {
+        Self::at("/proc", "/sys")
+    }
+}
+
+impl Sensors {
+    pub fn at(proc: impl Into<PathBuf>, sys: impl Into<PathBuf>) -> Self {
+        Self {
+            proc: proc.into(),
+            sys: sys.into(),
+        }
+    }
+
+    pub fn read(&self) -> Conditions {
+        let meminfo = read(&self.proc.join("meminfo")).unwrap_or_default();
+        let mem_kb = |key: &str| {
+            meminfo
+                .lines()
+                .find_map(|l| l.strip_prefix(key)?.strip_prefix(':'))
+                .and_then(|v| v.split_whitespace().next()?.parse::<u64>().ok())
+                .unwrap_or(0)
+        };
+        let (on_battery, battery_percent) = self.power();
+        Conditions {
+            mem_total_mb: mem_kb("MemTotal") / 1024,
+            mem_available_mb: mem_kb("MemAvailable") / 1024,
+            memory_psi: read(&self.proc.join("pressure/memory")).and_then(|t| psi_some_avg10(&t)),
+            cpu_temp_c: self.max_temp(),
+            on_battery,
+            battery_percent,
+        }
+    }
+
+    fn max_temp(&self) -> Option<f64> {
+        entries(&self.sys.join("class/thermal"))
+            .filter(|p| {
+                p.file_name()
+                    .is_some_and(|n| n.to_string_lossy().starts_with("thermal_zone"))
+            })
+            .filter_map(|p| read(&p.join("temp"))?.trim().parse::<f64>().ok())
+            .map(|milli| milli / 1000.0)
+            .reduce(f64::max)
+    }
+
+    /// Whether a battery is discharging, and the lowest battery charge.
+    fn power(&self) -> (bool, Option<u8>) {
+        let mut discharging = false;
+        let mut percent: Option<u8> = None;
+        for supply in entries(&self.sys.join("class/power_supply")) {
+            if read(&supply.join("type")).as_deref().map(str::trim) != Some("Battery") {
+                continue;
+            }
+            if read(&supply.join("status")).as_deref().map(str::trim) == Some("Discharging") {
+                discharging = true;
+            }
+            if let Some(p) = read(&supply.join("capacity")).and_then(|c| c.trim().parse().ok()) {
+                percent = Some(percent.map_or(p, |q: u8| q.min(p)));
+            }
+        }
+        (discharging, percent)
+    }
+}
+
+fn read(path: &Path) -> Option<String> {
+    std::fs::read_to_string(path).ok()
+}
+
+fn entries(dir: &Path) -> impl Iterator<Item = PathBuf> {
+    std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.ok().map(|e| e.path()))
+}
+
+/// `avg10` of the `some` line of a PSI file.
+fn psi_some_avg10(text: &str) -> Option<f64> {
+    text.lines()
+        .find_map(|l| l.strip_prefix("some "))?
+        .split_whitespace()
+        .find_map(|kv| kv.strip_prefix("avg10="))?
+        .parse()
+        .ok()
+}
+
+#[derive(Debug, Clone)]
+pub struct Thresholds {
+    /// Step down below this share of memory available...
+    pub low_memory: f64,
+    /// ...or above this memory PSI.
+    pub high_memory_psi: f64,
+    pub hot_c: f64,
+    /// Step down on battery at or below this charge.
+    pub low_battery: u8,
+    /// Calm means all of these hold (the other side of each threshold).
+    pub calm_memory: f64,
+    pub calm_memory_psi: f64,
+    pub cool_c: f64,
+    pub calm_battery: u8,
+    /// Least time between two step-downs, so a switch can take effect.
+    pub dwell: Duration,
+    /// How long conditions must stay calm before stepping back up.
+    pub recover_after: Duration,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Self {
+            low_memory: 0.10,
+            high_memory_psi: 10.0,
+            hot_c: 85.0,
+            low_battery: 20,
+            calm_memory: 0.25,
+            calm_memory_psi: 2.0,
+            cool_c: 75.0,
+            calm_battery: 40,
+            dwell: Duration::from_secs(20),
+            recover_after: Duration::from_secs(60),
+        }
+    }
+}
+
+impl Thresholds {
+    /// Why `c` counts as pressure, if it does.
+    pub fn pressure(&self, c: &Conditions) -> Option<String> {
+        if c.available_ratio() < self.low_memory {
+            return Some(format!(
+                "low memory ({} of {} MiB available)",
+                c.mem_available_mb, c.mem_total_mb
+            ));
+        }
+        if let Some(psi) = c.memory_psi.filter(|p| *p >= self.high_memory_psi) {
+            return Some(format!("memory pressure (PSI {psi:.1})"));
+        }
+        if let Some(t) = c.cpu_temp_c.filter(|t| *t >= self.hot_c) {
+            return Some(format!("CPU at {t:.0}°C"));
+        }
+        match c.battery_percent {
+            Some(p) if c.on_battery && p <= self.low_battery => Some(format!("on battery at {p}%")),
+            _ => None,
+        }
+    }
+
+    pub fn calm(&self, c: &Conditions) -> bool {
+        c.available_ratio() >= self.calm_memory
+            && c.memory_psi.is_none_or(|p| p < self.calm_memory_psi)
+            && c.cpu_temp_c.is_none_or(|t| t <= self.cool_c)
+            && (!c.on_battery || c.battery_percent.is_none_or(|p| p >= self.calm_battery))
+    }
+}
+
+/// Decides how many tiers below the selected one to serve.
+#[derive(Debug)]
+pub struct Adapter {
+    thresholds: Thresholds,
+    steps: u8,
+    last_change: Option<Instant>,
+    calm_since: Option<Instant>,
+}
+
+impl Adapter {
+    pub fn new(thresholds: Thresholds) -> Self {
+        Self {
+            thresholds,
+            steps: 0,
+            last_change: None,
+            calm_since: None,
+        }
+    }
+
+    /// Feed one reading. Returns the new step count and the reason when it
+    /// changes; never steps down more than `max_steps`.
+    pub fn observe(&mut self, c: &Conditions, now: Instant, max_steps: u8) -> Option<(u8, String)> {
+        if let Some(reason) = self.thresholds.pressure(c) {
+            self.calm_since = None;
+            let settled = self
+                .last_change
+                .is_none_or(|t| now.duration_since(t) >= self.thresholds.dwell);
+            if self.steps < max_steps && settled {
+                self.steps += 1;
+                self.last_change = Some(now);
+                return Some((self.steps, reason));
+            }
+            return None;
+        }
+        if self.steps == 0 || !self.thresholds.calm(c) {
+            self.calm_since = None;
+            return None;
+        }
+        let since = *self.calm_since.get_or_insert(now);
+        if now.duration_since(since) < self.thresholds.recover_after {
+            return None;
+        }
+        self.steps -= 1;
+        self.last_change = Some(now);
+        // Each further step up needs its own calm period.
+        self.calm_since = Some(now);
+        Some((self.steps, "conditions recovered".to_string()))
+    }
+}
+
+/// Read the sensors every `interval` and step `host` down or up. Runs
+/// until the process exits.
+pub fn watch(host: Arc<ModelHost>, sensors: Sensors, thresholds: Thresholds, interval: Duration) {
+    let mut adapter = Adapter::new(thresholds);
+    loop {
+        let conditions = sensors.read();
+        if let Some((steps, reason)) =
+            adapter.observe(&conditions, Instant::now(), host.adapt_headroom())
+        {
+            if let Err(e) = host.adapt(steps, &reason) {
+                tracing::warn!("tier adaptation failed: {e:#}");
+            }
+        }
+        host.set_conditions(conditions);
+        std::thread::sleep(interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_proc_and_sys_fixtures() {
+        let dir = std::env::temp_dir().join(format!("munin-sensors-{}", std::process::id()));
+        let write = |rel: &str, text: &str| {
+            let p = dir.join(rel);
+            std::fs::create_dir_all(p.parent().unwrap()).unwrap();
+            std::fs::write(p, text).unwrap();
+        };
+        write(
+            "proc/meminfo",
+            "MemTotal:        8192000 kB\nMemFree:  100 kB\nMemAvailable:     512000 kB\n",
+        );
+        write(
+            "proc/pressure/memory",
+            "some avg10=12.50 avg60=3.00 avg300=1.00 total=99\nfull avg10=4.00 avg60=0 avg300=0 total=9\n",
+        );
+        write("sys/class/thermal/thermal_zone0/temp", "48000\n");
+        write("sys/class/thermal/thermal_zone1/temp", "91500\n");
+        write("sys/class/power_supply/AC/type", "Mains\n");
+        write("sys/class/power_supply/BAT0/type", "Battery\n");
+        write("sys/class/power_supply/BAT0/status", "Discharging\n");
+        write("sys/class/power_supply/BAT0/capacity", "17\n");
+
+        let c = Sensors::at(dir.join("proc"), dir.join("sys")).read();
+        assert_eq!((c.mem_total_mb, c.mem_available_mb), (8000, 500));
+        assert_eq!(c.memory_psi, Some(12.5));
+        assert_eq!(c.cpu_temp_c, Some(91.5));
+        assert!(c.on_battery);
+        assert_eq!(c.battery_percent, Some(17));
+        assert!(Thresholds::default()
+            .pressure(&c)
+            .unwrap()
+            .contains("memory"));
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    fn calm() -> Conditions {
+        Conditions {
+            mem_total_mb: 8000,
+            mem_available_mb: 6000,
+            cpu_temp_c: Some(50.0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn steps_down_under_pressure_and_up_after_a_calm_hold() {
+        let t = Thresholds::default();
+        let mut a = Adapter::new(t.clone());
+        let start = Instant::now();
+        let at = |s: u64| start + Duration::from_secs(s);
+        let hot = Conditions {
+            cpu_temp_c: Some(90.0),
+            ..calm()
+        };
+
+        assert_eq!(a.observe(&hot, at(0), 2).unwrap().0, 1);
+        // Still hot, but the first switch has not had time to settle.
+        assert_eq!(a.observe(&hot, at(5), 2), None);
+        assert_eq!(a.observe(&hot, at(25), 2).unwrap().0, 2);
+        assert_eq!(a.observe(&hot, at(60), 2), None, "capped at max_steps");
+
+        // Between the thresholds: neither pressure nor calm.
+        let warm = Conditions {
+            cpu_temp_c: Some(80.0),
+            ..calm()
+        };
+        assert_eq!(a.observe(&warm, at(100), 2), None);
+        assert_eq!(a.observe(&calm(), at(110), 2), None);
+        assert_eq!(a.observe(&warm, at(150), 2), None, "warm resets the hold");
+        assert_eq!(a.observe(&calm(), at(160), 2), None);
+        assert_eq!(a.observe(&calm(), at(219), 2), None);
+        assert_eq!(a.observe(&calm(), at(220), 2).unwrap().0, 1);
+        assert_eq!(a.observe(&calm(), at(250), 2), None);
+        assert_eq!(a.observe(&calm(), at(280), 2).unwrap().0, 0);
+        assert_eq!(a.observe(&calm(), at(400), 2), None);
+    }
+}
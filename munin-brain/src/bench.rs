@@ -0,0 +1,253 @@
+//! Measured performance per preset, for picking a tier by latency instead
+//! of RAM and CPU thresholds.
+//!
+//! `munin-brain bench` runs llama.cpp's `llama-bench` on each downloaded
+//! preset and caches prompt-processing and generation rates. Time to first
+//! token is derived from those for a prompt of `prompt_tokens`: processing
+//! the prompt plus generating one token. Cached results are dropped when the
+//! model file changes.
+
+use crate::models::{tier_key, Catalogue};
+use crate::{tier_from_rank, ModelTier};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub const DEFAULT_CACHE: &str = "/var/lib/muninos/brain/bench.json";
+
+#[derive(Debug, Clone)]
+pub struct BenchConfig {
+    /// The `llama-bench` binary.
+    pub llama_bench: PathBuf,
+    /// Prompt size TTFT is computed for; roughly a system prompt plus a
+    /// spoken request.
+    pub prompt_tokens: u32,
+    pub gen_tokens: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchResult {
+    pub model_path: PathBuf,
+    pub file_size: u64,
+    pub file_mtime: u64,
+    pub prompt_tokens: u32,
+    pub prompt_tokens_per_sec: f64,
+    pub gen_tokens_per_sec: f64,
+    pub ttft_ms: u64,
+    pub measured_at: u64,
+}
+
+/// Results by preset, kept in a JSON file.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BenchCache {
+    #[serde(default)]
+    pub results: BTreeMap<String, BenchResult>,
+}
+
+impl BenchCache {
+    /// Load `path`; a missing or unreadable cache is empty.
+    pub fn load(path: &Path) -> Self {
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("failed creating {}", dir.display()))?;
+        }
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(&tmp, path).with_context(|| format!("failed writing {}", path.display()))
+    }
+
+    /// The cached result for `preset`, if its model file is unchanged.
+    pub fn fresh(&self, catalogue: &Catalogue, preset: &str) -> Option<&BenchResult> {
+        let result = self.results.get(preset)?;
+        let path = catalogue.path_of(catalogue.entry(preset).ok()?);
+        let (size, mtime) = file_stamp(&path).ok()?;
+        (result.model_path == path && result.file_size == size && result.file_mtime == mtime)
+            .then_some(result)
+    }
+
+    /// The largest tier whose measured first-token latency meets `target_ms`.
+    /// `None` until at least one tier has a fresh result.
+    pub fn pick_tier(&self, catalogue: &Catalogue, target_ms: u64) -> Option<(ModelTier, String)> {
+        let measured: Vec<(ModelTier, &BenchResult)> = (0..=3)
+            .rev()
+            .map(tier_from_rank)
+            .filter_map(|t| Some((t.clone(), self.fresh(catalogue, &tier_key(&t))?)))
+            .collect();
+        if measured.is_empty() {
+            return None;
+        }
+        let reason = |t: &ModelTier, r: &BenchResult, verdict: &str| {
+            format!(
+                "benchmark: {t:?} first token in {} ms {verdict} the {target_ms} ms target",
+                r.ttft_ms
+            )
+        };
+        match measured.iter().find(|(_, r)| r.ttft_ms <= target_ms) {
+            Some((t, r)) => Some((t.clone(), reason(t, r, "meets"))),
+            // Nothing is fast enough: the quickest measured tier is the best bet.
+            None => measured
+                .iter()
+                .min_by_key(|(_, r)| r.ttft_ms)
+                .map(|(t, r)| (t.clone(), reason(t, r, "misses"))),
+        }
+    }
+}
+
+/// Benchmark one preset's model file with `llama-bench`.
+pub fn run(catalogue: &Catalogue, preset: &str, config: &BenchConfig) -> Result<BenchResult> {
+    let path = catalogue.path_of(catalogue.entry(preset)?);
+    let (file_size, file_mtime) =
+        file_stamp(&path).with_context(|| format!("{} is not downloaded", path.display()))?;
+    let output = Command::new(&config.llama_bench)
+        .arg("-m")
+        .arg(&path)
+        .args(["-p", &config.prompt_tokens.to_string()])
+        .args(["-n", &config.gen_tokens.to_string()])
+        .args(["-o", "json"])
+        .output()
+        .with_context(|| format!("failed running {}", config.llama_bench.display()))?;
+    if !output.status.success() {
+        bail!(
+            "{} failed ({}): {}",
+            config.llama_bench.display(),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let (pp, tg) = parse_llama_bench(&output.stdout)?;
+    Ok(BenchResult {
+        model_path: path,
+        file_size,
+        file_mtime,
+        prompt_tokens: config.prompt_tokens,
+        prompt_tokens_per_sec: pp,
+        gen_tokens_per_sec: tg,
+        ttft_ms: ttft_ms(config.prompt_tokens, pp, tg),
+        measured_at: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+    })
+}
+
+/// Prompt-processing and generation tokens/sec from `llama-bench -o json`,
+/// which reports one entry per test (`n_prompt` only, or `n_gen` only).
+fn parse_llama_bench(stdout: &[u8]) -> Result<(f64, f64)> {
+    #[derive(Deserialize)]
+    struct Entry {
+        n_prompt: u64,
+        n_gen: u64,
+        avg_ts: f64,
+    }
+    let entries: Vec<Entry> =
+        serde_json::from_slice(stdout).context("unexpected llama-bench output")?;
+    let rate = |test: fn(&Entry) -> bool| {
+        entries
+            .iter()
+            .find(|e| test(e))
+            .map(|e| e.avg_ts)
+            .filter(|r| *r > 0.0)
+    };
+    match (rate(|e| e.n_gen == 0), rate(|e| e.n_prompt == 0)) {
+        (Some(pp), Some(tg)) => Ok((pp, tg)),
+        _ => bail!("llama-bench output lacks prompt or generation results"),
+    }
+}
+
+fn ttft_ms(prompt_tokens: u32, pp: f64, tg: f64) -> u64 {
+    ((prompt_tokens as f64 / pp + 1.0 / tg) * 1000.0).round() as u64
+}
+
+fn file_stamp(path: &Path) -> Result<(u64, u64)> {
+    let meta = path.metadata()?;
+    let mtime = meta
+        .modified()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    Ok((meta.len(), mtime))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A stand-in for llama-bench that reports faster rates for smaller files.
+    fn fake_llama_bench(dir: &Path) -> PathBuf {
+        let script = dir.join("llama-bench");
+        std::fs::write(
+            &script,
+            r#"#!/bin/sh
+size=$(wc -c < "$2")
+pp=$((4000 / size))
+echo "[{\"n_prompt\": $4, \"n_gen\": 0, \"avg_ts\": $pp.0},"
+echo " {\"n_prompt\": 0, \"n_gen\": $6, \"avg_ts\": 20.0}]"
+"#,
+        )
+        .unwrap();
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        script
+    }
+
+    #[test]
+    fn benchmarks_presets_and_picks_the_largest_within_target() {
+        let dir = std::env::temp_dir().join(format!("munin-bench-{}", std::process::id()));
+        let mut catalogue = Catalogue::load(None).unwrap();
+        catalogue.base_dir = dir.join("models");
+        std::fs::create_dir_all(&catalogue.base_dir).unwrap();
+        let sizes = [("Tier0Tiny", 4), ("Tier1Mobile", 8), ("Tier2Balanced", 16)];
+        for (preset, size) in sizes {
+            let path = catalogue.path_of(catalogue.entry(preset).unwrap());
+            std::fs::write(path, vec![0u8; size]).unwrap();
+        }
+        let config = BenchConfig {
+            llama_bench: fake_llama_bench(&dir),
+            prompt_tokens: 256,
+            gen_tokens: 16,
+        };
+
+        let mut cache = BenchCache::default();
+        assert!(cache.pick_tier(&catalogue, 800).is_none());
+        for (preset, _) in sizes {
+            let r = run(&catalogue, preset, &config).unwrap();
+            cache.results.insert(preset.into(), r);
+        }
+        // 256 tokens at 1000/500/250 t/s, plus one token at 20 t/s.
+        let ttft: Vec<u64> = sizes
+            .iter()
+            .map(|(p, _)| cache.results[*p].ttft_ms)
+            .collect();
+        assert_eq!(ttft, [306, 562, 1074]);
+        let (tier, why) = cache.pick_tier(&catalogue, 800).unwrap();
+        assert_eq!(tier, ModelTier::Tier1Mobile);
+        assert!(why.contains("562 ms meets"), "{why}");
+        assert_eq!(
+            cache.pick_tier(&catalogue, 2000).unwrap().0,
+            ModelTier::Tier2Balanced
+        );
+        assert_eq!(
+            cache.pick_tier(&catalogue, 100).unwrap().0,
+            ModelTier::Tier0Tiny
+        );
+
+        let path = dir.join("bench.json");
+        cache.save(&path).unwrap();
+        let reloaded = BenchCache::load(&path);
+        assert!(reloaded.fresh(&catalogue, "Tier1Mobile").is_some());
+        let mobile = catalogue.path_of(catalogue.entry("Tier1Mobile").unwrap());
+        std::fs::write(mobile, vec![0u8; 9]).unwrap();
+        assert!(reloaded.fresh(&catalogue, "Tier1Mobile").is_none());
+        assert!(run(&catalogue, "Tier3Performance", &config).is_err());
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
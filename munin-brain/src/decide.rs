@@ -0,0 +1,371 @@
+//! Transcript → decision. Each intent has command phrases that start a
+//! request ("read /etc/hostname") and hint words that suggest it anywhere,
+//! both from the request locale's phrase pack. A command scores high; hints
+//! score lower and add up. Every scored intent is reported, the runners-up
+//! as `alternatives`.
+//!
+//! A tool is only chosen at [`CONFIDENT`] or above with all its arguments.
+//! Otherwise the decision is `clarify`, with a question for the user in the
+//! request's language, rather than a guess. Transcripts that match nothing
+//! are `chat`.
+
+use crate::locale::{self, Date, IntentPhrases, PhrasePack};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// Least confidence at which a tool is picked without asking.
+pub const CONFIDENT: f32 = 0.7;
+const COMMAND_SCORE: f32 = 0.95;
+/// Confidence from hint words: the first, then each further one, up to a cap.
+const FIRST_HINT: f32 = 0.45;
+const MORE_HINTS: f32 = 0.35;
+const HINT_CAP: f32 = 0.85;
+/// Confidence of the `chat` fallback when nothing matched.
+const CHAT_SCORE: f32 = 0.5;
+const MAX_ALTERNATIVES: usize = 3;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Decision {
+    pub intent: String,
+    /// The phrase pack the transcript was read with.
+    pub locale: String,
+    pub tool: Option<String>,
+    pub args: Value,
+    pub requires_confirmation: bool,
+    pub confidence: f32,
+    /// Other intents that matched, most likely first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alternatives: Vec<Alternative>,
+    /// For `clarify`: what to ask the user.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub question: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Alternative {
+    pub intent: String,
+    pub tool: String,
+    pub confidence: f32,
+}
+
+struct Intent {
+    name: &'static str,
+    tool: &'static str,
+    confirm: bool,
+    /// Arguments from the text after the command; `None` if missing.
+    args: fn(&str, &Normalize) -> Option<Value>,
+}
+
+const INTENTS: &[Intent] = &[
+    Intent {
+        name: "system_status",
+        tool: "system.status",
+        confirm: false,
+        args: |_, _| Some(json!({})),
+    },
+    Intent {
+        name: "read_file",
+        tool: "file.read",
+        confirm: false,
+        args: |rest, n| Some(json!({"path": n.path(rest)?})),
+    },
+    Intent {
+        name: "write_file",
+        tool: "file.write",
+        confirm: true,
+        args: |rest, n| {
+            let (path, content) = rest.split_once("::")?;
+            Some(json!({"path": n.path(path)?, "content": non_empty(content)?}))
+        },
+    },
+    Intent {
+        name: "system_exec",
+        tool: "shell.exec",
+        confirm: true,
+        // Run exactly as said: quoting, spacing and words like "one" matter.
+        args: |rest, _| Some(json!({"command": non_empty(rest)?})),
+    },
+    Intent {
+        name: "network_get",
+        tool: "network.get",
+        confirm: false,
+        args: |rest, n| Some(json!({"url": n.path(rest)?})),
+    },
+    Intent {
+        name: "knowledge_search",
+        tool: "knowledge.search",
+        confirm: false,
+        args: |rest, n| Some(json!({"query": n.query(rest)?})),
+    },
+];
+
+fn non_empty(s: &str) -> Option<&str> {
+    Some(s.trim()).filter(|s| !s.is_empty())
+}
+
+/// Writes spoken arguments the way tools expect them, per the pack.
+struct Normalize {
+    pack: &'static PhrasePack,
+    today: Date,
+}
+
+impl Normalize {
+    fn path(&self, s: &str) -> Option<String> {
+        non_empty(s).map(|s| self.pack.path(s))
+    }
+
+    /// Dates, then number words: "chapter twenty five from yesterday".
+    fn query(&self, s: &str) -> Option<String> {
+        non_empty(s).map(|s| self.pack.numbers(&self.pack.dates(s, self.today)))
+    }
+}
+
+/// How one intent matched.
+struct Scored {
+    intent: &'static Intent,
+    phrases: &'static IntentPhrases,
+    confidence: f32,
+    /// Set when a command matched and its arguments parsed.
+    args: Option<Value>,
+    /// A command matched, so the intent is clear even without arguments.
+    command: bool,
+}
+
+/// Decide with the phrase pack for `locale`.
+pub fn decide(transcript: &str, locale: &str) -> Decision {
+    decide_on(transcript, locale::pack(locale), Date::today())
+}
+
+fn decide_on(transcript: &str, pack: &'static PhrasePack, today: Date) -> Decision {
+    let norm = Normalize { pack, today };
+    let text = transcript.trim();
+    let low = text.to_lowercase();
+    let words: Vec<&str> = low
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect();
+
+    let mut scored: Vec<Scored> = INTENTS
+        .iter()
+        .filter_map(|intent| score(intent, pack.intent(intent.name)?, &norm, text, &low, &words))
+        .collect();
+    scored.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+
+    let Some(best) = scored.first() else {
+        return Decision {
+            intent: "chat".into(),
+            locale: pack.locale.clone(),
+            tool: None,
+            args: json!({"text": transcript}),
+            requires_confirmation: false,
+            confidence: CHAT_SCORE,
+            alternatives: Vec::new(),
+            question: None,
+        };
+    };
+    let alternatives = |skip: usize| -> Vec<Alternative> {
+        scored
+            .iter()
+            .skip(skip)
+            .take(MAX_ALTERNATIVES)
+            .map(|s| Alternative {
+                intent: s.intent.name.into(),
+                tool: s.intent.tool.into(),
+                confidence: s.confidence,
+            })
+            .collect()
+    };
+
+    if let (Some(args), true) = (&best.args, best.confidence >= CONFIDENT) {
+        return Decision {
+            intent: best.intent.name.into(),
+            locale: pack.locale.clone(),
+            tool: Some(best.intent.tool.into()),
+            args: args.clone(),
+            requires_confirmation: best.intent.confirm,
+            confidence: best.confidence,
+            alternatives: alternatives(1),
+            question: None,
+        };
+    }
+
+    let question = if best.command || best.confidence >= CONFIDENT {
+        best.phrases.ask.clone()
+    } else {
+        let options: Vec<&str> = scored
+            .iter()
+            .take(2)
+            .map(|s| s.phrases.describe.as_str())
+            .collect();
+        pack.which(&options)
+    };
+    Decision {
+        intent: "clarify".into(),
+        locale: pack.locale.clone(),
+        tool: None,
+        args: json!({"text": transcript}),
+        requires_confirmation: false,
+        confidence: best.confidence,
+        alternatives: alternatives(0),
+        question: Some(question),
+    }
+}
+
+fn score(
+    intent: &'static Intent,
+    phrases: &'static IntentPhrases,
+    norm: &Normalize,
+    text: &str,
+    low: &str,
+    words: &[&str],
+) -> Option<Scored> {
+    // The longest command the transcript starts with (or is wrapped in),
+    // then the rest of the original text: paths and content keep their case.
+    let command = phrases
+        .commands
+        .iter()
+        .filter_map(|c| match c.split_once("{}") {
+            Some((before, after)) => {
+                let (before, after) = (before.trim_end(), after.trim_start());
+                let wrapped =
+                    low.starts_with(&format!("{before} ")) && low.ends_with(&format!(" {after}"));
+                wrapped.then(|| (c.len(), text.get(before.len()..text.len() - after.len())))
+            }
+            None => (low == c || low.starts_with(&format!("{c} ")))
+                .then(|| (c.len(), text.get(c.len()..))),
+        })
+        .max_by_key(|(len, _)| *len);
+    if let Some((_, rest)) = command {
+        return Some(Scored {
+            intent,
+            phrases,
+            confidence: COMMAND_SCORE,
+            args: (intent.args)(rest.unwrap_or(""), norm),
+            command: true,
+        });
+    }
+    let hits = phrases
+        .hints
+        .iter()
+        .filter(|h| {
+            if h.chars().all(char::is_alphanumeric) {
+                words.contains(&h.as_str())
+            } else {
+                low.contains(h.as_str())
+            }
+        })
+        .count();
+    if hits == 0 {
+        return None;
+    }
+    let confidence = (FIRST_HINT + MORE_HINTS * (hits - 1) as f32).min(HINT_CAP);
+    // Hints alone only carry arguments for intents that need none.
+    Some(Scored {
+        intent,
+        phrases,
+        confidence,
+        args: (intent.args)("", norm).filter(|a| a.as_object().is_some_and(|o| o.is_empty())),
+        command: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_pick_a_tool_with_high_confidence() {
+        let d = decide("read /etc/Hostname", "en-US");
+        assert_eq!(d.tool.as_deref(), Some("file.read"));
+        assert_eq!(d.args, json!({"path": "/etc/Hostname"}));
+        assert!(d.confidence >= CONFIDENT);
+
+        let d = decide("exec uptime -p", "en-US");
+        assert_eq!(d.tool.as_deref(), Some("shell.exec"));
+        assert!(d.requires_confirmation);
+        // "uptime" also hints at the system status.
+        assert_eq!(d.alternatives[0].intent, "system_status");
+
+        // Shell commands are passed on untouched.
+        let d = decide("run echo one  'two  three'", "en-US");
+        assert_eq!(d.args, json!({"command": "echo one  'two  three'"}));
+
+        let d = decide("what's the battery status", "en-US");
+        assert_eq!(d.tool.as_deref(), Some("system.status"));
+        assert!(d.confidence >= CONFIDENT && d.confidence < COMMAND_SCORE);
+    }
+
+    #[test]
+    fn missing_arguments_and_weak_matches_ask_instead_of_guessing() {
+        let d = decide("write a file", "en-US");
+        assert_eq!(d.intent, "clarify");
+        assert_eq!(d.tool, None);
+        assert!(d.question.unwrap().starts_with("Which file should I write"));
+        assert_eq!(d.alternatives[0].tool, "file.write");
+
+        let d = decide("exec", "en-US");
+        assert_eq!(d.question.as_deref(), Some("Which command should I run?"));
+
+        let d = decide("can you check my notes file", "en-US");
+        assert_eq!(d.intent, "clarify");
+        assert!(d.confidence < CONFIDENT);
+        assert_eq!(
+            d.question.as_deref(),
+            Some("Do you want me to read a file or write a file?")
+        );
+        let tools: Vec<&str> = d.alternatives.iter().map(|a| a.tool.as_str()).collect();
+        assert_eq!(tools, ["file.read", "file.write", "knowledge.search"]);
+    }
+
+    #[test]
+    fn reads_the_request_locale_and_answers_in_it() {
+        let today = Date {
+            year: 2026,
+            month: 10,
+            day: 18,
+        };
+        let de = locale::pack("de-DE");
+        let d = decide_on("Lies Schrägstrich etc Schrägstrich hostname", de, today);
+        assert_eq!(d.tool.as_deref(), Some("file.read"));
+        assert_eq!(d.args, json!({"path": "/etc/hostname"}));
+        assert_eq!(d.locale, "de-DE");
+
+        let d = decide_on("führe sleep fünf aus", de, today);
+        assert_eq!(d.tool.as_deref(), Some("shell.exec"));
+        assert_eq!(d.args, json!({"command": "sleep fünf"}));
+
+        let d = decide_on("durchsuche Dokumente Protokoll vom 17.10.2026", de, today);
+        assert_eq!(d.args, json!({"query": "Protokoll vom 2026-10-17"}));
+        let d = decide_on(
+            "search docs chapter twenty five from yesterday",
+            locale::pack("en-US"),
+            today,
+        );
+        assert_eq!(d.args, json!({"query": "chapter 25 from 2026-10-17"}));
+
+        let d = decide_on("schreibe", de, today);
+        assert_eq!(d.intent, "clarify");
+        assert!(d
+            .question
+            .unwrap()
+            .starts_with("In welche Datei soll ich schreiben"));
+        let d = decide_on("kannst du meine Datei anschauen", de, today);
+        assert_eq!(
+            d.question.as_deref(),
+            Some("Soll ich eine Datei lesen oder eine Datei schreiben?")
+        );
+
+        // German commands mean nothing to the English pack, and a locale
+        // without a pack falls back to it.
+        assert_eq!(decide("lies /etc/hostname", "en-US").intent, "chat");
+        assert_eq!(decide("read /etc/hostname", "fr-FR").locale, "en-US");
+    }
+
+    #[test]
+    fn unmatched_text_is_chat() {
+        let d = decide("tell me a joke", "en-US");
+        assert_eq!(d.intent, "chat");
+        assert_eq!(d.args, json!({"text": "tell me a joke"}));
+        assert!(d.alternatives.is_empty() && d.question.is_none());
+    }
+}
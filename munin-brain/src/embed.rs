@@ -0,0 +1,224 @@
+//! Text embeddings from the manifest's embedding preset, computed with
+//! llama.cpp's `llama-embedding` like `bench` uses `llama-bench`.
+//!
+//! Vectors are L2-normalised, so the dot product of two is their cosine
+//! similarity.
+
+use crate::models::{Catalogue, PresetKind};
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Separates the texts of one batch in the prompt file; chunks contain
+/// newlines, so the default separator will not do.
+const SEPARATOR: &str = "<#munin-embd#>";
+
+pub trait Embedder: Send + Sync {
+    fn model_id(&self) -> &str;
+
+    /// One normalised vector per text.
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>>;
+
+    /// Prefixes the model expects on search queries and on documents.
+    fn prefixes(&self) -> (&str, &str) {
+        ("", "")
+    }
+}
+
+pub struct LlamaEmbedder {
+    binary: PathBuf,
+    preset: String,
+    model_id: String,
+    model_path: PathBuf,
+    context: usize,
+    query_prefix: String,
+    document_prefix: String,
+}
+
+impl LlamaEmbedder {
+    /// The catalogue's embedding preset run with `binary`; `None` when the
+    /// manifest has no embedding preset.
+    pub fn from_catalogue(catalogue: &Catalogue, binary: PathBuf) -> Option<Self> {
+        let (preset, entry) = catalogue
+            .presets
+            .iter()
+            .find(|(_, e)| e.kind == PresetKind::Embedding)?;
+        Some(Self {
+            binary,
+            preset: preset.clone(),
+            model_id: entry.model_id.clone(),
+            model_path: catalogue.path_of(entry),
+            context: entry.context,
+            query_prefix: entry.query_prefix.clone(),
+            document_prefix: entry.document_prefix.clone(),
+        })
+    }
+}
+
+/// A fresh directory under the temp dir that only this user can enter.
+/// `mkdir` fails on anything already there, symlinks included, so the
+/// prompts can't be redirected or read by another user.
+fn private_dir() -> Result<PathBuf> {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    loop {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.subsec_nanos());
+        let dir = std::env::temp_dir().join(format!(
+            "munin-embd-{}-{}-{nanos:08x}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        match std::fs::DirBuilder::new().mode(0o700).create(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e).with_context(|| format!("failed creating {}", dir.display())),
+        }
+    }
+}
+
+impl Embedder for LlamaEmbedder {
+    fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    fn prefixes(&self) -> (&str, &str) {
+        (&self.query_prefix, &self.document_prefix)
+    }
+
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        if texts.is_empty() {
+            return Ok(Vec::new());
+        }
+        if !self.model_path.is_file() {
+            bail!(
+                "{} is not downloaded (munin-brain models pull {})",
+                self.model_path.display(),
+                self.preset
+            );
+        }
+        let dir = private_dir()?;
+        let prompts = dir.join("prompts.txt");
+        let written = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&prompts)
+            .and_then(|mut f| f.write_all(texts.join(SEPARATOR).as_bytes()));
+        if let Err(e) = written {
+            std::fs::remove_dir_all(&dir).ok();
+            return Err(e).with_context(|| format!("failed writing {}", prompts.display()));
+        }
+        let output = Command::new(&self.binary)
+            .arg("-m")
+            .arg(&self.model_path)
+            .arg("-f")
+            .arg(&prompts)
+            .args(["-c", &self.context.to_string()])
+            .args(["--embd-separator", SEPARATOR])
+            .args(["--embd-normalize", "2"])
+            .args(["--embd-output-format", "json"])
+            .output();
+        std::fs::remove_dir_all(&dir).ok();
+        let output = output.with_context(|| format!("failed running {}", self.binary.display()))?;
+        if !output.status.success() {
+            bail!(
+                "{} failed ({}): {}",
+                self.binary.display(),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        let vectors = parse_llama_embedding(&output.stdout)?;
+        if vectors.len() != texts.len() {
+            bail!(
+                "{} returned {} embeddings for {} texts",
+                self.binary.display(),
+                vectors.len(),
+                texts.len()
+            );
+        }
+        Ok(vectors.into_iter().map(normalize).collect())
+    }
+}
+
+/// Vectors from `llama-embedding --embd-output-format json`, in prompt order.
+fn parse_llama_embedding(stdout: &[u8]) -> Result<Vec<Vec<f32>>> {
+    #[derive(Deserialize)]
+    struct Output {
+        data: Vec<Item>,
+    }
+    #[derive(Deserialize)]
+    struct Item {
+        index: usize,
+        embedding: Vec<f32>,
+    }
+    let text = String::from_utf8_lossy(stdout);
+    let json = text.find('{').map_or("", |start| &text[start..]);
+    let mut out: Output =
+        serde_json::from_str(json).context("unexpected llama-embedding output")?;
+    out.data.sort_by_key(|item| item.index);
+    Ok(out.data.into_iter().map(|item| item.embedding).collect())
+}
+
+pub fn normalize(mut v: Vec<f32>) -> Vec<f32> {
+    let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        v.iter_mut().for_each(|x| *x /= norm);
+    }
+    v
+}
+
+pub fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_llama_embedding_on_the_embedding_preset() {
+        let dir = std::env::temp_dir().join(format!("munin-embed-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // Stand-in that answers with one unnormalised vector per prompt,
+        // in reverse index order, once it finds the prompts kept private.
+        let script = dir.join("llama-embedding");
+        std::fs::write(
+            &script,
+            r#"#!/bin/sh
+[ "$(stat -c %a "$(dirname "$4")") $(stat -c %a "$4")" = "700 600" ] || exit 1
+n=$(grep -o '<#munin-embd#>' "$4" | wc -l)
+echo "main: loading model" >&2
+echo '{"object": "list", "data": ['
+i=$n
+while [ $i -ge 0 ]; do
+  sep=","; [ $i -eq 0 ] && sep=""
+  echo "{\"object\": \"embedding\", \"index\": $i, \"embedding\": [3, $((i * 4))]}$sep"
+  i=$((i - 1))
+done
+echo ']}'
+"#,
+        )
+        .unwrap();
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut catalogue = Catalogue::load(None).unwrap();
+        catalogue.base_dir = dir.clone();
+        let embedder = LlamaEmbedder::from_catalogue(&catalogue, script).unwrap();
+        let texts = vec!["one".to_string(), "two\nlines".to_string()];
+        assert!(embedder.embed(&texts).is_err(), "model not downloaded yet");
+
+        std::fs::write(&embedder.model_path, b"GGUF").unwrap();
+        let vectors = embedder.embed(&texts).unwrap();
+        assert_eq!(vectors, [vec![1.0, 0.0], vec![0.6, 0.8]]);
+        assert_eq!(embedder.prefixes().0, "search_query: ");
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
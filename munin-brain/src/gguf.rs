@@ -0,0 +1,193 @@
+//! Minimal GGUF header reader: the magic, version, tensor count and the
+//! scalar and string metadata (arrays such as the tokenizer vocabulary are
+//! skipped). Enough to check a model file and read its chat template and
+//! context length without loading it.
+
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"GGUF";
+/// Bounds that keep a corrupt header from causing huge allocations.
+const MAX_KEY_LEN: u64 = 64 * 1024;
+const MAX_STRING_LEN: u64 = 64 * 1024 * 1024;
+const MAX_KV_COUNT: u64 = 1 << 20;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Uint(u64),
+    Float(f64),
+    Bool(bool),
+    String(String),
+    /// Arrays are skipped; only the element count is kept.
+    Array(u64),
+}
+
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub tensor_count: u64,
+    pub metadata: BTreeMap<String, Value>,
+}
+
+impl Header {
+    pub fn string(&self, key: &str) -> Option<&str> {
+        match self.metadata.get(key)? {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn uint(&self, key: &str) -> Option<u64> {
+        match self.metadata.get(key)? {
+            Value::Uint(n) => Some(*n),
+            Value::Int(n) => u64::try_from(*n).ok(),
+            _ => None,
+        }
+    }
+
+    /// Context length the model was trained with.
+    pub fn context_length(&self) -> Option<u64> {
+        let arch = self.string("general.architecture")?;
+        self.uint(&format!("{arch}.context_length"))
+    }
+
+    /// The Jinja chat template shipped with the model, if any.
+    pub fn chat_template(&self) -> Option<&str> {
+        self.string("tokenizer.chat_template")
+    }
+}
+
+pub fn read_header(path: &Path) -> Result<Header> {
+    let file =
+        std::fs::File::open(path).with_context(|| format!("failed opening {}", path.display()))?;
+    parse(&mut BufReader::new(file))
+        .with_context(|| format!("{} is not a valid GGUF file", path.display()))
+}
+
+fn parse(r: &mut impl Read) -> Result<Header> {
+    let mut magic = [0u8; 4];
+    r.read_exact(&mut magic).context("too short")?;
+    if &magic != MAGIC {
+        bail!("bad magic {magic:?}");
+    }
+    let version = u32::from_le_bytes(bytes(r)?);
+    if !(2..=3).contains(&version) {
+        bail!("unsupported GGUF version {version}");
+    }
+    let tensor_count = u64::from_le_bytes(bytes(r)?);
+    let kv_count = u64::from_le_bytes(bytes(r)?);
+    if kv_count > MAX_KV_COUNT {
+        bail!("implausible metadata count {kv_count}");
+    }
+    let mut metadata = BTreeMap::new();
+    for _ in 0..kv_count {
+        let key = string(r, MAX_KEY_LEN)?;
+        let kind = u32::from_le_bytes(bytes(r)?);
+        let value = value(r, kind).with_context(|| format!("bad value for {key}"))?;
+        metadata.insert(key, value);
+    }
+    Ok(Header {
+        tensor_count,
+        metadata,
+    })
+}
+
+fn value(r: &mut impl Read, kind: u32) -> Result<Value> {
+    Ok(match kind {
+        0 => Value::Uint(u8::from_le_bytes(bytes(r)?).into()),
+        1 => Value::Int(i8::from_le_bytes(bytes(r)?).into()),
+        2 => Value::Uint(u16::from_le_bytes(bytes(r)?).into()),
+        3 => Value::Int(i16::from_le_bytes(bytes(r)?).into()),
+        4 => Value::Uint(u32::from_le_bytes(bytes(r)?).into()),
+        5 => Value::Int(i32::from_le_bytes(bytes(r)?).into()),
+        6 => Value::Float(f32::from_le_bytes(bytes(r)?).into()),
+        7 => Value::Bool(u8::from_le_bytes(bytes(r)?) != 0),
+        8 => Value::String(string(r, MAX_STRING_LEN)?),
+        9 => {
+            let item = u32::from_le_bytes(bytes(r)?);
+            let count = u64::from_le_bytes(bytes(r)?);
+            for _ in 0..count {
+                value(r, item)?;
+            }
+            Value::Array(count)
+        }
+        10 => Value::Uint(u64::from_le_bytes(bytes(r)?)),
+        11 => Value::Int(i64::from_le_bytes(bytes(r)?)),
+        12 => Value::Float(f64::from_le_bytes(bytes(r)?)),
+        other => bail!("unknown value type {other}"),
+    })
+}
+
+fn bytes<const N: usize>(r: &mut impl Read) -> Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    r.read_exact(&mut buf).context("truncated header")?;
+    Ok(buf)
+}
+
+fn string(r: &mut impl Read, max: u64) -> Result<String> {
+    let len = u64::from_le_bytes(bytes(r)?);
+    if len > max {
+        bail!("implausible string length {len}");
+    }
+    let mut buf = vec![0u8; len as usize];
+    r.read_exact(&mut buf).context("truncated header")?;
+    String::from_utf8(buf).context("string is not UTF-8")
+}
+
+/// Build a GGUF header with string and u32 metadata, for tests.
+#[cfg(test)]
+pub fn fixture(strings: &[(&str, &str)], uints: &[(&str, u32)]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&3u32.to_le_bytes());
+    out.extend_from_slice(&1u64.to_le_bytes());
+    out.extend_from_slice(&((strings.len() + uints.len() + 1) as u64).to_le_bytes());
+    let put_str = |out: &mut Vec<u8>, s: &str| {
+        out.extend_from_slice(&(s.len() as u64).to_le_bytes());
+        out.extend_from_slice(s.as_bytes());
+    };
+    for (k, v) in strings {
+        put_str(&mut out, k);
+        out.extend_from_slice(&8u32.to_le_bytes());
+        put_str(&mut out, v);
+    }
+    for (k, v) in uints {
+        put_str(&mut out, k);
+        out.extend_from_slice(&4u32.to_le_bytes());
+        out.extend_from_slice(&v.to_le_bytes());
+    }
+    // A skipped array, like the tokenizer vocabulary.
+    put_str(&mut out, "tokenizer.ggml.tokens");
+    out.extend_from_slice(&9u32.to_le_bytes());
+    out.extend_from_slice(&8u32.to_le_bytes());
+    out.extend_from_slice(&2u64.to_le_bytes());
+    put_str(&mut out, "<s>");
+    put_str(&mut out, "</s>");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_metadata_and_rejects_non_gguf() {
+        let bytes = fixture(
+            &[
+                ("general.architecture", "llama"),
+                ("tokenizer.chat_template", "{{ '<|im_start|>' }}"),
+            ],
+            &[("llama.context_length", 4096)],
+        );
+        let header = parse(&mut bytes.as_slice()).unwrap();
+        assert_eq!(header.tensor_count, 1);
+        assert_eq!(header.context_length(), Some(4096));
+        assert_eq!(header.chat_template(), Some("{{ '<|im_start|>' }}"));
+        assert_eq!(header.metadata["tokenizer.ggml.tokens"], Value::Array(2));
+
+        assert!(parse(&mut &b"GGML\x03\0\0\0"[..]).is_err());
+        assert!(parse(&mut &bytes[..bytes.len() - 3]).is_err());
+    }
+}
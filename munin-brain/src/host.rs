@@ -0,0 +1,413 @@
+//! The model munin-brain is serving, and switching it at runtime.
+//!
+//! Requests that use the model hold a read guard from [`ModelHost::use_model`];
+//! a switch takes the write lock, so it waits for those requests to drain and
+//! holds new ones back until the new model is in place. The operator's choice
+//! is written to a state file and restored on the next start.
+//!
+//! Under memory, thermal or battery pressure [`crate::adapt`] asks for the
+//! `Auto` or pinned tier to be served a few steps smaller. Every change of
+//! the served tier is recorded as a [`TierEvent`].
+
+use crate::adapt::Conditions;
+use crate::models::{Catalogue, PresetKind};
+use crate::{resolve_model_with_fallback, tier_from_rank, tier_rank, ModelTier, RuntimeProfile};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Tier events kept for `GET /v1/events`.
+const EVENT_CAPACITY: usize = 64;
+
+/// Which model to serve.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum Selection {
+    /// The tier the hardware profile picks, falling back to smaller ones.
+    #[default]
+    Auto,
+    /// A pinned tier, still falling back when its file is missing.
+    Tier { tier: ModelTier },
+    /// Exactly this catalogue preset; refused when its file is missing.
+    Preset { preset: String },
+    /// No model loaded.
+    Unloaded,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Active {
+    pub selection: Selection,
+    pub loaded: bool,
+    /// Tiers below the selection served because of pressure.
+    pub pressure_steps: u8,
+    pub profile: RuntimeProfile,
+}
+
+/// The served tier changed (or the model was loaded or unloaded).
+#[derive(Debug, Clone, Serialize)]
+pub struct TierEvent {
+    pub seq: u64,
+    /// Unix time in milliseconds.
+    pub at: u64,
+    pub from: ModelTier,
+    pub to: ModelTier,
+    pub loaded: bool,
+    pub reason: String,
+}
+
+pub struct ModelHost {
+    catalogue: Catalogue,
+    /// Hardware profile from startup; `Auto` follows its tier.
+    detected: RuntimeProfile,
+    state_path: PathBuf,
+    active: RwLock<Active>,
+    in_flight: AtomicUsize,
+    pressure_steps: AtomicU8,
+    conditions: Mutex<Option<Conditions>>,
+    events: Mutex<VecDeque<TierEvent>>,
+    next_seq: AtomicU64,
+}
+
+/// Read access to the active model for the length of one request.
+pub struct ModelGuard<'a> {
+    active: RwLockReadGuard<'a, Active>,
+    in_flight: &'a AtomicUsize,
+}
+
+impl std::ops::Deref for ModelGuard<'_> {
+    type Target = Active;
+    fn deref(&self) -> &Active {
+        &self.active
+    }
+}
+
+impl Drop for ModelGuard<'_> {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl ModelHost {
+    /// Start with the selection saved in `state_path`, or `Auto` when there
+    /// is none or it no longer resolves.
+    pub fn new(catalogue: Catalogue, detected: RuntimeProfile, state_path: PathBuf) -> Self {
+        let saved = std::fs::read_to_string(&state_path)
+            .ok()
+            .and_then(|text| serde_json::from_str::<Selection>(&text).ok())
+            .unwrap_or_default();
+        let mut host = Self {
+            active: RwLock::new(Active {
+                selection: Selection::Auto,
+                loaded: false,
+                pressure_steps: 0,
+                profile: detected.clone(),
+            }),
+            catalogue,
+            detected,
+            state_path,
+            in_flight: AtomicUsize::new(0),
+            pressure_steps: AtomicU8::new(0),
+            conditions: Mutex::new(None),
+            events: Mutex::new(VecDeque::new()),
+            next_seq: AtomicU64::new(1),
+        };
+        let active = host.resolve(&saved).unwrap_or_else(|e| {
+            tracing::warn!("saved model selection {saved:?} ignored: {e:#}");
+            host.resolve(&Selection::Auto)
+                .expect("auto always resolves")
+        });
+        *host.active.get_mut().unwrap() = active;
+        host
+    }
+
+    pub fn catalogue(&self) -> &Catalogue {
+        &self.catalogue
+    }
+
+    pub fn use_model(&self) -> ModelGuard<'_> {
+        let active = self.active.read().unwrap_or_else(|e| e.into_inner());
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        ModelGuard {
+            active,
+            in_flight: &self.in_flight,
+        }
+    }
+
+    pub fn active(&self) -> Active {
+        self.use_model().clone()
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Switch to `selection` once in-flight requests have drained, and save
+    /// it for the next start.
+    pub fn load(&self, selection: Selection) -> Result<Active> {
+        let next = self.resolve(&selection)?;
+        let waiting = self.in_flight();
+        if waiting > 0 {
+            tracing::info!("waiting for {waiting} in-flight request(s) before switching model");
+        }
+        let mut active = self.active.write().unwrap_or_else(|e| e.into_inner());
+        self.save(&selection)?;
+        self.replace(&mut active, next, "selected through the API");
+        Ok(active.clone())
+    }
+
+    /// Serve `steps` tiers below the selection (for `Auto` and pinned tiers),
+    /// waiting for in-flight requests like [`ModelHost::load`].
+    pub fn adapt(&self, steps: u8, reason: &str) -> Result<Active> {
+        self.pressure_steps.store(steps, Ordering::SeqCst);
+        let mut active = self.active.write().unwrap_or_else(|e| e.into_inner());
+        let next = self.resolve(&active.selection)?;
+        self.replace(&mut active, next, reason);
+        Ok(active.clone())
+    }
+
+    /// How far pressure may step the current selection down.
+    pub fn adapt_headroom(&self) -> u8 {
+        match &self.active().selection {
+            Selection::Auto => tier_rank(&self.detected.tier),
+            Selection::Tier { tier } => tier_rank(tier),
+            Selection::Preset { .. } | Selection::Unloaded => 0,
+        }
+    }
+
+    pub fn set_conditions(&self, conditions: Conditions) {
+        *self.conditions.lock().unwrap_or_else(|e| e.into_inner()) = Some(conditions);
+    }
+
+    pub fn conditions(&self) -> Option<Conditions> {
+        self.conditions
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Tier events with a sequence number above `since`, oldest first.
+    pub fn events_since(&self, since: u64) -> Vec<TierEvent> {
+        let events = self.events.lock().unwrap_or_else(|e| e.into_inner());
+        events.iter().filter(|e| e.seq > since).cloned().collect()
+    }
+
+    fn replace(&self, active: &mut RwLockWriteGuard<'_, Active>, next: Active, reason: &str) {
+        let (from, to) = (&active.profile.resolved_tier, &next.profile.resolved_tier);
+        if from != to || active.loaded != next.loaded {
+            tracing::info!(
+                "serving {:?} ({}, loaded={}): {reason}",
+                to,
+                next.profile.selected_model.model_id,
+                next.loaded
+            );
+            let event = TierEvent {
+                seq: self.next_seq.fetch_add(1, Ordering::SeqCst),
+                at: now_ms(),
+                from: from.clone(),
+                to: to.clone(),
+                loaded: next.loaded,
+                reason: reason.to_string(),
+            };
+            let mut events = self.events.lock().unwrap_or_else(|e| e.into_inner());
+            if events.len() == EVENT_CAPACITY {
+                events.pop_front();
+            }
+            events.push_back(event);
+        }
+        **active = next;
+    }
+
+    fn save(&self, selection: &Selection) -> Result<()> {
+        if let Some(dir) = self.state_path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("failed creating {}", dir.display()))?;
+        }
+        let tmp = self.state_path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(selection)?)?;
+        std::fs::rename(&tmp, &self.state_path)
+            .with_context(|| format!("failed writing {}", self.state_path.display()))
+    }
+
+    fn resolve(&self, selection: &Selection) -> Result<Active> {
+        let mut profile = self.detected.clone();
+        let tier = match selection {
+            Selection::Auto | Selection::Unloaded => self.detected.tier.clone(),
+            Selection::Tier { tier } => tier.clone(),
+            Selection::Preset { preset } => {
+                if self.catalogue.entry(preset)?.kind != PresetKind::Chat {
+                    bail!("preset {preset} is not a chat model");
+                }
+                self.catalogue.check_file(preset)?;
+                profile.selected_model = self.catalogue.preset(preset);
+                let tier = profile.selected_model.tier.clone();
+                profile.tier = tier.clone();
+                profile.resolved_tier = tier;
+                profile.model_available = true;
+                profile.warning = None;
+                return Ok(Active {
+                    selection: selection.clone(),
+                    loaded: true,
+                    pressure_steps: 0,
+                    profile,
+                });
+            }
+        };
+        let steps = self
+            .pressure_steps
+            .load(Ordering::SeqCst)
+            .min(tier_rank(&tier));
+        let tier = tier_from_rank(tier_rank(&tier) - steps);
+        let (model, resolved, available, warning) =
+            resolve_model_with_fallback(&self.catalogue, &tier, self.detected.ram_gb);
+        profile.tier = tier;
+        profile.selected_model = model;
+        profile.resolved_tier = resolved;
+        profile.model_available = available;
+        profile.warning = warning;
+        Ok(Active {
+            loaded: available && *selection != Selection::Unloaded,
+            selection: selection.clone(),
+            pressure_steps: steps,
+            profile,
+        })
+    }
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    fn host(dir: &std::path::Path) -> ModelHost {
+        let mut catalogue = Catalogue::load(None).unwrap();
+        catalogue.base_dir = dir.join("models");
+        let detected = crate::detect_profile(&catalogue, None);
+        ModelHost::new(catalogue, detected, dir.join("state.json"))
+    }
+
+    #[test]
+    fn selection_is_validated_and_survives_a_restart() {
+        let dir = std::env::temp_dir().join(format!("munin-host-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("models")).unwrap();
+        let h = host(&dir);
+        assert_eq!(h.active().selection, Selection::Auto);
+
+        let err = h
+            .load(Selection::Preset {
+                preset: "Tier2Balanced".into(),
+            })
+            .unwrap_err();
+        assert!(err.to_string().contains("not downloaded"), "{err}");
+        std::fs::write(
+            dir.join("models/mistral-7b-instruct-v0.2.Q4_K_M.gguf"),
+            crate::gguf::fixture(&[], &[]),
+        )
+        .unwrap();
+        let active = h
+            .load(Selection::Preset {
+                preset: "Tier2Balanced".into(),
+            })
+            .unwrap();
+        assert!(active.loaded);
+        assert_eq!(
+            active.profile.selected_model.model_id,
+            "Mistral-7B-Instruct-v0.2-GGUF"
+        );
+
+        // A pinned tier whose file is missing falls back like auto does.
+        let active = h
+            .load(Selection::Tier {
+                tier: ModelTier::Tier3Performance,
+            })
+            .unwrap();
+        assert!(matches!(
+            active.profile.resolved_tier,
+            ModelTier::Tier2Balanced
+        ));
+        assert!(active.profile.warning.is_some());
+
+        h.load(Selection::Unloaded).unwrap();
+        let restarted = host(&dir);
+        assert_eq!(restarted.active().selection, Selection::Unloaded);
+        assert!(!restarted.active().loaded);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn switching_waits_for_requests_in_flight() {
+        let dir = std::env::temp_dir().join(format!("munin-drain-{}", std::process::id()));
+        let h = Arc::new(host(&dir));
+        let (held_tx, held_rx) = std::sync::mpsc::channel();
+        let worker = {
+            let h = h.clone();
+            std::thread::spawn(move || {
+                let _model = h.use_model();
+                held_tx.send(()).unwrap();
+                std::thread::sleep(Duration::from_millis(200));
+            })
+        };
+        held_rx.recv().unwrap();
+        assert_eq!(h.in_flight(), 1);
+        let started = Instant::now();
+        h.load(Selection::Unloaded).unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(150));
+        assert_eq!(h.in_flight(), 0);
+        worker.join().unwrap();
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn pressure_steps_the_served_tier_and_records_events() {
+        let dir = std::env::temp_dir().join(format!("munin-adapt-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("models")).unwrap();
+        for file in [
+            "tinyllama-1.1b-chat-v1.0.Q4_K_M.gguf",
+            "qwen2.5-3b-instruct-q4_k_m.gguf",
+            "mistral-7b-instruct-v0.2.Q4_K_M.gguf",
+        ] {
+            std::fs::write(
+                dir.join("models").join(file),
+                crate::gguf::fixture(&[], &[]),
+            )
+            .unwrap();
+        }
+        let h = host(&dir);
+        h.load(Selection::Tier {
+            tier: ModelTier::Tier2Balanced,
+        })
+        .unwrap();
+        assert_eq!(h.adapt_headroom(), 2);
+        let seen = h.events_since(0).last().map_or(0, |e| e.seq);
+
+        let active = h.adapt(1, "CPU at 90°C").unwrap();
+        assert_eq!(active.profile.resolved_tier, ModelTier::Tier1Mobile);
+        assert_eq!(active.pressure_steps, 1);
+        h.adapt(0, "conditions recovered").unwrap();
+
+        let events = h.events_since(seen);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].to, ModelTier::Tier1Mobile);
+        assert_eq!(events[0].reason, "CPU at 90°C");
+        assert_eq!(events[1].to, ModelTier::Tier2Balanced);
+        assert!(h.events_since(events[1].seq).is_empty());
+
+        // An exact preset is never stepped down.
+        h.load(Selection::Preset {
+            preset: "Tier2Balanced".into(),
+        })
+        .unwrap();
+        assert_eq!(h.adapt_headroom(), 0);
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,469 @@
+//! Local semantic index over the user's text files.
+//!
+//! Configured folders are walked for text files, which are cut into chunks
+//! of a few paragraphs and embedded with the embedding preset. The index is
+//! a JSON file keyed by path; a sync only re-embeds files whose size or
+//! modification time changed and drops files that went away. Changing the
+//! embedding model rebuilds it. [`watch`] syncs on an interval.
+
+use crate::embed::{dot, Embedder};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub const DEFAULT_INDEX: &str = "/var/lib/muninos/brain/index.json";
+
+/// Files larger than this are not indexed.
+const MAX_FILE_BYTES: u64 = 1024 * 1024;
+/// Target chunk size in characters; chunks break at blank lines once past
+/// half of it.
+const CHUNK_CHARS: usize = 1000;
+const SNIPPET_CHARS: usize = 240;
+/// Chunks per embedding call.
+const BATCH: usize = 32;
+
+const TEXT_EXTENSIONS: &[&str] = &[
+    "txt", "md", "markdown", "rst", "org", "tex", "csv", "tsv", "json", "yaml", "yml", "toml",
+    "ini", "conf", "log", "html", "xml",
+];
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Index {
+    model_id: String,
+    files: BTreeMap<PathBuf, IndexedFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct IndexedFile {
+    size: u64,
+    mtime: u64,
+    chunks: Vec<Chunk>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Chunk {
+    /// 1-based line the chunk starts on.
+    line: usize,
+    text: String,
+    vector: Vec<f32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Hit {
+    pub path: PathBuf,
+    pub line: usize,
+    pub score: f32,
+    pub snippet: String,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct SyncReport {
+    pub indexed: usize,
+    pub unchanged: usize,
+    pub removed: usize,
+    /// Files that could not be read or embedded, with the reason.
+    pub failed: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Status {
+    pub model_id: Option<String>,
+    pub roots: Vec<PathBuf>,
+    pub files: usize,
+    pub chunks: usize,
+    pub last_sync: Option<SyncReport>,
+}
+
+pub struct Knowledge {
+    path: PathBuf,
+    roots: Vec<PathBuf>,
+    embedder: Option<Arc<dyn Embedder>>,
+    index: Mutex<Index>,
+    last_sync: Mutex<Option<SyncReport>>,
+}
+
+impl Knowledge {
+    /// Open the index at `path` (empty if missing or unreadable) over
+    /// `roots`. Without an embedder, searches and syncs fail.
+    pub fn open(path: PathBuf, roots: Vec<PathBuf>, embedder: Option<Arc<dyn Embedder>>) -> Self {
+        let index = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            roots,
+            embedder,
+            index: Mutex::new(index),
+            last_sync: Mutex::new(None),
+        }
+    }
+
+    pub fn embedder(&self) -> Result<&dyn Embedder> {
+        self.embedder
+            .as_deref()
+            .context("the model manifest has no embedding preset")
+    }
+
+    pub fn status(&self) -> Status {
+        let index = self.index.lock().unwrap_or_else(|e| e.into_inner());
+        Status {
+            model_id: self.embedder.as_ref().map(|e| e.model_id().to_string()),
+            roots: self.roots.clone(),
+            files: index.files.len(),
+            chunks: index.files.values().map(|f| f.chunks.len()).sum(),
+            last_sync: self
+                .last_sync
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone(),
+        }
+    }
+
+    /// Bring the index up to date with the folders and save it.
+    pub fn sync(&self) -> Result<SyncReport> {
+        let embedder = self.embedder()?;
+        let mut report = SyncReport::default();
+        let found: BTreeMap<PathBuf, (u64, u64)> = self
+            .roots
+            .iter()
+            .flat_map(|root| text_files(root))
+            .collect();
+
+        let stale: Vec<(PathBuf, (u64, u64))> = {
+            let mut index = self.index.lock().unwrap_or_else(|e| e.into_inner());
+            if index.model_id != embedder.model_id() {
+                index.files.clear();
+                index.model_id = embedder.model_id().to_string();
+            }
+            let before = index.files.len();
+            index.files.retain(|path, _| found.contains_key(path));
+            report.removed = before - index.files.len();
+            found
+                .iter()
+                .filter(|(path, stamp)| {
+                    index
+                        .files
+                        .get(*path)
+                        .is_none_or(|f| (f.size, f.mtime) != **stamp)
+                })
+                .map(|(path, stamp)| (path.clone(), *stamp))
+                .collect()
+        };
+        report.unchanged = found.len() - stale.len();
+
+        // Embed without holding the lock, so searches keep working.
+        for (path, (size, mtime)) in stale {
+            match embed_file(embedder, &path) {
+                Ok(chunks) => {
+                    let mut index = self.index.lock().unwrap_or_else(|e| e.into_inner());
+                    index.files.insert(
+                        path,
+                        IndexedFile {
+                            size,
+                            mtime,
+                            chunks,
+                        },
+                    );
+                    report.indexed += 1;
+                }
+                Err(e) => report.failed.push(format!("{}: {e:#}", path.display())),
+            }
+        }
+
+        if report.indexed > 0 || report.removed > 0 {
+            self.save()?;
+        }
+        *self.last_sync.lock().unwrap_or_else(|e| e.into_inner()) = Some(report.clone());
+        Ok(report)
+    }
+
+    /// The `limit` chunks closest to `query`, best first.
+    pub fn search(&self, query: &str, limit: usize) -> Result<Vec<Hit>> {
+        let embedder = self.embedder()?;
+        let (query_prefix, _) = embedder.prefixes();
+        let q = embedder
+            .embed(&[format!("{query_prefix}{query}")])?
+            .pop()
+            .context("no embedding for the query")?;
+        let index = self.index.lock().unwrap_or_else(|e| e.into_inner());
+        let mut hits: Vec<Hit> = index
+            .files
+            .iter()
+            .flat_map(|(path, file)| {
+                file.chunks.iter().map(|c| Hit {
+                    path: path.clone(),
+                    line: c.line,
+                    score: dot(&q, &c.vector),
+                    snippet: snippet(&c.text),
+                })
+            })
+            .collect();
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hits.truncate(limit);
+        Ok(hits)
+    }
+
+    /// The index holds the text of every chunk, so it is kept 0600 in a
+    /// directory created 0700.
+    fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(dir)
+                .with_context(|| format!("failed creating {}", dir.display()))?;
+        }
+        let tmp = self.path.with_extension("tmp");
+        {
+            let index = self.index.lock().unwrap_or_else(|e| e.into_inner());
+            std::fs::remove_file(&tmp).ok();
+            std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(&tmp)
+                .and_then(|mut f| f.write_all(&serde_json::to_vec(&*index)?))
+                .with_context(|| format!("failed writing {}", tmp.display()))?;
+        }
+        std::fs::rename(&tmp, &self.path)
+            .with_context(|| format!("failed writing {}", self.path.display()))
+    }
+}
+
+/// Sync every `interval` until the process exits.
+pub fn watch(knowledge: Arc<Knowledge>, interval: Duration) {
+    loop {
+        match knowledge.sync() {
+            Ok(r) if r.indexed > 0 || r.removed > 0 || !r.failed.is_empty() => tracing::info!(
+                "knowledge index: {} indexed, {} removed, {} failed",
+                r.indexed,
+                r.removed,
+                r.failed.len()
+            ),
+            Ok(_) => {}
+            Err(e) => tracing::warn!("knowledge index sync failed: {e:#}"),
+        }
+        std::thread::sleep(interval);
+    }
+}
+
+fn embed_file(embedder: &dyn Embedder, path: &Path) -> Result<Vec<Chunk>> {
+    let text = std::fs::read_to_string(path)?;
+    let (_, document_prefix) = embedder.prefixes();
+    let pieces = chunks(&text);
+    let mut out = Vec::with_capacity(pieces.len());
+    for batch in pieces.chunks(BATCH) {
+        let texts: Vec<String> = batch
+            .iter()
+            .map(|(_, t)| format!("{document_prefix}{t}"))
+            .collect();
+        let vectors = embedder.embed(&texts)?;
+        for ((line, text), vector) in batch.iter().zip(vectors) {
+            out.push(Chunk {
+                line: *line,
+                text: text.clone(),
+                vector,
+            });
+        }
+    }
+    Ok(out)
+}
+
+/// Text files under `root` with their size and mtime. Hidden entries and
+/// symlinks are skipped.
+fn text_files(root: &Path) -> Vec<(PathBuf, (u64, u64))> {
+    let mut out = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir).into_iter().flatten().flatten() {
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let Ok(meta) = entry.path().symlink_metadata() else {
+                continue;
+            };
+            let path = entry.path();
+            if meta.is_dir() {
+                dirs.push(path);
+            } else if meta.is_file()
+                && meta.len() <= MAX_FILE_BYTES
+                && path
+                    .extension()
+                    .is_some_and(|x| TEXT_EXTENSIONS.contains(&&*x.to_string_lossy()))
+            {
+                let mtime = meta
+                    .modified()
+                    .ok()
+                    .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                    .map_or(0, |d| d.as_secs());
+                out.push((path, (meta.len(), mtime)));
+            }
+        }
+    }
+    out
+}
+
+/// Cut `text` into chunks of about [`CHUNK_CHARS`], preferring paragraph
+/// breaks, with the line each starts on.
+fn chunks(text: &str) -> Vec<(usize, String)> {
+    let mut out = Vec::new();
+    let mut start = 1;
+    let mut buf = String::new();
+    let mut flush = |buf: &mut String, start: usize| {
+        if !buf.trim().is_empty() {
+            out.push((start, buf.trim().to_string()));
+        }
+        buf.clear();
+    };
+    for (i, line) in text.lines().enumerate() {
+        let n = i + 1;
+        if line.trim().is_empty() && buf.len() >= CHUNK_CHARS / 2 {
+            flush(&mut buf, start);
+            continue;
+        }
+        if !buf.is_empty() && buf.len() + line.len() > CHUNK_CHARS {
+            flush(&mut buf, start);
+        }
+        if buf.trim().is_empty() {
+            buf.clear();
+            start = n;
+        }
+        // A single line longer than a chunk is cut on character boundaries.
+        let mut rest = line;
+        while rest.len() > CHUNK_CHARS {
+            let cut = (0..=CHUNK_CHARS)
+                .rev()
+                .find(|i| rest.is_char_boundary(*i))
+                .unwrap_or(0);
+            buf.push_str(&rest[..cut]);
+            flush(&mut buf, n);
+            rest = &rest[cut..];
+            start = n;
+        }
+        buf.push_str(rest);
+        buf.push('\n');
+    }
+    flush(&mut buf, start);
+    out
+}
+
+fn snippet(text: &str) -> String {
+    let flat = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match flat.char_indices().nth(SNIPPET_CHARS) {
+        Some((cut, _)) => format!("{}…", &flat[..cut]),
+        None => flat,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embed::normalize;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Bag-of-words over a few topics, counting how many texts it embedded.
+    struct Topics(AtomicUsize);
+
+    impl Embedder for Topics {
+        fn model_id(&self) -> &str {
+            "topics"
+        }
+
+        fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+            self.0.fetch_add(texts.len(), Ordering::SeqCst);
+            let topics = ["boat", "garden", "tax"];
+            Ok(texts
+                .iter()
+                .map(|t| {
+                    let t = t.to_lowercase();
+                    normalize(
+                        topics
+                            .iter()
+                            .map(|w| t.matches(w).count() as f32 + 0.01)
+                            .collect(),
+                    )
+                })
+                .collect())
+        }
+    }
+
+    #[test]
+    fn indexes_folders_incrementally_and_searches_them() {
+        let dir = std::env::temp_dir().join(format!("munin-knowledge-{}", std::process::id()));
+        let notes = dir.join("notes");
+        std::fs::create_dir_all(notes.join("trips")).unwrap();
+        std::fs::create_dir_all(notes.join(".git")).unwrap();
+        std::fs::write(
+            notes.join("garden.md"),
+            "# Garden\n\nPlant tomatoes in the garden in May.\n",
+        )
+        .unwrap();
+        std::fs::write(
+            notes.join("trips/sailing.txt"),
+            "Day one.\n\nThe boat left at dawn; boat trips need calm wind.\n",
+        )
+        .unwrap();
+        std::fs::write(notes.join("taxes.txt"), "Tax return due in April.\n").unwrap();
+        std::fs::write(notes.join(".git/config"), "boat boat boat").unwrap();
+        std::fs::write(notes.join("photo.jpg"), "boat").unwrap();
+
+        let embedder = Arc::new(Topics(AtomicUsize::new(0)));
+        let index = dir.join("state/index.json");
+        let k = Knowledge::open(index.clone(), vec![notes.clone()], Some(embedder.clone()));
+        let r = k.sync().unwrap();
+        assert_eq!((r.indexed, r.unchanged, r.removed), (3, 0, 0));
+        use std::os::unix::fs::PermissionsExt;
+        let mode = |p: &Path| std::fs::metadata(p).unwrap().permissions().mode() & 0o777;
+        assert_eq!((mode(&dir.join("state")), mode(&index)), (0o700, 0o600));
+
+        let hits = k.search("where is the boat?", 2).unwrap();
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].path, notes.join("trips/sailing.txt"));
+        assert_eq!(hits[0].line, 1);
+        assert!(
+            hits[0].snippet.contains("boat left at dawn"),
+            "{:?}",
+            hits[0]
+        );
+
+        // Only the changed file is embedded again; deleted files drop out.
+        let embedded = embedder.0.load(Ordering::SeqCst);
+        std::fs::write(notes.join("garden.md"), "Garden plans: more garden beds.\n").unwrap();
+        std::fs::remove_file(notes.join("taxes.txt")).unwrap();
+        let r = k.sync().unwrap();
+        assert_eq!((r.indexed, r.unchanged, r.removed), (1, 1, 1));
+        assert_eq!(embedder.0.load(Ordering::SeqCst), embedded + 1);
+
+        // The index persists across restarts.
+        let reopened = Knowledge::open(index, vec![notes.clone()], Some(embedder.clone()));
+        assert_eq!(reopened.status().files, 2);
+        let hits = reopened.search("garden", 1).unwrap();
+        assert_eq!(hits[0].path, notes.join("garden.md"));
+        assert_eq!(reopened.sync().unwrap().indexed, 0);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn chunks_break_at_paragraphs_and_keep_line_numbers() {
+        // Two 300-character paragraphs fill one chunk; the next blank line
+        // ends it.
+        let para = "word ".repeat(60);
+        let text = format!("{para}\n\n{para}\n\nshort tail\n");
+        let c = chunks(&text);
+        assert_eq!(c.len(), 2);
+        assert!(c[0].1.starts_with("word") && c[0].1.ends_with("word"));
+        assert_eq!(c[1], (5, "short tail".to_string()));
+        let long = "x".repeat(2500);
+        let c = chunks(&long);
+        assert_eq!(
+            c.iter().map(|(_, t)| t.len()).collect::<Vec<_>>(),
+            [1000, 1000, 500]
+        );
+    }
+}
@@ -0,0 +1,344 @@
+//! Per-locale phrase packs for `decide`: the phrases that pick each intent,
+//! the questions asked back, and how spoken numbers, dates and paths are
+//! written. The packs are built in from `locales/`. A locale tag picks its
+//! pack exactly, else one for the same language, else en-US.
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const BUILTIN_PACKS: &[&str] = &[
+    include_str!("../locales/en-US.json"),
+    include_str!("../locales/de-DE.json"),
+];
+pub const DEFAULT_LOCALE: &str = "en-US";
+
+#[derive(Debug, Deserialize)]
+pub struct PhrasePack {
+    pub locale: String,
+    /// System instruction asking a model to answer in the pack's language.
+    pub reply: String,
+    /// Question offering the likeliest intents: `{options}` is replaced by
+    /// their descriptions joined with `or`.
+    which: String,
+    or: String,
+    intents: BTreeMap<String, IntentPhrases>,
+    numbers: BTreeMap<String, u32>,
+    /// Joins a unit and a tens word into one ("fünfundzwanzig").
+    #[serde(default)]
+    compound: String,
+    /// Spoken words for path characters ("slash", "dot").
+    path_words: BTreeMap<String, String>,
+    dates: Dates,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IntentPhrases {
+    /// Phrases a request starts with, followed by its argument. A `{}`
+    /// marks the argument when the phrase wraps it ("führe {} aus").
+    pub commands: Vec<String>,
+    /// Words or phrases that suggest the intent anywhere.
+    pub hints: Vec<String>,
+    /// Completes the `which` question ("read a file").
+    pub describe: String,
+    /// Asked when the intent is clear but its argument is missing.
+    pub ask: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Dates {
+    order: DateOrder,
+    separator: char,
+    /// Day offsets from today ("yesterday": -1).
+    relative: BTreeMap<String, i64>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum DateOrder {
+    Mdy,
+    Dmy,
+    Ymd,
+}
+
+pub fn packs() -> &'static [PhrasePack] {
+    static PACKS: OnceLock<Vec<PhrasePack>> = OnceLock::new();
+    PACKS.get_or_init(|| {
+        BUILTIN_PACKS
+            .iter()
+            .map(|json| serde_json::from_str(json).expect("built-in phrase pack"))
+            .collect()
+    })
+}
+
+/// The pack for a locale tag such as "de-DE", "de_AT" or "de".
+pub fn pack(locale: &str) -> &'static PhrasePack {
+    let tag = locale.replace('_', "-");
+    let language = |tag: &str| tag.split('-').next().unwrap_or("").to_ascii_lowercase();
+    let packs = packs();
+    packs
+        .iter()
+        .find(|p| p.locale.eq_ignore_ascii_case(&tag))
+        .or_else(|| packs.iter().find(|p| language(&p.locale) == language(&tag)))
+        .or_else(|| packs.iter().find(|p| p.locale == DEFAULT_LOCALE))
+        .expect("built-in en-US phrase pack")
+}
+
+impl PhrasePack {
+    pub fn intent(&self, name: &str) -> Option<&IntentPhrases> {
+        self.intents.get(name)
+    }
+
+    /// "Do you want me to read a file or write a file?"
+    pub fn which(&self, options: &[&str]) -> String {
+        self.which
+            .replace("{options}", &options.join(self.or.as_str()))
+    }
+
+    /// Number words as digits: "chapter five" → "chapter 5", "twenty five" → 25.
+    pub fn numbers(&self, text: &str) -> String {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let mut out: Vec<String> = Vec::new();
+        let mut i = 0;
+        while i < words.len() {
+            match self.number(words[i]) {
+                Some(tens) if tens >= 20 && tens % 10 == 0 => {
+                    match words.get(i + 1).and_then(|w| self.number(w)) {
+                        Some(unit) if (1..10).contains(&unit) => {
+                            out.push((tens + unit).to_string());
+                            i += 1;
+                        }
+                        _ => out.push(tens.to_string()),
+                    }
+                }
+                Some(n) => out.push(n.to_string()),
+                None => out.push(words[i].to_string()),
+            }
+            i += 1;
+        }
+        out.join(" ")
+    }
+
+    fn number(&self, word: &str) -> Option<u32> {
+        let word = word.to_lowercase();
+        if let Some(&n) = self.numbers.get(&word) {
+            return Some(n);
+        }
+        let (unit, tens) = word
+            .split_once(self.compound.as_str())
+            .filter(|_| !self.compound.is_empty())?;
+        let (unit, tens) = (*self.numbers.get(unit)?, *self.numbers.get(tens)?);
+        ((1..10).contains(&unit) && tens >= 20 && tens % 10 == 0).then_some(tens + unit)
+    }
+
+    /// Spoken path characters written out: "slash etc slash hostname" →
+    /// "/etc/hostname", "notes dot txt" → "notes.txt".
+    pub fn path(&self, text: &str) -> String {
+        let mut out = String::new();
+        let mut glue = true;
+        for word in phrases(text, &self.path_words) {
+            match word {
+                Word::Phrase(symbol) => {
+                    out.push_str(symbol);
+                    glue = true;
+                }
+                Word::Text(w) => {
+                    if !glue {
+                        out.push(' ');
+                    }
+                    out.push_str(w);
+                    glue = false;
+                }
+            }
+        }
+        out
+    }
+
+    /// Dates as ISO 8601: relative words ("yesterday") from `today`, and
+    /// numeric dates in the locale's order ("10/18/2026", "18.10.2026").
+    pub fn dates(&self, text: &str, today: Date) -> String {
+        let words: Vec<String> = phrases(text, &self.dates.relative)
+            .into_iter()
+            .map(|word| match word {
+                Word::Phrase(offset) => today.add_days(*offset).to_string(),
+                Word::Text(w) => self
+                    .numeric_date(w)
+                    .map_or(w.to_string(), |d| d.to_string()),
+            })
+            .collect();
+        words.join(" ")
+    }
+
+    fn numeric_date(&self, word: &str) -> Option<Date> {
+        let word = word.trim_end_matches([',', '?', '!']);
+        let parts: Vec<u32> = word
+            .trim_end_matches('.')
+            .split(self.dates.separator)
+            .map(|p| p.parse().ok())
+            .collect::<Option<_>>()?;
+        let [a, b, c] = parts[..] else {
+            return None;
+        };
+        let (year, month, day) = match self.dates.order {
+            DateOrder::Mdy => (c, a, b),
+            DateOrder::Dmy => (c, b, a),
+            DateOrder::Ymd => (a, b, c),
+        };
+        let year = if year < 100 { year + 2000 } else { year } as i32;
+        let date = Date { year, month, day };
+        // Rejects out-of-range months and days, e.g. 31 April.
+        ((1..=12).contains(&month) && Date::from_days(date.days()) == date).then_some(date)
+    }
+}
+
+enum Word<'a, V> {
+    Text(&'a str),
+    Phrase(&'a V),
+}
+
+/// The words of `text`, with the longest phrase of `table` starting at each
+/// word replaced by its value. Phrases match case-insensitively and ignore
+/// trailing punctuation.
+fn phrases<'a, V>(text: &'a str, table: &'a BTreeMap<String, V>) -> Vec<Word<'a, V>> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let plain: Vec<String> = words
+        .iter()
+        .map(|w| w.trim_end_matches([',', '.', '?', '!']).to_lowercase())
+        .collect();
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < words.len() {
+        let longest = table
+            .iter()
+            .filter_map(|(phrase, value)| {
+                let n = phrase.split_whitespace().count();
+                let matches = plain
+                    .get(i..i + n)
+                    .is_some_and(|ws| ws.iter().map(String::as_str).eq(phrase.split_whitespace()));
+                matches.then_some((n, value))
+            })
+            .max_by_key(|(n, _)| *n);
+        match longest {
+            Some((n, value)) => {
+                out.push(Word::Phrase(value));
+                i += n;
+            }
+            None => {
+                out.push(Word::Text(words[i]));
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+/// A calendar date (proleptic Gregorian).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Date {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+}
+
+impl Date {
+    /// Today in UTC.
+    pub fn today() -> Self {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        Self::from_days((secs / 86_400) as i64)
+    }
+
+    pub fn add_days(self, days: i64) -> Self {
+        Self::from_days(self.days() + days)
+    }
+
+    /// Days since 1970-01-01.
+    fn days(self) -> i64 {
+        let (m, d) = (self.month as i64, self.day as i64);
+        let y = self.year as i64 - i64::from(m <= 2);
+        let era = y.div_euclid(400);
+        let yoe = y - era * 400;
+        let doy = (153 * (m + if m > 2 { -3 } else { 9 }) + 2) / 5 + d - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        era * 146_097 + doe - 719_468
+    }
+
+    fn from_days(days: i64) -> Self {
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z - era * 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = (yoe + era * 400 + i64::from(month <= 2)) as i32;
+        Self { year, month, day }
+    }
+}
+
+impl std::fmt::Display for Date {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packs_resolve_by_tag_then_language() {
+        assert_eq!(pack("de-DE").locale, "de-DE");
+        assert_eq!(pack("de_AT").locale, "de-DE");
+        assert_eq!(pack("EN-gb").locale, "en-US");
+        assert_eq!(pack("fr-FR").locale, "en-US");
+        // Every pack phrases every intent in the en-US pack.
+        for p in packs() {
+            for name in pack(DEFAULT_LOCALE).intents.keys() {
+                assert!(p.intent(name).is_some(), "{} lacks {name}", p.locale);
+            }
+        }
+    }
+
+    #[test]
+    fn normalises_numbers_paths_and_dates_per_locale() {
+        let today = Date {
+            year: 2026,
+            month: 3,
+            day: 1,
+        };
+        let en = pack("en-US");
+        assert_eq!(en.numbers("chapter twenty five"), "chapter 25");
+        assert_eq!(en.numbers("page ten notes"), "page 10 notes");
+        assert_eq!(en.path("slash etc slash hostname"), "/etc/hostname");
+        assert_eq!(
+            en.path("slash home slash ada slash my notes dot txt"),
+            "/home/ada/my notes.txt"
+        );
+        assert_eq!(en.path("/etc/Hostname"), "/etc/Hostname");
+        assert_eq!(
+            en.dates("notes from yesterday and 12/24/2025", today),
+            "notes from 2026-02-28 and 2025-12-24"
+        );
+        assert_eq!(en.dates("due 24/12/2025", today), "due 24/12/2025");
+
+        let de = pack("de-DE");
+        assert_eq!(de.numbers("Kapitel fünfundzwanzig"), "Kapitel 25");
+        assert_eq!(
+            de.path("schrägstrich etc schrägstrich hostname"),
+            "/etc/hostname"
+        );
+        assert_eq!(
+            de.dates("Notizen von heute morgen und vom 24.12.2025.", today),
+            "Notizen von 2026-03-01 und vom 2025-12-24"
+        );
+        assert_eq!(de.dates("übermorgen", today), "2026-03-03");
+        assert_eq!(
+            de.which(&["eine Datei lesen", "eine Datei schreiben"]),
+            "Soll ich eine Datei lesen oder eine Datei schreiben?"
+        );
+    }
+}
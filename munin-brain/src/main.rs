@@ -1,9 +1,29 @@
-use anyhow::Result;
+mod adapt;
+mod bench;
+mod decide;
+mod embed;
+mod gguf;
+mod host;
+mod knowledge;
+mod locale;
+mod models;
+mod template;
+
+use adapt::{Sensors, Thresholds};
+use anyhow::{anyhow, Result};
+use bench::{BenchCache, BenchConfig};
 use clap::{Parser, Subcommand};
+use decide::decide;
+use embed::{Embedder, LlamaEmbedder};
+use host::{ModelHost, Selection};
+use knowledge::Knowledge;
+use models::{Catalogue, Check};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::io::Read;
+use std::path::PathBuf;
+use std::sync::Arc;
 use sysinfo::System;
+use template::{ChatFormat, Message, Role, Tool};
 use tiny_http::{Header, Method, Response, Server, StatusCode};
 
 #[derive(Parser, Debug)]
@@ -17,19 +37,120 @@ struct Args {
 
     #[arg(long, default_value = "hey munin")]
     wake_phrase: String,
+
+    /// Model manifest (default: /opt/muninos/models/manifest.json, else the built-in one)
+    #[arg(long, global = true)]
+    manifest: Option<PathBuf>,
+
+    /// Directory of custom presets (`<name>.toml`) added to the manifest's
+    #[arg(long, global = true, default_value = models::CUSTOM_DIR)]
+    custom_presets: PathBuf,
+
+    /// Results of `munin-brain bench`, used to pick the tier when present
+    #[arg(long, global = true, default_value = bench::DEFAULT_CACHE)]
+    bench_cache: PathBuf,
+
+    /// Pick the largest benchmarked tier whose first token comes within this
+    #[arg(long, global = true, default_value_t = 800)]
+    ttft_target_ms: u64,
+
+    /// llama.cpp's embedding tool, run on the manifest's embedding preset
+    #[arg(long, global = true, default_value = "llama-embedding")]
+    llama_embedding: PathBuf,
+
+    /// Where the knowledge index is kept
+    #[arg(long, global = true, default_value = knowledge::DEFAULT_INDEX)]
+    index_file: PathBuf,
+
+    /// Folder of text files to index for `knowledge.search` (repeatable)
+    #[arg(long = "knowledge-dir", global = true, value_name = "DIR")]
+    knowledge_dirs: Vec<PathBuf>,
 }
 
 #[derive(Subcommand, Debug)]
 enum Commands {
     Profile,
-    Decide { transcript: String },
+    Decide {
+        transcript: String,
+    },
     Serve {
         #[arg(long, default_value = "127.0.0.1:8790")]
         listen: String,
+        /// Where the model selection made through the API is kept
+        #[arg(long, default_value = "/var/lib/muninos/brain/state.json")]
+        state_file: PathBuf,
+        /// Seconds between memory/thermal/battery readings for tier adaptation
+        #[arg(long, default_value_t = 5)]
+        adapt_interval: u64,
+        /// Keep serving the selected tier regardless of pressure
+        #[arg(long)]
+        no_adapt: bool,
+        /// Seconds between scans of the knowledge folders
+        #[arg(long, default_value_t = 60)]
+        index_interval: u64,
+    },
+    /// Measure each downloaded preset with llama-bench and cache the results
+    Bench {
+        /// Presets to measure (default: every downloaded one)
+        presets: Vec<String>,
+        #[arg(long, default_value = "llama-bench")]
+        llama_bench: PathBuf,
+        /// Prompt size the time to first token is computed for
+        #[arg(long, default_value_t = 256)]
+        prompt_tokens: u32,
+        #[arg(long, default_value_t = 64)]
+        gen_tokens: u32,
+        /// Measure again even if the cached result matches the model file
+        #[arg(long)]
+        force: bool,
+    },
+    /// Build or query the knowledge index over `--knowledge-dir` folders
+    Knowledge {
+        #[command(subcommand)]
+        command: KnowledgeCommands,
+    },
+    /// Manage the model files listed in the manifest
+    Models {
+        /// Directory holding the model files (default: the manifest's baseDir)
+        #[arg(long)]
+        dir: Option<PathBuf>,
+        #[command(subcommand)]
+        command: ModelCommands,
     },
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Subcommand, Debug)]
+enum ModelCommands {
+    /// Show each preset and whether its file is present
+    List,
+    /// Download a preset (default: the one for this machine's tier)
+    Pull {
+        preset: Option<String>,
+        /// Fetch `<mirror>/<file>` instead of the manifest URL (http(s):// or file://)
+        #[arg(long)]
+        mirror: Option<String>,
+    },
+    /// Check present model files against the manifest's SHA-256
+    Verify { preset: Option<String> },
+    /// Delete a preset's file and any partial download
+    Remove { preset: String },
+}
+
+#[derive(Subcommand, Debug)]
+enum KnowledgeCommands {
+    /// Index new and changed files and drop removed ones
+    Sync,
+    /// Show the closest indexed passages
+    Search {
+        query: String,
+        #[arg(long, default_value_t = 5)]
+        limit: usize,
+    },
+    /// Show what is indexed
+    Status,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 enum ModelTier {
     Tier0Tiny,
     Tier1Mobile,
@@ -39,11 +160,16 @@ enum ModelTier {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct ModelPreset {
+    /// Catalogue key: a tier name, or a custom preset's name.
+    preset: String,
     tier: ModelTier,
     model_id: String,
     model_path: String,
     quant: String,
     context: usize,
+    /// Prompt template and tool-call convention (`template`, `tool_calls`).
+    #[serde(flatten)]
+    chat: ChatFormat,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -53,6 +179,9 @@ struct RuntimeProfile {
     ram_gb: u64,
     gpu_hint: bool,
     tier: ModelTier,
+    /// Why `tier` was picked: hardware thresholds or benchmark results.
+    #[serde(default)]
+    tier_reason: String,
     backend: String,
     selected_model: ModelPreset,
     resolved_tier: ModelTier,
@@ -60,53 +189,12 @@ struct RuntimeProfile {
     warning: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct Decision {
-    intent: String,
-    tool: Option<String>,
-    args: serde_json::Value,
-    requires_confirmation: bool,
-}
-
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct DecideIn {
     transcript: String,
     locale: Option<String>,
 }
 
-fn preset_for_tier(tier: &ModelTier) -> ModelPreset {
-    match tier {
-        ModelTier::Tier0Tiny => ModelPreset {
-            tier: ModelTier::Tier0Tiny,
-            model_id: "TinyLlama-1.1B-Chat-v1.0-GGUF".into(),
-            model_path: "/opt/muninos/models/tinyllama-1.1b-chat-v1.0.Q4_K_M.gguf".into(),
-            quant: "Q4_K_M".into(),
-            context: 2048,
-        },
-        ModelTier::Tier1Mobile => ModelPreset {
-            tier: ModelTier::Tier1Mobile,
-            model_id: "Qwen2.5-3B-Instruct-GGUF".into(),
-            model_path: "/opt/muninos/models/qwen2.5-3b-instruct-q4_k_m.gguf".into(),
-            quant: "Q4_K_M".into(),
-            context: 4096,
-        },
-        ModelTier::Tier2Balanced => ModelPreset {
-            tier: ModelTier::Tier2Balanced,
-            model_id: "Mistral-7B-Instruct-v0.2-GGUF".into(),
-            model_path: "/opt/muninos/models/mistral-7b-instruct-v0.2.Q4_K_M.gguf".into(),
-            quant: "Q4_K_M".into(),
-            context: 8192,
-        },
-        ModelTier::Tier3Performance => ModelPreset {
-            tier: ModelTier::Tier3Performance,
-            model_id: "Llama-2-13B-Chat-GGUF".into(),
-            model_path: "/opt/muninos/models/llama-2-13b-chat.Q5_K_M.gguf".into(),
-            quant: "Q5_K_M".into(),
-            context: 8192,
-        },
-    }
-}
-
 fn tier_rank(t: &ModelTier) -> u8 {
     match t {
         ModelTier::Tier0Tiny => 0,
@@ -125,38 +213,54 @@ fn tier_from_rank(r: u8) -> ModelTier {
     }
 }
 
-fn resolve_model_with_fallback(target_tier: &ModelTier) -> (ModelPreset, ModelTier, bool, Option<String>) {
-    let mut r = tier_rank(target_tier);
-    loop {
+/// The first usable preset at `target_tier`, else at each smaller tier in
+/// turn. Custom presets come before the manifest's at each rank, and presets
+/// needing more than `ram_gb` are passed over.
+fn resolve_model_with_fallback(
+    catalogue: &Catalogue,
+    target_tier: &ModelTier,
+    ram_gb: u64,
+) -> (ModelPreset, ModelTier, bool, Option<String>) {
+    for r in (0..=tier_rank(target_tier)).rev() {
         let tier = tier_from_rank(r);
-        let preset = preset_for_tier(&tier);
-        if std::path::Path::new(&preset.model_path).exists() {
+        let usable = catalogue
+            .candidates(r, ram_gb)
+            .into_iter()
+            .find(|name| catalogue.check_file(name).is_ok());
+        if let Some(name) = usable {
             let warning = if r != tier_rank(target_tier) {
-                Some(format!("requested {:?} unavailable; fell back to {:?}", target_tier, tier))
+                Some(format!(
+                    "requested {:?} unavailable; fell back to {:?}",
+                    target_tier, tier
+                ))
             } else {
                 None
             };
-            return (preset, tier, true, warning);
+            return (catalogue.preset(name), tier, true, warning);
         }
-        if r == 0 {
-            let mut p = preset_for_tier(&tier);
-            let w = Some(format!("no model file found for any tier under /opt/muninos/models; expected one of preset paths"));
-            return (p, tier, false, w);
-        }
-        r -= 1;
     }
+    let tier = ModelTier::Tier0Tiny;
+    let p = catalogue.preset(&models::tier_key(&tier));
+    let w = Some(format!(
+        "no model file found for any tier under {}; expected one of preset paths",
+        catalogue.base_dir.display()
+    ));
+    (p, tier, false, w)
 }
 
-fn detect_profile() -> RuntimeProfile {
+/// Hardware profile and tier. A benchmarked tier (see [`BenchCache::pick_tier`])
+/// takes precedence over the RAM/CPU thresholds.
+fn detect_profile(catalogue: &Catalogue, measured: Option<(ModelTier, String)>) -> RuntimeProfile {
     let mut sys = System::new_all();
     sys.refresh_all();
 
-    let ram_gb = (sys.total_memory() / 1024 / 1024) as u64;
+    // Bytes, rounded to the nearest GB.
+    let ram_gb = (sys.total_memory() + (1 << 29)) >> 30;
     let cpus = num_cpus::get();
     let arch = std::env::consts::ARCH.to_string();
     let gpu_hint = std::env::var("MUNIN_GPU").ok().as_deref() == Some("1");
 
-    let tier = if ram_gb <= 4 || cpus <= 2 {
+    let by_hardware = if ram_gb <= 4 || cpus <= 2 {
         ModelTier::Tier0Tiny
     } else if ram_gb <= 8 || cpus <= 4 {
         ModelTier::Tier1Mobile
@@ -165,8 +269,13 @@ fn detect_profile() -> RuntimeProfile {
     } else {
         ModelTier::Tier3Performance
     };
+    let (tier, tier_reason) = measured.unwrap_or_else(|| {
+        let reason = format!("hardware: {ram_gb} GB RAM, {cpus} CPUs, gpu_hint={gpu_hint}");
+        (by_hardware, reason)
+    });
 
-    let (selected_model, resolved_tier, model_available, warning) = resolve_model_with_fallback(&tier);
+    let (selected_model, resolved_tier, model_available, warning) =
+        resolve_model_with_fallback(catalogue, &tier, ram_gb);
 
     RuntimeProfile {
         arch,
@@ -175,108 +284,224 @@ fn detect_profile() -> RuntimeProfile {
         gpu_hint,
         selected_model,
         tier,
+        tier_reason,
         resolved_tier,
         model_available,
         warning,
-        backend: "llama.cpp".into(),
+        backend: catalogue.backend.clone(),
     }
 }
 
-fn decide(transcript: &str) -> Decision {
-    let low = transcript.to_lowercase();
+#[derive(Debug, Clone, Deserialize)]
+struct PromptIn {
+    messages: Vec<Message>,
+    #[serde(default)]
+    tools: Vec<Tool>,
+    /// Asks the model to answer in this locale's language.
+    locale: Option<String>,
+}
 
-    if low.contains("status") {
-        return Decision {
-            intent: "system_status".into(),
-            tool: Some("system.status".into()),
-            args: json!({}),
-            requires_confirmation: false,
-        };
-    }
+/// `input` is one string or a list of them.
+#[derive(Debug, Clone, Deserialize)]
+struct EmbeddingsIn {
+    input: serde_json::Value,
+}
 
-    if let Some(path) = low.strip_prefix("read ") {
-        return Decision {
-            intent: "read_file".into(),
-            tool: Some("file.read".into()),
-            args: json!({"path": path.trim()}),
-            requires_confirmation: false,
-        };
-    }
+#[derive(Debug, Clone, Deserialize)]
+struct SearchIn {
+    query: String,
+    limit: Option<usize>,
+}
 
-    if let Some(rest) = transcript.strip_prefix("write ") {
-        if let Some((path, content)) = rest.split_once("::") {
-            return Decision {
-                intent: "write_file".into(),
-                tool: Some("file.write".into()),
-                args: json!({"path": path.trim(), "content": content.trim()}),
-                requires_confirmation: true,
-            };
-        }
-    }
+#[derive(Debug, Clone, Deserialize)]
+struct ToolCallIn {
+    output: String,
+}
 
-    if let Some(cmd) = transcript.strip_prefix("exec ") {
-        return Decision {
-            intent: "system_exec".into(),
-            tool: Some("system.exec".into()),
-            args: json!({"command": cmd.trim()}),
-            requires_confirmation: true,
-        };
-    }
+#[derive(Debug, Clone, Deserialize)]
+struct LoadIn {
+    /// A tier name, or "auto" to follow the hardware profile again.
+    tier: Option<String>,
+    preset: Option<String>,
+}
 
-    if let Some(url) = transcript.strip_prefix("get ") {
-        return Decision {
-            intent: "network_get".into(),
-            tool: Some("network.get".into()),
-            args: json!({"url": url.trim()}),
-            requires_confirmation: false,
-        };
-    }
+fn serve_http(
+    listen: &str,
+    host: Arc<ModelHost>,
+    knowledge: Arc<Knowledge>,
+    locale: String,
+) -> Result<()> {
+    let server = Server::http(listen).map_err(|e| anyhow!(e))?;
+    tracing::info!("munin-brain api listening on http://{}", listen);
 
-    Decision {
-        intent: "chat".into(),
-        tool: None,
-        args: json!({"text": transcript}),
-        requires_confirmation: false,
+    // One thread per request, so a model switch can wait for the requests
+    // in flight while new ones queue behind it.
+    for req in server.incoming_requests() {
+        let (host, knowledge, locale) = (host.clone(), knowledge.clone(), locale.clone());
+        std::thread::spawn(move || handle(req, &host, &knowledge, &locale));
     }
-}
 
-fn serve_http(listen: &str) -> Result<()> {
-    let server = Server::http(listen)?;
-    tracing::info!("munin-brain api listening on http://{}", listen);
+    Ok(())
+}
 
-    for mut req in server.incoming_requests() {
-        let path = req.url().to_string();
-        let method = req.method().clone();
+/// `locale` applies to requests that don't name one.
+fn handle(mut req: tiny_http::Request, host: &ModelHost, knowledge: &Knowledge, locale: &str) {
+    let url = req.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    let method = req.method().clone();
+    let mut body = String::new();
+    if method == Method::Post {
+        let _ = req.as_reader().read_to_string(&mut body);
+    }
 
-        let mut response = match (method, path.as_str()) {
-            (Method::Get, "/health") => Response::from_string(
-                json!({"ok": true, "profile": detect_profile(), "mode": "local-only"}).to_string(),
-            )
-            .with_status_code(StatusCode(200)),
-            (Method::Post, "/v1/decide") => {
-                let mut body = String::new();
-                let _ = req.as_reader().read_to_string(&mut body);
-                match serde_json::from_str::<DecideIn>(&body) {
-                    Ok(input) => {
-                        let decision = decide(&input.transcript);
-                        Response::from_string(json!({"decision": decision}).to_string())
-                            .with_status_code(StatusCode(200))
+    // Indexed documents and what is embedded stay on this machine, whatever
+    // address the API listens on.
+    let local = req.remote_addr().is_some_and(|a| a.ip().is_loopback());
+    let (status, out) = match (method, path) {
+        (_, "/v1/embeddings" | "/v1/knowledge/search") if !local => {
+            (403, json!({"error": "only answered on loopback"}))
+        }
+        (Method::Get, "/health") => (
+            200,
+            json!({"ok": true, "profile": host.active().profile, "mode": "local-only"}),
+        ),
+        (Method::Post, "/v1/decide") => match serde_json::from_str::<DecideIn>(&body) {
+            Ok(input) => {
+                let _model = host.use_model();
+                (
+                    200,
+                    json!({"decision": decide(&input.transcript, input.locale.as_deref().unwrap_or(locale))}),
+                )
+            }
+            Err(e) => (400, json!({"error": e.to_string()})),
+        },
+        (Method::Post, "/v1/chat/prompt") => match serde_json::from_str::<PromptIn>(&body) {
+            Ok(input) => {
+                let model = host.use_model();
+                let preset = &model.profile.selected_model;
+                if model.loaded {
+                    let mut messages = input.messages;
+                    if let Some(locale) = &input.locale {
+                        messages.insert(
+                            0,
+                            Message {
+                                role: Role::System,
+                                content: locale::pack(locale).reply.clone(),
+                                name: None,
+                            },
+                        );
                     }
-                    Err(e) => Response::from_string(json!({"error": e.to_string()}).to_string())
-                        .with_status_code(StatusCode(400)),
+                    let prompt = preset.chat.render(&messages, &input.tools);
+                    (
+                        200,
+                        json!({
+                            "model_id": preset.model_id,
+                            "chat": preset.chat,
+                            "prompt": prompt.prompt,
+                            "stop": prompt.stop,
+                        }),
+                    )
+                } else {
+                    (503, json!({"error": "no model loaded"}))
                 }
             }
-            _ => Response::from_string(json!({"error": "not_found"}).to_string())
-                .with_status_code(StatusCode(404)),
-        };
-
-        if let Ok(h) = Header::from_bytes("Content-Type", "application/json") {
-            response = response.with_header(h);
+            Err(e) => (400, json!({"error": e.to_string()})),
+        },
+        (Method::Post, "/v1/chat/tool_call") => match serde_json::from_str::<ToolCallIn>(&body) {
+            Ok(input) => {
+                let model = host.use_model();
+                let chat = model.profile.selected_model.chat;
+                (200, json!({"call": chat.parse_tool_call(&input.output)}))
+            }
+            Err(e) => (400, json!({"error": e.to_string()})),
+        },
+        (Method::Post, "/v1/embeddings") => match serde_json::from_str::<EmbeddingsIn>(&body) {
+            Ok(input) => match embeddings(knowledge, input.input) {
+                Ok(out) => (200, out),
+                Err(e) => (503, json!({"error": format!("{e:#}")})),
+            },
+            Err(e) => (400, json!({"error": e.to_string()})),
+        },
+        (Method::Post, "/v1/knowledge/search") => match serde_json::from_str::<SearchIn>(&body) {
+            Ok(input) => {
+                let limit = input.limit.unwrap_or(5).clamp(1, 50);
+                match knowledge.search(&input.query, limit) {
+                    Ok(results) => (200, json!({"query": input.query, "results": results})),
+                    Err(e) => (503, json!({"error": format!("{e:#}")})),
+                }
+            }
+            Err(e) => (400, json!({"error": e.to_string()})),
+        },
+        (Method::Get, "/v1/knowledge") => (200, json!(knowledge.status())),
+        (Method::Get, "/v1/models") => (
+            200,
+            json!({
+                "active": host.active(),
+                "in_flight": host.in_flight(),
+                "conditions": host.conditions(),
+                "presets": host.catalogue().list(),
+            }),
+        ),
+        (Method::Post, "/v1/models/load") => {
+            let selection = serde_json::from_str::<LoadIn>(&body)
+                .map_err(anyhow::Error::from)
+                .and_then(selection_for);
+            match selection.and_then(|s| host.load(s)) {
+                Ok(active) => (200, json!({"active": active})),
+                Err(e) => (400, json!({"error": format!("{e:#}")})),
+            }
         }
-        let _ = req.respond(response);
+        (Method::Post, "/v1/models/unload") => match host.load(Selection::Unloaded) {
+            Ok(active) => (200, json!({"active": active})),
+            Err(e) => (500, json!({"error": format!("{e:#}")})),
+        },
+        (Method::Get, "/v1/events") => {
+            let since = query
+                .split('&')
+                .find_map(|kv| kv.strip_prefix("since="))
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0);
+            (200, json!({"events": host.events_since(since)}))
+        }
+        _ => (404, json!({"error": "not_found"})),
+    };
+
+    let mut response = Response::from_string(out.to_string()).with_status_code(StatusCode(status));
+    if let Ok(h) = Header::from_bytes("Content-Type", "application/json") {
+        response = response.with_header(h);
     }
+    let _ = req.respond(response);
+}
 
-    Ok(())
+/// An OpenAI-style embeddings response for a string or list of strings.
+fn embeddings(knowledge: &Knowledge, input: serde_json::Value) -> Result<serde_json::Value> {
+    let texts: Vec<String> = match input {
+        serde_json::Value::String(s) => vec![s],
+        other => serde_json::from_value(other)
+            .map_err(|_| anyhow!("input must be a string or a list of strings"))?,
+    };
+    let embedder = knowledge.embedder()?;
+    let data: Vec<serde_json::Value> = embedder
+        .embed(&texts)?
+        .into_iter()
+        .enumerate()
+        .map(|(index, embedding)| json!({"object": "embedding", "index": index, "embedding": embedding}))
+        .collect();
+    Ok(json!({"object": "list", "model": embedder.model_id(), "data": data}))
+}
+
+fn selection_for(input: LoadIn) -> Result<Selection> {
+    match (input.tier.as_deref(), input.preset) {
+        (Some(_), Some(_)) => Err(anyhow!("give either tier or preset, not both")),
+        (Some("auto"), None) => Ok(Selection::Auto),
+        (Some(tier), None) => {
+            let tier =
+                serde_json::from_value(json!(tier)).map_err(|_| anyhow!("unknown tier {tier}"))?;
+            Ok(Selection::Tier { tier })
+        }
+        (None, Some(preset)) => Ok(Selection::Preset { preset }),
+        (None, None) => Err(anyhow!("give a tier (or \"auto\") or a preset")),
+    }
 }
 
 #[tokio::main]
@@ -284,21 +509,194 @@ async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
     let args = Args::parse();
 
+    let mut catalogue = Catalogue::load(args.manifest.as_deref())?;
+    for warning in catalogue.add_custom(&args.custom_presets) {
+        tracing::warn!("{warning}");
+    }
+    let measured = BenchCache::load(&args.bench_cache).pick_tier(&catalogue, args.ttft_target_ms);
+    let embedder = LlamaEmbedder::from_catalogue(&catalogue, args.llama_embedding.clone())
+        .map(|e| Arc::new(e) as Arc<dyn Embedder>);
+    let knowledge = Arc::new(Knowledge::open(
+        args.index_file.clone(),
+        args.knowledge_dirs.clone(),
+        embedder,
+    ));
+
     match args.command {
-        Commands::Profile => println!("{}", serde_json::to_string_pretty(&detect_profile())?),
-        Commands::Decide { transcript } => println!("{}", serde_json::to_string_pretty(&decide(&transcript))?),
-        Commands::Serve { listen } => {
-            let profile = detect_profile();
+        Commands::Profile => println!(
+            "{}",
+            serde_json::to_string_pretty(&detect_profile(&catalogue, measured))?
+        ),
+        Commands::Decide { transcript } => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&decide(&transcript, &args.locale))?
+            )
+        }
+        Commands::Serve {
+            listen,
+            state_file,
+            adapt_interval,
+            no_adapt,
+            index_interval,
+        } => {
+            let detected = detect_profile(&catalogue, measured);
+            let host = ModelHost::new(catalogue, detected, state_file);
+            let active = host.active();
+            let profile = &active.profile;
             if !profile.model_available {
-                tracing::warn!("No local model available. Install one with: TIER=Tier1Mobile make models");
+                tracing::warn!(
+                    "No local model available. Install one with: munin-brain models pull"
+                );
             }
             if let Some(w) = &profile.warning {
                 tracing::warn!("{}", w);
             }
             tracing::info!("profile={:?}", profile);
-            serve_http(&listen)?;
+            let host = Arc::new(host);
+            if !no_adapt {
+                let host = host.clone();
+                let interval = std::time::Duration::from_secs(adapt_interval.max(1));
+                std::thread::spawn(move || {
+                    adapt::watch(host, Sensors::default(), Thresholds::default(), interval)
+                });
+            }
+            if !args.knowledge_dirs.is_empty() {
+                let knowledge = knowledge.clone();
+                let interval = std::time::Duration::from_secs(index_interval.max(1));
+                std::thread::spawn(move || knowledge::watch(knowledge, interval));
+            }
+            serve_http(&listen, host, knowledge, args.locale.clone())?;
+        }
+        Commands::Knowledge { command } => {
+            let out = match command {
+                KnowledgeCommands::Sync => json!(knowledge.sync()?),
+                KnowledgeCommands::Search { query, limit } => {
+                    json!(knowledge.search(&query, limit)?)
+                }
+                KnowledgeCommands::Status => json!(knowledge.status()),
+            };
+            println!("{}", serde_json::to_string_pretty(&out)?);
+        }
+        Commands::Models { dir, command } => {
+            if let Some(dir) = dir {
+                catalogue.base_dir = dir;
+            }
+            run_models(&catalogue, command, measured).await?;
+        }
+        Commands::Bench {
+            presets,
+            llama_bench,
+            prompt_tokens,
+            gen_tokens,
+            force,
+        } => {
+            let config = BenchConfig {
+                llama_bench,
+                prompt_tokens,
+                gen_tokens,
+            };
+            let presets = match presets {
+                p if !p.is_empty() => p,
+                _ => catalogue
+                    .list()
+                    .into_iter()
+                    .filter(|m| m.present && m.rank.is_some())
+                    .map(|m| m.tier)
+                    .collect(),
+            };
+            if presets.is_empty() {
+                return Err(anyhow!(
+                    "no presets downloaded; run munin-brain models pull first"
+                ));
+            }
+            let mut cache = BenchCache::load(&args.bench_cache);
+            for preset in presets {
+                if !force && cache.fresh(&catalogue, &preset).is_some() {
+                    eprintln!("[bench] {preset}: cached");
+                    continue;
+                }
+                eprintln!("[bench] {preset}: running llama-bench");
+                let result = bench::run(&catalogue, &preset, &config)?;
+                eprintln!(
+                    "[bench] {preset}: {:.1} t/s prompt, {:.1} t/s generation, first token {} ms",
+                    result.prompt_tokens_per_sec, result.gen_tokens_per_sec, result.ttft_ms
+                );
+                cache.results.insert(preset, result);
+                cache.save(&args.bench_cache)?;
+            }
+            let pick = cache.pick_tier(&catalogue, args.ttft_target_ms);
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&json!({
+                    "results": cache.results,
+                    "ttft_target_ms": args.ttft_target_ms,
+                    "tier": pick.as_ref().map(|p| &p.0),
+                    "reason": pick.as_ref().map(|p| &p.1),
+                }))?
+            );
         }
     }
 
     Ok(())
 }
+
+async fn run_models(
+    catalogue: &Catalogue,
+    command: ModelCommands,
+    measured: Option<(ModelTier, String)>,
+) -> Result<()> {
+    match command {
+        ModelCommands::List => {
+            println!("{}", serde_json::to_string_pretty(&catalogue.list())?)
+        }
+        ModelCommands::Pull { preset, mirror } => {
+            let preset = match preset {
+                Some(p) => p,
+                None => models::tier_key(&detect_profile(catalogue, measured).tier),
+            };
+            let entry = catalogue.entry(&preset)?;
+            eprintln!("[models] pulling {preset}: {}", entry.model_id);
+            let mut last = None;
+            let path = catalogue
+                .pull(&preset, mirror.as_deref(), &mut |done, total| {
+                    let line = match total {
+                        Some(t) if t > 0 => {
+                            format!("{} / {} MiB ({}%)", done >> 20, t >> 20, done * 100 / t)
+                        }
+                        _ => format!("{} MiB", done >> 20),
+                    };
+                    if last.as_ref() != Some(&line) {
+                        eprint!("\r[models] {line}   ");
+                        last = Some(line);
+                    }
+                })
+                .await;
+            eprintln!();
+            println!("{}", path?.display());
+        }
+        ModelCommands::Verify { preset } => {
+            let presets = match preset {
+                Some(p) => vec![p],
+                None => catalogue.presets.keys().cloned().collect(),
+            };
+            let mut failed = false;
+            let mut out = Vec::new();
+            for p in presets {
+                let check = catalogue.verify(&p)?;
+                failed |= matches!(check, Check::Mismatch { .. });
+                out.push(json!({"preset": p, "check": check}));
+            }
+            println!("{}", serde_json::to_string_pretty(&out)?);
+            if failed {
+                return Err(anyhow!("model verification failed"));
+            }
+        }
+        ModelCommands::Remove { preset } => {
+            for path in catalogue.remove(&preset)? {
+                println!("removed {}", path.display());
+            }
+        }
+    }
+    Ok(())
+}
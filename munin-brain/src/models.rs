@@ -0,0 +1,723 @@
+//! Model catalogue: `models/manifest.json` describes one preset per tier
+//! (file, download URL, quantisation, context, chat template, SHA-256 and
+//! optionally size), and this module downloads, verifies and removes those
+//! files. Presets without a SHA-256 are never downloaded.
+//!
+//! Downloads go to `<file>.part` first and resume from it when interrupted.
+//! A `file://` mirror serves the same file names as the manifest URLs, which
+//! is how offline builds and tests pull models.
+//!
+//! Custom presets in `/etc/muninos/models.d/*.toml` point at a local GGUF
+//! and name the tier rank they slot into. Tier resolution tries them before
+//! the manifest's preset at each rank; files whose GGUF header does not
+//! check out are never picked.
+
+use crate::gguf;
+use crate::template::{ChatFormat, ChatTemplate, ToolCalls};
+use crate::{tier_from_rank, ModelPreset, ModelTier};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Where the catalogue is installed on a device.
+pub const DEFAULT_MANIFEST: &str = "/opt/muninos/models/manifest.json";
+
+/// Where custom presets are declared, one `<name>.toml` each.
+pub const CUSTOM_DIR: &str = "/etc/muninos/models.d";
+
+/// The repository's manifest, used when none is installed.
+const BUILTIN_MANIFEST: &str = include_str!("../../models/manifest.json");
+
+/// Free space left over after a download, so pulling a model never fills
+/// the disk completely.
+const SPACE_MARGIN: u64 = 256 * 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Catalogue {
+    pub backend: String,
+    pub base_dir: PathBuf,
+    pub presets: BTreeMap<String, CatalogueEntry>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CatalogueEntry {
+    #[serde(default, skip_serializing_if = "PresetKind::is_chat")]
+    pub kind: PresetKind,
+    pub model_id: String,
+    pub file: String,
+    /// Empty for custom presets, which are not downloaded.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub url: String,
+    pub quant: String,
+    pub context: usize,
+    /// Prompt template and tool-call convention; when absent they are
+    /// recognised from the GGUF's `tokenizer.chat_template`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<ChatTemplate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<ToolCalls>,
+    /// Hex SHA-256 of the file; pulls refuse presets without one, `verify`
+    /// checks it when present.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Tier rank (0-3) a custom preset slots into; manifest presets take
+    /// theirs from their key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rank: Option<u8>,
+    /// Resolution skips this preset on machines with less RAM.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_ram_gb: Option<u64>,
+    /// A custom preset's model file, used instead of `baseDir/file`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    /// What an embedding model expects before search queries and documents.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub query_prefix: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub document_prefix: String,
+}
+
+/// Chat presets serve tiers; an embedding preset serves `/v1/embeddings`
+/// and the knowledge index.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresetKind {
+    #[default]
+    Chat,
+    Embedding,
+}
+
+impl PresetKind {
+    fn is_chat(&self) -> bool {
+        *self == PresetKind::Chat
+    }
+}
+
+/// A `models.d/<name>.toml` preset. Context, template and tool calls
+/// default to what the GGUF says.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CustomPreset {
+    path: PathBuf,
+    rank: u8,
+    model_id: Option<String>,
+    context: Option<usize>,
+    quant: Option<String>,
+    template: Option<ChatTemplate>,
+    tool_calls: Option<ToolCalls>,
+    min_ram_gb: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelStatus {
+    pub tier: String,
+    /// `None` for the embedding preset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rank: Option<u8>,
+    pub custom: bool,
+    pub model_id: String,
+    pub path: PathBuf,
+    pub present: bool,
+    /// Why a present file cannot be used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Bytes of an interrupted download waiting to be resumed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub partial: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "status")]
+pub enum Check {
+    Missing,
+    Ok,
+    /// Present, but the manifest has no checksum to compare against.
+    Unverified {
+        sha256: String,
+    },
+    Mismatch {
+        expected: String,
+        actual: String,
+    },
+}
+
+impl Catalogue {
+    /// Load `path`, else the installed manifest, else the built-in copy.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let installed = Path::new(DEFAULT_MANIFEST);
+        let text = match path {
+            Some(p) => std::fs::read_to_string(p)
+                .with_context(|| format!("failed reading {}", p.display()))?,
+            None if installed.is_file() => std::fs::read_to_string(installed)?,
+            None => BUILTIN_MANIFEST.to_string(),
+        };
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let catalogue: Catalogue = serde_json::from_str(text).context("invalid model manifest")?;
+        for rank in 0..=3 {
+            let tier = tier_key(&tier_from_rank(rank));
+            let Some(entry) = catalogue.presets.get(&tier) else {
+                bail!("model manifest has no {tier} preset");
+            };
+            if entry.file.contains('/') || entry.file.starts_with('.') {
+                bail!("{tier} has a bad file name {:?}", entry.file);
+            }
+        }
+        Ok(catalogue)
+    }
+
+    /// Add the presets declared in `dir/*.toml`. A missing directory adds
+    /// nothing; a bad file is skipped and reported in the returned warnings.
+    pub fn add_custom(&mut self, dir: &Path) -> Vec<String> {
+        let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|x| x == "toml"))
+            .collect();
+        files.sort();
+        let mut warnings = Vec::new();
+        for file in files {
+            if let Err(e) = self.add_custom_file(&file) {
+                warnings.push(format!("ignoring {}: {e:#}", file.display()));
+            }
+        }
+        warnings
+    }
+
+    fn add_custom_file(&mut self, file: &Path) -> Result<()> {
+        let name = file
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        if self.presets.contains_key(&name) {
+            bail!("preset {name} already exists");
+        }
+        let text = std::fs::read_to_string(file)?;
+        let custom: CustomPreset = toml::from_str(&text)?;
+        if custom.rank > 3 {
+            bail!("rank must be 0-3, not {}", custom.rank);
+        }
+        if !custom.path.is_absolute() {
+            bail!("path {} is not absolute", custom.path.display());
+        }
+        let header = validate(&custom.path)?;
+        let file_name = custom.path.file_name().unwrap_or_default();
+        let entry = CatalogueEntry {
+            model_id: custom.model_id.unwrap_or_else(|| name.clone()),
+            file: file_name.to_string_lossy().into_owned(),
+            url: String::new(),
+            quant: custom.quant.unwrap_or_else(|| "unknown".into()),
+            context: custom
+                .context
+                .or_else(|| header.context_length().map(|n| n as usize))
+                .context("no context given and none in the GGUF")?,
+            template: custom.template,
+            tool_calls: custom.tool_calls,
+            rank: Some(custom.rank),
+            min_ram_gb: custom.min_ram_gb,
+            path: Some(custom.path),
+            ..Default::default()
+        };
+        self.presets.insert(name, entry);
+        Ok(())
+    }
+
+    /// The tier rank `name` serves.
+    pub fn rank_of(&self, name: &str) -> Option<u8> {
+        let entry = self.presets.get(name)?;
+        entry
+            .rank
+            .or_else(|| (0..=3).find(|r| tier_key(&tier_from_rank(*r)) == name))
+    }
+
+    /// Presets that may serve tier `rank` on a machine with `ram_gb`, in the
+    /// order resolution tries them: custom presets by name, then the
+    /// manifest's.
+    pub fn candidates(&self, rank: u8, ram_gb: u64) -> Vec<&str> {
+        let fits = |e: &CatalogueEntry| e.min_ram_gb.is_none_or(|min| ram_gb >= min);
+        let (mut custom, builtin): (Vec<&str>, Vec<&str>) = self
+            .presets
+            .iter()
+            .filter(|(name, e)| self.rank_of(name) == Some(rank) && fits(e))
+            .map(|(name, _)| name.as_str())
+            .partition(|name| self.presets[*name].path.is_some());
+        custom.extend(builtin);
+        custom
+    }
+
+    /// Check that a preset's file is there and is a GGUF model.
+    pub fn check_file(&self, name: &str) -> Result<()> {
+        let entry = self.entry(name)?;
+        let path = self.path_of(entry);
+        if !path.is_file() {
+            if entry.url.is_empty() {
+                bail!("{} does not exist", path.display());
+            }
+            bail!(
+                "{} is not downloaded (munin-brain models pull {name})",
+                path.display()
+            );
+        }
+        validate(&path).map(|_| ())
+    }
+
+    pub fn preset(&self, name: &str) -> ModelPreset {
+        let entry = &self.presets[name];
+        ModelPreset {
+            preset: name.to_string(),
+            tier: tier_from_rank(self.rank_of(name).unwrap_or(0)),
+            model_id: entry.model_id.clone(),
+            model_path: self.path_of(entry).to_string_lossy().into_owned(),
+            quant: entry.quant.clone(),
+            context: entry.context,
+            chat: self.chat_format(entry),
+        }
+    }
+
+    /// The entry's declared chat format, filling gaps from the model file's
+    /// embedded template, else ChatML with JSON tool calls.
+    pub fn chat_format(&self, entry: &CatalogueEntry) -> ChatFormat {
+        let embedded = match (entry.template, entry.tool_calls) {
+            (Some(_), Some(_)) => None,
+            _ => gguf::read_header(&self.path_of(entry))
+                .ok()
+                .and_then(|h| h.chat_template().map(str::to_string)),
+        };
+        let jinja = embedded.as_deref();
+        ChatFormat {
+            template: entry
+                .template
+                .or_else(|| jinja.and_then(ChatTemplate::detect))
+                .unwrap_or_default(),
+            tool_calls: entry
+                .tool_calls
+                .or_else(|| jinja.map(ToolCalls::detect))
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn entry(&self, tier: &str) -> Result<&CatalogueEntry> {
+        self.presets.get(tier).with_context(|| {
+            let known: Vec<&str> = self.presets.keys().map(String::as_str).collect();
+            format!("unknown preset {tier} (one of {})", known.join(", "))
+        })
+    }
+
+    pub fn path_of(&self, entry: &CatalogueEntry) -> PathBuf {
+        match &entry.path {
+            Some(path) => path.clone(),
+            None => self.base_dir.join(&entry.file),
+        }
+    }
+
+    pub fn list(&self) -> Vec<ModelStatus> {
+        self.presets
+            .iter()
+            .map(|(tier, entry)| {
+                let path = self.path_of(entry);
+                let present = path.is_file();
+                ModelStatus {
+                    tier: tier.clone(),
+                    rank: self.rank_of(tier),
+                    custom: entry.path.is_some(),
+                    model_id: entry.model_id.clone(),
+                    present,
+                    error: present
+                        .then(|| validate(&path).err().map(|e| format!("{e:#}")))
+                        .flatten(),
+                    partial: part_path(&path).metadata().ok().map(|m| m.len()),
+                    size: path.metadata().ok().map(|m| m.len()).or(entry.size),
+                    path,
+                }
+            })
+            .collect()
+    }
+
+    pub fn verify(&self, tier: &str) -> Result<Check> {
+        let entry = self.entry(tier)?;
+        let path = self.path_of(entry);
+        if !path.is_file() {
+            return Ok(Check::Missing);
+        }
+        let actual = sha256_file(&path)?;
+        Ok(match &entry.sha256 {
+            None => Check::Unverified { sha256: actual },
+            Some(expected) if expected.eq_ignore_ascii_case(&actual) => Check::Ok,
+            Some(expected) => Check::Mismatch {
+                expected: expected.clone(),
+                actual,
+            },
+        })
+    }
+
+    /// Delete a preset's file and any partial download; returns what went.
+    /// Custom presets' files are left alone.
+    pub fn remove(&self, tier: &str) -> Result<Vec<PathBuf>> {
+        let entry = self.entry(tier)?;
+        if entry.path.is_some() {
+            bail!("{tier} is a custom preset; its file is not managed by munin-brain");
+        }
+        let path = self.path_of(entry);
+        let mut removed = Vec::new();
+        for p in [part_path(&path), path] {
+            match std::fs::remove_file(&p) {
+                Ok(()) => removed.push(p),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    return Err(e).with_context(|| format!("failed removing {}", p.display()))
+                }
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Download a preset (from `mirror` instead of its URL when given),
+    /// resuming a partial download, and check it against the manifest.
+    /// `progress` gets bytes done and the total, when known.
+    pub async fn pull(
+        &self,
+        tier: &str,
+        mirror: Option<&str>,
+        progress: &mut dyn FnMut(u64, Option<u64>),
+    ) -> Result<PathBuf> {
+        let entry = self.entry(tier)?;
+        if entry.url.is_empty() {
+            bail!("{tier} is a custom preset with nothing to download");
+        }
+        let dest = self.path_of(entry);
+        match self.verify(tier)? {
+            Check::Missing => {}
+            Check::Mismatch { .. } => bail!(
+                "{} exists but fails verification; remove it first",
+                dest.display()
+            ),
+            _ => return Ok(dest),
+        }
+        let Some(expected) = &entry.sha256 else {
+            bail!("{tier} has no SHA-256 in the manifest; refusing to download an unpinned model");
+        };
+        std::fs::create_dir_all(&self.base_dir)
+            .with_context(|| format!("failed creating {}", self.base_dir.display()))?;
+        let url = match mirror {
+            Some(m) => format!("{}/{}", m.trim_end_matches('/'), entry.file),
+            None => entry.url.clone(),
+        };
+        let part = part_path(&dest);
+        let have = part.metadata().map(|m| m.len()).unwrap_or(0);
+
+        let (mut body, start, total) = Body::open(&url, have).await?;
+        let total = entry.size.or(total);
+        if let Some(total) = total {
+            check_space(&self.base_dir, total.saturating_sub(start))?;
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(start > 0)
+            .write(true)
+            .truncate(start == 0)
+            .open(&part)
+            .with_context(|| format!("failed opening {}", part.display()))?;
+        let mut done = start;
+        progress(done, total);
+        while let Some(chunk) = body.chunk().await? {
+            file.write_all(&chunk)?;
+            done += chunk.len() as u64;
+            progress(done, total);
+        }
+        file.sync_all()?;
+        drop(file);
+
+        if let Some(size) = entry.size.filter(|s| *s != done) {
+            bail!("{url} gave {done} bytes, manifest says {size}; run pull again to resume");
+        }
+        let actual = sha256_file(&part)?;
+        if !expected.eq_ignore_ascii_case(&actual) {
+            std::fs::remove_file(&part).ok();
+            bail!("{url} has SHA-256 {actual}, manifest says {expected}");
+        }
+        std::fs::rename(&part, &dest)?;
+        Ok(dest)
+    }
+}
+
+/// Manifest key for a tier, e.g. `Tier1Mobile`.
+pub fn tier_key(tier: &ModelTier) -> String {
+    format!("{tier:?}")
+}
+
+/// Read `path`'s GGUF header and check it describes a model.
+fn validate(path: &Path) -> Result<gguf::Header> {
+    let header = gguf::read_header(path)?;
+    if header.tensor_count == 0 {
+        bail!("{} has no tensors", path.display());
+    }
+    Ok(header)
+}
+
+fn part_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".part");
+    PathBuf::from(name)
+}
+
+/// A download source, positioned at the resume offset.
+enum Body {
+    File(std::fs::File),
+    Http(reqwest::Response),
+}
+
+impl Body {
+    /// Open `url` from byte `have`. Returns the body, the offset it actually
+    /// starts at (0 when the server ignores the range) and the full size.
+    async fn open(url: &str, have: u64) -> Result<(Self, u64, Option<u64>)> {
+        if let Some(path) = url.strip_prefix("file://") {
+            let mut file =
+                std::fs::File::open(path).with_context(|| format!("failed opening {path}"))?;
+            let total = file.metadata()?.len();
+            let start = if have <= total { have } else { 0 };
+            std::io::Seek::seek(&mut file, std::io::SeekFrom::Start(start))?;
+            return Ok((Body::File(file), start, Some(total)));
+        }
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            bail!("unsupported model URL {url}");
+        }
+        let client = reqwest::Client::new();
+        let mut req = client.get(url);
+        if have > 0 {
+            req = req.header(reqwest::header::RANGE, format!("bytes={have}-"));
+        }
+        let resp = req
+            .send()
+            .await
+            .with_context(|| format!("failed fetching {url}"))?;
+        if have > 0 && resp.status() == reqwest::StatusCode::RANGE_NOT_SATISFIABLE {
+            // The partial file is stale or already complete; start over.
+            return Box::pin(Self::open(url, 0)).await;
+        }
+        let resp = resp
+            .error_for_status()
+            .with_context(|| format!("failed fetching {url}"))?;
+        let resumed = resp.status() == reqwest::StatusCode::PARTIAL_CONTENT;
+        let start = if resumed { have } else { 0 };
+        let total = resp.content_length().map(|len| start + len);
+        Ok((Body::Http(resp), start, total))
+    }
+
+    async fn chunk(&mut self) -> Result<Option<Vec<u8>>> {
+        match self {
+            Body::File(file) => {
+                let mut buf = vec![0u8; 1 << 20];
+                let n = file.read(&mut buf)?;
+                buf.truncate(n);
+                Ok((n > 0).then_some(buf))
+            }
+            Body::Http(resp) => Ok(resp.chunk().await?.map(|b| b.to_vec())),
+        }
+    }
+}
+
+/// Refuse a download of `needed` bytes into `dir` that would leave less
+/// than [`SPACE_MARGIN`] free.
+fn check_space(dir: &Path, needed: u64) -> Result<()> {
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    let disks = sysinfo::Disks::new_with_refreshed_list();
+    let Some(disk) = disks
+        .list()
+        .iter()
+        .filter(|d| dir.starts_with(d.mount_point()))
+        .max_by_key(|d| d.mount_point().as_os_str().len())
+    else {
+        return Ok(());
+    };
+    let free = disk.available_space();
+    if free < needed.saturating_add(SPACE_MARGIN) {
+        bail!(
+            "not enough space in {}: need {} MiB, {} MiB free",
+            dir.display(),
+            needed.div_ceil(1 << 20),
+            free >> 20
+        );
+    }
+    Ok(())
+}
+
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file =
+        std::fs::File::open(path).with_context(|| format!("failed opening {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn catalogue(dir: &Path, model: &[u8], sha256: Option<String>) -> Catalogue {
+        let mut c = Catalogue::parse(BUILTIN_MANIFEST).unwrap();
+        c.base_dir = dir.join("models");
+        let entry = c.presets.get_mut("Tier0Tiny").unwrap();
+        entry.sha256 = sha256;
+        entry.size = Some(model.len() as u64);
+        c
+    }
+
+    #[tokio::test]
+    async fn pulls_from_a_file_mirror_resuming_a_partial_download() {
+        let dir = std::env::temp_dir().join(format!("munin-models-{}", std::process::id()));
+        let mirror = dir.join("mirror");
+        std::fs::create_dir_all(&mirror).unwrap();
+        let model: Vec<u8> = (0..3_000_000u32).map(|i| (i % 251) as u8).collect();
+        let c = catalogue(&dir, &model, Some(format!("{:x}", Sha256::digest(&model))));
+        let file = &c.presets["Tier0Tiny"].file;
+        std::fs::write(mirror.join(file), &model).unwrap();
+        let mirror_url = format!("file://{}", mirror.display());
+
+        // Half a download left behind by an interrupted pull.
+        let dest = c.base_dir.join(file);
+        std::fs::create_dir_all(&c.base_dir).unwrap();
+        std::fs::write(part_path(&dest), &model[..1_000_000]).unwrap();
+        let tiny = c
+            .list()
+            .into_iter()
+            .find(|m| m.tier == "Tier0Tiny")
+            .unwrap();
+        assert_eq!(tiny.partial, Some(1_000_000));
+
+        let mut seen = Vec::new();
+        let path = c
+            .pull("Tier0Tiny", Some(&mirror_url), &mut |done, _| {
+                seen.push(done)
+            })
+            .await
+            .unwrap();
+        assert_eq!(path, dest);
+        assert_eq!(seen[0], 1_000_000);
+        assert_eq!(*seen.last().unwrap(), model.len() as u64);
+        assert_eq!(std::fs::read(&dest).unwrap(), model);
+        assert_eq!(c.verify("Tier0Tiny").unwrap(), Check::Ok);
+
+        std::fs::write(&dest, b"corrupt").unwrap();
+        assert!(matches!(
+            c.verify("Tier0Tiny").unwrap(),
+            Check::Mismatch { .. }
+        ));
+        assert_eq!(c.remove("Tier0Tiny").unwrap(), std::slice::from_ref(&dest));
+        assert_eq!(c.verify("Tier0Tiny").unwrap(), Check::Missing);
+
+        let bad = catalogue(&dir, &model, Some("00".repeat(32)));
+        let err = bad
+            .pull("Tier0Tiny", Some(&mirror_url), &mut |_, _| {})
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("SHA-256"), "{err}");
+        assert!(!part_path(&dest).exists());
+
+        let unpinned = catalogue(&dir, &model, None);
+        let err = unpinned
+            .pull("Tier0Tiny", Some(&mirror_url), &mut |_, _| {})
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("unpinned"), "{err}");
+        assert!(!part_path(&dest).exists() && !dest.exists());
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn manifest_drives_the_tier_presets() {
+        let c = Catalogue::parse(BUILTIN_MANIFEST).unwrap();
+        let p = c.preset("Tier3Performance");
+        assert_eq!(p.quant, "Q5_K_M");
+        assert_eq!(p.chat.template, ChatTemplate::Llama2);
+        assert!(p.model_path.starts_with("/opt/muninos/models/"));
+        assert!(Catalogue::parse(r#"{"backend":"x","baseDir":"/m","presets":{}}"#).is_err());
+    }
+
+    #[test]
+    fn undeclared_chat_format_comes_from_the_gguf() {
+        let dir = std::env::temp_dir().join(format!("munin-chat-{}", std::process::id()));
+        let mut c = Catalogue::parse(BUILTIN_MANIFEST).unwrap();
+        c.base_dir = dir.clone();
+        std::fs::create_dir_all(&dir).unwrap();
+        let entry = c.presets.get_mut("Tier2Balanced").unwrap();
+        entry.template = None;
+        entry.tool_calls = None;
+        let entry = c.presets["Tier2Balanced"].clone();
+        assert_eq!(c.chat_format(&entry), ChatFormat::default());
+
+        let jinja = "{{ '<|im_start|>' }}{% if tools %}<tool_call>{% endif %}";
+        let header = gguf::fixture(&[("tokenizer.chat_template", jinja)], &[]);
+        std::fs::write(c.path_of(&entry), header).unwrap();
+        let p = c.preset("Tier2Balanced");
+        assert_eq!(p.chat.template, ChatTemplate::ChatMl);
+        assert_eq!(p.chat.tool_calls, ToolCalls::Hermes);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn custom_presets_slot_into_tiers_and_are_validated() {
+        let dir = std::env::temp_dir().join(format!("munin-custom-{}", std::process::id()));
+        let conf = dir.join("models.d");
+        std::fs::create_dir_all(&conf).unwrap();
+        let phi = dir.join("phi-3-mini.gguf");
+        std::fs::write(
+            &phi,
+            gguf::fixture(
+                &[("general.architecture", "phi3")],
+                &[("phi3.context_length", 4096)],
+            ),
+        )
+        .unwrap();
+        let broken = dir.join("broken.gguf");
+        std::fs::write(&broken, b"not a model").unwrap();
+        let write = |name: &str, text: String| std::fs::write(conf.join(name), text).unwrap();
+        write(
+            "phi3.toml",
+            format!(
+                "path = {:?}\nrank = 1\ntemplate = \"chatml\"\nmin_ram_gb = 6\n",
+                phi
+            ),
+        );
+        write("broken.toml", format!("path = {broken:?}\nrank = 1\n"));
+        write("Tier0Tiny.toml", format!("path = {phi:?}\nrank = 0\n"));
+        write("typo.toml", format!("path = {phi:?}\nrank = 1\nram = 6\n"));
+
+        let mut c = Catalogue::parse(BUILTIN_MANIFEST).unwrap();
+        c.base_dir = dir.join("models");
+        let warnings = c.add_custom(&conf);
+        assert_eq!(warnings.len(), 3, "{warnings:?}");
+        for expected in ["Tier0Tiny.toml", "broken.toml", "unknown field"] {
+            assert!(
+                warnings.iter().any(|w| w.contains(expected)),
+                "{warnings:?}"
+            );
+        }
+
+        let p = c.preset("phi3");
+        assert_eq!((p.tier, p.context), (ModelTier::Tier1Mobile, 4096));
+        assert_eq!(p.model_path, phi.to_string_lossy());
+        assert_eq!(p.chat.template, ChatTemplate::ChatMl);
+        assert_eq!(c.candidates(1, 8), ["phi3", "Tier1Mobile"]);
+        assert_eq!(c.candidates(1, 4), ["Tier1Mobile"]);
+        assert!(c.check_file("phi3").is_ok());
+        assert!(c.remove("phi3").is_err());
+
+        // A file that stops being a valid GGUF is no longer usable.
+        std::fs::write(&phi, b"GGUF").unwrap();
+        assert!(c.check_file("phi3").is_err());
+        let status = c.list().into_iter().find(|m| m.tier == "phi3").unwrap();
+        assert!(status.custom && status.error.is_some());
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,423 @@
+//! Chat prompt formats. Each preset declares the template its model was
+//! tuned on and how it asks for tools; munin-brain renders system, user,
+//! assistant and tool turns into one prompt string for llama.cpp and reads
+//! tool calls back out of the completion.
+//!
+//! Tool results are fed back as user-side turns, since none of these
+//! templates has a tool role of its own. Consecutive turns from the same side
+//! are merged so the `[INST]` formats keep strictly alternating.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatTemplate {
+    /// `<|im_start|>role ... <|im_end|>` (Qwen and most recent models).
+    #[default]
+    ChatMl,
+    /// `[INST] <<SYS>> ... <</SYS>> ... [/INST]`.
+    Llama2,
+    /// `[INST] ... [/INST]` with no system block; the system prompt leads
+    /// the first user turn.
+    Mistral,
+    /// `<|system|>`, `<|user|>`, `<|assistant|>` (TinyLlama chat).
+    Zephyr,
+}
+
+impl ChatTemplate {
+    /// Recognise a GGUF `tokenizer.chat_template` (Jinja) by its markers.
+    pub fn detect(jinja: &str) -> Option<Self> {
+        if jinja.contains("<|im_start|>") {
+            Some(Self::ChatMl)
+        } else if jinja.contains("<|user|>") {
+            Some(Self::Zephyr)
+        } else if jinja.contains("<<SYS>>") {
+            Some(Self::Llama2)
+        } else if jinja.contains("[INST]") {
+            Some(Self::Mistral)
+        } else {
+            None
+        }
+    }
+
+    fn stop(self) -> &'static [&'static str] {
+        match self {
+            Self::ChatMl => &["<|im_end|>"],
+            Self::Llama2 | Self::Mistral => &["</s>", "[INST]"],
+            Self::Zephyr => &["</s>", "<|user|>"],
+        }
+    }
+}
+
+/// How tools are offered to the model and how it calls them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ToolCalls {
+    /// Tools listed in plain text; the model answers with a bare
+    /// `{"tool": ..., "args": {...}}` object.
+    #[default]
+    Json,
+    /// Hermes-style `<tools>` signatures, `<tool_call>` replies and
+    /// `<tool_response>` results, as Qwen2.5 was trained on.
+    Hermes,
+}
+
+impl ToolCalls {
+    pub fn detect(jinja: &str) -> Self {
+        if jinja.contains("<tool_call>") {
+            Self::Hermes
+        } else {
+            Self::Json
+        }
+    }
+}
+
+/// A preset's prompt template and tool-call convention.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChatFormat {
+    pub template: ChatTemplate,
+    pub tool_calls: ToolCalls,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+    Tool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Message {
+    pub role: Role,
+    pub content: String,
+    /// The tool a `tool` message is the result of.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tool {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// JSON Schema of the arguments.
+    #[serde(default = "no_parameters")]
+    pub parameters: Value,
+}
+
+fn no_parameters() -> Value {
+    json!({"type": "object", "properties": {}})
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub name: String,
+    pub args: Value,
+}
+
+/// A rendered prompt, ending where the assistant's reply starts.
+#[derive(Debug, Clone, Serialize)]
+pub struct Prompt {
+    pub prompt: String,
+    /// Strings that end the assistant's turn.
+    pub stop: Vec<String>,
+}
+
+impl ChatFormat {
+    pub fn render(&self, messages: &[Message], tools: &[Tool]) -> Prompt {
+        let mut system: Vec<String> = messages
+            .iter()
+            .filter(|m| m.role == Role::System)
+            .map(|m| m.content.trim().to_string())
+            .collect();
+        if !tools.is_empty() {
+            system.push(self.tool_prompt(tools));
+        }
+        let system = system.join("\n\n");
+
+        // (is_user, text) with same-side turns merged.
+        let mut turns: Vec<(bool, String)> = Vec::new();
+        for m in messages {
+            let (user, text) = match m.role {
+                Role::System => continue,
+                Role::User => (true, m.content.trim().to_string()),
+                Role::Assistant => (false, m.content.trim().to_string()),
+                Role::Tool => (true, self.tool_result(m.name.as_deref(), &m.content)),
+            };
+            match turns.last_mut() {
+                Some((side, prev)) if *side == user => {
+                    prev.push_str("\n\n");
+                    prev.push_str(&text);
+                }
+                _ => turns.push((user, text)),
+            }
+        }
+
+        let prompt = match self.template {
+            ChatTemplate::ChatMl => {
+                tagged(&system, &turns, |role, text| {
+                    format!("<|im_start|>{role}\n{text}<|im_end|>\n")
+                }) + "<|im_start|>assistant\n"
+            }
+            ChatTemplate::Zephyr => {
+                tagged(&system, &turns, |role, text| {
+                    format!("<|{role}|>\n{text}</s>\n")
+                }) + "<|assistant|>\n"
+            }
+            ChatTemplate::Llama2 => inst(&turns, |first, user| match first && !system.is_empty() {
+                true => format!("<<SYS>>\n{system}\n<</SYS>>\n\n{user}"),
+                false => user.to_string(),
+            }),
+            ChatTemplate::Mistral => {
+                inst(&turns, |first, user| match first && !system.is_empty() {
+                    true => format!("{system}\n\n{user}"),
+                    false => user.to_string(),
+                })
+            }
+        };
+        Prompt {
+            prompt,
+            stop: self.template.stop().iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    /// The tool call in a completion, if it made one.
+    pub fn parse_tool_call(&self, output: &str) -> Option<ToolCall> {
+        match self.tool_calls {
+            ToolCalls::Hermes => {
+                let body = output.split_once("<tool_call>")?.1;
+                let body = body.split_once("</tool_call>").map_or(body, |(b, _)| b);
+                let v: Value = serde_json::from_str(body.trim()).ok()?;
+                Some(ToolCall {
+                    name: v.get("name")?.as_str()?.to_string(),
+                    args: v.get("arguments").cloned().unwrap_or_else(|| json!({})),
+                })
+            }
+            ToolCalls::Json => {
+                let start = output.find('{')?;
+                let end = output.rfind('}')?;
+                let v: Value = serde_json::from_str(output.get(start..=end)?).ok()?;
+                Some(ToolCall {
+                    name: v.get("tool")?.as_str()?.to_string(),
+                    args: v.get("args").cloned().unwrap_or_else(|| json!({})),
+                })
+            }
+        }
+    }
+
+    fn tool_prompt(&self, tools: &[Tool]) -> String {
+        match self.tool_calls {
+            ToolCalls::Hermes => {
+                let signatures: Vec<String> = tools
+                    .iter()
+                    .map(|t| {
+                        json!({"type": "function", "function": {
+                            "name": t.name,
+                            "description": t.description,
+                            "parameters": t.parameters,
+                        }})
+                        .to_string()
+                    })
+                    .collect();
+                format!(
+                    "# Tools\n\nYou may call one or more functions to assist with the user query.\n\n\
+                     You are provided with function signatures within <tools></tools> XML tags:\n\
+                     <tools>\n{}\n</tools>\n\n\
+                     For each function call, return a json object with function name and arguments \
+                     within <tool_call></tool_call> XML tags:\n\
+                     <tool_call>\n{{\"name\": <function-name>, \"arguments\": <args-json-object>}}\n</tool_call>",
+                    signatures.join("\n")
+                )
+            }
+            ToolCalls::Json => {
+                let lines: Vec<String> = tools
+                    .iter()
+                    .map(|t| format!("- {}: {} {}", t.name, t.description, t.parameters))
+                    .collect();
+                format!(
+                    "You can use these tools:\n{}\n\n\
+                     To use one, reply with only a JSON object: {{\"tool\": \"<name>\", \"args\": {{...}}}}",
+                    lines.join("\n")
+                )
+            }
+        }
+    }
+
+    fn tool_result(&self, name: Option<&str>, content: &str) -> String {
+        match self.tool_calls {
+            ToolCalls::Hermes => format!("<tool_response>\n{}\n</tool_response>", content.trim()),
+            ToolCalls::Json => format!(
+                "Result of {}:\n{}",
+                name.unwrap_or("the tool"),
+                content.trim()
+            ),
+        }
+    }
+}
+
+/// Formats with a tag per role and a system turn of their own.
+fn tagged(system: &str, turns: &[(bool, String)], turn: impl Fn(&str, &str) -> String) -> String {
+    let mut out = String::new();
+    if !system.is_empty() {
+        out += &turn("system", system);
+    }
+    for (user, text) in turns {
+        out += &turn(if *user { "user" } else { "assistant" }, text);
+    }
+    out
+}
+
+/// The `[INST]` formats: each user turn is wrapped, each assistant reply
+/// closes its exchange with `</s>`. `first_user` decorates the first user
+/// turn with the system prompt.
+fn inst(turns: &[(bool, String)], first_user: impl Fn(bool, &str) -> String) -> String {
+    let mut out = String::new();
+    let mut first = true;
+    let mut open = false;
+    for (user, text) in turns {
+        if *user {
+            out += &format!("<s>[INST] {} [/INST]", first_user(first, text));
+            open = true;
+        } else {
+            if !open {
+                // A reply with no request before it: give it an empty one.
+                out += &format!("<s>[INST] {} [/INST]", first_user(first, ""));
+            }
+            out += &format!(" {text} </s>");
+            open = false;
+        }
+        first = false;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msg(role: Role, content: &str) -> Message {
+        Message {
+            role,
+            content: content.into(),
+            name: None,
+        }
+    }
+
+    fn conversation() -> Vec<Message> {
+        vec![
+            msg(Role::System, "Be brief."),
+            msg(Role::User, "Hi"),
+            msg(Role::Assistant, "Hello."),
+            msg(Role::User, "Status?"),
+        ]
+    }
+
+    fn render(template: ChatTemplate) -> String {
+        let format = ChatFormat {
+            template,
+            tool_calls: ToolCalls::Json,
+        };
+        format.render(&conversation(), &[]).prompt
+    }
+
+    #[test]
+    fn renders_each_template() {
+        assert_eq!(
+            render(ChatTemplate::ChatMl),
+            "<|im_start|>system\nBe brief.<|im_end|>\n<|im_start|>user\nHi<|im_end|>\n\
+             <|im_start|>assistant\nHello.<|im_end|>\n<|im_start|>user\nStatus?<|im_end|>\n\
+             <|im_start|>assistant\n"
+        );
+        assert_eq!(
+            render(ChatTemplate::Zephyr),
+            "<|system|>\nBe brief.</s>\n<|user|>\nHi</s>\n<|assistant|>\nHello.</s>\n\
+             <|user|>\nStatus?</s>\n<|assistant|>\n"
+        );
+        assert_eq!(
+            render(ChatTemplate::Llama2),
+            "<s>[INST] <<SYS>>\nBe brief.\n<</SYS>>\n\nHi [/INST] Hello. </s><s>[INST] Status? [/INST]"
+        );
+        assert_eq!(
+            render(ChatTemplate::Mistral),
+            "<s>[INST] Be brief.\n\nHi [/INST] Hello. </s><s>[INST] Status? [/INST]"
+        );
+    }
+
+    #[test]
+    fn tools_are_offered_and_their_calls_and_results_round_trip() {
+        let tools = [Tool {
+            name: "system.status".into(),
+            description: "Report system status".into(),
+            parameters: json!({"type": "object"}),
+        }];
+        let mut messages = conversation();
+        messages.push(msg(
+            Role::Assistant,
+            "<tool_call>{\"name\": \"system.status\"}",
+        ));
+        messages.push(Message {
+            name: Some("system.status".into()),
+            ..msg(Role::Tool, "cpu 3%")
+        });
+
+        let hermes = ChatFormat {
+            template: ChatTemplate::ChatMl,
+            tool_calls: ToolCalls::Hermes,
+        };
+        let p = hermes.render(&messages, &tools).prompt;
+        assert!(p.contains("<tools>\n{\"function\""), "{p}");
+        assert!(p.ends_with(
+            "<|im_start|>user\n<tool_response>\ncpu 3%\n</tool_response><|im_end|>\n<|im_start|>assistant\n"
+        ));
+        let call = hermes
+            .parse_tool_call(
+                "<tool_call>\n{\"name\": \"file.read\", \"arguments\": {\"path\": \"/etc/hostname\"}}\n</tool_call>",
+            )
+            .unwrap();
+        assert_eq!(call.name, "file.read");
+        assert_eq!(call.args, json!({"path": "/etc/hostname"}));
+        assert_eq!(hermes.parse_tool_call("Just text."), None);
+
+        // A tool result right after a user turn merges into one [INST].
+        let json_style = ChatFormat {
+            template: ChatTemplate::Mistral,
+            tool_calls: ToolCalls::Json,
+        };
+        let mut messages = conversation();
+        messages.push(Message {
+            name: Some("system.status".into()),
+            ..msg(Role::Tool, "cpu 3%")
+        });
+        let p = json_style.render(&messages, &tools).prompt;
+        assert!(p.contains("- system.status: Report system status"), "{p}");
+        assert!(
+            p.ends_with("[INST] Status?\n\nResult of system.status:\ncpu 3% [/INST]"),
+            "{p}"
+        );
+        let call = json_style
+            .parse_tool_call("Sure. {\"tool\": \"system.status\", \"args\": {}}")
+            .unwrap();
+        assert_eq!(call.name, "system.status");
+    }
+
+    #[test]
+    fn detects_templates_from_gguf_jinja() {
+        let qwen = "{%- if tools %}{{- '<|im_start|>system\\n' }}<tool_call>{%- endif %}";
+        assert_eq!(ChatTemplate::detect(qwen), Some(ChatTemplate::ChatMl));
+        assert_eq!(ToolCalls::detect(qwen), ToolCalls::Hermes);
+        let llama2 = "{{ bos_token + '[INST] <<SYS>>\\n' + system + '\\n<</SYS>>\\n\\n' }}";
+        assert_eq!(ChatTemplate::detect(llama2), Some(ChatTemplate::Llama2));
+        assert_eq!(
+            ChatTemplate::detect("{{ '[INST] ' + m['content'] + ' [/INST]' }}"),
+            Some(ChatTemplate::Mistral)
+        );
+        assert_eq!(
+            ChatTemplate::detect("{{ '<|user|>\\n' + m['content'] + eos_token }}"),
+            Some(ChatTemplate::Zephyr)
+        );
+        assert_eq!(ChatTemplate::detect("{{ m.content }}"), None);
+    }
+}
@@ -1,53 +1,143 @@
+use crate::calls::CallTracker;
 use crate::policy::PolicyEngine;
+use crate::preview;
 use crate::protocol::{CoreEvent, ToolCall, ToolResult};
+use crate::supervisor::{Mode, ModeHandle};
 use crate::tools::ToolRouter;
-use anyhow::Result;
-use serde_json::json;
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::time::Duration;
 use uuid::Uuid;
 
+/// How long to wait for munin-brain's decision before giving up on it.
+const DECIDE_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub struct AgentRuntime {
-    policy: PolicyEngine,
+    pub policy: PolicyEngine,
+    pub calls: CallTracker,
+    tools: ToolRouter,
+    /// Asked to decide on input the local rules don't match.
+    brain_endpoint: String,
+    mode: ModeHandle,
+}
+
+/// munin-brain's answer to `POST /v1/decide`.
+#[derive(Debug, Deserialize)]
+struct BrainDecision {
+    intent: String,
+    tool: Option<String>,
+    #[serde(default)]
+    args: Value,
+    question: Option<String>,
 }
 
 impl AgentRuntime {
-    pub fn new() -> Self {
-        Self { policy: PolicyEngine }
+    pub fn new(policy: PolicyEngine, tools: ToolRouter, brain_endpoint: &str) -> Self {
+        Self {
+            policy,
+            calls: CallTracker::default(),
+            tools,
+            brain_endpoint: brain_endpoint.trim_end_matches('/').to_string(),
+            mode: ModeHandle::default(),
+        }
+    }
+
+    /// The degraded mode this runtime follows; `start` hands it to the
+    /// supervisor.
+    pub fn mode(&self) -> &ModeHandle {
+        &self.mode
     }
 
-    pub async fn handle_text(&self, input: &str, auto_approve: bool) -> Result<Vec<CoreEvent>> {
+    /// Execute a tool call under its deadline; see `CallTracker::run`.
+    pub async fn execute(&self, call: &ToolCall) -> Result<Value> {
+        if call.tool == "knowledge.search" && self.mode.get() == Mode::RulesOnly {
+            bail!("munin-brain is down; knowledge.search is unavailable in rules-only mode");
+        }
+        let token = bpkg::CancelToken::default();
+        self.calls
+            .run(
+                &call.id,
+                &call.tool,
+                &token,
+                self.tools.execute(&call.id, &call.tool, &call.args, &token),
+            )
+            .await
+    }
+
+    /// `locale` is the speaker's, for munin-brain; its default when `None`.
+    pub async fn handle_text(
+        &self,
+        input: &str,
+        locale: Option<&str>,
+        auto_approve: bool,
+    ) -> Result<Vec<CoreEvent>> {
         let mut events = vec![CoreEvent::ResponseText(format!("Heard: {input}"))];
 
-        let (tool, args) = decide_tool(input);
-        let Some((tool, args)) = tool.zip(args) else {
-            events.push(CoreEvent::ResponseText(
-                "No tool selected. I can run: system status, read/write file, shell exec, network get.".into(),
-            ));
-            return Ok(events);
+        let (tool, args) = match decide_tool(input) {
+            (Some(tool), Some(args)) => (tool.to_string(), args),
+            // Not a command the rules know: let the brain decide, and ask
+            // the user when it is unsure rather than guessing.
+            _ => match self.ask_brain(input, locale).await {
+                Some(BrainDecision {
+                    tool: Some(tool),
+                    args,
+                    ..
+                }) => (tool, args),
+                Some(BrainDecision {
+                    intent,
+                    question: Some(question),
+                    ..
+                }) if intent == "clarify" => {
+                    events.push(CoreEvent::ResponseText(question));
+                    return Ok(events);
+                }
+                _ => {
+                    events.push(CoreEvent::ResponseText(
+                        "No tool selected. I can run: system status, read/write/list/find/delete files, shell exec, network get, search docs.".into(),
+                    ));
+                    return Ok(events);
+                }
+            },
         };
+        events.extend(self.propose(&tool, args, auto_approve).await);
+        Ok(events)
+    }
 
+    /// Put a call through policy: refused, queued for approval (with its
+    /// preview) or run. Callers queue `ToolCall`s needing confirmation.
+    pub async fn propose(&self, tool: &str, args: Value, auto_approve: bool) -> Vec<CoreEvent> {
+        let mut events = Vec::new();
         let decision = self.policy.evaluate(tool, &args);
         if !decision.allowed {
             events.push(CoreEvent::Error(decision.reason));
-            return Ok(events);
+            return events;
         }
 
+        let needs_approval = decision.requires_confirmation && !auto_approve;
         let call = ToolCall {
             id: Uuid::new_v4().to_string(),
             tool: tool.to_string(),
             args: args.clone(),
             requires_confirmation: decision.requires_confirmation,
+            preview: if needs_approval {
+                preview::preview(tool, &args).await
+            } else {
+                None
+            },
         };
         events.push(CoreEvent::ToolCall(call.clone()));
 
-        if decision.requires_confirmation && !auto_approve {
+        if needs_approval {
+            self.calls.queue(&call.id, &call.tool);
             events.push(CoreEvent::ResponseText(format!(
                 "Tool {} requires confirmation: {}",
                 call.tool, decision.reason
             )));
-            return Ok(events);
+            return events;
         }
 
-        match ToolRouter::execute(&call.tool, &call.args).await {
+        match self.execute(&call).await {
             Ok(output) => events.push(CoreEvent::ToolResult(ToolResult {
                 id: call.id,
                 ok: true,
@@ -59,8 +149,38 @@ impl AgentRuntime {
                 output: json!({"error": e.to_string()}),
             })),
         }
+        events
+    }
 
-        Ok(events)
+    /// munin-brain's decision for `input`; `None` when it can't be reached.
+    async fn ask_brain(&self, input: &str, locale: Option<&str>) -> Option<BrainDecision> {
+        if self.mode.get() == Mode::RulesOnly {
+            return None;
+        }
+        #[derive(Deserialize)]
+        struct Reply {
+            decision: BrainDecision,
+        }
+        let reply = reqwest::Client::new()
+            .post(format!("{}/v1/decide", self.brain_endpoint))
+            .timeout(DECIDE_TIMEOUT)
+            .json(&json!({"transcript": input, "locale": locale}))
+            .send()
+            .await
+            .and_then(|r| r.error_for_status());
+        match reply {
+            Ok(r) => match r.json::<Reply>().await {
+                Ok(reply) => Some(reply.decision),
+                Err(e) => {
+                    tracing::warn!("unexpected decision from munin-brain: {e}");
+                    None
+                }
+            },
+            Err(e) => {
+                tracing::debug!("munin-brain did not decide: {e}");
+                None
+            }
+        }
     }
 }
 
@@ -81,12 +201,174 @@ fn decide_tool(input: &str) -> (Option<&'static str>, Option<serde_json::Value>)
             );
         }
     }
+    if let Some(path) = input.strip_prefix("list ") {
+        return (Some("file.list"), Some(json!({"path": path.trim()})));
+    }
+    if let Some(path) = input.strip_prefix("stat ") {
+        return (Some("file.stat"), Some(json!({"path": path.trim()})));
+    }
+    // "find *.md in ~/notes" searches names; "find trip in ~/notes" searches contents.
+    if let Some((what, dir)) = input
+        .strip_prefix("find ")
+        .and_then(|r| r.rsplit_once(" in "))
+    {
+        let key = if what.contains(['*', '?']) {
+            "name"
+        } else {
+            "content"
+        };
+        return (
+            Some("file.search"),
+            Some(json!({"path": dir.trim(), key: what.trim()})),
+        );
+    }
+    if let Some(query) = input.strip_prefix("search packages ") {
+        return (Some("package.search"), Some(json!({"query": query.trim()})));
+    }
+    if let Some(names) = input.strip_prefix("install ") {
+        let names: Vec<&str> = names.split_whitespace().collect();
+        return (Some("package.install"), Some(json!({"names": names})));
+    }
+    if let Some(names) = input.strip_prefix("uninstall ") {
+        let names: Vec<&str> = names.split_whitespace().collect();
+        return (Some("package.remove"), Some(json!({"names": names})));
+    }
+    if let Some(path) = input.strip_prefix("delete ") {
+        return (Some("file.delete"), Some(json!({"path": path.trim()})));
+    }
+    if let Some(target) = input.strip_prefix("undo ") {
+        return (Some("file.undo"), Some(json!({"token": target.trim()})));
+    }
     if let Some(cmd) = input.strip_prefix("exec ") {
         return (Some("shell.exec"), Some(json!({"command": cmd.trim()})));
     }
     if let Some(url) = input.strip_prefix("get ") {
         return (Some("network.get"), Some(json!({"url": url.trim()})));
     }
+    if let Some(query) = input.strip_prefix("search docs ") {
+        return (
+            Some("knowledge.search"),
+            Some(json!({"query": query.trim()})),
+        );
+    }
 
     (None, None)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::undo::UndoJournal;
+
+    /// Answers each `/v1/decide` request with the next of `decisions`.
+    fn fake_brain(decisions: Vec<Value>) -> String {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let addr = format!("http://{}", server.server_addr().to_ip().unwrap());
+        std::thread::spawn(move || {
+            for decision in decisions {
+                let req = server.recv().unwrap();
+                assert_eq!(req.url(), "/v1/decide");
+                let reply = json!({"decision": decision}).to_string();
+                req.respond(tiny_http::Response::from_string(reply))
+                    .unwrap();
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn asks_the_brain_and_relays_its_questions() {
+        let dir = std::env::temp_dir().join(format!("munin-agent-{}", std::process::id()));
+        let brain = fake_brain(vec![
+            json!({"intent": "clarify", "tool": null, "args": {}, "confidence": 0.95,
+                   "question": "Which file should I write?"}),
+            json!({"intent": "system_exec", "tool": "shell.exec", "args": {"command": "uptime"},
+                   "confidence": 0.8}),
+        ]);
+        let tools = ToolRouter::new(UndoJournal::new(&dir), bpkg::Bpkg::open(&dir), &brain);
+        let agent = AgentRuntime::new(PolicyEngine::default(), tools, &brain);
+
+        let events = agent
+            .handle_text("write a file", None, false)
+            .await
+            .unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(
+            matches!(&events[1], CoreEvent::ResponseText(q) if q == "Which file should I write?")
+        );
+
+        // A tool the brain picks still goes through policy and approval.
+        let events = agent
+            .handle_text("run uptime please", Some("en-US"), false)
+            .await
+            .unwrap();
+        assert!(matches!(&events[1], CoreEvent::ToolCall(c) if c.tool == "shell.exec"));
+        assert!(
+            matches!(&events[2], CoreEvent::ResponseText(t) if t.contains("requires confirmation"))
+        );
+
+        // Rules alone when the brain is gone.
+        let offline = ToolRouter::new(
+            UndoJournal::new(&dir),
+            bpkg::Bpkg::open(&dir),
+            "http://127.0.0.1:9",
+        );
+        let agent = AgentRuntime::new(PolicyEngine::default(), offline, "http://127.0.0.1:9");
+        let events = agent
+            .handle_text("write a file", None, false)
+            .await
+            .unwrap();
+        assert!(
+            matches!(&events[1], CoreEvent::ResponseText(t) if t.starts_with("No tool selected"))
+        );
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn undo_waits_for_approval() {
+        let dir = std::env::temp_dir().join(format!("munin-agent-undo-{}", std::process::id()));
+        let file = dir.join("notes.txt");
+        let journal = UndoJournal::new(dir.join("undo"));
+        let token = journal.write("c1", &file, Some(b"new")).unwrap().unwrap();
+        let tools = ToolRouter::new(journal, bpkg::Bpkg::open(&dir), "http://127.0.0.1:9");
+        let agent = AgentRuntime::new(PolicyEngine::default(), tools, "http://127.0.0.1:9");
+
+        let events = agent
+            .propose("file.undo", json!({"token": token, "force": true}), false)
+            .await;
+        assert!(matches!(&events[0], CoreEvent::ToolCall(c) if c.requires_confirmation));
+        assert!(!events.iter().any(|e| matches!(e, CoreEvent::ToolResult(_))));
+        assert!(file.exists());
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn rules_only_mode_leaves_the_brain_alone() {
+        let dir = std::env::temp_dir().join(format!("munin-agent-ro-{}", std::process::id()));
+        // Would answer with a question if it were asked.
+        let brain = fake_brain(vec![
+            json!({"intent": "clarify", "question": "Which file?"}),
+        ]);
+        let tools = ToolRouter::new(UndoJournal::new(&dir), bpkg::Bpkg::open(&dir), &brain);
+        let agent = AgentRuntime::new(PolicyEngine::default(), tools, &brain);
+        agent.mode().set(Mode::RulesOnly);
+
+        let events = agent
+            .handle_text("write a file", None, false)
+            .await
+            .unwrap();
+        assert!(
+            matches!(&events[1], CoreEvent::ResponseText(t) if t.starts_with("No tool selected"))
+        );
+        let call = ToolCall {
+            id: "k1".into(),
+            tool: "knowledge.search".into(),
+            args: json!({"query": "boat"}),
+            requires_confirmation: false,
+            preview: None,
+        };
+        let err = agent.execute(&call).await.unwrap_err();
+        assert!(err.to_string().contains("rules-only"));
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
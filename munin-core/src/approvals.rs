@@ -0,0 +1,422 @@
+use crate::policy::{risk_for, Risk};
+use crate::preview::Preview;
+use crate::protocol::ToolCall;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Wrong PINs tolerated per call before it is denied outright.
+const MAX_PIN_ATTEMPTS: u32 = 3;
+
+/// An independent way the user confirmed a call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Factor {
+    /// Approve button in munin-ui (or any API client).
+    Ui,
+    /// Spoken yes relayed by munin-sts, which proves itself with the voice
+    /// token; clients can't claim this factor.
+    Voice,
+    /// The configured approval PIN.
+    Pin,
+}
+
+/// Which calls need more than one factor, and the secrets that prove a
+/// factor other than a click.
+#[derive(Debug, Clone, Default)]
+pub struct ApprovalRules {
+    /// Tool names, `prefix.*` patterns or risk classes (`destructive`)
+    /// treated as critical.
+    two_factor: Vec<String>,
+    pin: Option<String>,
+    /// Bearer token munin-sts sends with spoken answers.
+    voice_token: Option<String>,
+}
+
+impl ApprovalRules {
+    pub fn new(two_factor: Vec<String>, pin: Option<String>) -> Self {
+        Self {
+            two_factor,
+            pin: pin.filter(|p| !p.is_empty()),
+            voice_token: None,
+        }
+    }
+
+    pub fn with_voice_token(mut self, token: String) -> Self {
+        self.voice_token = Some(token).filter(|t| !t.is_empty());
+        self
+    }
+
+    /// The factor a confirmation request counts as: `voice` only with the
+    /// voice token, `ui` for everything else.
+    pub fn factor(&self, bearer: Option<&str>) -> Factor {
+        match (&self.voice_token, bearer) {
+            (Some(expected), Some(token)) if same_secret(expected, token) => Factor::Voice,
+            _ => Factor::Ui,
+        }
+    }
+
+    /// Distinct factors needed before `tool` runs.
+    pub fn required(&self, tool: &str) -> usize {
+        let critical = self
+            .two_factor
+            .iter()
+            .any(|rule| match rule.strip_suffix('*') {
+                Some(prefix) => tool.starts_with(prefix),
+                None => tool == rule || risk_name(tool) == Some(rule.as_str()),
+            });
+        if critical {
+            2
+        } else {
+            1
+        }
+    }
+
+    fn pin_matches(&self, pin: &str) -> bool {
+        self.pin
+            .as_deref()
+            .is_some_and(|expected| same_secret(expected, pin))
+    }
+}
+
+/// Compare every byte so timing doesn't leak the matching prefix.
+fn same_secret(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// The voice token shared with munin-sts, created (mode 0600) on first use.
+pub fn load_voice_token(path: &Path) -> Result<String> {
+    match std::fs::read_to_string(path) {
+        Ok(token) if !token.trim().is_empty() => return Ok(token.trim().to_string()),
+        Ok(_) => anyhow::bail!("voice token file {} is empty", path.display()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e).with_context(|| format!("failed reading {}", path.display())),
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("failed creating {}", parent.display()))?;
+    }
+    let token = uuid::Uuid::new_v4().simple().to_string();
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .with_context(|| format!("failed creating {}", path.display()))?;
+    file.write_all(token.as_bytes())?;
+    Ok(token)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PendingApproval {
+    pub id: String,
+    pub tool: String,
+    pub args: serde_json::Value,
+    pub preview: Option<Preview>,
+    /// One sentence suitable for reading aloud.
+    pub summary: String,
+    /// Spoken code naming this call in a voice answer ("yes 4711").
+    pub code: String,
+    pub required: usize,
+    pub factors: Vec<Factor>,
+}
+
+struct Entry {
+    call: ToolCall,
+    factors: Vec<Factor>,
+    pin_failures: u32,
+}
+
+#[derive(Debug)]
+pub enum Vote {
+    /// Enough factors collected; the call is removed from the queue.
+    Approved(ToolCall),
+    Denied(ToolCall),
+    /// Recorded, still waiting for another factor.
+    NeedMore {
+        have: Vec<Factor>,
+        need: usize,
+    },
+    BadPin {
+        attempts_left: u32,
+    },
+    NotFound,
+}
+
+/// Tool calls awaiting confirmation and the factors gathered for each.
+#[derive(Default)]
+pub struct ApprovalQueue {
+    rules: ApprovalRules,
+    pending: Mutex<HashMap<String, Entry>>,
+}
+
+impl ApprovalQueue {
+    pub fn new(rules: ApprovalRules) -> Self {
+        Self {
+            rules,
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn required(&self, tool: &str) -> usize {
+        self.rules.required(tool)
+    }
+
+    pub fn factor(&self, bearer: Option<&str>) -> Factor {
+        self.rules.factor(bearer)
+    }
+
+    pub fn push(&self, call: ToolCall) {
+        self.pending.lock().unwrap().insert(
+            call.id.clone(),
+            Entry {
+                call,
+                factors: Vec::new(),
+                pin_failures: 0,
+            },
+        );
+    }
+
+    pub fn remove(&self, id: &str) -> Option<ToolCall> {
+        self.pending.lock().unwrap().remove(id).map(|e| e.call)
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    pub fn list(&self) -> Vec<PendingApproval> {
+        let mut out: Vec<PendingApproval> = self
+            .pending
+            .lock()
+            .unwrap()
+            .values()
+            .map(|e| PendingApproval {
+                id: e.call.id.clone(),
+                tool: e.call.tool.clone(),
+                args: e.call.args.clone(),
+                preview: e.call.preview.clone(),
+                summary: summarize(&e.call),
+                code: spoken_code(&e.call.id),
+                required: self.rules.required(&e.call.tool),
+                factors: e.factors.clone(),
+            })
+            .collect();
+        out.sort_by(|a, b| a.id.cmp(&b.id));
+        out
+    }
+
+    /// Record one confirmation. A deny from any factor denies the call; a PIN
+    /// counts as its own factor alongside `via`.
+    pub fn vote(&self, id: &str, approve: bool, via: Factor, pin: Option<&str>) -> Vote {
+        let mut pending = self.pending.lock().unwrap();
+        let Some(entry) = pending.get_mut(id) else {
+            return Vote::NotFound;
+        };
+
+        if !approve {
+            let entry = pending.remove(id).unwrap();
+            return Vote::Denied(entry.call);
+        }
+
+        if let Some(pin) = pin {
+            if !self.rules.pin_matches(pin) {
+                entry.pin_failures += 1;
+                if entry.pin_failures >= MAX_PIN_ATTEMPTS {
+                    let entry = pending.remove(id).unwrap();
+                    return Vote::Denied(entry.call);
+                }
+                return Vote::BadPin {
+                    attempts_left: MAX_PIN_ATTEMPTS - entry.pin_failures,
+                };
+            }
+            if !entry.factors.contains(&Factor::Pin) {
+                entry.factors.push(Factor::Pin);
+            }
+        }
+        if via != Factor::Pin && !entry.factors.contains(&via) {
+            entry.factors.push(via);
+        }
+
+        let need = self.rules.required(&entry.call.tool);
+        if entry.factors.len() >= need {
+            let entry = pending.remove(id).unwrap();
+            Vote::Approved(entry.call)
+        } else {
+            Vote::NeedMore {
+                have: entry.factors.clone(),
+                need,
+            }
+        }
+    }
+}
+
+fn risk_name(tool: &str) -> Option<&'static str> {
+    risk_for(tool).map(|(risk, _)| match risk {
+        Risk::ReadOnly => "read_only",
+        Risk::Write => "write",
+        Risk::Destructive => "destructive",
+    })
+}
+
+/// Short spoken description of a call, e.g. for munin-sts to read out.
+pub fn summarize(call: &ToolCall) -> String {
+    let arg = |k: &str| call.args.get(k).and_then(|v| v.as_str()).unwrap_or("?");
+    let what = match call.tool.as_str() {
+        "shell.exec" => format!("run the command {}", arg("command")),
+        "file.write" => match &call.preview {
+            Some(Preview::FileDiff { exists: false, .. }) => {
+                format!("create the file {}", arg("path"))
+            }
+            _ => format!("overwrite the file {}", arg("path")),
+        },
+        "file.delete" => format!("delete {}", arg("path")),
+        "file.move" => format!("move {} to {}", arg("from"), arg("to")),
+        "file.copy" => format!("copy {} to {}", arg("from"), arg("to")),
+        "package.install" | "package.remove" => {
+            let names = match call.args.get("names").and_then(|v| v.as_array()) {
+                Some(list) => list
+                    .iter()
+                    .filter_map(|v| v.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                None => arg("name").to_string(),
+            };
+            let verb = if call.tool == "package.install" {
+                "install"
+            } else {
+                "remove"
+            };
+            format!("{verb} the package {names}")
+        }
+        "network.post" => {
+            let host = reqwest::Url::parse(arg("url"))
+                .ok()
+                .and_then(|u| u.host_str().map(str::to_string))
+                .unwrap_or_else(|| arg("url").to_string());
+            format!("send data to {host}")
+        }
+        tool => format!("use {tool}"),
+    };
+    format!("Munin wants to {what}. Call {}.", spoken_code(&call.id))
+}
+
+/// Four digits derived from a call id, used to refer to it by voice. Digits,
+/// unlike hex, can't spell a word ("dead", "beef") said in passing.
+pub fn spoken_code(id: &str) -> String {
+    let hash = id
+        .bytes()
+        .fold(0u32, |h, b| h.wrapping_mul(31).wrapping_add(b.into()));
+    format!("{:04}", hash % 10_000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn call(id: &str, tool: &str) -> ToolCall {
+        ToolCall {
+            id: id.into(),
+            tool: tool.into(),
+            args: json!({"command": "reboot"}),
+            requires_confirmation: true,
+            preview: None,
+        }
+    }
+
+    #[test]
+    fn single_factor_approves_ordinary_calls() {
+        let queue = ApprovalQueue::new(ApprovalRules::default());
+        queue.push(call("a1", "file.write"));
+        assert!(matches!(
+            queue.vote("a1", true, Factor::Voice, None),
+            Vote::Approved(_)
+        ));
+        assert_eq!(queue.len(), 0);
+    }
+
+    #[test]
+    fn critical_calls_need_two_distinct_factors() {
+        let rules = ApprovalRules::new(vec!["shell.*".into()], Some("4242".into()));
+        let queue = ApprovalQueue::new(rules);
+        queue.push(call("b1", "shell.exec"));
+
+        assert!(matches!(
+            queue.vote("b1", true, Factor::Ui, None),
+            Vote::NeedMore { need: 2, .. }
+        ));
+        // Clicking again is not a second factor.
+        assert!(matches!(
+            queue.vote("b1", true, Factor::Ui, None),
+            Vote::NeedMore { .. }
+        ));
+        assert!(matches!(
+            queue.vote("b1", true, Factor::Voice, None),
+            Vote::Approved(_)
+        ));
+
+        let by_risk = ApprovalRules::new(vec!["destructive".into()], None);
+        assert_eq!(by_risk.required("file.delete"), 2);
+        assert_eq!(by_risk.required("file.copy"), 1);
+
+        queue.push(call("b2", "shell.exec"));
+        assert!(matches!(
+            queue.vote("b2", true, Factor::Ui, Some("4242")),
+            Vote::Approved(_)
+        ));
+    }
+
+    #[test]
+    fn wrong_pins_eventually_deny() {
+        let rules = ApprovalRules::new(vec!["shell.exec".into()], Some("4242".into()));
+        let queue = ApprovalQueue::new(rules);
+        queue.push(call("c1", "shell.exec"));
+        assert!(matches!(
+            queue.vote("c1", true, Factor::Ui, Some("0000")),
+            Vote::BadPin { attempts_left: 2 }
+        ));
+        queue.vote("c1", true, Factor::Ui, Some("0000"));
+        assert!(matches!(
+            queue.vote("c1", true, Factor::Ui, Some("0000")),
+            Vote::Denied(_)
+        ));
+        assert!(matches!(
+            queue.vote("c1", true, Factor::Ui, Some("4242")),
+            Vote::NotFound
+        ));
+    }
+
+    #[test]
+    fn summary_names_the_call() {
+        let s = summarize(&call("deadbeef", "shell.exec"));
+        assert_eq!(
+            s,
+            format!(
+                "Munin wants to run the command reboot. Call {}.",
+                spoken_code("deadbeef")
+            )
+        );
+        assert!(spoken_code("deadbeef").chars().all(|c| c.is_ascii_digit()));
+        assert_eq!(spoken_code("deadbeef").len(), 4);
+    }
+
+    #[test]
+    fn only_the_voice_token_makes_a_voice_factor() {
+        let rules =
+            ApprovalRules::new(vec!["shell.exec".into()], None).with_voice_token("s3cret".into());
+        assert_eq!(rules.factor(Some("s3cret")), Factor::Voice);
+        assert_eq!(rules.factor(Some("guess")), Factor::Ui);
+        assert_eq!(rules.factor(None), Factor::Ui);
+        assert_eq!(ApprovalRules::default().factor(Some("")), Factor::Ui);
+    }
+}
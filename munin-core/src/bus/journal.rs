@@ -0,0 +1,70 @@
+use super::{Message, TopicFilter};
+use anyhow::{Context, Result};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+/// JSON-lines log of messages on topics marked persistent. Once it reaches
+/// `max_bytes` it is rotated to `bus.jsonl.1`, replacing the previous one,
+/// so the two files never hold much more than twice that.
+pub struct Journal {
+    path: PathBuf,
+    rotated: PathBuf,
+    max_bytes: u64,
+}
+
+impl Journal {
+    pub fn open(dir: impl Into<PathBuf>, max_bytes: u64) -> Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("failed creating bus journal dir {}", dir.display()))?;
+        Ok(Self {
+            path: dir.join("bus.jsonl"),
+            rotated: dir.join("bus.jsonl.1"),
+            max_bytes,
+        })
+    }
+
+    pub fn append(&self, msg: &Message) -> Result<()> {
+        let line = format!("{}\n", serde_json::to_string(msg)?);
+        let size = std::fs::metadata(&self.path).map_or(0, |m| m.len());
+        if size > 0 && size + line.len() as u64 > self.max_bytes {
+            std::fs::rename(&self.path, &self.rotated)
+                .with_context(|| format!("failed rotating {}", self.path.display()))?;
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("failed opening {}", self.path.display()))?;
+        file.write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    /// Messages matching `filter` with a timestamp at or after `since_ms`,
+    /// from the rotated file first.
+    pub fn replay(&self, filter: &TopicFilter, since_ms: u64) -> Result<Vec<Message>> {
+        let mut out = Vec::new();
+        for path in [&self.rotated, &self.path] {
+            let file = match std::fs::File::open(path) {
+                Ok(f) => f,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    return Err(e).with_context(|| format!("failed opening {}", path.display()))
+                }
+            };
+            for line in BufReader::new(file).lines() {
+                let line = line?;
+                match serde_json::from_str::<Message>(&line) {
+                    Ok(msg) if msg.timestamp >= since_ms && filter.matches(&msg.topic) => {
+                        out.push(msg)
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        tracing::warn!("skipping corrupt journal line in {}: {}", path.display(), e)
+                    }
+                }
+            }
+        }
+        Ok(out)
+    }
+}
@@ -1,11 +1,37 @@
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::{broadcast, RwLock};
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, RwLock};
 use uuid::Uuid;
 
+mod journal;
+mod registry;
+mod topic;
+
+use journal::Journal;
+use registry::Registry;
+pub use registry::{AgentInfo, AgentStatus, Health};
+pub use topic::{Topic, TopicFilter};
+
 pub const DEFAULT_BUFFER_SIZE: usize = 64;
+pub const DEFAULT_HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Agents silent for this many heartbeat timeouts are unregistered.
+pub const EVICT_AFTER_TIMEOUTS: u32 = 3;
+/// Messages kept in memory for `MessageBus::tail`.
+pub const RECENT_CAPACITY: usize = 256;
+/// Size at which the journal is rotated; it keeps one rotated file.
+pub const DEFAULT_JOURNAL_MAX_BYTES: u64 = 8 * 1024 * 1024;
+
+// Well-known topics used by the core services.
+pub const SYSTEM: &str = "system";
+/// Join/leave events are published on `system/agents/joined` and `system/agents/left`.
+pub const AGENTS: &str = "system/agents";
+pub const TOOLS: &str = "tools";
+pub const ERROR: &str = "error";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
@@ -14,122 +40,408 @@ pub struct Message {
     pub payload: serde_json::Value,
     pub sender: AgentId,
     pub timestamp: u64,
+    /// Monotonic per-bus sequence number, assigned on publish.
+    #[serde(default)]
+    pub seq: u64,
+    /// Set on replies: the id of the request message being answered.
+    #[serde(default)]
+    pub correlation_id: Option<String>,
+    /// Set on requests: the sender is waiting for a `MessageBus::reply`.
+    #[serde(default)]
+    pub expects_reply: bool,
+    /// Kept as the topic's last message and handed to new subscribers.
+    #[serde(default)]
+    pub retained: bool,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
-pub enum Topic {
-    System,
-    Voice,
-    Files,
-    Network,
-    Shell,
-    Calendar,
-    UI,
-    Error,
+pub struct AgentId(pub String);
+
+#[derive(Debug, Clone)]
+pub struct BusConfig {
+    /// Capacity of each subscriber's queue before messages are dropped.
+    pub queue_size: usize,
+    /// Directory for the on-disk journal; persistence is off when `None`.
+    pub journal_dir: Option<PathBuf>,
+    /// Topic patterns whose messages are appended to the journal for replay.
+    pub persistent_topics: Vec<String>,
+    /// Journal size at which it is rotated, so it stays under twice this.
+    pub journal_max_bytes: u64,
+    /// An agent without a heartbeat for this long is reported stale.
+    pub heartbeat_timeout: Duration,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
-pub struct AgentId(pub String);
+impl Default for BusConfig {
+    fn default() -> Self {
+        Self {
+            queue_size: DEFAULT_BUFFER_SIZE,
+            journal_dir: None,
+            persistent_topics: vec!["error/#".into(), "tools/shell/#".into()],
+            journal_max_bytes: DEFAULT_JOURNAL_MAX_BYTES,
+            heartbeat_timeout: DEFAULT_HEARTBEAT_TIMEOUT,
+        }
+    }
+}
+
+/// Outcome of publishing a message.
+#[derive(Debug, Clone, Serialize)]
+pub struct Delivery {
+    pub id: String,
+    pub delivered: usize,
+    /// Subscribers whose queue was full; the message was not delivered to them.
+    pub dropped: Vec<AgentId>,
+}
+
+struct Subscriber {
+    agent: AgentId,
+    filter: TopicFilter,
+    tx: mpsc::Sender<Message>,
+    dropped: Arc<AtomicU64>,
+}
+
+/// A bounded per-subscriber queue. Retained messages are yielded before
+/// live traffic.
+pub struct Subscription {
+    backlog: VecDeque<Message>,
+    rx: mpsc::Receiver<Message>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Subscription {
+    pub async fn recv(&mut self) -> Option<Message> {
+        if let Some(msg) = self.backlog.pop_front() {
+            return Some(msg);
+        }
+        self.rx.recv().await
+    }
+
+    /// Number of messages dropped for this subscriber since the last call.
+    pub fn take_dropped(&self) -> u64 {
+        self.dropped.swap(0, Ordering::Relaxed)
+    }
+}
 
 pub struct MessageBus {
-    topics: Arc<RwLock<HashMap<Topic, broadcast::Sender<Message>>>>,
-    agents: Arc<RwLock<Vec<AgentId>>>,
+    subscribers: Arc<RwLock<Vec<Subscriber>>>,
+    agents: Arc<RwLock<Registry>>,
+    heartbeat_timeout: Duration,
+    retained: Arc<RwLock<HashMap<Topic, Message>>>,
+    recent: Arc<Mutex<VecDeque<Message>>>,
+    replies: Arc<Mutex<HashMap<String, oneshot::Sender<Message>>>>,
+    journal: Option<Journal>,
+    persistent_topics: Vec<TopicFilter>,
+    queue_size: usize,
+    seq: AtomicU64,
 }
 
 impl MessageBus {
+    #[cfg(test)]
     pub async fn new() -> Result<Self> {
-        let bus = Self {
-            topics: Arc::new(RwLock::new(HashMap::new())),
-            agents: Arc::new(RwLock::new(Vec::new())),
-        };
+        Self::with_config(BusConfig::default()).await
+    }
 
-        for topic in [
-            Topic::System,
-            Topic::Voice,
-            Topic::Files,
-            Topic::Network,
-            Topic::Shell,
-            Topic::Calendar,
-            Topic::UI,
-            Topic::Error,
-        ] {
-            bus.ensure_topic(topic).await;
-        }
+    pub async fn with_config(config: BusConfig) -> Result<Self> {
+        let journal = config
+            .journal_dir
+            .map(|dir| Journal::open(dir, config.journal_max_bytes))
+            .transpose()?;
+        let persistent_topics = config
+            .persistent_topics
+            .iter()
+            .map(|p| TopicFilter::new(p))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            subscribers: Arc::new(RwLock::new(Vec::new())),
+            agents: Arc::new(RwLock::new(Registry::new(
+                config.heartbeat_timeout.as_millis() as u64,
+            ))),
+            heartbeat_timeout: config.heartbeat_timeout,
+            retained: Arc::new(RwLock::new(HashMap::new())),
+            recent: Arc::new(Mutex::new(VecDeque::with_capacity(RECENT_CAPACITY))),
+            replies: Arc::new(Mutex::new(HashMap::new())),
+            journal,
+            persistent_topics,
+            queue_size: config.queue_size.max(1),
+            seq: AtomicU64::new(0),
+        })
+    }
 
-        Ok(bus)
+    pub async fn send(
+        &self,
+        topic: &str,
+        payload: impl Into<serde_json::Value>,
+    ) -> Result<Delivery> {
+        let msg = new_message(Topic::new(topic)?, payload.into(), None, false);
+        self.publish(msg).await
     }
 
-    async fn ensure_topic(&self, topic: Topic) {
-        let mut map = self.topics.write().await;
-        map.entry(topic).or_insert_with(|| {
-            let (tx, _) = broadcast::channel(DEFAULT_BUFFER_SIZE);
-            tx
-        });
+    /// Publish and keep the message as `topic`'s retained value.
+    pub async fn send_retained(
+        &self,
+        topic: &str,
+        payload: impl Into<serde_json::Value>,
+    ) -> Result<Delivery> {
+        let mut msg = new_message(Topic::new(topic)?, payload.into(), None, false);
+        msg.retained = true;
+        self.publish(msg).await
     }
 
-    pub async fn subscribe(&self, agent: AgentId, topic: Topic) -> broadcast::Receiver<Message> {
-        self.ensure_topic(topic.clone()).await;
-        self.agents.write().await.push(agent);
+    /// Retained messages on topics matching `pattern`.
+    pub async fn retained(&self, pattern: &str) -> Result<Vec<Message>> {
+        Ok(self.retained_matching(&TopicFilter::new(pattern)?).await)
+    }
 
-        let map = self.topics.read().await;
-        map.get(&topic).expect("topic exists").subscribe()
+    /// Journaled messages matching `pattern` from `since_ms` on, oldest first,
+    /// for callers that start late.
+    pub fn replay(&self, pattern: &str, since_ms: u64) -> Result<Vec<Message>> {
+        let filter = TopicFilter::new(pattern)?;
+        self.journal
+            .as_ref()
+            .ok_or_else(|| anyhow!("bus journal is not enabled"))?
+            .replay(&filter, since_ms)
     }
 
-    pub async fn send(&self, topic: Topic, payload: impl Into<serde_json::Value>) -> Result<()> {
-        self.ensure_topic(topic.clone()).await;
-        let msg = Message {
-            id: Uuid::new_v4().to_string(),
-            topic: topic.clone(),
-            payload: payload.into(),
-            sender: AgentId("system".into()),
-            timestamp: now_ms(),
+    /// Recent messages matching `pattern` with a sequence number above `after_seq`.
+    pub fn tail(&self, pattern: &str, after_seq: u64) -> Result<Vec<Message>> {
+        let filter = TopicFilter::new(pattern)?;
+        let recent = self.recent.lock().unwrap();
+        Ok(recent
+            .iter()
+            .filter(|m| m.seq > after_seq && filter.matches(&m.topic))
+            .cloned()
+            .collect())
+    }
+
+    async fn retained_matching(&self, filter: &TopicFilter) -> Vec<Message> {
+        let retained = self.retained.read().await;
+        let mut out: Vec<Message> = retained
+            .values()
+            .filter(|m| filter.matches(&m.topic))
+            .cloned()
+            .collect();
+        out.sort_by_key(|m| m.seq);
+        out
+    }
+
+    async fn publish(&self, mut msg: Message) -> Result<Delivery> {
+        msg.seq = self.seq.fetch_add(1, Ordering::Relaxed) + 1;
+
+        if self.persistent_topics.iter().any(|f| f.matches(&msg.topic)) {
+            if let Some(journal) = &self.journal {
+                if let Err(e) = journal.append(&msg) {
+                    tracing::warn!("bus journal append failed: {}", e);
+                }
+            }
+        }
+        if msg.retained {
+            self.retained
+                .write()
+                .await
+                .insert(msg.topic.clone(), msg.clone());
+        }
+        {
+            let mut recent = self.recent.lock().unwrap();
+            if recent.len() == RECENT_CAPACITY {
+                recent.pop_front();
+            }
+            recent.push_back(msg.clone());
+        }
+
+        let mut delivery = Delivery {
+            id: msg.id.clone(),
+            delivered: 0,
+            dropped: Vec::new(),
         };
 
-        let map = self.topics.read().await;
-        if let Some(tx) = map.get(&topic) {
-            let _ = tx.send(msg);
+        let mut subs = self.subscribers.write().await;
+        subs.retain(|sub| {
+            if !sub.filter.matches(&msg.topic) {
+                return !sub.tx.is_closed();
+            }
+            match sub.tx.try_send(msg.clone()) {
+                Ok(()) => {
+                    delivery.delivered += 1;
+                    true
+                }
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    sub.dropped.fetch_add(1, Ordering::Relaxed);
+                    tracing::warn!(
+                        "bus queue full for {}; dropped {} on {}",
+                        sub.agent.0,
+                        msg.id,
+                        msg.topic
+                    );
+                    delivery.dropped.push(sub.agent.clone());
+                    true
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => false,
+            }
+        });
+
+        Ok(delivery)
+    }
+
+    /// Register or refresh an agent. Publishes a join event the first time.
+    pub async fn register(&self, info: AgentInfo) -> Result<()> {
+        let joined = self.agents.write().await.register(info.clone(), now_ms());
+        if joined {
+            tracing::info!("agent joined: {}", info.id.0);
+            self.send(&format!("{AGENTS}/joined"), serde_json::to_value(&info)?)
+                .await?;
         }
         Ok(())
     }
 
-    pub async fn list_agents(&self) -> Result<Vec<String>> {
-        let agents = self.agents.read().await;
-        Ok(agents.iter().map(|a| a.0.clone()).collect())
+    /// Record liveness. Fails for unknown agents so they know to re-register.
+    pub async fn heartbeat(&self, id: &AgentId) -> Result<()> {
+        if !self.agents.write().await.heartbeat(id, now_ms()) {
+            bail!("agent {} is not registered", id.0);
+        }
+        Ok(())
+    }
+
+    pub async fn unregister(&self, id: &AgentId) -> Result<bool> {
+        let removed = self.agents.write().await.unregister(id);
+        match removed {
+            Some(info) => {
+                self.announce_left(&info, "unregistered").await?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    pub async fn list_agents(&self) -> Result<Vec<AgentStatus>> {
+        Ok(self.agents.read().await.list(now_ms()))
     }
 
-    pub async fn start_voice_mode(&self, api_endpoint: String) -> Result<()> {
-        tracing::info!("Voice/STS mode active. Endpoint: {}", api_endpoint);
-        self.run().await
+    /// Unregister agents that stopped heartbeating and announce their departure.
+    pub async fn reap_expired(&self) -> Result<Vec<AgentId>> {
+        let evict_after = self.heartbeat_timeout * EVICT_AFTER_TIMEOUTS;
+        let expired = self
+            .agents
+            .write()
+            .await
+            .reap(now_ms(), evict_after.as_millis() as u64);
+        for info in &expired {
+            self.announce_left(info, "heartbeat_timeout").await?;
+        }
+        Ok(expired.into_iter().map(|i| i.id).collect())
     }
 
+    async fn announce_left(&self, info: &AgentInfo, reason: &str) -> Result<()> {
+        tracing::info!("agent left: {} ({})", info.id.0, reason);
+        self.send(
+            &format!("{AGENTS}/left"),
+            serde_json::json!({"id": info.id, "reason": reason}),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Housekeeping loop: evicts agents whose heartbeats have lapsed.
     pub async fn run(&self) -> Result<()> {
+        let mut tick = tokio::time::interval(self.heartbeat_timeout / 2);
         loop {
-            tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+            tick.tick().await;
+            self.reap_expired().await?;
         }
     }
+}
 
-    pub async fn run_cli(&self) -> Result<()> {
-        use std::io::{self, Write};
-        println!("MuninOS CLI mode. Type 'quit' to exit.");
+/// In-process subscriptions and request/reply, for tasks linked into the
+/// core such as the supervisor; HTTP clients read the bus through `tail`.
+impl MessageBus {
+    /// Subscribe to every topic matching `pattern` (which may use `+`/`#`).
+    pub async fn subscribe(&self, agent: AgentId, pattern: &str) -> Result<Subscription> {
+        let filter = TopicFilter::new(pattern)?;
 
-        let stdin = io::stdin();
-        loop {
-            print!("> ");
-            io::stdout().flush()?;
+        let backlog = self.retained_matching(&filter).await.into();
+        let (tx, rx) = mpsc::channel(self.queue_size);
+        let dropped = Arc::new(AtomicU64::new(0));
+        self.subscribers.write().await.push(Subscriber {
+            agent,
+            filter,
+            tx,
+            dropped: dropped.clone(),
+        });
 
-            let mut input = String::new();
-            if stdin.read_line(&mut input)? == 0 {
-                break;
-            }
-            let input = input.trim();
-            if input.eq_ignore_ascii_case("quit") || input.is_empty() {
-                break;
+        Ok(Subscription {
+            backlog,
+            rx,
+            dropped,
+        })
+    }
+
+    /// Publish a request and wait for a correlated reply.
+    pub async fn request(
+        &self,
+        topic: &str,
+        payload: impl Into<serde_json::Value>,
+        timeout: Duration,
+    ) -> Result<Message> {
+        let msg = new_message(Topic::new(topic)?, payload.into(), None, true);
+        let id = msg.id.clone();
+
+        let (tx, rx) = oneshot::channel();
+        self.replies.lock().unwrap().insert(id.clone(), tx);
+
+        let delivery = self.publish(msg).await?;
+        if delivery.delivered == 0 {
+            self.replies.lock().unwrap().remove(&id);
+            bail!("no subscriber accepted request on {topic}");
+        }
+
+        match tokio::time::timeout(timeout, rx).await {
+            Ok(Ok(reply)) => Ok(reply),
+            Ok(Err(_)) => bail!("request {id} was abandoned"),
+            Err(_) => {
+                self.replies.lock().unwrap().remove(&id);
+                bail!("request {id} on {topic} timed out after {:?}", timeout)
             }
-            self.send(Topic::System, input.to_string()).await?;
-            println!("sent: {}", input);
         }
+    }
 
-        Ok(())
+    /// Answer a request received from a subscription.
+    pub fn reply(&self, request: &Message, payload: impl Into<serde_json::Value>) -> Result<()> {
+        if !request.expects_reply {
+            bail!("message {} is not a request", request.id);
+        }
+        let waiter = self
+            .replies
+            .lock()
+            .unwrap()
+            .remove(&request.id)
+            .ok_or_else(|| anyhow!("request {} already answered or timed out", request.id))?;
+
+        let reply = new_message(
+            request.topic.clone(),
+            payload.into(),
+            Some(request.id.clone()),
+            false,
+        );
+        waiter
+            .send(reply)
+            .map_err(|_| anyhow!("requester for {} went away", request.id))
+    }
+}
+
+fn new_message(
+    topic: Topic,
+    payload: serde_json::Value,
+    correlation_id: Option<String>,
+    expects_reply: bool,
+) -> Message {
+    Message {
+        id: Uuid::new_v4().to_string(),
+        topic,
+        payload,
+        sender: AgentId("system".into()),
+        timestamp: now_ms(),
+        seq: 0,
+        correlation_id,
+        expects_reply,
+        retained: false,
     }
 }
 
@@ -147,9 +459,168 @@ mod tests {
     #[tokio::test]
     async fn bus_smoke() {
         let bus = MessageBus::new().await.unwrap();
-        let mut rx = bus.subscribe(AgentId("tester".into()), Topic::System).await;
-        bus.send(Topic::System, "hello").await.unwrap();
+        let mut rx = bus
+            .subscribe(AgentId("tester".into()), SYSTEM)
+            .await
+            .unwrap();
+        bus.send(SYSTEM, "hello").await.unwrap();
         let msg = rx.recv().await.unwrap();
         assert_eq!(msg.payload, serde_json::Value::String("hello".into()));
     }
+
+    #[tokio::test]
+    async fn wildcard_subscription_sees_nested_topics() {
+        let bus = MessageBus::new().await.unwrap();
+        let mut rx = bus
+            .subscribe(AgentId("tools".into()), "tools/#")
+            .await
+            .unwrap();
+        bus.send("voice/transcript", "ignored").await.unwrap();
+        bus.send("tools/shell/progress", 50).await.unwrap();
+        let msg = rx.recv().await.unwrap();
+        assert_eq!(msg.topic.to_string(), "tools/shell/progress");
+        assert_eq!(bus.tail("tools/#", 0).unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn retained_message_reaches_new_subscriber() {
+        let bus = MessageBus::new().await.unwrap();
+        bus.send_retained("system/mode", "text-only").await.unwrap();
+        bus.send_retained("system/mode", "voice").await.unwrap();
+        let mut rx = bus
+            .subscribe(AgentId("late".into()), "system/+")
+            .await
+            .unwrap();
+        assert_eq!(rx.recv().await.unwrap().payload, serde_json::json!("voice"));
+    }
+
+    #[tokio::test]
+    async fn register_and_unregister_announce_on_bus() {
+        let bus = MessageBus::new().await.unwrap();
+        let mut rx = bus
+            .subscribe(AgentId("watcher".into()), "system/agents/+")
+            .await
+            .unwrap();
+        let info = AgentInfo {
+            id: AgentId("munin-sts".into()),
+            capabilities: vec!["voice".into()],
+            version: None,
+            pid: None,
+        };
+
+        bus.register(info.clone()).await.unwrap();
+        bus.register(info.clone()).await.unwrap();
+        bus.heartbeat(&info.id).await.unwrap();
+        assert_eq!(bus.list_agents().await.unwrap().len(), 1);
+        assert!(bus.unregister(&info.id).await.unwrap());
+
+        assert_eq!(
+            rx.recv().await.unwrap().topic.to_string(),
+            "system/agents/joined"
+        );
+        assert_eq!(
+            rx.recv().await.unwrap().topic.to_string(),
+            "system/agents/left"
+        );
+        assert!(bus.heartbeat(&info.id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn request_reply_roundtrip() {
+        let bus = Arc::new(MessageBus::new().await.unwrap());
+        let mut rx = bus.subscribe(AgentId("echo".into()), SYSTEM).await.unwrap();
+
+        let responder = bus.clone();
+        tokio::spawn(async move {
+            let req = rx.recv().await.unwrap();
+            responder.reply(&req, req.payload.clone()).unwrap();
+        });
+
+        let reply = bus
+            .request(SYSTEM, "ping", Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(reply.payload, serde_json::json!("ping"));
+        assert!(reply.correlation_id.is_some());
+    }
+
+    #[tokio::test]
+    async fn request_times_out_without_reply() {
+        let bus = MessageBus::new().await.unwrap();
+        let _rx = bus
+            .subscribe(AgentId("silent".into()), SYSTEM)
+            .await
+            .unwrap();
+        let err = bus
+            .request(SYSTEM, "ping", Duration::from_millis(20))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+    }
+
+    #[tokio::test]
+    async fn full_queue_reports_drops() {
+        let bus = MessageBus::with_config(BusConfig {
+            queue_size: 1,
+            ..BusConfig::default()
+        })
+        .await
+        .unwrap();
+        let rx = bus.subscribe(AgentId("slow".into()), SYSTEM).await.unwrap();
+
+        bus.send(SYSTEM, "one").await.unwrap();
+        let delivery = bus.send(SYSTEM, "two").await.unwrap();
+        assert_eq!(delivery.delivered, 0);
+        assert_eq!(delivery.dropped, vec![AgentId("slow".into())]);
+        assert_eq!(rx.take_dropped(), 1);
+    }
+
+    #[tokio::test]
+    async fn persisted_topic_replays_to_late_readers() {
+        let dir = std::env::temp_dir().join(format!("munin-bus-{}", Uuid::new_v4()));
+        let bus = MessageBus::with_config(BusConfig {
+            journal_dir: Some(dir.clone()),
+            ..BusConfig::default()
+        })
+        .await
+        .unwrap();
+
+        bus.send("error/tools", "disk full").await.unwrap();
+        bus.send(SYSTEM, "not journaled").await.unwrap();
+        let replayed = bus.replay("#", 0).unwrap();
+        assert_eq!(replayed.len(), 1);
+        assert_eq!(replayed[0].payload, serde_json::json!("disk full"));
+        assert!(bus.replay("#", now_ms() + 1000).unwrap().is_empty());
+
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn journal_rotates_instead_of_growing() {
+        let dir = std::env::temp_dir().join(format!("munin-bus-{}", Uuid::new_v4()));
+        let bus = MessageBus::with_config(BusConfig {
+            journal_dir: Some(dir.clone()),
+            journal_max_bytes: 1024,
+            ..BusConfig::default()
+        })
+        .await
+        .unwrap();
+
+        for i in 0..100 {
+            bus.send("error/tools", i).await.unwrap();
+        }
+        let size = |name: &str| std::fs::metadata(dir.join(name)).unwrap().len();
+        assert!(size("bus.jsonl") <= 1024 && size("bus.jsonl.1") <= 1024);
+        // What is left is the newest messages, in order.
+        let replayed = bus.replay("#", 0).unwrap();
+        let seqs: Vec<u64> = replayed
+            .iter()
+            .map(|m| m.payload.as_u64().unwrap())
+            .collect();
+        assert!(seqs.len() > 2 && seqs.len() < 100);
+        assert_eq!(seqs.last(), Some(&99));
+        assert!(seqs.windows(2).all(|w| w[1] == w[0] + 1));
+
+        std::fs::remove_dir_all(dir).ok();
+    }
 }
@@ -0,0 +1,156 @@
+use super::AgentId;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Metadata an agent supplies when it registers with the bus.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AgentInfo {
+    pub id: AgentId,
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(default)]
+    pub pid: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Health {
+    /// Heartbeat seen within the liveness timeout.
+    Alive,
+    /// Missed the liveness timeout; evicted once it reaches the eviction window.
+    Stale,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentStatus {
+    #[serde(flatten)]
+    pub info: AgentInfo,
+    pub registered_at: u64,
+    pub last_seen: u64,
+    pub health: Health,
+}
+
+struct Entry {
+    info: AgentInfo,
+    registered_at: u64,
+    last_seen: u64,
+}
+
+/// Registered agents keyed by id. Times are milliseconds since the epoch.
+pub struct Registry {
+    agents: HashMap<AgentId, Entry>,
+    timeout_ms: u64,
+}
+
+impl Registry {
+    pub fn new(timeout_ms: u64) -> Self {
+        Self {
+            agents: HashMap::new(),
+            timeout_ms,
+        }
+    }
+
+    /// Returns true when the agent was not registered before.
+    pub fn register(&mut self, info: AgentInfo, now: u64) -> bool {
+        match self.agents.get_mut(&info.id) {
+            Some(entry) => {
+                entry.info = info;
+                entry.last_seen = now;
+                false
+            }
+            None => {
+                self.agents.insert(
+                    info.id.clone(),
+                    Entry {
+                        info,
+                        registered_at: now,
+                        last_seen: now,
+                    },
+                );
+                true
+            }
+        }
+    }
+
+    pub fn heartbeat(&mut self, id: &AgentId, now: u64) -> bool {
+        match self.agents.get_mut(id) {
+            Some(entry) => {
+                entry.last_seen = now;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn unregister(&mut self, id: &AgentId) -> Option<AgentInfo> {
+        self.agents.remove(id).map(|e| e.info)
+    }
+
+    /// Remove agents silent for longer than `evict_after_ms`.
+    pub fn reap(&mut self, now: u64, evict_after_ms: u64) -> Vec<AgentInfo> {
+        let expired: Vec<AgentId> = self
+            .agents
+            .iter()
+            .filter(|(_, e)| now.saturating_sub(e.last_seen) > evict_after_ms)
+            .map(|(id, _)| id.clone())
+            .collect();
+        expired
+            .iter()
+            .filter_map(|id| self.unregister(id))
+            .collect()
+    }
+
+    pub fn list(&self, now: u64) -> Vec<AgentStatus> {
+        let mut out: Vec<AgentStatus> = self
+            .agents
+            .values()
+            .map(|e| AgentStatus {
+                info: e.info.clone(),
+                registered_at: e.registered_at,
+                last_seen: e.last_seen,
+                health: if now.saturating_sub(e.last_seen) <= self.timeout_ms {
+                    Health::Alive
+                } else {
+                    Health::Stale
+                },
+            })
+            .collect();
+        out.sort_by(|a, b| a.info.id.0.cmp(&b.info.id.0));
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(id: &str) -> AgentInfo {
+        AgentInfo {
+            id: AgentId(id.into()),
+            capabilities: vec!["voice".into()],
+            version: Some("0.1.0".into()),
+            pid: Some(42),
+        }
+    }
+
+    #[test]
+    fn reregistering_does_not_duplicate() {
+        let mut reg = Registry::new(1_000);
+        assert!(reg.register(info("sts"), 0));
+        assert!(!reg.register(info("sts"), 10));
+        assert_eq!(reg.list(10).len(), 1);
+    }
+
+    #[test]
+    fn missed_heartbeats_go_stale_then_evict() {
+        let mut reg = Registry::new(1_000);
+        reg.register(info("sts"), 0);
+        assert_eq!(reg.list(500)[0].health, Health::Alive);
+        assert_eq!(reg.list(1_500)[0].health, Health::Stale);
+        assert!(reg.reap(2_000, 3_000).is_empty());
+        assert_eq!(reg.reap(3_500, 3_000), vec![info("sts")]);
+        assert!(!reg.heartbeat(&AgentId("sts".into()), 3_600));
+    }
+}
@@ -0,0 +1,107 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A concrete, slash-separated topic path such as `voice/transcript`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Topic(String);
+
+impl Topic {
+    pub fn new(path: &str) -> Result<Self> {
+        for level in levels(path)? {
+            if level == "#" || level == "+" {
+                bail!("wildcards are only allowed in subscriptions: {path}");
+            }
+        }
+        Ok(Self(path.to_string()))
+    }
+}
+
+impl fmt::Display for Topic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// A subscription pattern. `+` matches exactly one level and a trailing `#`
+/// matches the parent level and everything beneath it, so `tools/#` matches
+/// `tools` and `tools/shell/progress`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopicFilter(String);
+
+impl TopicFilter {
+    pub fn new(pattern: &str) -> Result<Self> {
+        let parts = levels(pattern)?;
+        for (i, level) in parts.iter().enumerate() {
+            if level.contains('#') && (*level != "#" || i + 1 != parts.len()) {
+                bail!("'#' must be the whole last level: {pattern}");
+            }
+            if level.contains('+') && *level != "+" {
+                bail!("'+' must be a whole level: {pattern}");
+            }
+        }
+        Ok(Self(pattern.to_string()))
+    }
+
+    pub fn matches(&self, topic: &Topic) -> bool {
+        let mut pat = self.0.split('/');
+        let mut path = topic.0.split('/');
+        loop {
+            match (pat.next(), path.next()) {
+                (Some("#"), _) => return true,
+                (Some("+"), Some(_)) => {}
+                (Some(p), Some(t)) if p == t => {}
+                (None, None) => return true,
+                _ => return false,
+            }
+        }
+    }
+}
+
+impl fmt::Display for TopicFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+fn levels(path: &str) -> Result<Vec<&str>> {
+    if path.is_empty() {
+        bail!("topic must not be empty");
+    }
+    let parts: Vec<&str> = path.split('/').collect();
+    if parts.iter().any(|l| l.is_empty()) {
+        bail!("topic has an empty level: {path}");
+    }
+    Ok(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn m(pattern: &str, topic: &str) -> bool {
+        TopicFilter::new(pattern)
+            .unwrap()
+            .matches(&Topic::new(topic).unwrap())
+    }
+
+    #[test]
+    fn filter_matching() {
+        assert!(m("voice/transcript", "voice/transcript"));
+        assert!(m("tools/#", "tools/shell/progress"));
+        assert!(m("tools/#", "tools"));
+        assert!(m("tools/+/progress", "tools/shell/progress"));
+        assert!(!m("tools/+", "tools/shell/progress"));
+        assert!(!m("voice", "voice/transcript"));
+        assert!(m("#", "error"));
+    }
+
+    #[test]
+    fn rejects_malformed_paths() {
+        assert!(Topic::new("tools/#").is_err());
+        assert!(Topic::new("a//b").is_err());
+        assert!(TopicFilter::new("tools/#/x").is_err());
+        assert!(TopicFilter::new("tools/sh+").is_err());
+    }
+}
@@ -0,0 +1,267 @@
+use anyhow::{anyhow, bail, Result};
+use bpkg::CancelToken;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// Finished records kept for `GET /v1/calls` before the oldest are pruned.
+const MAX_FINISHED: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CallState {
+    /// Waiting for approval.
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+    TimedOut,
+}
+
+impl CallState {
+    pub fn is_finished(self) -> bool {
+        !matches!(self, CallState::Queued | CallState::Running)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CallRecord {
+    pub id: String,
+    pub tool: String,
+    pub state: CallState,
+    pub created_at: u64,
+    pub finished_at: Option<u64>,
+    pub deadline_ms: u64,
+}
+
+struct Entry {
+    record: CallRecord,
+    cancel: watch::Sender<bool>,
+    /// Shared with the tool, which can claim a point past which it finishes.
+    token: CancelToken,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CancelOutcome {
+    Cancelled,
+    AlreadyFinished(CallState),
+    /// The tool is committing its changes and will finish.
+    Committing,
+    NotFound,
+}
+
+/// Tracks tool calls from approval queue to completion and lets callers
+/// cancel them while queued or running.
+#[derive(Default)]
+pub struct CallTracker {
+    calls: Mutex<HashMap<String, Entry>>,
+}
+
+impl CallTracker {
+    /// Record a call that is waiting for approval.
+    pub fn queue(&self, id: &str, tool: &str) {
+        self.insert(id, tool, CallState::Queued);
+    }
+
+    /// Run `fut` as call `id`, enforcing the tool's deadline and honouring
+    /// `cancel`. The future is dropped on timeout or cancellation, which kills
+    /// child processes and aborts HTTP requests it owns. A tool that has
+    /// started committing through `token` (package transactions, file
+    /// writes) is left to finish instead.
+    pub async fn run<F>(&self, id: &str, tool: &str, token: &CancelToken, fut: F) -> Result<Value>
+    where
+        F: Future<Output = Result<Value>>,
+    {
+        let deadline = deadline_for(tool);
+        let mut cancelled = self.start(id, tool, token)?;
+        tokio::pin!(fut);
+
+        let finished = |res: Result<Value>| match res {
+            Ok(v) => (CallState::Done, Ok(v)),
+            Err(e) => (CallState::Failed, Err(e)),
+        };
+        let settled = tokio::select! {
+            res = tokio::time::timeout(deadline, &mut fut) => res.ok().map(finished),
+            _ = cancelled.wait_for(|c| *c) => Some((CallState::Cancelled, Err(anyhow!("tool call {id} was cancelled")))),
+        };
+        let outcome = match settled {
+            Some(outcome) => outcome,
+            // Past its commit point the call finishes whatever the deadline.
+            None if !token.cancel() => finished(fut.await),
+            None => (
+                CallState::TimedOut,
+                Err(anyhow!(
+                    "{tool} exceeded its {}s deadline",
+                    deadline.as_secs()
+                )),
+            ),
+        };
+
+        self.finish(id, outcome.0);
+        outcome.1
+    }
+
+    pub fn cancel(&self, id: &str) -> CancelOutcome {
+        let mut calls = self.calls.lock().unwrap();
+        let Some(entry) = calls.get_mut(id) else {
+            return CancelOutcome::NotFound;
+        };
+        match entry.record.state {
+            CallState::Queued => {
+                entry.record.state = CallState::Cancelled;
+                entry.record.finished_at = Some(now_ms());
+                CancelOutcome::Cancelled
+            }
+            CallState::Running if !entry.token.cancel() => CancelOutcome::Committing,
+            CallState::Running => {
+                // `run` observes the flag, drops the future and records the state.
+                let _ = entry.cancel.send(true);
+                CancelOutcome::Cancelled
+            }
+            state => CancelOutcome::AlreadyFinished(state),
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<CallRecord> {
+        self.calls.lock().unwrap().get(id).map(|e| e.record.clone())
+    }
+
+    pub fn list(&self) -> Vec<CallRecord> {
+        let mut out: Vec<CallRecord> = self
+            .calls
+            .lock()
+            .unwrap()
+            .values()
+            .map(|e| e.record.clone())
+            .collect();
+        out.sort_by_key(|r| r.created_at);
+        out
+    }
+
+    fn insert(&self, id: &str, tool: &str, state: CallState) {
+        let (cancel, _) = watch::channel(false);
+        self.calls.lock().unwrap().insert(
+            id.to_string(),
+            Entry {
+                record: CallRecord {
+                    id: id.to_string(),
+                    tool: tool.to_string(),
+                    state,
+                    created_at: now_ms(),
+                    finished_at: None,
+                    deadline_ms: deadline_for(tool).as_millis() as u64,
+                },
+                cancel,
+                token: CancelToken::default(),
+            },
+        );
+    }
+
+    fn start(&self, id: &str, tool: &str, token: &CancelToken) -> Result<watch::Receiver<bool>> {
+        let mut calls = self.calls.lock().unwrap();
+        if let Some(entry) = calls.get_mut(id) {
+            if entry.record.state != CallState::Queued {
+                bail!("tool call {id} is already {:?}", entry.record.state);
+            }
+            entry.record.state = CallState::Running;
+            entry.token = token.clone();
+            return Ok(entry.cancel.subscribe());
+        }
+        drop(calls);
+        self.insert(id, tool, CallState::Running);
+        let mut calls = self.calls.lock().unwrap();
+        let entry = calls.get_mut(id).unwrap();
+        entry.token = token.clone();
+        Ok(entry.cancel.subscribe())
+    }
+
+    fn finish(&self, id: &str, state: CallState) {
+        let mut calls = self.calls.lock().unwrap();
+        if let Some(entry) = calls.get_mut(id) {
+            entry.record.state = state;
+            entry.record.finished_at = Some(now_ms());
+        }
+
+        let mut finished: Vec<(u64, String)> = calls
+            .values()
+            .filter(|e| e.record.state.is_finished())
+            .map(|e| (e.record.created_at, e.record.id.clone()))
+            .collect();
+        if finished.len() > MAX_FINISHED {
+            finished.sort();
+            for (_, id) in &finished[..finished.len() - MAX_FINISHED] {
+                calls.remove(id);
+            }
+        }
+    }
+}
+
+/// Upper bound on how long a tool may run before it is aborted.
+pub fn deadline_for(tool: &str) -> Duration {
+    match tool {
+        "shell.exec" => Duration::from_secs(60),
+        "network.get" | "network.post" => Duration::from_secs(20),
+        "system.status" => Duration::from_secs(5),
+        // Downloads and maintainer scripts; see `CancelToken` for cancelling.
+        "package.install" | "package.remove" => Duration::from_secs(600),
+        // The brain embeds the query first, which may load the model.
+        "knowledge.search" => Duration::from_secs(30),
+        _ => Duration::from_secs(10),
+    }
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn cancel_stops_running_call() {
+        let tracker = Arc::new(CallTracker::default());
+        let t = tracker.clone();
+        let task = tokio::spawn(async move {
+            t.run("c1", "shell.exec", &CancelToken::default(), async {
+                tokio::time::sleep(Duration::from_secs(30)).await;
+                Ok(json!({}))
+            })
+            .await
+        });
+
+        while tracker.get("c1").map(|r| r.state) != Some(CallState::Running) {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(tracker.cancel("c1"), CancelOutcome::Cancelled);
+        assert!(task.await.unwrap().is_err());
+        assert_eq!(tracker.get("c1").unwrap().state, CallState::Cancelled);
+    }
+
+    #[tokio::test]
+    async fn queued_call_can_be_cancelled_before_it_runs() {
+        let tracker = CallTracker::default();
+        tracker.queue("c2", "file.write");
+        assert_eq!(tracker.cancel("c2"), CancelOutcome::Cancelled);
+        assert!(tracker
+            .run("c2", "file.write", &CancelToken::default(), async {
+                Ok(json!({}))
+            })
+            .await
+            .is_err());
+        assert_eq!(
+            tracker.cancel("c2"),
+            CancelOutcome::AlreadyFinished(CallState::Cancelled)
+        );
+    }
+}
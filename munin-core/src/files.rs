@@ -0,0 +1,505 @@
+//! File tools beyond plain read/write: listing, metadata, search and
+//! copy/move/delete. All work on local paths with hard caps so a voice
+//! request can't pull a whole disk into a response.
+
+use crate::undo::UndoJournal;
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use std::fs::{self, Metadata};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Largest slice `file.read` returns in one call.
+pub const MAX_READ_BYTES: u64 = 1024 * 1024;
+const MAX_LIST_ENTRIES: usize = 1000;
+const MAX_SEARCH_RESULTS: usize = 500;
+/// Entries visited by one search before it gives up.
+const MAX_SEARCH_VISITS: usize = 20_000;
+/// Files larger than this are skipped by content search.
+const MAX_GREP_BYTES: u64 = 2 * 1024 * 1024;
+/// Copies up to this size go through the undo journal.
+const MAX_UNDOABLE_COPY: u64 = 16 * 1024 * 1024;
+
+fn str_arg<'a>(args: &'a Value, key: &str, tool: &str) -> Result<&'a str> {
+    args.get(key)
+        .and_then(|v| v.as_str())
+        .with_context(|| format!("{tool} requires args.{key}"))
+}
+
+fn u64_arg(args: &Value, key: &str) -> Option<u64> {
+    args.get(key).and_then(|v| v.as_u64())
+}
+
+fn bool_arg(args: &Value, key: &str) -> bool {
+    args.get(key).and_then(|v| v.as_bool()).unwrap_or(false)
+}
+
+/// Ranged, binary-safe read. Text comes back as `content`, anything else as
+/// `content_base64`.
+pub fn read(args: &Value) -> Result<Value> {
+    let path = str_arg(args, "path", "file.read")?;
+    let offset = u64_arg(args, "offset").unwrap_or(0);
+    let length = u64_arg(args, "length")
+        .unwrap_or(MAX_READ_BYTES)
+        .min(MAX_READ_BYTES);
+
+    let mut file = fs::File::open(path).with_context(|| format!("failed reading {path}"))?;
+    let size = file.metadata()?.len();
+    file.seek(SeekFrom::Start(offset))?;
+    let mut buf = Vec::with_capacity(length.min(size.saturating_sub(offset)) as usize);
+    file.take(length).read_to_end(&mut buf)?;
+
+    let mime = sniff_mime(Path::new(path), &buf);
+    let mut out = json!({
+        "path": path,
+        "size": size,
+        "offset": offset,
+        "bytes": buf.len(),
+        "truncated": offset + (buf.len() as u64) < size,
+        "mime": mime,
+    });
+    match std::str::from_utf8(&buf) {
+        Ok(text) if !is_binary(&buf) => out["content"] = json!(text),
+        // A range can split a multi-byte character; trim to the last full one.
+        Err(e) if e.error_len().is_none() && !is_binary(&buf) => {
+            out["content"] = json!(std::str::from_utf8(&buf[..e.valid_up_to()]).unwrap_or_default())
+        }
+        _ => out["content_base64"] = json!(base64(&buf)),
+    }
+    Ok(out)
+}
+
+pub fn list(args: &Value) -> Result<Value> {
+    let path = str_arg(args, "path", "file.list")?;
+    let hidden = bool_arg(args, "hidden");
+    let limit = u64_arg(args, "limit")
+        .map(|l| l as usize)
+        .unwrap_or(200)
+        .min(MAX_LIST_ENTRIES);
+
+    let mut entries = Vec::new();
+    let mut total = 0usize;
+    for entry in fs::read_dir(path).with_context(|| format!("failed listing {path}"))? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !hidden && name.starts_with('.') {
+            continue;
+        }
+        total += 1;
+        let Ok(meta) = entry.path().symlink_metadata() else {
+            continue;
+        };
+        entries.push(json!({
+            "name": name,
+            "kind": kind(&meta),
+            "size": meta.len(),
+            "modified": modified_ms(&meta),
+        }));
+    }
+    entries.sort_by(|a, b| a["name"].as_str().cmp(&b["name"].as_str()));
+    entries.truncate(limit);
+    Ok(json!({
+        "path": path,
+        "entries": entries,
+        "total": total,
+        "truncated": total > limit,
+    }))
+}
+
+pub fn stat(args: &Value) -> Result<Value> {
+    let path = str_arg(args, "path", "file.stat")?;
+    let meta = fs::symlink_metadata(path).with_context(|| format!("failed to stat {path}"))?;
+    let mut out = json!({
+        "path": path,
+        "kind": kind(&meta),
+        "size": meta.len(),
+        "modified": modified_ms(&meta),
+        "readonly": meta.permissions().readonly(),
+    });
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        out["mode"] = json!(format!("{:o}", meta.mode() & 0o7777));
+        out["uid"] = json!(meta.uid());
+        out["gid"] = json!(meta.gid());
+    }
+    if meta.is_symlink() {
+        out["target"] = json!(fs::read_link(path)?);
+    }
+    if meta.is_file() {
+        let mut head = Vec::with_capacity(512);
+        fs::File::open(path)?.take(512).read_to_end(&mut head)?;
+        out["mime"] = json!(sniff_mime(Path::new(path), &head));
+    }
+    Ok(out)
+}
+
+/// Walk `path` for entries whose name matches `name` (glob, `*` and `?`)
+/// and/or whose text contains `content` (case-insensitive).
+pub fn search(args: &Value) -> Result<Value> {
+    let root = str_arg(args, "path", "file.search")?;
+    let name = args.get("name").and_then(|v| v.as_str());
+    let needle = args
+        .get("content")
+        .and_then(|v| v.as_str())
+        .map(str::to_lowercase);
+    if name.is_none() && needle.is_none() {
+        bail!("file.search requires args.name or args.content");
+    }
+    let limit = u64_arg(args, "limit")
+        .map(|l| l as usize)
+        .unwrap_or(50)
+        .min(MAX_SEARCH_RESULTS);
+    let max_depth = u64_arg(args, "max_depth").unwrap_or(8) as usize;
+    let hidden = bool_arg(args, "hidden");
+
+    let mut matches = Vec::new();
+    let mut visited = 0usize;
+    let mut stack = vec![(PathBuf::from(root), 0usize)];
+    let mut exhausted = true;
+
+    'walk: while let Some((dir, depth)) = stack.pop() {
+        let Ok(read) = fs::read_dir(&dir) else {
+            continue;
+        };
+        let mut children: Vec<_> = read.flatten().collect();
+        children.sort_by_key(|e| e.file_name());
+        for entry in children {
+            visited += 1;
+            if visited > MAX_SEARCH_VISITS || matches.len() >= limit {
+                exhausted = false;
+                break 'walk;
+            }
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            if !hidden && file_name.starts_with('.') {
+                continue;
+            }
+            let path = entry.path();
+            let Ok(meta) = path.symlink_metadata() else {
+                continue;
+            };
+            if meta.is_dir() && depth < max_depth {
+                stack.push((path.clone(), depth + 1));
+            }
+            if name.is_some_and(|g| !glob_match(g, &file_name)) {
+                continue;
+            }
+            match &needle {
+                None => matches.push(json!({"path": path, "kind": kind(&meta)})),
+                Some(needle) if meta.is_file() && meta.len() <= MAX_GREP_BYTES => {
+                    if let Some((line, text)) = grep_file(&path, needle) {
+                        matches.push(json!({"path": path, "line": line, "text": text}));
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    Ok(json!({
+        "path": root,
+        "matches": matches,
+        "truncated": !exhausted,
+    }))
+}
+
+/// First matching line (1-based) of a text file.
+fn grep_file(path: &Path, needle: &str) -> Option<(usize, String)> {
+    let bytes = fs::read(path).ok()?;
+    if is_binary(&bytes) {
+        return None;
+    }
+    let text = String::from_utf8_lossy(&bytes);
+    text.lines().enumerate().find_map(|(i, line)| {
+        line.to_lowercase().contains(needle).then(|| {
+            let snippet: String = line.trim().chars().take(200).collect();
+            (i + 1, snippet)
+        })
+    })
+}
+
+pub fn rename(args: &Value) -> Result<Value> {
+    let from = str_arg(args, "from", "file.move")?;
+    let to = str_arg(args, "to", "file.move")?;
+    if Path::new(to).exists() && !bool_arg(args, "overwrite") {
+        bail!("{to} already exists; pass overwrite to replace it");
+    }
+    if let Some(parent) = Path::new(to).parent() {
+        fs::create_dir_all(parent).ok();
+    }
+    fs::rename(from, to).with_context(|| format!("failed moving {from} to {to}"))?;
+    Ok(json!({"from": from, "to": to}))
+}
+
+/// Copy a file. Copies that fit the undo journal return a `rollback` token
+/// restoring whatever `to` was before.
+pub fn copy(undo: &UndoJournal, call_id: &str, args: &Value) -> Result<Value> {
+    let from = str_arg(args, "from", "file.copy")?;
+    let to = str_arg(args, "to", "file.copy")?;
+    let meta = fs::metadata(from).with_context(|| format!("failed to stat {from}"))?;
+    if !meta.is_file() {
+        bail!(
+            "file.copy only copies regular files; {from} is a {}",
+            kind(&meta)
+        );
+    }
+    if Path::new(to).exists() && !bool_arg(args, "overwrite") {
+        bail!("{to} already exists; pass overwrite to replace it");
+    }
+
+    let rollback = if meta.len() <= MAX_UNDOABLE_COPY {
+        let bytes = fs::read(from).with_context(|| format!("failed reading {from}"))?;
+        undo.write(call_id, Path::new(to), Some(&bytes))?
+    } else {
+        if let Some(parent) = Path::new(to).parent() {
+            fs::create_dir_all(parent).ok();
+        }
+        fs::copy(from, to).with_context(|| format!("failed copying {from} to {to}"))?;
+        None
+    };
+    Ok(json!({"from": from, "to": to, "bytes": meta.len(), "rollback": rollback}))
+}
+
+/// Delete a file (undoable via the journal) or, with `recursive`, a directory.
+pub fn delete(undo: &UndoJournal, call_id: &str, args: &Value) -> Result<Value> {
+    let path = str_arg(args, "path", "file.delete")?;
+    let meta = fs::symlink_metadata(path).with_context(|| format!("failed to stat {path}"))?;
+    if meta.is_dir() {
+        if !bool_arg(args, "recursive") {
+            fs::remove_dir(path)
+                .with_context(|| format!("{path} is a non-empty directory; pass recursive"))?;
+        } else {
+            fs::remove_dir_all(path).with_context(|| format!("failed deleting {path}"))?;
+        }
+        return Ok(json!({"path": path, "kind": "dir", "rollback": null}));
+    }
+    if meta.is_symlink() {
+        fs::remove_file(path)?;
+        return Ok(json!({"path": path, "kind": "symlink", "rollback": null}));
+    }
+    // Files too large to snapshot are still deleted, just without a token.
+    let rollback = undo.write(call_id, Path::new(path), None)?;
+    Ok(json!({"path": path, "kind": "file", "rollback": rollback}))
+}
+
+fn kind(meta: &Metadata) -> &'static str {
+    if meta.is_symlink() {
+        "symlink"
+    } else if meta.is_dir() {
+        "dir"
+    } else if meta.is_file() {
+        "file"
+    } else {
+        "other"
+    }
+}
+
+fn modified_ms(meta: &Metadata) -> Option<u64> {
+    meta.modified()
+        .ok()?
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .ok()
+        .map(|d| d.as_millis() as u64)
+}
+
+fn is_binary(bytes: &[u8]) -> bool {
+    bytes.iter().take(8192).any(|&b| b == 0)
+}
+
+/// Best-effort MIME type from magic bytes, then extension, then a text check.
+pub fn sniff_mime(path: &Path, head: &[u8]) -> &'static str {
+    const MAGIC: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"\x28\xb5\x2f\xfd", "application/zstd"),
+        (b"\x7fELF", "application/x-elf"),
+        (b"GGUF", "application/x-gguf"),
+        (b"OggS", "audio/ogg"),
+        (b"fLaC", "audio/flac"),
+        (b"ID3", "audio/mpeg"),
+    ];
+    if let Some((_, mime)) = MAGIC.iter().find(|(magic, _)| head.starts_with(magic)) {
+        return mime;
+    }
+    if head.len() >= 12 && &head[..4] == b"RIFF" {
+        match &head[8..12] {
+            b"WAVE" => return "audio/wav",
+            b"WEBP" => return "image/webp",
+            _ => {}
+        }
+    }
+
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+    let by_ext = match ext.as_deref() {
+        Some("txt" | "log" | "conf" | "cfg" | "ini") => "text/plain",
+        Some("md") => "text/markdown",
+        Some("json") => "application/json",
+        Some("toml") => "application/toml",
+        Some("yaml" | "yml") => "application/yaml",
+        Some("html" | "htm") => "text/html",
+        Some("css") => "text/css",
+        Some("js") => "text/javascript",
+        Some("csv") => "text/csv",
+        Some("rs") => "text/x-rust",
+        Some("sh") => "text/x-shellscript",
+        Some("py") => "text/x-python",
+        Some("svg") => "image/svg+xml",
+        Some("mp3") => "audio/mpeg",
+        Some("mp4") => "video/mp4",
+        _ => "",
+    };
+    if !by_ext.is_empty() {
+        by_ext
+    } else if !is_binary(head) && std::str::from_utf8(head).is_ok() {
+        "text/plain"
+    } else {
+        "application/octet-stream"
+    }
+}
+
+/// Shell-style name match supporting `*` and `?`, case-insensitive.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let p: Vec<char> = pattern.to_lowercase().chars().collect();
+    let n: Vec<char> = name.to_lowercase().chars().collect();
+    let (mut pi, mut ni) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while ni < n.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == n[ni]) {
+            pi += 1;
+            ni += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ni));
+            pi += 1;
+        } else if let Some((sp, sn)) = star {
+            pi = sp + 1;
+            ni = sn + 1;
+            star = Some((sp, sn + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(TABLE[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("munin-files-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(dir.join("notes/old")).unwrap();
+        fs::write(
+            dir.join("notes/todo.md"),
+            "buy milk\nCall Alice about the trip\n",
+        )
+        .unwrap();
+        fs::write(dir.join("notes/old/trip.txt"), "flights booked").unwrap();
+        fs::write(dir.join("photo.png"), b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR").unwrap();
+        dir
+    }
+
+    #[test]
+    fn ranged_reads_are_binary_safe() {
+        let dir = scratch();
+        let png = read(&json!({"path": dir.join("photo.png")})).unwrap();
+        assert_eq!(png["mime"], "image/png");
+        assert!(png.get("content").is_none());
+        assert_eq!(png["content_base64"], "iVBORw0KGgoAAAANSUhEUg==");
+
+        let part =
+            read(&json!({"path": dir.join("notes/todo.md"), "offset": 4, "length": 4})).unwrap();
+        assert_eq!(part["content"], "milk");
+        assert_eq!(part["truncated"], true);
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn search_by_name_and_content() {
+        let dir = scratch();
+        let by_name = search(&json!({"path": dir, "name": "*.TXT"})).unwrap();
+        let paths: Vec<&str> = by_name["matches"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|m| m["path"].as_str().unwrap())
+            .collect();
+        assert_eq!(paths.len(), 1);
+        assert!(paths[0].ends_with("notes/old/trip.txt"));
+
+        let by_content = search(&json!({"path": dir, "content": "alice"})).unwrap();
+        assert_eq!(by_content["matches"][0]["line"], 2);
+
+        let limited = search(&json!({"path": dir, "name": "*", "limit": 1})).unwrap();
+        assert_eq!(limited["matches"].as_array().unwrap().len(), 1);
+        assert_eq!(limited["truncated"], true);
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn delete_and_copy_are_undoable() {
+        let dir = scratch();
+        let journal = UndoJournal::new(dir.join(".undo"));
+        let todo = dir.join("notes/todo.md");
+
+        let copied = copy(
+            &journal,
+            "c1",
+            &json!({"from": todo, "to": dir.join("todo.bak")}),
+        )
+        .unwrap();
+        assert!(copied["rollback"].is_string());
+        assert!(copy(
+            &journal,
+            "c2",
+            &json!({"from": todo, "to": dir.join("todo.bak")})
+        )
+        .is_err());
+
+        let deleted = delete(&journal, "c3", &json!({"path": todo})).unwrap();
+        assert!(!todo.exists());
+        journal
+            .undo("u1", deleted["rollback"].as_str().unwrap(), false)
+            .unwrap();
+        assert!(fs::read_to_string(&todo).unwrap().starts_with("buy milk"));
+
+        assert!(delete(&journal, "c4", &json!({"path": dir.join("notes")})).is_err());
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn glob_handles_stars_and_question_marks() {
+        assert!(glob_match("*.md", "todo.md"));
+        assert!(glob_match("t?do*", "TODO.md"));
+        assert!(!glob_match("*.md", "todo.mdx"));
+    }
+}
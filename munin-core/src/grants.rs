@@ -0,0 +1,340 @@
+use crate::policy::{risk_for, Risk};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use uuid::Uuid;
+
+/// How long an approver wants "allow similar" to last.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GrantDuration {
+    Minutes(u64),
+    /// Until munin-core restarts.
+    Session,
+    /// Persisted to the grants file.
+    Always,
+}
+
+/// A standing approval: calls to `tool` whose subject matches `pattern`
+/// skip the confirmation queue. In the pattern `*` matches within one path
+/// segment (not a leading `.`) and `**` matches any text.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Grant {
+    pub id: String,
+    pub tool: String,
+    pub pattern: String,
+    pub duration: GrantDuration,
+    pub created_at: u64,
+    pub expires_at: Option<u64>,
+    /// The call whose approval created this grant.
+    pub source_call: String,
+}
+
+impl Grant {
+    fn expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|t| now >= t)
+    }
+
+    fn covers(&self, tool: &str, args: &Value) -> bool {
+        self.tool == tool
+            && grantable(tool)
+            && subject(tool, args).is_some_and(|s| glob_match(&self.pattern, &s))
+    }
+}
+
+#[derive(Default)]
+pub struct GrantStore {
+    grants: Mutex<Vec<Grant>>,
+    path: Option<PathBuf>,
+}
+
+impl GrantStore {
+    /// Load persisted `always` grants from `path` (missing file is empty).
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let grants = match std::fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text)
+                .with_context(|| format!("failed parsing grants file {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e).with_context(|| format!("failed reading {}", path.display())),
+        };
+        Ok(Self {
+            grants: Mutex::new(grants),
+            path: Some(path),
+        })
+    }
+
+    /// Derive a scoped rule from an approved call and store it. Returns `None`
+    /// when the tool has no notion of "similar" calls or is destructive.
+    pub fn grant_for(
+        &self,
+        call_id: &str,
+        tool: &str,
+        args: &Value,
+        duration: GrantDuration,
+    ) -> Result<Option<Grant>> {
+        let Some(pattern) = derive_pattern(tool, args).filter(|_| grantable(tool)) else {
+            return Ok(None);
+        };
+        let now = now_ms();
+        let grant = Grant {
+            id: Uuid::new_v4().to_string(),
+            tool: tool.to_string(),
+            pattern,
+            duration,
+            created_at: now,
+            expires_at: match duration {
+                GrantDuration::Minutes(m) => Some(now + m * 60_000),
+                GrantDuration::Session | GrantDuration::Always => None,
+            },
+            source_call: call_id.to_string(),
+        };
+
+        self.grants.lock().unwrap().push(grant.clone());
+        if duration == GrantDuration::Always {
+            self.save()?;
+        }
+        Ok(Some(grant))
+    }
+
+    /// The first live grant covering this call, if any.
+    pub fn find(&self, tool: &str, args: &Value) -> Option<Grant> {
+        let now = now_ms();
+        let mut grants = self.grants.lock().unwrap();
+        grants.retain(|g| !g.expired(now));
+        grants.iter().find(|g| g.covers(tool, args)).cloned()
+    }
+
+    pub fn list(&self) -> Vec<Grant> {
+        let now = now_ms();
+        let mut grants = self.grants.lock().unwrap();
+        grants.retain(|g| !g.expired(now));
+        grants.clone()
+    }
+
+    pub fn revoke(&self, id: &str) -> Result<bool> {
+        let removed = {
+            let mut grants = self.grants.lock().unwrap();
+            let before = grants.len();
+            grants.retain(|g| g.id != id);
+            grants.len() != before
+        };
+        if removed {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let always: Vec<Grant> = self
+            .grants
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|g| g.duration == GrantDuration::Always)
+            .cloned()
+            .collect();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).ok();
+        }
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(&always)?)
+            .with_context(|| format!("failed writing {}", tmp.display()))?;
+        std::fs::rename(&tmp, path)
+            .with_context(|| format!("failed replacing {}", path.display()))?;
+        Ok(())
+    }
+}
+
+/// Destructive tools (`shell.exec`, `file.delete`, `package.*`, ...) are
+/// approved call by call, never by a standing grant.
+fn grantable(tool: &str) -> bool {
+    !matches!(risk_for(tool), Some((Risk::Destructive, _)))
+}
+
+/// The string a grant pattern is matched against for a given call.
+fn subject(tool: &str, args: &Value) -> Option<String> {
+    let arg = |k: &str| args.get(k).and_then(|v| v.as_str());
+    match tool {
+        t if t.starts_with("file.") => Some(normalize(arg("path")?).to_string_lossy().into_owned()),
+        t if t.starts_with("network.") => Some(reqwest::Url::parse(arg("url")?).ok()?.to_string()),
+        _ => None,
+    }
+}
+
+/// "Similar" calls: files directly in the same directory (never `/`), or
+/// URLs on the same origin.
+fn derive_pattern(tool: &str, args: &Value) -> Option<String> {
+    let arg = |k: &str| args.get(k).and_then(|v| v.as_str());
+    match tool {
+        t if t.starts_with("file.") => {
+            let path = normalize(arg("path")?);
+            let dir = path.parent().filter(|d| d.parent().is_some())?;
+            Some(format!("{}/*", dir.to_string_lossy().trim_end_matches('/')))
+        }
+        t if t.starts_with("network.") => {
+            let url = reqwest::Url::parse(arg("url")?).ok()?;
+            Some(format!("{}/**", url.origin().ascii_serialization()))
+        }
+        _ => None,
+    }
+}
+
+/// Lexically resolve `.` and `..` so `/tmp/a/../../etc` can't hide behind `/tmp/a/*`.
+fn normalize(path: &str) -> PathBuf {
+    let base = if Path::new(path).is_absolute() {
+        PathBuf::new()
+    } else {
+        std::env::current_dir().unwrap_or_default()
+    };
+    let mut out = base;
+    for c in Path::new(path).components() {
+        match c {
+            Component::ParentDir => {
+                out.pop();
+            }
+            Component::CurDir => {}
+            other => out.push(other.as_os_str()),
+        }
+    }
+    out
+}
+
+fn glob_match(pattern: &str, text: &str) -> bool {
+    fn go(p: &[u8], t: &[u8], segment_start: bool) -> bool {
+        match p {
+            [] => t.is_empty(),
+            [b'*', b'*', rest @ ..] => (0..=t.len()).any(|i| go(rest, &t[i..], false)),
+            [b'*', rest @ ..] => {
+                // Like a shell glob, `*` skips hidden files such as `.ssh`.
+                if segment_start && t.first() == Some(&b'.') {
+                    return go(rest, t, segment_start);
+                }
+                let segment = t.iter().position(|&c| c == b'/').unwrap_or(t.len());
+                (0..=segment).any(|i| go(rest, &t[i..], segment_start && i == 0))
+            }
+            [c, rest @ ..] => t.first() == Some(c) && go(rest, &t[1..], *c == b'/'),
+        }
+    }
+    go(pattern.as_bytes(), text.as_bytes(), true)
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn file_grant_covers_files_directly_in_the_directory() {
+        let store = GrantStore::default();
+        let args = json!({"path": "/tmp/scratch/a.txt", "content": "x"});
+        let grant = store
+            .grant_for("c1", "file.write", &args, GrantDuration::Session)
+            .unwrap()
+            .unwrap();
+        assert_eq!(grant.pattern, "/tmp/scratch/*");
+
+        assert!(store
+            .find("file.write", &json!({"path": "/tmp/scratch/b.txt"}))
+            .is_some());
+        assert!(store
+            .find("file.write", &json!({"path": "/tmp/other/b.txt"}))
+            .is_none());
+        for path in [
+            "/tmp/scratch/sub/b.txt",
+            "/tmp/scratch/.ssh",
+            "/tmp/scratch",
+        ] {
+            assert!(store.find("file.write", &json!({"path": path})).is_none());
+        }
+        assert!(store
+            .find(
+                "file.write",
+                &json!({"path": "/tmp/scratch/../../etc/passwd"})
+            )
+            .is_none());
+        assert!(store
+            .find("shell.exec", &json!({"command": "ls"}))
+            .is_none());
+    }
+
+    #[test]
+    fn destructive_tools_and_the_root_get_no_grants() {
+        let store = GrantStore::default();
+        for (tool, args) in [
+            ("shell.exec", json!({"command": "ls -l"})),
+            ("file.delete", json!({"path": "/tmp/scratch/a.txt"})),
+            (
+                "file.move",
+                json!({"path": "/tmp/scratch/a.txt", "to": "/tmp/b"}),
+            ),
+            ("package.install", json!({"name": "hello"})),
+            ("file.write", json!({"path": "/a.txt"})),
+        ] {
+            let grant = store.grant_for("c1", tool, &args, GrantDuration::Session);
+            assert!(grant.unwrap().is_none(), "{tool} {args}");
+        }
+        assert!(store.list().is_empty());
+    }
+
+    #[test]
+    fn expired_and_revoked_grants_stop_matching() {
+        let store = GrantStore::default();
+        let args = json!({"path": "/tmp/scratch/a.txt"});
+        let g = store
+            .grant_for("c1", "file.write", &args, GrantDuration::Minutes(0))
+            .unwrap()
+            .unwrap();
+        assert!(store.find("file.write", &args).is_none());
+        assert!(!store.revoke(&g.id).unwrap());
+
+        let g = store
+            .grant_for("c2", "file.write", &args, GrantDuration::Minutes(10))
+            .unwrap()
+            .unwrap();
+        assert!(store.find("file.write", &args).is_some());
+        assert!(store.revoke(&g.id).unwrap());
+        assert!(store.find("file.write", &args).is_none());
+    }
+
+    #[test]
+    fn always_grants_survive_reload() {
+        let path = std::env::temp_dir().join(format!("munin-grants-{}.json", Uuid::new_v4()));
+        let args = json!({"url": "https://example.com/api"});
+        {
+            let store = GrantStore::load(&path).unwrap();
+            store
+                .grant_for("c1", "network.post", &args, GrantDuration::Always)
+                .unwrap();
+            store
+                .grant_for(
+                    "c2",
+                    "file.write",
+                    &json!({"path": "/tmp/scratch/a.txt"}),
+                    GrantDuration::Session,
+                )
+                .unwrap();
+        }
+        let store = GrantStore::load(&path).unwrap();
+        assert_eq!(store.list().len(), 1);
+        assert!(store
+            .find(
+                "network.post",
+                &json!({"url": "https://example.com/v2/other"})
+            )
+            .is_some());
+        std::fs::remove_file(path).ok();
+    }
+}
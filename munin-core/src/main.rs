@@ -1,16 +1,26 @@
 use anyhow::Result;
 use clap::{Parser, Subcommand};
-use tracing_subscriber;
+use std::sync::Arc;
 
 mod agent;
+mod approvals;
 mod bus;
+mod calls;
+mod files;
+mod grants;
 mod policy;
+mod preview;
 mod protocol;
-mod tools;
+mod sdnotify;
 mod server;
+mod supervisor;
+mod tools;
+mod undo;
 
 use agent::AgentRuntime;
-use bus::MessageBus;
+use bus::{BusConfig, MessageBus};
+use std::time::Duration;
+use supervisor::Supervisor;
 
 #[derive(Parser, Debug)]
 #[command(name = "munin-core")]
@@ -20,7 +30,7 @@ struct Args {
     command: Commands,
 
     /// Enable speech mode (STS integration)
-    #[arg(long, default_value_t = false)]
+    #[arg(long, global = true, default_value_t = false)]
     sts: bool,
 
     /// Auto-approve risky tool calls (dev mode)
@@ -30,16 +40,58 @@ struct Args {
     /// Local brain endpoint
     #[arg(long, default_value = "http://127.0.0.1:8790")]
     brain_endpoint: String,
+
+    /// Journal persistent bus topics (`error/#`, `tools/shell/#`) under this directory
+    #[arg(long)]
+    bus_journal: Option<std::path::PathBuf>,
+
+    /// Directory for core state such as persisted approval grants
+    #[arg(long, default_value = "/var/lib/muninos/core")]
+    state_dir: std::path::PathBuf,
+
+    /// Root that `package.*` tools install into
+    #[arg(long, default_value = "/")]
+    package_root: std::path::PathBuf,
+
+    /// Tools (`shell.exec`, `file.*`) or risk classes (`destructive`) needing two approval factors
+    #[arg(long = "two-factor", value_name = "TOOL")]
+    two_factor: Vec<String>,
+
+    /// PIN accepted as an approval factor
+    #[arg(long, env = "MUNIN_APPROVAL_PIN", hide_env_values = true)]
+    approval_pin: Option<String>,
 }
 
 #[derive(Subcommand, Debug)]
 enum Commands {
-    /// Start the MuninOS agent core event loop
-    Start,
+    /// Run the core: API, bus and supervision of brain/sts/audio
+    Start {
+        #[arg(long, default_value = "0.0.0.0:8787")]
+        listen: String,
+        /// Seconds between health probes of the other services
+        #[arg(long, default_value_t = 5)]
+        probe_interval: u64,
+    },
     /// Send a command to the bus
-    Send { message: String },
-    /// List registered agents
-    ListAgents,
+    Send {
+        message: String,
+        /// Topic path, e.g. `system` or `tools/shell/progress`
+        #[arg(long, default_value = bus::SYSTEM)]
+        topic: String,
+    },
+    /// List agents registered with a running core API and their health
+    ListAgents {
+        #[arg(long, default_value = "http://127.0.0.1:8787")]
+        core_endpoint: String,
+    },
+    /// Restore the file a previous `file.write` call replaced
+    Undo {
+        /// Call id or rollback token of the write
+        call_id: String,
+        /// Restore even if the file changed after the write
+        #[arg(long, default_value_t = false)]
+        force: bool,
+    },
     /// Run interactive agent REPL
     Repl,
     /// One-shot agent command
@@ -49,6 +101,27 @@ enum Commands {
         #[arg(long, default_value = "0.0.0.0:8787")]
         listen: String,
     },
+    /// Inspect bus traffic on a running core API
+    Bus {
+        #[command(subcommand)]
+        command: BusCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum BusCommand {
+    /// Print live messages whose topic matches a pattern (`+` and `#` wildcards)
+    Tail {
+        #[arg(default_value = "#")]
+        pattern: String,
+        #[arg(long, default_value = "http://127.0.0.1:8787")]
+        core_endpoint: String,
+        #[arg(long, default_value_t = 500)]
+        interval_ms: u64,
+        /// First print journaled messages from this Unix time in ms on
+        #[arg(long)]
+        since_ms: Option<u64>,
+    },
 }
 
 #[tokio::main]
@@ -56,46 +129,188 @@ async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
 
     let args = Args::parse();
-    let bus = MessageBus::new().await?;
-    let agent = AgentRuntime::new();
+    let bus = MessageBus::with_config(BusConfig {
+        journal_dir: args.bus_journal.clone(),
+        ..BusConfig::default()
+    })
+    .await?;
+    let grants = grants::GrantStore::load(args.state_dir.join("grants.json"))?;
+    let tools = tools::ToolRouter::new(
+        undo::UndoJournal::new(args.state_dir.join("undo")),
+        bpkg::Bpkg::open(&args.package_root),
+        &args.brain_endpoint,
+    );
+    let rules = approvals::ApprovalRules::new(args.two_factor.clone(), args.approval_pin.clone());
+    let agent = AgentRuntime::new(
+        policy::PolicyEngine::new(grants, rules.clone()),
+        tools,
+        &args.brain_endpoint,
+    );
+    let voice_token = args.state_dir.join("voice.token");
 
     match args.command {
-        Commands::Start => {
+        Commands::Start {
+            listen,
+            probe_interval,
+        } => {
             tracing::info!("Starting MuninOS Core with sts={}", args.sts);
-            if args.sts {
-                bus.start_voice_mode(args.brain_endpoint).await?;
-            } else {
-                bus.run().await?;
+            let bus = Arc::new(bus);
+            let reaper = bus.clone();
+            tokio::spawn(async move { reaper.run().await });
+
+            let server = server::bind(&listen)?;
+            let mode = agent.mode().clone();
+            let rules = rules.with_voice_token(approvals::load_voice_token(&voice_token)?);
+            let state = server::ApiState::new(agent, bus.clone(), rules);
+            let api = tokio::task::spawn_blocking(move || server::serve_on(server, state));
+
+            let supervisor = Supervisor::new(bus, &args.brain_endpoint, args.sts, mode);
+            tokio::select! {
+                res = api => res??,
+                res = supervisor.run(Duration::from_secs(probe_interval.max(1))) => res?,
             }
         }
-        Commands::Send { message } => {
-            bus.send(bus::Topic::System, message).await?;
+        Commands::Send { message, topic } => {
+            let delivery = bus.send(&topic, message).await?;
+            tracing::info!(
+                "sent {} to {} subscriber(s)",
+                delivery.id,
+                delivery.delivered
+            );
         }
-        Commands::ListAgents => {
-            let agents = bus.list_agents().await?;
-            tracing::info!("Registered agents: {:?}", agents);
+        Commands::ListAgents { core_endpoint } => list_agents(&core_endpoint).await?,
+        Commands::Undo { call_id, force } => {
+            let call = protocol::ToolCall {
+                id: uuid::Uuid::new_v4().to_string(),
+                tool: "file.undo".into(),
+                args: serde_json::json!({"token": call_id, "force": force}),
+                requires_confirmation: false,
+                preview: None,
+            };
+            let output = agent.execute(&call).await?;
+            println!("{}", serde_json::to_string_pretty(&output)?);
         }
-        Commands::Repl => run_repl(&agent, args.auto_approve).await?,
+        Commands::Repl => run_repl(Arc::new(agent), args.auto_approve).await?,
         Commands::Agent { input } => run_one_shot(&agent, &input, args.auto_approve).await?,
         Commands::Api { listen } => {
-            let state = server::ApiState::new(agent);
-            server::serve(&listen, state)?;
+            let bus = Arc::new(bus);
+            let reaper = bus.clone();
+            tokio::spawn(async move { reaper.run().await });
+            let rules = rules.with_voice_token(approvals::load_voice_token(&voice_token)?);
+            let state = server::ApiState::new(agent, bus, rules);
+            tokio::task::spawn_blocking(move || server::serve(&listen, state)).await??;
         }
+        Commands::Bus {
+            command:
+                BusCommand::Tail {
+                    pattern,
+                    core_endpoint,
+                    interval_ms,
+                    since_ms,
+                },
+        } => run_bus_tail(&core_endpoint, &pattern, interval_ms, since_ms).await?,
     }
 
     Ok(())
 }
 
+async fn list_agents(core_endpoint: &str) -> Result<()> {
+    let url = format!("{}/v1/agents", core_endpoint.trim_end_matches('/'));
+    let resp: serde_json::Value = reqwest::get(&url).await?.json().await?;
+    let agents: Vec<bus::AgentStatus> =
+        serde_json::from_value(resp.get("agents").cloned().unwrap_or_default())?;
+    if agents.is_empty() {
+        println!("no agents registered");
+    }
+    for a in agents {
+        println!(
+            "{:<16} {:<6} version={} pid={} capabilities=[{}]",
+            a.info.id.0,
+            match a.health {
+                bus::Health::Alive => "alive",
+                bus::Health::Stale => "stale",
+            },
+            a.info.version.as_deref().unwrap_or("-"),
+            a.info
+                .pid
+                .map(|p| p.to_string())
+                .unwrap_or_else(|| "-".into()),
+            a.info.capabilities.join(",")
+        );
+    }
+    Ok(())
+}
+
+async fn run_bus_tail(
+    core_endpoint: &str,
+    pattern: &str,
+    interval_ms: u64,
+    since_ms: Option<u64>,
+) -> Result<()> {
+    let base = core_endpoint.trim_end_matches('/');
+    let client = reqwest::Client::new();
+    if let Some(since) = since_ms {
+        let resp: serde_json::Value = client
+            .get(format!("{base}/v1/bus/journal"))
+            .query(&[("pattern", pattern), ("since_ms", &since.to_string())])
+            .send()
+            .await?
+            .json()
+            .await?;
+        if let Some(err) = resp.get("error") {
+            anyhow::bail!("bus journal replay failed: {err}");
+        }
+        let messages: Vec<bus::Message> =
+            serde_json::from_value(resp.get("messages").cloned().unwrap_or_default())?;
+        for msg in messages {
+            println!("[journal] {} {}", msg.topic, msg.payload);
+        }
+    }
+    let url = format!("{base}/v1/bus/tail");
+    let mut after = 0u64;
+    loop {
+        let resp: serde_json::Value = client
+            .get(&url)
+            .query(&[("pattern", pattern), ("after", &after.to_string())])
+            .send()
+            .await?
+            .json()
+            .await?;
+        if let Some(err) = resp.get("error") {
+            anyhow::bail!("bus tail failed: {err}");
+        }
+        let messages: Vec<bus::Message> =
+            serde_json::from_value(resp.get("messages").cloned().unwrap_or_default())?;
+        for msg in messages {
+            after = after.max(msg.seq);
+            println!("[{}] {} {}", msg.seq, msg.topic, msg.payload);
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(interval_ms)).await;
+    }
+}
+
 async fn run_one_shot(agent: &AgentRuntime, input: &str, auto_approve: bool) -> Result<()> {
-    let events = agent.handle_text(input, auto_approve).await?;
+    let events = agent.handle_text(input, None, auto_approve).await?;
+    print_events(&events);
+    Ok(())
+}
+
+fn print_events(events: &[protocol::CoreEvent]) {
     for ev in events {
         println!("{:?}", ev);
+        if let protocol::CoreEvent::ToolCall(protocol::ToolCall {
+            preview: Some(preview),
+            ..
+        }) = ev
+        {
+            println!("preview:\n{}", preview.render());
+        }
     }
-    Ok(())
 }
 
-async fn run_repl(agent: &AgentRuntime, auto_approve: bool) -> Result<()> {
+async fn run_repl(agent: Arc<AgentRuntime>, auto_approve: bool) -> Result<()> {
     use std::io::{self, Write};
+    use tokio::io::{AsyncBufReadExt, BufReader};
     println!("MuninOS Agentic REPL");
     println!("Examples:");
     println!("  status");
@@ -103,26 +318,56 @@ async fn run_repl(agent: &AgentRuntime, auto_approve: bool) -> Result<()> {
     println!("  write /tmp/hello.txt::hello from munin");
     println!("  exec uptime");
     println!("  get https://example.com");
+    println!("  search docs boat insurance");
+    println!("  calls            (list tracked tool calls)");
+    println!("  cancel <call-id> (stop a queued or running call)");
+    println!("  grants           (list standing approvals)");
+    println!("  revoke <grant-id>");
     println!("Type 'quit' to exit.");
 
-    let stdin = io::stdin();
+    // Turns run in the background so a slow tool can be cancelled from the prompt.
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
     loop {
         print!("> ");
         io::stdout().flush()?;
 
-        let mut input = String::new();
-        if stdin.read_line(&mut input)? == 0 {
+        let Some(input) = lines.next_line().await? else {
             break;
-        }
+        };
         let input = input.trim();
         if input.eq_ignore_ascii_case("quit") || input.is_empty() {
             break;
         }
 
-        let events = agent.handle_text(input, auto_approve).await?;
-        for ev in events {
-            println!("{:?}", ev);
+        if input == "calls" {
+            for c in agent.calls.list() {
+                println!("{} {:<14} {:?}", c.id, c.tool, c.state);
+            }
+            continue;
         }
+        if let Some(id) = input.strip_prefix("cancel ") {
+            println!("{:?}", agent.calls.cancel(id.trim()));
+            continue;
+        }
+        if input == "grants" {
+            for g in agent.policy.grants().list() {
+                println!("{} {:<14} {} {:?}", g.id, g.tool, g.pattern, g.duration);
+            }
+            continue;
+        }
+        if let Some(id) = input.strip_prefix("revoke ") {
+            println!("revoked: {}", agent.policy.grants().revoke(id.trim())?);
+            continue;
+        }
+
+        let agent = agent.clone();
+        let input = input.to_string();
+        tokio::spawn(async move {
+            match agent.handle_text(&input, None, auto_approve).await {
+                Ok(events) => print_events(&events),
+                Err(e) => println!("error: {e}"),
+            }
+        });
     }
 
     Ok(())
@@ -1,43 +1,162 @@
+use crate::approvals::ApprovalRules;
+use crate::grants::GrantStore;
+use serde::Serialize;
 use serde_json::Value;
 
+/// How much damage a tool can do, which decides whether it needs approval.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Risk {
+    /// Reads local or remote state; runs without asking.
+    ReadOnly,
+    /// Creates or changes data in a recoverable way.
+    Write,
+    /// Can destroy data or change the system beyond what undo covers.
+    Destructive,
+}
+
 #[derive(Debug, Clone)]
 pub struct PolicyDecision {
     pub allowed: bool,
     pub requires_confirmation: bool,
     pub reason: String,
+    pub risk: Option<Risk>,
 }
 
 #[derive(Default)]
-pub struct PolicyEngine;
+pub struct PolicyEngine {
+    grants: GrantStore,
+    approvals: ApprovalRules,
+}
 
 impl PolicyEngine {
+    pub fn new(grants: GrantStore, approvals: ApprovalRules) -> Self {
+        Self { grants, approvals }
+    }
+
+    pub fn grants(&self) -> &GrantStore {
+        &self.grants
+    }
+
+    /// Built-in risk rules, relaxed by any standing grant the user approved.
+    /// Grants never stand in for the second factor of a two-factor tool.
     pub fn evaluate(&self, tool: &str, args: &Value) -> PolicyDecision {
-        match tool {
-            "shell.exec" => PolicyDecision {
-                allowed: true,
-                requires_confirmation: true,
-                reason: "Shell execution can change system state".into(),
-            },
-            "file.write" => PolicyDecision {
-                allowed: true,
-                requires_confirmation: true,
-                reason: "Writing files should be user-approved".into(),
-            },
-            "network.post" => PolicyDecision {
-                allowed: true,
-                requires_confirmation: true,
-                reason: "Outbound data write requires approval".into(),
-            },
-            "file.read" | "network.get" | "system.status" => PolicyDecision {
+        let decision = Self::rules(tool, args);
+        if !decision.requires_confirmation || self.approvals.required(tool) > 1 {
+            return decision;
+        }
+        match self.grants.find(tool, args) {
+            Some(grant) => PolicyDecision {
                 allowed: true,
                 requires_confirmation: false,
-                reason: "Read-only action".into(),
+                reason: format!("Allowed by grant {} ({})", grant.id, grant.pattern),
+                risk: decision.risk,
             },
-            _ => PolicyDecision {
+            None => decision,
+        }
+    }
+
+    fn rules(tool: &str, args: &Value) -> PolicyDecision {
+        let Some((risk, reason)) = risk_for(tool) else {
+            return PolicyDecision {
                 allowed: false,
                 requires_confirmation: false,
                 reason: format!("Unknown or unsupported tool: {tool}; args={args}"),
-            },
+                risk: None,
+            };
+        };
+        PolicyDecision {
+            allowed: true,
+            requires_confirmation: risk != Risk::ReadOnly,
+            reason: reason.into(),
+            risk: Some(risk),
+        }
+    }
+}
+
+/// Risk class of every known tool, with the reason shown to the approver.
+pub fn risk_for(tool: &str) -> Option<(Risk, &'static str)> {
+    Some(match tool {
+        "shell.exec" => (Risk::Destructive, "Shell execution can change system state"),
+        "file.delete" => (Risk::Destructive, "Deleting files or directories"),
+        "package.install" => (
+            Risk::Destructive,
+            "Installing packages runs their scripts as root",
+        ),
+        "package.remove" => (Risk::Destructive, "Removing packages deletes system files"),
+        "file.move" => (
+            Risk::Destructive,
+            "Moving files can replace the destination",
+        ),
+        "file.write" => (Risk::Write, "Writing files should be user-approved"),
+        "file.copy" => (
+            Risk::Write,
+            "Copying creates or replaces the destination file",
+        ),
+        "file.undo" => (Risk::Write, "Undo overwrites or deletes a file"),
+        "network.post" => (Risk::Write, "Outbound data write requires approval"),
+        "file.read" | "file.list" | "file.stat" | "file.search" | "package.search"
+        | "package.info" | "network.get" | "system.status" | "knowledge.search" => {
+            (Risk::ReadOnly, "Read-only action")
+        }
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grants::GrantDuration;
+    use serde_json::json;
+
+    #[test]
+    fn grant_skips_confirmation_for_similar_calls() {
+        let policy = PolicyEngine::default();
+        let args = json!({"path": "/tmp/scratch/a.txt", "content": "x"});
+        assert!(policy.evaluate("file.write", &args).requires_confirmation);
+
+        policy
+            .grants()
+            .grant_for("c1", "file.write", &args, GrantDuration::Session)
+            .unwrap();
+        assert!(
+            !policy
+                .evaluate("file.write", &json!({"path": "/tmp/scratch/b.txt"}))
+                .requires_confirmation
+        );
+        assert!(
+            policy
+                .evaluate("file.write", &json!({"path": "/etc/hosts"}))
+                .requires_confirmation
+        );
+    }
+
+    #[test]
+    fn two_factor_rules_outrank_grants() {
+        let grants = GrantStore::default();
+        let args = json!({"path": "/tmp/scratch/a.txt", "content": "x"});
+        grants
+            .grant_for("c1", "file.write", &args, GrantDuration::Always)
+            .unwrap();
+        let policy = PolicyEngine::new(grants, ApprovalRules::new(vec!["file.*".into()], None));
+        assert!(policy.evaluate("file.write", &args).requires_confirmation);
+    }
+
+    #[test]
+    fn file_tools_have_risk_classes() {
+        let policy = PolicyEngine::default();
+        let args = json!({"path": "/tmp"});
+        for (tool, risk) in [
+            ("file.list", Risk::ReadOnly),
+            ("file.search", Risk::ReadOnly),
+            ("file.copy", Risk::Write),
+            ("file.delete", Risk::Destructive),
+        ] {
+            let d = policy.evaluate(tool, &args);
+            assert!(d.allowed);
+            assert_eq!(d.risk, Some(risk));
+            assert_eq!(d.requires_confirmation, risk != Risk::ReadOnly);
         }
+        assert!(!policy.evaluate("file.chmod", &args).allowed);
     }
 }
@@ -0,0 +1,464 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Lines of unchanged context around each diff hunk.
+const DIFF_CONTEXT: usize = 3;
+/// Line-pair budget for the LCS table; larger files get a summary instead.
+const DIFF_MAX_CELLS: usize = 4_000_000;
+/// Diff output is cut after this many lines.
+const DIFF_MAX_LINES: usize = 400;
+/// Existing files larger than this are summarised without being read.
+const DIFF_MAX_BYTES: u64 = 1 << 20;
+const DNS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What a risky tool call would do, computed before it is approved.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Preview {
+    FileDiff {
+        path: String,
+        exists: bool,
+        diff: String,
+        truncated: bool,
+    },
+    Command {
+        command_line: String,
+        program: Option<String>,
+        cwd: String,
+        paths: Vec<AffectedPath>,
+    },
+    Network {
+        url: String,
+        host: String,
+        port: u16,
+        addresses: Vec<String>,
+        error: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AffectedPath {
+    pub path: String,
+    pub exists: bool,
+}
+
+impl Preview {
+    /// Plain-text rendering for the REPL.
+    pub fn render(&self) -> String {
+        match self {
+            Preview::FileDiff { path, diff, .. } if diff.is_empty() => {
+                format!("{path}: no changes")
+            }
+            Preview::FileDiff { diff, .. } => diff.clone(),
+            Preview::Command {
+                command_line,
+                program,
+                cwd,
+                paths,
+            } => {
+                let mut out = format!(
+                    "$ {command_line}\n  program: {}\n  cwd: {cwd}",
+                    program.as_deref().unwrap_or("(not found on PATH)")
+                );
+                for p in paths {
+                    let state = if p.exists { "exists" } else { "missing" };
+                    out.push_str(&format!("\n  path: {} ({state})", p.path));
+                }
+                out
+            }
+            Preview::Network {
+                url,
+                addresses,
+                error,
+                ..
+            } => match error {
+                Some(e) => format!("{url} -> unresolved ({e})"),
+                None => format!("{url} -> {}", addresses.join(", ")),
+            },
+        }
+    }
+}
+
+/// Build a preview for tools whose effect isn't obvious from their args.
+pub async fn preview(tool: &str, args: &Value) -> Option<Preview> {
+    let arg = |k: &str| args.get(k).and_then(|v| v.as_str());
+    match tool {
+        "file.write" => Some(file_write_preview(arg("path")?, arg("content")?).await),
+        "shell.exec" => Some(shell_preview(arg("command")?)),
+        "network.get" | "network.post" => Some(network_preview(arg("url")?).await),
+        _ => None,
+    }
+}
+
+async fn file_write_preview(path: &str, content: &str) -> Preview {
+    let summary = |diff: String| Preview::FileDiff {
+        path: path.to_string(),
+        exists: true,
+        diff,
+        truncated: true,
+    };
+    let old = match tokio::fs::metadata(path).await {
+        Ok(meta) if !meta.is_file() => {
+            return summary(format!("{path} is not a regular file"));
+        }
+        Ok(meta) if meta.len() > DIFF_MAX_BYTES => {
+            return summary(format!(
+                "{path} is too large to diff: {} bytes will be replaced by {} bytes",
+                meta.len(),
+                content.len()
+            ));
+        }
+        Ok(_) => tokio::fs::read(path).await.ok(),
+        Err(_) => None,
+    };
+    let exists = old.is_some();
+
+    let (diff, truncated) = match old.as_deref().map(std::str::from_utf8) {
+        Some(Err(_)) => (
+            format!(
+                "Binary file {path} will be replaced with {} bytes of text",
+                content.len()
+            ),
+            false,
+        ),
+        Some(Ok(old)) => unified_diff(path, Some(old), content),
+        None => unified_diff(path, None, content),
+    };
+
+    Preview::FileDiff {
+        path: path.to_string(),
+        exists,
+        diff,
+        truncated,
+    }
+}
+
+fn shell_preview(command: &str) -> Preview {
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let words = shell_words(command);
+    let program = words
+        .iter()
+        .find(|w| !is_env_assignment(w))
+        .and_then(|w| resolve_program(w));
+
+    let mut paths: Vec<AffectedPath> = Vec::new();
+    for w in &words {
+        let Some(p) = path_like(w, &cwd) else {
+            continue;
+        };
+        let path = p.to_string_lossy().into_owned();
+        if !paths.iter().any(|a| a.path == path) {
+            paths.push(AffectedPath {
+                exists: p.exists(),
+                path,
+            });
+        }
+    }
+
+    Preview::Command {
+        command_line: format!("bash -lc '{}'", command.replace('\'', r"'\''")),
+        program,
+        cwd: cwd.to_string_lossy().into_owned(),
+        paths,
+    }
+}
+
+async fn network_preview(url: &str) -> Preview {
+    let parsed = match reqwest::Url::parse(url) {
+        Ok(u) => u,
+        Err(e) => {
+            return Preview::Network {
+                url: url.to_string(),
+                host: String::new(),
+                port: 0,
+                addresses: Vec::new(),
+                error: Some(e.to_string()),
+            }
+        }
+    };
+    let host = parsed.host_str().unwrap_or_default().to_string();
+    let port = parsed.port_or_known_default().unwrap_or(0);
+
+    let target = format!("{host}:{port}");
+    let lookup = tokio::time::timeout(DNS_TIMEOUT, tokio::net::lookup_host(target)).await;
+    let (addresses, error) = match lookup {
+        Ok(Ok(addrs)) => {
+            let mut ips: Vec<String> = addrs.map(|a| a.ip().to_string()).collect();
+            ips.dedup();
+            (ips, None)
+        }
+        Ok(Err(e)) => (Vec::new(), Some(e.to_string())),
+        Err(_) => (Vec::new(), Some("dns lookup timed out".to_string())),
+    };
+
+    Preview::Network {
+        url: url.to_string(),
+        host,
+        port,
+        addresses,
+        error,
+    }
+}
+
+/// Split on whitespace and shell operators, dropping surrounding quotes.
+fn shell_words(command: &str) -> Vec<String> {
+    command
+        .split(|c: char| c.is_whitespace() || matches!(c, ';' | '|' | '&' | '<' | '>'))
+        .map(|w| w.trim_matches(|c| c == '"' || c == '\''))
+        .filter(|w| !w.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn is_env_assignment(word: &str) -> bool {
+    match word.split_once('=') {
+        Some((name, _)) => {
+            !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        None => false,
+    }
+}
+
+fn resolve_program(word: &str) -> Option<String> {
+    if word.contains('/') {
+        return Some(word.to_string());
+    }
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(word))
+        .find(|p| p.is_file())
+        .map(|p| p.to_string_lossy().into_owned())
+}
+
+fn path_like(word: &str, cwd: &Path) -> Option<PathBuf> {
+    if let Some(rest) = word.strip_prefix("~/") {
+        return Some(PathBuf::from(std::env::var_os("HOME")?).join(rest));
+    }
+    if word.starts_with('/') {
+        return Some(PathBuf::from(word));
+    }
+    if word.starts_with("./") || word.starts_with("../") {
+        return Some(cwd.join(word));
+    }
+    None
+}
+
+enum Op {
+    Eq,
+    Del,
+    Ins,
+}
+
+/// Unified diff of `old` (None for a new file) against `new`.
+/// Returns the diff text and whether it was truncated or summarised.
+/// Lines keep their newline, so a change to the final newline shows up.
+pub fn unified_diff(path: &str, old: Option<&str>, new: &str) -> (String, bool) {
+    let a: Vec<&str> = old
+        .map(|o| o.split_inclusive('\n').collect())
+        .unwrap_or_default();
+    let b: Vec<&str> = new.split_inclusive('\n').collect();
+    let sep = if path.starts_with('/') { "" } else { "/" };
+    let old_name = if old.is_some() {
+        format!("a{sep}{path}")
+    } else {
+        "/dev/null".to_string()
+    };
+    let header = format!("--- {old_name}\n+++ b{sep}{path}\n");
+
+    if (a.len() + 1) * (b.len() + 1) > DIFF_MAX_CELLS {
+        return (
+            format!(
+                "{header}@@ -1,{} +1,{} @@\n(file too large to diff: {} lines replaced by {} lines)\n",
+                a.len(),
+                b.len(),
+                a.len(),
+                b.len()
+            ),
+            true,
+        );
+    }
+
+    let ops = diff_ops(&a, &b);
+    let changes: Vec<usize> = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, op)| !matches!(op, Op::Eq))
+        .map(|(i, _)| i)
+        .collect();
+    if changes.is_empty() {
+        return (String::new(), false);
+    }
+
+    // Lines of a/b consumed before each op.
+    let mut before = Vec::with_capacity(ops.len() + 1);
+    let (mut ai, mut bi) = (0usize, 0usize);
+    for op in &ops {
+        before.push((ai, bi));
+        match op {
+            Op::Eq => {
+                ai += 1;
+                bi += 1;
+            }
+            Op::Del => ai += 1,
+            Op::Ins => bi += 1,
+        }
+    }
+    before.push((ai, bi));
+
+    let mut out = header;
+    let mut lines = 0usize;
+    let mut truncated = false;
+    let mut k = 0;
+    while k < changes.len() {
+        let start = changes[k].saturating_sub(DIFF_CONTEXT);
+        let mut last = changes[k];
+        while k + 1 < changes.len() && changes[k + 1] - last <= 2 * DIFF_CONTEXT {
+            k += 1;
+            last = changes[k];
+        }
+        let end = (last + DIFF_CONTEXT + 1).min(ops.len());
+        k += 1;
+
+        let (a0, b0) = before[start];
+        let (a1, b1) = before[end];
+        let (a_len, b_len) = (a1 - a0, b1 - b0);
+        let a_start = if a_len == 0 { a0 } else { a0 + 1 };
+        let b_start = if b_len == 0 { b0 } else { b0 + 1 };
+        out.push_str(&format!("@@ -{a_start},{a_len} +{b_start},{b_len} @@\n"));
+
+        for (i, op) in ops[start..end].iter().enumerate() {
+            if lines == DIFF_MAX_LINES {
+                truncated = true;
+                break;
+            }
+            let (ai, bi) = before[start + i];
+            match op {
+                Op::Eq => push_line(&mut out, ' ', a[ai]),
+                Op::Del => push_line(&mut out, '-', a[ai]),
+                Op::Ins => push_line(&mut out, '+', b[bi]),
+            }
+            lines += 1;
+        }
+        if truncated {
+            out.push_str("... (diff truncated)\n");
+            break;
+        }
+    }
+    (out, truncated)
+}
+
+fn push_line(out: &mut String, prefix: char, line: &str) {
+    out.push(prefix);
+    match line.strip_suffix('\n') {
+        Some(text) => {
+            out.push_str(text);
+            out.push('\n');
+        }
+        None => {
+            out.push_str(line);
+            out.push_str("\n\\ No newline at end of file\n");
+        }
+    }
+}
+
+fn diff_ops(a: &[&str], b: &[&str]) -> Vec<Op> {
+    let (n, m) = (a.len(), b.len());
+    // lcs[i][j] = LCS length of a[i..] and b[j..]
+    let mut lcs = vec![0u32; (n + 1) * (m + 1)];
+    let idx = |i: usize, j: usize| i * (m + 1) + j;
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[idx(i, j)] = if a[i] == b[j] {
+                lcs[idx(i + 1, j + 1)] + 1
+            } else {
+                lcs[idx(i + 1, j)].max(lcs[idx(i, j + 1)])
+            };
+        }
+    }
+
+    let mut ops = Vec::with_capacity(n + m);
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && a[i] == b[j] {
+            ops.push(Op::Eq);
+            i += 1;
+            j += 1;
+        } else if i < n && (j == m || lcs[idx(i + 1, j)] >= lcs[idx(i, j + 1)]) {
+            ops.push(Op::Del);
+            i += 1;
+        } else {
+            ops.push(Op::Ins);
+            j += 1;
+        }
+    }
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_shows_changed_line_with_context() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\n";
+        let new = "a\nb\nc\nd\nE\nf\ng\nh\n";
+        let (diff, truncated) = unified_diff("/tmp/x", Some(old), new);
+        assert!(!truncated);
+        assert_eq!(
+            diff,
+            "--- a/tmp/x\n+++ b/tmp/x\n@@ -2,7 +2,7 @@\n b\n c\n d\n-e\n+E\n f\n g\n h\n"
+        );
+    }
+
+    #[test]
+    fn new_file_diffs_against_dev_null() {
+        let (diff, _) = unified_diff("/tmp/new", None, "hello\n");
+        assert_eq!(
+            diff,
+            "--- /dev/null\n+++ b/tmp/new\n@@ -0,0 +1,1 @@\n+hello\n"
+        );
+        assert!(unified_diff("/tmp/same", Some("x\n"), "x\n").0.is_empty());
+    }
+
+    #[test]
+    fn diff_reports_a_dropped_final_newline() {
+        let (diff, _) = unified_diff("/tmp/x", Some("a\nb\n"), "a\nb");
+        assert_eq!(
+            diff,
+            "--- a/tmp/x\n+++ b/tmp/x\n@@ -1,2 +1,2 @@\n a\n-b\n+b\n\\ No newline at end of file\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn large_files_are_summarised_without_reading() {
+        let path = std::env::temp_dir().join(format!("munin-preview-{}", std::process::id()));
+        let file = std::fs::File::create(&path).unwrap();
+        file.set_len(DIFF_MAX_BYTES + 1).unwrap();
+        let Preview::FileDiff {
+            exists,
+            diff,
+            truncated,
+            ..
+        } = file_write_preview(path.to_str().unwrap(), "small").await
+        else {
+            panic!("expected file diff");
+        };
+        assert!(exists && truncated);
+        assert!(diff.contains("too large to diff"));
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn shell_preview_lists_paths() {
+        let Preview::Command { program, paths, .. } =
+            shell_preview("FOO=1 ls -l /etc > /tmp/out.txt")
+        else {
+            panic!("expected command preview");
+        };
+        assert!(program.is_some_and(|p| p.ends_with("/ls")));
+        let listed: Vec<&str> = paths.iter().map(|p| p.path.as_str()).collect();
+        assert_eq!(listed, vec!["/etc", "/tmp/out.txt"]);
+    }
+}
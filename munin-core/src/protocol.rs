@@ -1,3 +1,4 @@
+use crate::preview::Preview;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
@@ -14,6 +15,9 @@ pub struct ToolCall {
     pub tool: String,
     pub args: Value,
     pub requires_confirmation: bool,
+    /// Dry-run view of the effect, attached while the call awaits approval.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preview: Option<Preview>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
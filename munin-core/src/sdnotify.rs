@@ -0,0 +1,67 @@
+use anyhow::Result;
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+
+/// Send a state string (e.g. `READY=1`) to systemd. Returns false when not
+/// running under a unit with `NotifyAccess` (no `NOTIFY_SOCKET`).
+pub fn notify(state: &str) -> Result<bool> {
+    let Some(path) = std::env::var("NOTIFY_SOCKET").ok() else {
+        return Ok(false);
+    };
+    notify_to(&path, state)?;
+    Ok(true)
+}
+
+fn notify_to(path: &str, state: &str) -> Result<()> {
+    let sock = UnixDatagram::unbound()?;
+    if let Some(name) = path.strip_prefix('@') {
+        send_abstract(&sock, name, state)?;
+    } else {
+        sock.send_to(state.as_bytes(), path)?;
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn send_abstract(sock: &UnixDatagram, name: &str, state: &str) -> Result<()> {
+    use std::os::linux::net::SocketAddrExt;
+    let addr = std::os::unix::net::SocketAddr::from_abstract_name(name.as_bytes())?;
+    sock.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send_abstract(_sock: &UnixDatagram, name: &str, _state: &str) -> Result<()> {
+    anyhow::bail!("abstract notify socket @{name} is only supported on linux")
+}
+
+/// Half the unit's `WatchdogSec`, if the watchdog is enabled for this process.
+pub fn watchdog_interval() -> Option<Duration> {
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    if let Some(pid) = std::env::var("WATCHDOG_PID")
+        .ok()
+        .and_then(|p| p.parse::<u32>().ok())
+    {
+        if pid != std::process::id() {
+            return None;
+        }
+    }
+    Some(Duration::from_micros(usec / 2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notify_writes_datagram() {
+        let path = std::env::temp_dir().join(format!("munin-notify-{}", uuid::Uuid::new_v4()));
+        let server = UnixDatagram::bind(&path).unwrap();
+        notify_to(path.to_str().unwrap(), "READY=1").unwrap();
+
+        let mut buf = [0u8; 64];
+        let n = server.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1");
+        std::fs::remove_file(path).ok();
+    }
+}
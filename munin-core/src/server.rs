@@ -1,25 +1,30 @@
 use crate::agent::AgentRuntime;
-use crate::protocol::{CoreEvent, SpeechTurn, ToolCall, ToolResult};
-use crate::tools::ToolRouter;
-use anyhow::Result;
-use serde::{Deserialize, Serialize};
+use crate::approvals::{ApprovalQueue, ApprovalRules, Factor, Vote};
+use crate::bus::{self, AgentId, AgentInfo, MessageBus};
+use crate::calls::CancelOutcome;
+use crate::grants::GrantDuration;
+use crate::protocol::{CoreEvent, ToolResult};
+use crate::supervisor::{Mode, HEALTH_CHECK_TOPIC, HEALTH_TOPIC};
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
 use serde_json::json;
 use std::collections::HashMap;
-use std::io::Read;
-use std::sync::{Arc, Mutex};
-use tiny_http::{Header, Method, Response, Server, StatusCode};
+use std::sync::Arc;
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
 
 #[derive(Clone)]
 pub struct ApiState {
     runtime: Arc<AgentRuntime>,
-    pending: Arc<Mutex<HashMap<String, ToolCall>>>,
+    bus: Arc<MessageBus>,
+    pending: Arc<ApprovalQueue>,
 }
 
 impl ApiState {
-    pub fn new(runtime: AgentRuntime) -> Self {
+    pub fn new(runtime: AgentRuntime, bus: Arc<MessageBus>, rules: ApprovalRules) -> Self {
         Self {
             runtime: Arc::new(runtime),
-            pending: Arc::new(Mutex::new(HashMap::new())),
+            bus,
+            pending: Arc::new(ApprovalQueue::new(rules)),
         }
     }
 }
@@ -35,120 +40,452 @@ struct TranscriptIn {
 struct ConfirmIn {
     id: String,
     approve: bool,
+    #[serde(default)]
+    pin: Option<String>,
+    /// Also allow similar calls for this long without asking.
+    #[serde(default)]
+    remember: Option<GrantDuration>,
 }
 
-#[derive(Debug, Serialize)]
-struct PendingItem {
-    id: String,
-    tool: String,
-    args: serde_json::Value,
+#[derive(Debug, Deserialize)]
+struct AgentIdIn {
+    id: AgentId,
 }
 
-pub fn serve(addr: &str, state: ApiState) -> Result<()> {
-    let server = Server::http(addr)?;
-    tracing::info!("munin-core api listening on http://{}", addr);
+#[derive(Debug, Deserialize)]
+struct UndoIn {
+    /// Rollback token or the id of the write call.
+    token: String,
+    #[serde(default)]
+    force: bool,
+}
 
-    for mut req in server.incoming_requests() {
-        let path = req.url().to_string();
-        let method = req.method().clone();
+pub fn serve(addr: &str, state: ApiState) -> Result<()> {
+    serve_on(bind(addr)?, state)
+}
 
-        let resp = match (method, path.as_str()) {
-            (Method::Post, "/v1/transcript") => {
-                let mut body = String::new();
-                let _ = req.as_reader().read_to_string(&mut body);
-                handle_transcript(&state, &body)
-            }
-            (Method::Get, "/v1/pending") => handle_pending(&state),
-            (Method::Post, "/v1/confirm") => {
-                let mut body = String::new();
-                let _ = req.as_reader().read_to_string(&mut body);
-                handle_confirm(&state, &body)
-            }
-            (Method::Get, "/health") => ok(json!({"ok": true})),
-            _ => json_response(StatusCode(404), json!({"error": "not_found"})),
-        };
+pub fn bind(addr: &str) -> Result<Server> {
+    let server = Server::http(addr).map_err(|e| anyhow!(e))?;
+    tracing::info!("munin-core api listening on http://{}", addr);
+    Ok(server)
+}
 
-        let _ = req.respond(resp);
+pub fn serve_on(server: Server, state: ApiState) -> Result<()> {
+    // Each request gets its own thread so a long tool call can't block
+    // `/v1/calls/{id}/cancel` or the pending queue.
+    let rt = tokio::runtime::Handle::try_current().ok();
+    for req in server.incoming_requests() {
+        let state = state.clone();
+        let rt = rt.clone();
+        std::thread::spawn(move || {
+            let _guard = rt.as_ref().map(|h| h.enter());
+            route(req, &state);
+        });
     }
 
     Ok(())
 }
 
+fn route(mut req: Request, state: &ApiState) {
+    let url = req.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((url.as_str(), ""));
+    let method = req.method().clone();
+    let call_path = path.strip_prefix("/v1/calls/");
+
+    let resp = match (method, path) {
+        (Method::Post, "/v1/transcript") => {
+            let mut body = String::new();
+            let _ = req.as_reader().read_to_string(&mut body);
+            handle_transcript(state, &body)
+        }
+        (Method::Get, "/v1/pending") => handle_pending(state),
+        (Method::Post, "/v1/confirm") => {
+            let mut body = String::new();
+            let _ = req.as_reader().read_to_string(&mut body);
+            let bearer = req
+                .headers()
+                .iter()
+                .find(|h| h.field.equiv("Authorization"))
+                .and_then(|h| h.value.as_str().strip_prefix("Bearer "))
+                .map(str::to_string);
+            handle_confirm(state, &body, bearer.as_deref())
+        }
+        (Method::Get, "/v1/bus/tail") => handle_bus_tail(state, query),
+        (Method::Get, "/v1/agents") => handle_agents(state),
+        (Method::Get, "/v1/bus/journal") => handle_bus_journal(state, query),
+        (Method::Get, "/v1/system/health") => handle_system_health(state, query),
+        (Method::Post, "/v1/agents/register") => {
+            let mut body = String::new();
+            let _ = req.as_reader().read_to_string(&mut body);
+            handle_agent_register(state, &body)
+        }
+        (Method::Post, "/v1/agents/heartbeat") => {
+            let mut body = String::new();
+            let _ = req.as_reader().read_to_string(&mut body);
+            handle_agent_heartbeat(state, &body)
+        }
+        (Method::Post, "/v1/agents/unregister") => {
+            let mut body = String::new();
+            let _ = req.as_reader().read_to_string(&mut body);
+            handle_agent_unregister(state, &body)
+        }
+        (Method::Get, "/v1/calls") => ok(json!({"calls": state.runtime.calls.list()})),
+        (Method::Post, _) if call_path.is_some_and(|p| p.ends_with("/cancel")) => {
+            let id = call_path
+                .and_then(|p| p.strip_suffix("/cancel"))
+                .unwrap_or_default();
+            handle_cancel(state, id)
+        }
+        (Method::Get, _) if call_path.is_some() => {
+            match state.runtime.calls.get(call_path.unwrap_or_default()) {
+                Some(record) => ok(json!(record)),
+                None => json_response(StatusCode(404), json!({"error": "call_not_found"})),
+            }
+        }
+        (Method::Post, "/v1/undo") => {
+            let mut body = String::new();
+            let _ = req.as_reader().read_to_string(&mut body);
+            handle_undo(state, &body)
+        }
+        (Method::Get, "/v1/grants") => ok(json!({"grants": state.runtime.policy.grants().list()})),
+        (Method::Delete, _) if path.starts_with("/v1/grants/") => {
+            handle_revoke_grant(state, &path["/v1/grants/".len()..])
+        }
+        (Method::Get, "/health") => ok(json!({"ok": true})),
+        _ => json_response(StatusCode(404), json!({"error": "not_found"})),
+    };
+
+    let _ = req.respond(resp);
+}
+
 fn handle_transcript(state: &ApiState, body: &str) -> Response<std::io::Cursor<Vec<u8>>> {
     let input: TranscriptIn = match serde_json::from_str(body) {
         Ok(v) => v,
         Err(e) => return json_response(StatusCode(400), json!({"error": e.to_string()})),
     };
 
-    let events = match tokio::runtime::Handle::try_current() {
-        Ok(h) => h.block_on(state.runtime.handle_text(&input.transcript, false)),
-        Err(_) => {
-            let rt = tokio::runtime::Runtime::new().unwrap();
-            rt.block_on(state.runtime.handle_text(&input.transcript, false))
-        }
-    };
+    let events = block_on(state.runtime.handle_text(
+        &input.transcript,
+        input.locale.as_deref(),
+        false,
+    ));
 
     match events {
         Ok(events) => {
-            for ev in &events {
-                if let CoreEvent::ToolCall(call) = ev {
-                    if call.requires_confirmation {
-                        state.pending.lock().unwrap().insert(call.id.clone(), call.clone());
-                    }
-                }
-            }
+            publish_events(state, &events);
+            queue_pending(state, &events);
             ok(json!({
                 "session": input.session_id.unwrap_or_else(|| "default".into()),
                 "events": events,
-                "pending_count": state.pending.lock().unwrap().len()
+                "pending_count": state.pending.len()
             }))
         }
         Err(e) => json_response(StatusCode(500), json!({"error": e.to_string()})),
     }
 }
 
+fn queue_pending(state: &ApiState, events: &[CoreEvent]) {
+    for ev in events {
+        if let CoreEvent::ToolCall(call) = ev {
+            if call.requires_confirmation {
+                state.pending.push(call.clone());
+            }
+        }
+    }
+}
+
 fn handle_pending(state: &ApiState) -> Response<std::io::Cursor<Vec<u8>>> {
-    let list: Vec<PendingItem> = state
-        .pending
-        .lock()
-        .unwrap()
-        .values()
-        .map(|c| PendingItem {
-            id: c.id.clone(),
-            tool: c.tool.clone(),
-            args: c.args.clone(),
-        })
-        .collect();
-    ok(json!({"pending": list}))
-}
-
-fn handle_confirm(state: &ApiState, body: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    ok(json!({"pending": state.pending.list()}))
+}
+
+/// The factor a confirmation counts as comes from how the caller
+/// authenticated, never from the body: clients could otherwise name two
+/// factors in two posts and clear a two-factor call alone.
+fn handle_confirm(
+    state: &ApiState,
+    body: &str,
+    bearer: Option<&str>,
+) -> Response<std::io::Cursor<Vec<u8>>> {
     let input: ConfirmIn = match serde_json::from_str(body) {
         Ok(v) => v,
         Err(e) => return json_response(StatusCode(400), json!({"error": e.to_string()})),
     };
+    let via = state.pending.factor(bearer);
+    // A spoken answer only counts while the voice pipeline is healthy.
+    let mode = state.runtime.mode().get();
+    if via == Factor::Voice && mode != Mode::Full {
+        return json_response(
+            StatusCode(409),
+            json!({"id": input.id, "ok": false, "error": "voice_unavailable", "mode": mode}),
+        );
+    }
 
-    let call = match state.pending.lock().unwrap().remove(&input.id) {
-        Some(c) => c,
-        None => return json_response(StatusCode(404), json!({"error": "pending_id_not_found"})),
+    let call = match state
+        .pending
+        .vote(&input.id, input.approve, via, input.pin.as_deref())
+    {
+        Vote::Approved(call) => call,
+        Vote::Denied(call) => {
+            state.runtime.calls.cancel(&call.id);
+            return ok(json!({"id": call.id, "ok": false, "message": "denied"}));
+        }
+        Vote::NeedMore { have, need } => {
+            return json_response(
+                StatusCode(202),
+                json!({"id": input.id, "ok": false, "status": "awaiting_factor", "factors": have, "required": need}),
+            )
+        }
+        Vote::BadPin { attempts_left } => {
+            return json_response(
+                StatusCode(403),
+                json!({"id": input.id, "ok": false, "error": "bad_pin", "attempts_left": attempts_left}),
+            )
+        }
+        Vote::NotFound => {
+            return json_response(StatusCode(404), json!({"error": "pending_id_not_found"}))
+        }
     };
 
-    if !input.approve {
-        return ok(json!({"id": call.id, "ok": false, "message": "denied"}));
+    // A standing grant would bypass the second factor, so critical tools
+    // can't be remembered.
+    let remember = input
+        .remember
+        .filter(|_| state.pending.required(&call.tool) < 2);
+    let grant = match remember {
+        Some(duration) => {
+            match state
+                .runtime
+                .policy
+                .grants()
+                .grant_for(&call.id, &call.tool, &call.args, duration)
+            {
+                Ok(g) => g,
+                Err(e) => {
+                    tracing::warn!("failed to store grant for {}: {e:#}", call.id);
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+
+    let result = block_on(state.runtime.execute(&call));
+    let (ok_flag, output) = match &result {
+        Ok(output) => (true, output.clone()),
+        Err(e) => (false, json!({"error": e.to_string()})),
+    };
+    publish(
+        state,
+        &tool_topic(&call.tool, "result"),
+        json!({"id": call.id, "ok": ok_flag, "output": output}),
+    );
+
+    match result {
+        Ok(output) => ok(
+            json!({"id": call.id, "ok": true, "grant": grant, "result": ToolResult { id: call.id, ok: true, output }}),
+        ),
+        Err(e) => json_response(
+            StatusCode(500),
+            json!({"id": call.id, "ok": false, "error": e.to_string()}),
+        ),
+    }
+}
+
+fn handle_cancel(state: &ApiState, id: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    state.pending.remove(id);
+    match state.runtime.calls.cancel(id) {
+        CancelOutcome::Cancelled => ok(json!({"id": id, "ok": true, "state": "cancelled"})),
+        CancelOutcome::AlreadyFinished(s) => json_response(
+            StatusCode(409),
+            json!({"id": id, "ok": false, "error": "already_finished", "state": s}),
+        ),
+        CancelOutcome::Committing => json_response(
+            StatusCode(409),
+            json!({"id": id, "ok": false, "error": "committing", "state": "running"}),
+        ),
+        CancelOutcome::NotFound => {
+            json_response(StatusCode(404), json!({"error": "call_not_found"}))
+        }
     }
+}
+
+/// Clicking "undo" on a write's result is itself the approval, so the
+/// restore runs directly instead of joining the pending queue.
+/// The UI's undo button. `file.undo` overwrites or deletes a file, so it
+/// goes through policy and the approval queue like any other write.
+fn handle_undo(state: &ApiState, body: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    let input: UndoIn = match serde_json::from_str(body) {
+        Ok(v) => v,
+        Err(e) => return json_response(StatusCode(400), json!({"error": e.to_string()})),
+    };
+    let args = json!({"token": input.token, "force": input.force});
+    let events = block_on(state.runtime.propose("file.undo", args, false));
+    publish_events(state, &events);
+    queue_pending(state, &events);
+    ok(json!({"events": events, "pending_count": state.pending.len()}))
+}
 
-    let result = match tokio::runtime::Handle::try_current() {
-        Ok(h) => h.block_on(ToolRouter::execute(&call.tool, &call.args)),
+fn handle_revoke_grant(state: &ApiState, id: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    match state.runtime.policy.grants().revoke(id) {
+        Ok(true) => ok(json!({"id": id, "ok": true})),
+        Ok(false) => json_response(StatusCode(404), json!({"error": "grant_not_found"})),
+        Err(e) => json_response(StatusCode(500), json!({"error": e.to_string()})),
+    }
+}
+
+fn handle_bus_tail(state: &ApiState, query: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    let pattern = query_param(query, "pattern").unwrap_or_else(|| "#".into());
+    let after = query_param(query, "after")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+    match state.bus.tail(&pattern, after) {
+        Ok(messages) => ok(json!({"messages": messages})),
+        Err(e) => json_response(StatusCode(400), json!({"error": e.to_string()})),
+    }
+}
+
+fn handle_bus_journal(state: &ApiState, query: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    let pattern = query_param(query, "pattern").unwrap_or_else(|| "#".into());
+    let since = query_param(query, "since_ms")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+    match state.bus.replay(&pattern, since) {
+        Ok(messages) => ok(json!({"messages": messages})),
+        Err(e) => json_response(StatusCode(400), json!({"error": e.to_string()})),
+    }
+}
+
+/// The supervisor's last published view, or with `fresh=1` one it probes
+/// for this request.
+fn handle_system_health(state: &ApiState, query: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    if query_param(query, "fresh").is_some_and(|v| v == "1" || v == "true") {
+        let timeout = std::time::Duration::from_secs(10);
+        return match block_on(state.bus.request(HEALTH_CHECK_TOPIC, json!({}), timeout)) {
+            Ok(reply) => ok(reply.payload),
+            Err(e) => json_response(StatusCode(503), json!({"error": e.to_string()})),
+        };
+    }
+    match block_on(state.bus.retained(HEALTH_TOPIC)) {
+        Ok(msgs) => match msgs.into_iter().next() {
+            Some(msg) => ok(msg.payload),
+            None => ok(json!({"mode": "unsupervised", "components": []})),
+        },
+        Err(e) => json_response(StatusCode(500), json!({"error": e.to_string()})),
+    }
+}
+
+fn handle_agents(state: &ApiState) -> Response<std::io::Cursor<Vec<u8>>> {
+    match block_on(state.bus.list_agents()) {
+        Ok(agents) => ok(json!({"agents": agents})),
+        Err(e) => json_response(StatusCode(500), json!({"error": e.to_string()})),
+    }
+}
+
+fn handle_agent_register(state: &ApiState, body: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    let info: AgentInfo = match serde_json::from_str(body) {
+        Ok(v) => v,
+        Err(e) => return json_response(StatusCode(400), json!({"error": e.to_string()})),
+    };
+    match block_on(state.bus.register(info)) {
+        Ok(()) => ok(json!({"ok": true})),
+        Err(e) => json_response(StatusCode(500), json!({"error": e.to_string()})),
+    }
+}
+
+fn handle_agent_heartbeat(state: &ApiState, body: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    let input: AgentIdIn = match serde_json::from_str(body) {
+        Ok(v) => v,
+        Err(e) => return json_response(StatusCode(400), json!({"error": e.to_string()})),
+    };
+    match block_on(state.bus.heartbeat(&input.id)) {
+        Ok(()) => ok(json!({"ok": true})),
+        Err(e) => json_response(StatusCode(404), json!({"error": e.to_string()})),
+    }
+}
+
+fn handle_agent_unregister(state: &ApiState, body: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    let input: AgentIdIn = match serde_json::from_str(body) {
+        Ok(v) => v,
+        Err(e) => return json_response(StatusCode(400), json!({"error": e.to_string()})),
+    };
+    match block_on(state.bus.unregister(&input.id)) {
+        Ok(removed) => ok(json!({"ok": removed})),
+        Err(e) => json_response(StatusCode(500), json!({"error": e.to_string()})),
+    }
+}
+
+/// Mirror a turn's events onto the bus so `munin-core bus tail` can follow them.
+fn publish_events(state: &ApiState, events: &[CoreEvent]) {
+    let mut tools = HashMap::new();
+    for ev in events {
+        match ev {
+            CoreEvent::Transcript(_) => {}
+            CoreEvent::ToolCall(call) => {
+                tools.insert(call.id.clone(), call.tool.clone());
+                publish(state, &tool_topic(&call.tool, "call"), json!(call));
+            }
+            CoreEvent::ToolResult(result) => {
+                if let Some(tool) = tools.get(&result.id) {
+                    publish(state, &tool_topic(tool, "result"), json!(result));
+                }
+            }
+            CoreEvent::ResponseText(text) => publish(state, "ui/response", json!(text)),
+            CoreEvent::Error(err) => publish(state, bus::ERROR, json!(err)),
+        }
+    }
+}
+
+fn publish(state: &ApiState, topic: &str, payload: serde_json::Value) {
+    if let Err(e) = block_on(state.bus.send(topic, payload)) {
+        tracing::warn!("bus publish on {} failed: {}", topic, e);
+    }
+}
+
+/// `shell.exec` + `call` -> `tools/shell/exec/call`.
+fn tool_topic(tool: &str, event: &str) -> String {
+    format!("{}/{}/{}", bus::TOOLS, tool.replace('.', "/"), event)
+}
+
+fn block_on<F: std::future::Future>(fut: F) -> F::Output {
+    match tokio::runtime::Handle::try_current() {
+        Ok(h) => h.block_on(fut),
         Err(_) => {
             let rt = tokio::runtime::Runtime::new().unwrap();
-            rt.block_on(ToolRouter::execute(&call.tool, &call.args))
+            rt.block_on(fut)
         }
-    };
+    }
+}
 
-    match result {
-        Ok(output) => ok(json!({"id": call.id, "ok": true, "result": ToolResult { id: call.id, ok: true, output }})),
-        Err(e) => json_response(StatusCode(500), json!({"id": call.id, "ok": false, "error": e.to_string()})),
+fn query_param(query: &str, key: &str) -> Option<String> {
+    query.split('&').find_map(|pair| {
+        let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+        (k == key).then(|| percent_decode(v))
+    })
+}
+
+/// `%XX` escapes only: `+` stays a `+`, the single-level topic wildcard.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+                match u8::from_str_radix(hex, 16) {
+                    Ok(b) => {
+                        out.push(b);
+                        i += 3;
+                        continue;
+                    }
+                    Err(_) => out.push(b'%'),
+                }
+            }
+            b => out.push(b),
+        }
+        i += 1;
     }
+    String::from_utf8_lossy(&out).into_owned()
 }
 
 fn ok(v: serde_json::Value) -> Response<std::io::Cursor<Vec<u8>>> {
@@ -162,3 +499,21 @@ fn json_response(code: StatusCode, v: serde_json::Value) -> Response<std::io::Cu
     }
     resp
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_params_keep_topic_wildcards() {
+        let query = "pattern=agents/+/heartbeat&after=3";
+        assert_eq!(
+            query_param(query, "pattern").as_deref(),
+            Some("agents/+/heartbeat")
+        );
+        assert_eq!(
+            query_param("pattern=tools%2F%23", "pattern").as_deref(),
+            Some("tools/#")
+        );
+    }
+}
@@ -0,0 +1,293 @@
+use crate::bus::{AgentId, Health, MessageBus};
+use crate::sdnotify;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Retained topic carrying the latest `SystemHealth`.
+pub const HEALTH_TOPIC: &str = "system/health";
+/// Requests here are answered with a fresh `SystemHealth`.
+pub const HEALTH_CHECK_TOPIC: &str = "system/health/check";
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+enum Probe {
+    /// GET the URL and expect a 2xx.
+    Http(String),
+    /// Expect a live heartbeat from the agent in the bus registry.
+    Heartbeat(AgentId),
+}
+
+struct Component {
+    name: &'static str,
+    probe: Probe,
+    voice: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    /// Brain and voice pipeline are up.
+    Full,
+    /// Voice pipeline is down or disabled; transcripts arrive via API/UI only.
+    TextOnly,
+    /// Brain is unreachable; core falls back to its built-in rules.
+    RulesOnly,
+}
+
+/// The supervisor's latest `Mode`, shared with the parts of the core that
+/// use the brain or the voice pipeline. `Full` until the first probe.
+#[derive(Clone)]
+pub struct ModeHandle(Arc<RwLock<Mode>>);
+
+impl Default for ModeHandle {
+    fn default() -> Self {
+        Self(Arc::new(RwLock::new(Mode::Full)))
+    }
+}
+
+impl ModeHandle {
+    pub fn get(&self) -> Mode {
+        *self.0.read().unwrap()
+    }
+
+    pub fn set(&self, mode: Mode) {
+        *self.0.write().unwrap() = mode;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ComponentHealth {
+    pub name: String,
+    pub up: bool,
+    pub voice: bool,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SystemHealth {
+    pub mode: Mode,
+    pub voice_expected: bool,
+    pub components: Vec<ComponentHealth>,
+}
+
+/// Probes the other Munin services and publishes an aggregated health view.
+pub struct Supervisor {
+    bus: Arc<MessageBus>,
+    components: Vec<Component>,
+    voice_expected: bool,
+    mode: ModeHandle,
+    client: reqwest::Client,
+}
+
+impl Supervisor {
+    /// Keeps `mode` up to date with every probe.
+    pub fn new(
+        bus: Arc<MessageBus>,
+        brain_endpoint: &str,
+        voice_expected: bool,
+        mode: ModeHandle,
+    ) -> Self {
+        let mut components = vec![Component {
+            name: "munin-brain",
+            probe: Probe::Http(format!("{}/health", brain_endpoint.trim_end_matches('/'))),
+            voice: false,
+        }];
+        if voice_expected {
+            for name in ["munin-sts", "munin-audio"] {
+                components.push(Component {
+                    name,
+                    probe: Probe::Heartbeat(AgentId(name.into())),
+                    voice: true,
+                });
+            }
+        }
+
+        Self {
+            bus,
+            components,
+            voice_expected,
+            mode,
+            client: reqwest::Client::builder()
+                .timeout(PROBE_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+
+    pub async fn check(&self) -> SystemHealth {
+        let agents = self.bus.list_agents().await.unwrap_or_default();
+
+        let mut components = Vec::with_capacity(self.components.len());
+        for c in &self.components {
+            let (up, detail) = match &c.probe {
+                Probe::Http(url) => match self.client.get(url).send().await {
+                    Ok(resp) if resp.status().is_success() => (true, "ok".to_string()),
+                    Ok(resp) => (false, format!("http {}", resp.status())),
+                    Err(e) => (false, e.to_string()),
+                },
+                Probe::Heartbeat(id) => match agents.iter().find(|a| &a.info.id == id) {
+                    Some(a) if a.health == Health::Alive => (true, "heartbeat ok".to_string()),
+                    Some(_) => (false, "heartbeat stale".to_string()),
+                    None => (false, "not registered".to_string()),
+                },
+            };
+            components.push(ComponentHealth {
+                name: c.name.to_string(),
+                up,
+                voice: c.voice,
+                detail,
+            });
+        }
+
+        SystemHealth {
+            mode: mode_for(&components, self.voice_expected),
+            voice_expected: self.voice_expected,
+            components,
+        }
+    }
+
+    /// Probe every `interval`, when an agent joins or leaves, and on requests
+    /// to `HEALTH_CHECK_TOPIC`, publishing changes and keeping systemd
+    /// informed. Signals `READY=1` after the first round. Publish and notify
+    /// failures are logged; they don't stop supervision.
+    pub async fn run(self, interval: Duration) -> Result<()> {
+        let interval = match sdnotify::watchdog_interval() {
+            Some(wd) => interval.min(wd),
+            None => interval,
+        };
+        let me = AgentId("munin-core/supervisor".into());
+        let mut agents = self.bus.subscribe(me.clone(), "system/agents/+").await?;
+        let mut checks = self.bus.subscribe(me, HEALTH_CHECK_TOPIC).await?;
+        let mut tick = tokio::time::interval(interval);
+        let mut last: Option<SystemHealth> = None;
+
+        loop {
+            let request = tokio::select! {
+                _ = tick.tick() => None,
+                Some(_) = agents.recv() => None,
+                Some(req) = checks.recv() => Some(req),
+            };
+            let lost = agents.take_dropped() + checks.take_dropped();
+            if lost > 0 {
+                tracing::warn!("supervisor missed {lost} bus message(s)");
+            }
+            let health = self.check().await;
+            self.mode.set(health.mode);
+            if let Some(req) = request.filter(|r| r.expects_reply) {
+                if let Err(e) = self.bus.reply(&req, json!(health)) {
+                    tracing::warn!("failed answering health check: {e}");
+                }
+            }
+
+            if last.as_ref() != Some(&health) {
+                let down: Vec<&str> = health
+                    .components
+                    .iter()
+                    .filter(|c| !c.up)
+                    .map(|c| c.name.as_str())
+                    .collect();
+                let status = format!("mode={:?} down=[{}]", health.mode, down.join(","));
+                tracing::info!("system health: {}", status);
+                if let Err(e) = self.bus.send_retained(HEALTH_TOPIC, json!(health)).await {
+                    tracing::warn!("failed publishing system health: {e}");
+                }
+                notify(&format!("STATUS={status}"));
+                if last.is_none() {
+                    notify("READY=1");
+                }
+                last = Some(health);
+            }
+            notify("WATCHDOG=1");
+        }
+    }
+}
+
+fn notify(state: &str) {
+    if let Err(e) = sdnotify::notify(state) {
+        tracing::warn!("sd_notify {state} failed: {e}");
+    }
+}
+
+fn mode_for(components: &[ComponentHealth], voice_expected: bool) -> Mode {
+    let brain_up = components.iter().filter(|c| !c.voice).all(|c| c.up);
+    let voice_up = components.iter().filter(|c| c.voice).all(|c| c.up);
+    if !brain_up {
+        Mode::RulesOnly
+    } else if voice_expected && voice_up {
+        Mode::Full
+    } else {
+        Mode::TextOnly
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comp(name: &str, up: bool, voice: bool) -> ComponentHealth {
+        ComponentHealth {
+            name: name.into(),
+            up,
+            voice,
+            detail: String::new(),
+        }
+    }
+
+    #[test]
+    fn voice_outage_degrades_to_text_only() {
+        let all_up = [
+            comp("munin-brain", true, false),
+            comp("munin-sts", true, true),
+        ];
+        assert_eq!(mode_for(&all_up, true), Mode::Full);
+        assert_eq!(mode_for(&all_up, false), Mode::TextOnly);
+
+        let sts_down = [
+            comp("munin-brain", true, false),
+            comp("munin-sts", false, true),
+        ];
+        assert_eq!(mode_for(&sts_down, true), Mode::TextOnly);
+
+        let brain_down = [
+            comp("munin-brain", false, false),
+            comp("munin-sts", true, true),
+        ];
+        assert_eq!(mode_for(&brain_down, true), Mode::RulesOnly);
+    }
+
+    #[tokio::test]
+    async fn unregistered_voice_agents_report_down() {
+        let bus = Arc::new(MessageBus::new().await.unwrap());
+        let sup = Supervisor::new(bus, "http://127.0.0.1:9", true, ModeHandle::default());
+        let health = sup.check().await;
+        assert_eq!(health.mode, Mode::RulesOnly);
+        assert!(health.components.iter().all(|c| !c.up));
+    }
+
+    #[tokio::test]
+    async fn answers_health_check_requests() {
+        let bus = Arc::new(MessageBus::new().await.unwrap());
+        let sup = Supervisor::new(
+            bus.clone(),
+            "http://127.0.0.1:9",
+            false,
+            ModeHandle::default(),
+        );
+        tokio::spawn(sup.run(Duration::from_secs(3600)));
+        // Asked before the supervisor subscribed, nobody takes the request.
+        let reply = loop {
+            match bus
+                .request(HEALTH_CHECK_TOPIC, json!({}), Duration::from_secs(5))
+                .await
+            {
+                Ok(reply) => break reply,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+        let health: SystemHealth = serde_json::from_value(reply.payload).unwrap();
+        assert_eq!(health.mode, Mode::RulesOnly);
+    }
+}
@@ -1,20 +1,126 @@
+use crate::files;
+use crate::undo::UndoJournal;
 use anyhow::{anyhow, Context, Result};
 use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::Arc;
 use tokio::process::Command;
 
-pub struct ToolRouter;
+pub struct ToolRouter {
+    undo: Arc<UndoJournal>,
+    packages: Arc<bpkg::Bpkg>,
+    /// munin-brain, which holds the knowledge index.
+    brain_endpoint: String,
+}
 
 impl ToolRouter {
-    pub async fn execute(tool: &str, args: &Value) -> Result<Value> {
+    pub fn new(undo: UndoJournal, packages: bpkg::Bpkg, brain_endpoint: &str) -> Self {
+        Self {
+            undo: Arc::new(undo),
+            packages: Arc::new(packages),
+            brain_endpoint: brain_endpoint.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// `token` reaches the tools that change the disk: package transactions
+    /// stop at it until they commit, and file changes claim it before starting.
+    pub async fn execute(
+        &self,
+        call_id: &str,
+        tool: &str,
+        args: &Value,
+        token: &bpkg::CancelToken,
+    ) -> Result<Value> {
         match tool {
             "system.status" => Ok(system_status().await),
-            "file.read" => file_read(args).await,
-            "file.write" => file_write(args).await,
+            "file.read" => blocking(args, files::read).await,
+            "file.list" => blocking(args, files::list).await,
+            "file.stat" => blocking(args, files::stat).await,
+            "file.search" => blocking(args, files::search).await,
+            "file.move" => committing(args, token, files::rename).await,
+            "file.copy" => {
+                let (undo, id) = (self.undo.clone(), call_id.to_string());
+                committing(args, token, move |a| files::copy(&undo, &id, a)).await
+            }
+            "file.delete" => {
+                let (undo, id) = (self.undo.clone(), call_id.to_string());
+                committing(args, token, move |a| files::delete(&undo, &id, a)).await
+            }
+            "file.write" => self.file_write(call_id, args, token).await,
+            "file.undo" => self.file_undo(call_id, args, token).await,
+            "package.search" | "package.info" | "package.install" | "package.remove" => {
+                let (packages, tool, token) =
+                    (self.packages.clone(), tool.to_string(), token.clone());
+                blocking(args, move |a| package_tool(&packages, &tool, a, &token)).await
+            }
             "shell.exec" => shell_exec(args).await,
             "network.get" => network_get(args).await,
+            "knowledge.search" => knowledge_search(&self.brain_endpoint, args).await,
             _ => Err(anyhow!("unknown tool: {tool}")),
         }
     }
+
+    /// Writes go through the undo journal; the result's `rollback` token
+    /// (null if the old file was too large to keep) restores the old contents.
+    async fn file_write(
+        &self,
+        call_id: &str,
+        args: &Value,
+        token: &bpkg::CancelToken,
+    ) -> Result<Value> {
+        let path = args
+            .get("path")
+            .and_then(|v| v.as_str())
+            .context("file.write requires args.path")?;
+        let content = args
+            .get("content")
+            .and_then(|v| v.as_str())
+            .context("file.write requires args.content")?;
+
+        let (undo, call_id, target, bytes, token) = (
+            self.undo.clone(),
+            call_id.to_string(),
+            PathBuf::from(path),
+            content.as_bytes().to_vec(),
+            token.clone(),
+        );
+        let rollback = tokio::task::spawn_blocking(move || {
+            token.commit()?;
+            undo.write(&call_id, &target, Some(&bytes))
+        })
+        .await??;
+        Ok(json!({"path": path, "written": content.len(), "rollback": rollback}))
+    }
+
+    /// Restore the file a previous `file.write` replaced, by `token` or `call_id`.
+    async fn file_undo(
+        &self,
+        call_id: &str,
+        args: &Value,
+        token: &bpkg::CancelToken,
+    ) -> Result<Value> {
+        let target = args
+            .get("token")
+            .or_else(|| args.get("call_id"))
+            .and_then(|v| v.as_str())
+            .context("file.undo requires args.token or args.call_id")?
+            .to_string();
+        let force = args.get("force").and_then(|v| v.as_bool()).unwrap_or(false);
+
+        let (undo, call_id, token) = (self.undo.clone(), call_id.to_string(), token.clone());
+        let (entry, rollback) = tokio::task::spawn_blocking(move || {
+            token.commit()?;
+            undo.undo(&call_id, &target, force)
+        })
+        .await??;
+        Ok(json!({
+            "path": entry.path,
+            "undone_call": entry.call_id,
+            "restored": if entry.existed { "previous_contents" } else { "deleted" },
+            "rollback": rollback,
+        }))
+    }
 }
 
 async fn system_status() -> Value {
@@ -25,25 +131,105 @@ async fn system_status() -> Value {
     })
 }
 
-async fn file_read(args: &Value) -> Result<Value> {
-    let path = args.get("path").and_then(|v| v.as_str()).context("file.read requires args.path")?;
-    let content = tokio::fs::read_to_string(path).await.with_context(|| format!("failed reading {path}"))?;
-    Ok(json!({"path": path, "content": content}))
+/// File tools do blocking I/O and directory walks; keep them off the runtime.
+async fn blocking<F>(args: &Value, f: F) -> Result<Value>
+where
+    F: FnOnce(&Value) -> Result<Value> + Send + 'static,
+{
+    let args = args.clone();
+    tokio::task::spawn_blocking(move || f(&args)).await?
 }
 
-async fn file_write(args: &Value) -> Result<Value> {
-    let path = args.get("path").and_then(|v| v.as_str()).context("file.write requires args.path")?;
-    let content = args.get("content").and_then(|v| v.as_str()).context("file.write requires args.content")?;
-    if let Some(parent) = std::path::Path::new(path).parent() {
-        tokio::fs::create_dir_all(parent).await.ok();
+/// `blocking` for file tools that change the disk: the work claims `token`'s
+/// commit first, so a call cancelled or timed out before it starts changes
+/// nothing, and one that has started is left to finish.
+async fn committing<F>(args: &Value, token: &bpkg::CancelToken, f: F) -> Result<Value>
+where
+    F: FnOnce(&Value) -> Result<Value> + Send + 'static,
+{
+    let token = token.clone();
+    blocking(args, move |a| {
+        token.commit()?;
+        f(a)
+    })
+    .await
+}
+
+/// `package.*` tools call the bpkg library directly; install and remove
+/// return the transaction's per-package version changes. Installs take
+/// package names only: a path would install whatever files it points at.
+fn package_tool(
+    packages: &bpkg::Bpkg,
+    tool: &str,
+    args: &Value,
+    token: &bpkg::CancelToken,
+) -> Result<Value> {
+    let names = || -> Result<Vec<String>> {
+        match (args.get("names"), args.get("name")) {
+            (Some(Value::Array(list)), _) => Ok(list
+                .iter()
+                .filter_map(|v| v.as_str().map(str::to_string))
+                .collect()),
+            (_, Some(Value::String(name))) => Ok(vec![name.clone()]),
+            _ => Err(anyhow!("{tool} requires args.name or args.names")),
+        }
+    };
+    match tool {
+        "package.search" => {
+            let query = args
+                .get("query")
+                .and_then(|v| v.as_str())
+                .context("package.search requires args.query")?;
+            Ok(json!({"query": query, "packages": packages.search(query)?}))
+        }
+        "package.info" => {
+            let name = names()?
+                .into_iter()
+                .next()
+                .context("package.info requires args.name")?;
+            match packages.info(&name)? {
+                Some(info) => Ok(json!({"name": name, "info": info})),
+                None => Err(anyhow!("package {name} not found")),
+            }
+        }
+        "package.install" => {
+            let plan = packages.plan_install_names(&names()?)?;
+            Ok(json!(packages.apply_cancellable(
+                &plan,
+                bpkg::InstallOptions::default(),
+                token
+            )?))
+        }
+        "package.remove" => {
+            let plan = packages.plan_remove(&names()?)?;
+            Ok(json!(packages.apply_cancellable(
+                &plan,
+                bpkg::InstallOptions::default(),
+                token
+            )?))
+        }
+        _ => Err(anyhow!("unknown tool: {tool}")),
     }
-    tokio::fs::write(path, content).await.with_context(|| format!("failed writing {path}"))?;
-    Ok(json!({"path": path, "written": content.len()}))
 }
 
 async fn shell_exec(args: &Value) -> Result<Value> {
-    let command = args.get("command").and_then(|v| v.as_str()).context("shell.exec requires args.command")?;
-    let output = Command::new("bash").arg("-lc").arg(command).output().await?;
+    let command = args
+        .get("command")
+        .and_then(|v| v.as_str())
+        .context("shell.exec requires args.command")?;
+    let child = Command::new("bash")
+        .arg("-lc")
+        .arg(command)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .kill_on_drop(true)
+        .spawn()?;
+    let mut group = child.id().map(ProcessGroupGuard);
+    let output = child.wait_with_output().await?;
+    if let Some(g) = group.as_mut() {
+        g.disarm();
+    }
     Ok(json!({
         "status": output.status.code().unwrap_or(-1),
         "stdout": String::from_utf8_lossy(&output.stdout),
@@ -51,9 +237,158 @@ async fn shell_exec(args: &Value) -> Result<Value> {
     }))
 }
 
+/// Kills the shell's whole process group if `shell_exec` is dropped before the
+/// command finishes (deadline or cancellation), not just the `bash` parent.
+struct ProcessGroupGuard(u32);
+
+impl ProcessGroupGuard {
+    fn disarm(&mut self) {
+        self.0 = 0;
+    }
+}
+
+impl Drop for ProcessGroupGuard {
+    fn drop(&mut self) {
+        if self.0 != 0 {
+            // SAFETY: killpg has no memory-safety preconditions.
+            unsafe {
+                libc::killpg(self.0 as libc::pid_t, libc::SIGKILL);
+            }
+        }
+    }
+}
+
 async fn network_get(args: &Value) -> Result<Value> {
-    let url = args.get("url").and_then(|v| v.as_str()).context("network.get requires args.url")?;
+    let url = args
+        .get("url")
+        .and_then(|v| v.as_str())
+        .context("network.get requires args.url")?;
     let body = reqwest::get(url).await?.text().await?;
     let preview: String = body.chars().take(2000).collect();
     Ok(json!({"url": url, "preview": preview, "chars": body.len()}))
 }
+
+/// Ask munin-brain's index for the passages closest to `args.query`; each
+/// result carries the source `path`, `line`, `snippet` and `score`.
+async fn knowledge_search(brain_endpoint: &str, args: &Value) -> Result<Value> {
+    let query = args
+        .get("query")
+        .and_then(|v| v.as_str())
+        .context("knowledge.search requires args.query")?;
+    let limit = args.get("limit").and_then(|v| v.as_u64()).unwrap_or(5);
+    let resp = reqwest::Client::new()
+        .post(format!("{brain_endpoint}/v1/knowledge/search"))
+        .json(&json!({"query": query, "limit": limit}))
+        .send()
+        .await
+        .context("munin-brain is unreachable")?;
+    let status = resp.status();
+    let body: Value = resp.json().await?;
+    if !status.is_success() {
+        let error = body.get("error").and_then(|v| v.as_str()).unwrap_or("");
+        return Err(anyhow!("knowledge search failed ({status}): {error}"));
+    }
+    Ok(json!({"query": query, "results": body.get("results").cloned().unwrap_or_default()}))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers one request the way munin-brain's search endpoint does,
+    /// echoing the request body back as the snippet.
+    fn fake_brain() -> String {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let addr = format!("http://{}", server.server_addr().to_ip().unwrap());
+        std::thread::spawn(move || {
+            let mut req = server.recv().unwrap();
+            let mut body = String::new();
+            req.as_reader().read_to_string(&mut body).unwrap();
+            let reply = json!({
+                "results": [{"path": "/home/u/notes/boat.md", "line": 3, "score": 0.8, "snippet": body}],
+            });
+            assert_eq!(req.url(), "/v1/knowledge/search");
+            req.respond(tiny_http::Response::from_string(reply.to_string()))
+                .unwrap();
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn knowledge_search_returns_sources_and_snippets() {
+        let brain = fake_brain();
+        let out = knowledge_search(&brain, &json!({"query": "boat trip", "limit": 2}))
+            .await
+            .unwrap();
+        let hit = &out["results"][0];
+        assert_eq!(hit["path"], "/home/u/notes/boat.md");
+        let sent: Value = serde_json::from_str(hit["snippet"].as_str().unwrap()).unwrap();
+        assert_eq!(sent, json!({"query": "boat trip", "limit": 2}));
+        assert!(knowledge_search(&brain, &json!({})).await.is_err());
+    }
+
+    fn router(root: &std::path::Path) -> ToolRouter {
+        ToolRouter::new(
+            UndoJournal::new(root.join("undo")),
+            bpkg::Bpkg::open(root.join("pkgroot")),
+            "http://127.0.0.1:9",
+        )
+    }
+
+    #[tokio::test]
+    async fn changes_stop_at_a_cancelled_token_and_claim_it_otherwise() {
+        let root = std::env::temp_dir().join(format!("munin-tools-{}", uuid::Uuid::new_v4()));
+        let tools = router(&root);
+        let file = root.join("notes.txt");
+        let args = json!({"path": file, "content": "hello"});
+
+        let token = bpkg::CancelToken::default();
+        assert!(token.cancel());
+        assert!(tools
+            .execute("c1", "file.write", &args, &token)
+            .await
+            .is_err());
+        assert!(!file.exists());
+
+        let token = bpkg::CancelToken::default();
+        tools
+            .execute("c2", "file.write", &args, &token)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "hello");
+        // Past its commit the call can no longer be cancelled.
+        assert!(!token.cancel());
+
+        // Nor do deletes, moves and copies once their call is cancelled.
+        let cancelled = bpkg::CancelToken::default();
+        cancelled.cancel();
+        let moved = root.join("moved.txt");
+        for (tool, args) in [
+            ("file.delete", json!({"path": file})),
+            ("file.move", json!({"from": file, "to": moved})),
+            ("file.copy", json!({"from": file, "to": moved})),
+        ] {
+            assert!(tools.execute("c3", tool, &args, &cancelled).await.is_err());
+        }
+        assert!(file.exists() && !moved.exists());
+        std::fs::remove_dir_all(root).ok();
+    }
+
+    #[test]
+    fn package_install_refuses_paths() {
+        let root = std::env::temp_dir().join(format!("munin-pkg-{}", uuid::Uuid::new_v4()));
+        let dir = root.join("evil");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("bpkg.toml"),
+            "[package]\nname = \"evil\"\nversion = \"1\"\n",
+        )
+        .unwrap();
+        let packages = bpkg::Bpkg::open(&root);
+        let args = json!({"name": dir.to_str().unwrap()});
+        let token = bpkg::CancelToken::default();
+        assert!(package_tool(&packages, "package.install", &args, &token).is_err());
+        assert!(packages.installed().unwrap().is_empty());
+        std::fs::remove_dir_all(root).ok();
+    }
+}
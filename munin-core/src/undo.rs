@@ -0,0 +1,390 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use uuid::Uuid;
+
+/// Snapshots kept before the oldest are dropped.
+const MAX_ENTRIES: usize = 100;
+/// Files larger than this are written without a snapshot.
+const MAX_SNAPSHOT_BYTES: u64 = 16 * 1024 * 1024;
+
+/// What a file looked like before a write, and what the write left behind.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UndoEntry {
+    /// Rollback token handed back in the write's result.
+    pub token: String,
+    pub call_id: String,
+    pub path: PathBuf,
+    /// False when the write created the file; undo deletes it.
+    pub existed: bool,
+    /// Permissions and owner of the file the write replaced, put back on undo.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<Owner>,
+    pub created_at: u64,
+    /// Size and mtime right after the write, to notice later edits.
+    pub written_len: u64,
+    pub written_mtime: Option<u64>,
+    #[serde(default)]
+    pub undone: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Owner {
+    /// Permission bits, including setuid/setgid/sticky.
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl Owner {
+    fn of(meta: &std::fs::Metadata) -> Self {
+        Self {
+            mode: meta.mode() & 0o7777,
+            uid: meta.uid(),
+            gid: meta.gid(),
+        }
+    }
+
+    fn restore(&self, path: &Path) -> Result<()> {
+        let meta = std::fs::metadata(path)?;
+        if (meta.uid(), meta.gid()) != (self.uid, self.gid) {
+            std::os::unix::fs::chown(path, Some(self.uid), Some(self.gid))
+                .with_context(|| format!("failed restoring the owner of {}", path.display()))?;
+        }
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(self.mode))
+            .with_context(|| format!("failed restoring the mode of {}", path.display()))
+    }
+}
+
+/// Bounded on-disk journal of file contents replaced by `file.write`.
+///
+/// Layout: `<dir>/index.json` plus one `<token>.bin` per snapshot, readable
+/// only by the core: they hold copies of whatever it overwrote.
+pub struct UndoJournal {
+    dir: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl UndoJournal {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Replace `path` with `content` (or delete it when `None`), snapshotting
+    /// the previous state first. Returns the rollback token, or `None` when the
+    /// old file was too large to keep.
+    pub fn write(
+        &self,
+        call_id: &str,
+        path: &Path,
+        content: Option<&[u8]>,
+    ) -> Result<Option<String>> {
+        let _guard = self.lock.lock().unwrap();
+        self.write_locked(call_id, path, content, None)
+    }
+
+    /// `write` for callers holding the lock. `owner` is applied to the
+    /// written file, for restores.
+    fn write_locked(
+        &self,
+        call_id: &str,
+        path: &Path,
+        content: Option<&[u8]>,
+        owner: Option<Owner>,
+    ) -> Result<Option<String>> {
+        let (previous, previous_owner) = match std::fs::metadata(path) {
+            Ok(meta) if meta.len() > MAX_SNAPSHOT_BYTES => (None, None),
+            Ok(meta) => (
+                Some(Some(std::fs::read(path).with_context(|| {
+                    format!("failed snapshotting {}", path.display())
+                })?)),
+                Some(Owner::of(&meta)),
+            ),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (Some(None), None),
+            Err(e) => return Err(e).with_context(|| format!("failed reading {}", path.display())),
+        };
+
+        let token = match &previous {
+            Some(old) => {
+                self.create_dir()?;
+                let token = Uuid::new_v4().to_string();
+                if let Some(bytes) = old {
+                    write_private(&self.blob(&token), bytes)?;
+                }
+                Some(token)
+            }
+            None => {
+                tracing::warn!(
+                    "{} is too large to snapshot; write is not undoable",
+                    path.display()
+                );
+                None
+            }
+        };
+
+        apply(path, content)?;
+        if let (Some(owner), Some(_)) = (owner, content) {
+            owner.restore(path)?;
+        }
+
+        if let Some(token) = &token {
+            let (written_len, written_mtime) = stamp(path);
+            let mut index = self.load()?;
+            index.push(UndoEntry {
+                token: token.clone(),
+                call_id: call_id.to_string(),
+                path: path.to_path_buf(),
+                existed: matches!(previous, Some(Some(_))),
+                owner: previous_owner,
+                created_at: now_ms(),
+                written_len,
+                written_mtime,
+                undone: false,
+            });
+            self.prune(&mut index);
+            self.save(&index)?;
+        }
+        Ok(token)
+    }
+
+    /// Find the snapshot for a rollback token or the call that wrote it.
+    /// Callers hold the lock.
+    fn find(&self, token_or_call: &str) -> Result<Option<UndoEntry>> {
+        Ok(self
+            .load()?
+            .into_iter()
+            .rev()
+            .find(|e| e.token == token_or_call || e.call_id == token_or_call))
+    }
+
+    /// Restore the snapshot, with the file's old mode and owner. Refuses when
+    /// the file changed after the write unless `force` is set. The restore is
+    /// itself journaled, so the returned token undoes the undo. The journal
+    /// stays locked throughout, so a snapshot is restored at most once.
+    pub fn undo(
+        &self,
+        call_id: &str,
+        token_or_call: &str,
+        force: bool,
+    ) -> Result<(UndoEntry, Option<String>)> {
+        let _guard = self.lock.lock().unwrap();
+        let entry = self
+            .find(token_or_call)?
+            .with_context(|| format!("no undo snapshot for {token_or_call}"))?;
+        if entry.undone {
+            bail!("{} was already undone", entry.token);
+        }
+        if !force && stamp(&entry.path) != (entry.written_len, entry.written_mtime) {
+            bail!(
+                "{} changed after call {} wrote it; pass force to restore anyway",
+                entry.path.display(),
+                entry.call_id
+            );
+        }
+
+        let previous = if entry.existed {
+            Some(
+                std::fs::read(self.blob(&entry.token))
+                    .with_context(|| format!("snapshot {} is missing", entry.token))?,
+            )
+        } else {
+            None
+        };
+        let token = self.write_locked(call_id, &entry.path, previous.as_deref(), entry.owner)?;
+
+        let mut index = self.load()?;
+        if let Some(e) = index.iter_mut().find(|e| e.token == entry.token) {
+            e.undone = true;
+        }
+        self.save(&index)?;
+        Ok((entry, token))
+    }
+
+    fn prune(&self, index: &mut Vec<UndoEntry>) {
+        if index.len() > MAX_ENTRIES {
+            for old in index.drain(..index.len() - MAX_ENTRIES) {
+                std::fs::remove_file(self.blob(&old.token)).ok();
+            }
+        }
+    }
+
+    fn blob(&self, token: &str) -> PathBuf {
+        self.dir.join(format!("{token}.bin"))
+    }
+
+    fn load(&self) -> Result<Vec<UndoEntry>> {
+        let path = self.dir.join("index.json");
+        match std::fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text)
+                .with_context(|| format!("failed parsing {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e).with_context(|| format!("failed reading {}", path.display())),
+        }
+    }
+
+    fn save(&self, index: &[UndoEntry]) -> Result<()> {
+        self.create_dir()?;
+        let path = self.dir.join("index.json");
+        let tmp = self.dir.join("index.json.tmp");
+        write_private(&tmp, &serde_json::to_vec_pretty(index)?)?;
+        std::fs::rename(&tmp, &path).with_context(|| format!("failed replacing {}", path.display()))
+    }
+
+    /// The journal directory, made (or narrowed to) 0700.
+    fn create_dir(&self) -> Result<()> {
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&self.dir)
+            .and_then(|()| {
+                std::fs::set_permissions(&self.dir, std::fs::Permissions::from_mode(0o700))
+            })
+            .with_context(|| format!("failed creating {}", self.dir.display()))
+    }
+}
+
+/// Replace `path` with `bytes`, readable by the owner only.
+fn write_private(path: &Path, bytes: &[u8]) -> Result<()> {
+    std::fs::remove_file(path).ok();
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .and_then(|mut f| f.write_all(bytes))
+        .with_context(|| format!("failed writing {}", path.display()))
+}
+
+fn apply(path: &Path, content: Option<&[u8]>) -> Result<()> {
+    match content {
+        Some(bytes) => {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent).ok();
+            }
+            std::fs::write(path, bytes)
+                .with_context(|| format!("failed writing {}", path.display()))
+        }
+        None => match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("failed removing {}", path.display()))
+            }
+            _ => Ok(()),
+        },
+    }
+}
+
+fn stamp(path: &Path) -> (u64, Option<u64>) {
+    match std::fs::metadata(path) {
+        Ok(meta) => (
+            meta.len(),
+            meta.modified()
+                .ok()
+                .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .map(|d| d.as_nanos() as u64),
+        ),
+        Err(_) => (0, None),
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch() -> PathBuf {
+        std::env::temp_dir().join(format!("munin-undo-{}", Uuid::new_v4()))
+    }
+
+    #[test]
+    fn undo_restores_previous_contents_and_creation() {
+        let root = scratch();
+        let journal = UndoJournal::new(root.join("journal"));
+        let file = root.join("notes.txt");
+
+        journal.write("c1", &file, Some(b"first")).unwrap().unwrap();
+        journal
+            .write("c2", &file, Some(b"second"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(std::fs::read(&file).unwrap(), b"second");
+
+        let (entry, redo) = journal.undo("u1", "c2", false).unwrap();
+        assert!(entry.existed);
+        assert!(redo.is_some());
+        assert_eq!(std::fs::read(&file).unwrap(), b"first");
+        assert!(journal.undo("u2", "c2", false).is_err());
+
+        journal.undo("u3", "c1", true).unwrap();
+        assert!(!file.exists());
+        std::fs::remove_dir_all(root).ok();
+    }
+
+    #[test]
+    fn snapshots_are_private_and_restores_keep_the_mode() {
+        let root = scratch();
+        let journal = UndoJournal::new(root.join("journal"));
+        let file = root.join("secret");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(&file, b"hunter2").unwrap();
+        std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o640)).unwrap();
+
+        let token = journal.write("c1", &file, None).unwrap().unwrap();
+        let mode = |p: PathBuf| std::fs::metadata(p).unwrap().mode() & 0o777;
+        assert_eq!(mode(root.join("journal")), 0o700);
+        assert_eq!(mode(journal.blob(&token)), 0o600);
+        assert_eq!(mode(root.join("journal/index.json")), 0o600);
+
+        journal.undo("u1", &token, false).unwrap();
+        assert_eq!(std::fs::read(&file).unwrap(), b"hunter2");
+        assert_eq!(mode(file), 0o640);
+        std::fs::remove_dir_all(root).ok();
+    }
+
+    #[test]
+    fn concurrent_undos_restore_once() {
+        let root = scratch();
+        let journal = std::sync::Arc::new(UndoJournal::new(root.join("journal")));
+        let file = root.join("notes.txt");
+        let token = journal.write("c1", &file, Some(b"a")).unwrap().unwrap();
+
+        let undos: Vec<_> = (0..8)
+            .map(|i| {
+                let (journal, token) = (journal.clone(), token.clone());
+                std::thread::spawn(move || journal.undo(&format!("u{i}"), &token, false).is_ok())
+            })
+            .collect();
+        let restored = undos
+            .into_iter()
+            .filter_map(|t| t.join().unwrap().then_some(()))
+            .count();
+        assert_eq!(restored, 1);
+        std::fs::remove_dir_all(root).ok();
+    }
+
+    #[test]
+    fn undo_refuses_when_file_changed_since_write() {
+        let root = scratch();
+        let journal = UndoJournal::new(root.join("journal"));
+        let file = root.join("conf");
+
+        let token = journal.write("c1", &file, Some(b"a")).unwrap().unwrap();
+        std::fs::write(&file, b"edited by hand").unwrap();
+        assert!(journal.undo("u1", &token, false).is_err());
+        journal.undo("u1", &token, true).unwrap();
+        assert!(!file.exists());
+        std::fs::remove_dir_all(root).ok();
+    }
+}
@@ -1,10 +1,14 @@
 use anyhow::Result;
 use clap::{Parser, Subcommand};
 use reqwest::Client;
+use serde::Deserialize;
+use std::sync::Mutex;
 use tokio::time::{sleep, Duration};
-use tracing::info;
+use tracing::{info, warn};
 use uuid::Uuid;
 
+const AGENT_ID: &str = "munin-sts";
+
 #[derive(Parser, Debug)]
 #[command(name = "munin-sts")]
 #[command(author, version, about = "MuninOS local STS orchestration service")]
@@ -31,20 +35,44 @@ struct Args {
     /// Munin brain endpoint (local)
     #[arg(long, default_value = "http://127.0.0.1:8790")]
     brain_endpoint: String,
+
+    /// Token munin-core issues to accept spoken approvals
+    #[arg(long, default_value = "/var/lib/muninos/core/voice.token")]
+    voice_token_file: std::path::PathBuf,
 }
 
 #[derive(Subcommand, Debug)]
 enum Commands {
     Start,
     TestAudio,
-    Interact { audio_file: String },
+    Interact {
+        audio_file: String,
+    },
+    /// Handle one utterance as if it had been heard. Nothing has been read
+    /// out in this process, so it can't answer approvals.
+    Say {
+        text: String,
+    },
+}
+
+/// A call in core's approval queue, as returned by `/v1/pending`.
+#[derive(Debug, Clone, Deserialize)]
+struct PendingCall {
+    id: String,
+    summary: String,
+    /// Digits naming the call in an answer ("yes 4711").
+    #[serde(default)]
+    code: String,
 }
 
 struct STSService {
     session_id: String,
     core_endpoint: String,
     brain_endpoint: String,
+    voice_token_file: std::path::PathBuf,
     client: Client,
+    /// Call ids already read out, most recent last.
+    announced: Mutex<Vec<String>>,
 }
 
 impl STSService {
@@ -53,7 +81,9 @@ impl STSService {
             session_id: Uuid::new_v4().to_string(),
             core_endpoint: args.core_endpoint.clone(),
             brain_endpoint: args.brain_endpoint.clone(),
+            voice_token_file: args.voice_token_file.clone(),
             client: Client::new(),
+            announced: Mutex::new(Vec::new()),
         }
     }
 
@@ -62,16 +92,151 @@ impl STSService {
         info!("Mode: local-only (no external API keys required)");
 
         let test_text = std::env::var("MUNIN_STS_TEST_TEXT").ok();
+        let mut registered = false;
 
         loop {
+            registered = match self.keep_registered(registered).await {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!("core registration failed: {}", e);
+                    false
+                }
+            };
+            if let Err(e) = self.announce_pending().await {
+                warn!("failed to fetch pending approvals: {}", e);
+            }
             if let Some(text) = &test_text {
-                let _ = self.route_transcript(text).await;
+                let _ = self.handle_utterance(text).await;
             }
             sleep(Duration::from_secs(3)).await;
             info!("STS service alive");
         }
     }
 
+    /// Heartbeat to core, (re-)registering when core doesn't know us.
+    async fn keep_registered(&self, registered: bool) -> Result<()> {
+        let base = self.core_endpoint.trim_end_matches('/');
+        if registered {
+            let resp = self
+                .client
+                .post(format!("{base}/v1/agents/heartbeat"))
+                .json(&serde_json::json!({"id": AGENT_ID}))
+                .send()
+                .await?;
+            if resp.status().is_success() {
+                return Ok(());
+            }
+        }
+
+        let info = serde_json::json!({
+            "id": AGENT_ID,
+            "capabilities": ["voice", "stt", "tts"],
+            "version": env!("CARGO_PKG_VERSION"),
+            "pid": std::process::id(),
+        });
+        self.client
+            .post(format!("{base}/v1/agents/register"))
+            .json(&info)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// Answers to a pending approval are confirmed by voice; anything else
+    /// goes to brain and core as a normal turn.
+    async fn handle_utterance(&self, text: &str) -> Result<()> {
+        let Some((approve, code)) = parse_answer(text) else {
+            return self.route_transcript(text).await;
+        };
+
+        let pending = self.fetch_pending().await?;
+        if pending.is_empty() {
+            return self.route_transcript(text).await;
+        }
+        let announced = self.announced.lock().unwrap().clone();
+        let Some(id) = match_call(&pending, &announced, code.as_deref()) else {
+            self.speak("Which call? Say yes or no followed by the call code.");
+            return Ok(());
+        };
+        // Core only counts the answer as a voice factor with its token.
+        let token = match std::fs::read_to_string(&self.voice_token_file) {
+            Ok(token) => token.trim().to_string(),
+            Err(e) => {
+                warn!(
+                    "no voice token at {}: {}",
+                    self.voice_token_file.display(),
+                    e
+                );
+                self.speak("Voice approval isn't set up. Please confirm on screen.");
+                return Ok(());
+            }
+        };
+
+        let url = format!("{}/v1/confirm", self.core_endpoint.trim_end_matches('/'));
+        let resp = self
+            .client
+            .post(&url)
+            .bearer_auth(token)
+            .json(&serde_json::json!({"id": id, "approve": approve}))
+            .send()
+            .await?;
+        let status = resp.status();
+        let body: serde_json::Value = resp.json().await.unwrap_or_default();
+        info!("voice confirm {} -> {} {}", id, status, body);
+
+        match (approve, status.as_u16()) {
+            (false, _) => self.speak("Cancelled."),
+            (true, 202) => {
+                self.speak("Noted. This call also needs confirmation on screen or with your PIN.")
+            }
+            (true, s) if (200..300).contains(&s) => self.speak("Done."),
+            (true, _) => self.speak("That didn't work."),
+        }
+        Ok(())
+    }
+
+    /// Read out calls that joined the approval queue since the last poll.
+    async fn announce_pending(&self) -> Result<()> {
+        let pending = self.fetch_pending().await?;
+        let fresh: Vec<PendingCall> = {
+            let mut announced = self.announced.lock().unwrap();
+            announced.retain(|id| pending.iter().any(|p| &p.id == id));
+            let fresh: Vec<PendingCall> = pending
+                .into_iter()
+                .filter(|p| !announced.contains(&p.id))
+                .collect();
+            announced.extend(fresh.iter().map(|p| p.id.clone()));
+            fresh
+        };
+        for call in fresh {
+            self.speak(&format!("{} Say yes or no.", call.summary));
+        }
+        Ok(())
+    }
+
+    async fn fetch_pending(&self) -> Result<Vec<PendingCall>> {
+        #[derive(Deserialize)]
+        struct PendingOut {
+            pending: Vec<PendingCall>,
+        }
+        let url = format!("{}/v1/pending", self.core_endpoint.trim_end_matches('/'));
+        let out: PendingOut = self
+            .client
+            .get(&url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(out.pending)
+    }
+
+    fn speak(&self, text: &str) {
+        // TTS stub (TODO): hand `text` to the local voice model for playback.
+        info!("speak: {}", text);
+    }
+
     async fn route_transcript(&self, transcript: &str) -> Result<()> {
         let decide_url = format!("{}/v1/decide", self.brain_endpoint.trim_end_matches('/'));
         let decide_payload = serde_json::json!({
@@ -79,7 +244,12 @@ impl STSService {
             "locale": "en-US"
         });
 
-        let decide_resp = self.client.post(&decide_url).json(&decide_payload).send().await?;
+        let decide_resp = self
+            .client
+            .post(&decide_url)
+            .json(&decide_payload)
+            .send()
+            .await?;
         let decide_json: serde_json::Value = decide_resp.json().await.unwrap_or_default();
         info!("brain decision: {}", decide_json);
 
@@ -90,7 +260,12 @@ impl STSService {
             "transcript": transcript
         });
 
-        let core_resp = self.client.post(&core_url).json(&core_payload).send().await?;
+        let core_resp = self
+            .client
+            .post(&core_url)
+            .json(&core_payload)
+            .send()
+            .await?;
         let core_text = core_resp.text().await.unwrap_or_default();
         info!("core transcript response: {}", core_text);
 
@@ -98,6 +273,53 @@ impl STSService {
     }
 }
 
+/// Recognise a spoken yes/no, optionally followed by a call code
+/// ("yes", "no 4711"). Returns `None` for anything else or mixed answers.
+/// Filler words like "ok" don't count as a yes.
+fn parse_answer(text: &str) -> Option<(bool, Option<String>)> {
+    const YES: &[&str] = &["yes", "yeah", "yep", "approve", "approved", "confirm"];
+    const NO: &[&str] = &["no", "nope", "deny", "denied", "cancel", "stop", "reject"];
+
+    let lower = text.to_lowercase();
+    let words: Vec<&str> = lower
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect();
+    // Long utterances are requests, not answers.
+    if words.is_empty() || words.len() > 4 {
+        return None;
+    }
+
+    let yes = words.iter().any(|w| YES.contains(w));
+    let no = words.iter().any(|w| NO.contains(w));
+    let code = words
+        .iter()
+        .find(|w| w.len() == 4 && w.chars().all(|c| c.is_ascii_digit()))
+        .map(|w| w.to_string());
+    match (yes, no) {
+        (true, false) => Some((true, code)),
+        (false, true) => Some((false, code)),
+        _ => None,
+    }
+}
+
+/// Pick the call an answer refers to: the one named by code, else the most
+/// recently announced. Calls that were never read out can't be answered.
+fn match_call(pending: &[PendingCall], announced: &[String], code: Option<&str>) -> Option<String> {
+    let mut heard = announced
+        .iter()
+        .rev()
+        .filter_map(|id| pending.iter().find(|p| &p.id == id));
+    let Some(code) = code else {
+        return heard.next().map(|p| p.id.clone());
+    };
+    let mut hits = heard.filter(|p| p.code == code);
+    match (hits.next(), hits.next()) {
+        (Some(p), None) => Some(p.id.clone()),
+        _ => None,
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
@@ -114,7 +336,62 @@ async fn main() -> Result<()> {
         Commands::Interact { audio_file } => {
             info!("Interact stub (TODO): processing file {}", audio_file);
         }
+        Commands::Say { ref text } => {
+            let service = STSService::new(&args);
+            service.handle_utterance(text).await?;
+        }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(id: &str, code: &str) -> PendingCall {
+        PendingCall {
+            id: id.into(),
+            summary: String::new(),
+            code: code.into(),
+        }
+    }
+
+    #[test]
+    fn answers_are_short_and_unambiguous() {
+        assert_eq!(parse_answer("Yes."), Some((true, None)));
+        assert_eq!(parse_answer("no 4711"), Some((false, Some("4711".into()))));
+        assert_eq!(parse_answer("ok"), None);
+        assert_eq!(parse_answer("yes dead"), Some((true, None)));
+        assert_eq!(parse_answer("yes no"), None);
+        assert_eq!(
+            parse_answer("yes please delete every file in my home"),
+            None
+        );
+        assert_eq!(parse_answer("what time is it"), None);
+    }
+
+    #[test]
+    fn answer_matches_code_then_latest_announcement() {
+        let pending = [call("3f2a-1", "1234"), call("9c01-2", "5678")];
+        let announced = ["9c01-2".to_string(), "3f2a-1".to_string()];
+        assert_eq!(
+            match_call(&pending, &announced, Some("5678")).as_deref(),
+            Some("9c01-2")
+        );
+        assert_eq!(
+            match_call(&pending, &announced, None).as_deref(),
+            Some("3f2a-1")
+        );
+        assert_eq!(match_call(&pending, &announced, Some("0000")), None);
+    }
+
+    #[test]
+    fn unannounced_calls_are_never_answered() {
+        let pending = [call("3f2a-1", "1234"), call("9c01-2", "5678")];
+        assert_eq!(match_call(&pending[..1], &[], None), None);
+        assert_eq!(match_call(&pending, &[], Some("1234")), None);
+        let announced = ["9c01-2".to_string()];
+        assert_eq!(match_call(&pending, &announced, Some("1234")), None);
+    }
+}
@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use clap::Parser;
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -21,7 +21,7 @@ fn main() -> Result<()> {
     let addr = format!("{}:{}", args.host, args.port);
     let ui_root = PathBuf::from(args.ui_dir);
 
-    let server = Server::http(&addr)?;
+    let server = Server::http(&addr).map_err(|e| anyhow!(e))?;
     tracing::info!("munin-ui serving {:?} at http://{}", ui_root, addr);
 
     for req in server.incoming_requests() {
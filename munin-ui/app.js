@@ -23,6 +23,7 @@ class MuninUI {
         this.startAnimation();
         this.updateTime();
         this.startPendingPoll();
+        this.startHealthPoll();
 
         setInterval(() => this.updateTime(), 1000);
     }
@@ -123,6 +124,34 @@ class MuninUI {
             <div class="history-time">${new Date().toLocaleTimeString()}</div>
         `;
         this.historyList.insertBefore(item, this.historyList.firstChild);
+        return item;
+    }
+
+    // Writes return a rollback token; offer it as an undo button.
+    offerUndo(item, token) {
+        if (!token) return;
+        const btn = document.createElement('button');
+        btn.className = 'undo';
+        btn.textContent = 'Undo';
+        btn.onclick = async () => {
+            btn.disabled = true;
+            try {
+                const res = await fetch(`${this.coreApi}/v1/undo`, {
+                    method: 'POST',
+                    headers: { 'Content-Type': 'application/json' },
+                    body: JSON.stringify({ token })
+                });
+                const data = await res.json();
+                const undoItem = this.addToHistory(`undo ${token}`, escapeHtml(JSON.stringify(data)));
+                // Usually queued for approval; a standing grant runs it at once.
+                const result = (data.events || []).find(ev => ev.type === 'ToolResult');
+                this.offerUndo(undoItem, result && result.data.output && result.data.output.rollback);
+            } catch (e) {
+                this.addToHistory('undo failed', String(e));
+                btn.disabled = false;
+            }
+        };
+        item.appendChild(btn);
     }
 
     updateStatus(status) {
@@ -160,6 +189,39 @@ class MuninUI {
         setInterval(tick, 2500);
     }
 
+    startHealthPoll() {
+        const tick = async () => {
+            try {
+                const res = await fetch(`${this.coreApi}/v1/system/health`);
+                this.renderHealth(await res.json());
+            } catch (_) {
+                // core api might not be running yet
+            }
+        };
+        tick();
+        setInterval(tick, 5000);
+    }
+
+    renderHealth(health) {
+        switch (health.mode) {
+            case 'full':
+                this.systemStatus.textContent = '● System Ready';
+                this.systemStatus.className = 'status-ok';
+                break;
+            case 'text_only':
+                this.systemStatus.textContent = '● Text-only mode';
+                this.systemStatus.className = 'status-thinking';
+                if (health.voice_expected) {
+                    this.voiceStatus.textContent = '🔇 Voice offline';
+                }
+                break;
+            case 'rules_only':
+                this.systemStatus.textContent = '● Degraded (brain offline)';
+                this.systemStatus.className = 'status-thinking';
+                break;
+        }
+    }
+
     renderPending(items) {
         if (!this.pendingList) return;
         this.pendingList.innerHTML = '';
@@ -170,31 +232,76 @@ class MuninUI {
         items.forEach(item => {
             const el = document.createElement('div');
             el.className = 'pending-item';
+            el.dataset.id = item.id;
             el.innerHTML = `
               <div>
-                <div><strong>${item.tool}</strong></div>
-                <div style="font-size:0.8rem;color:#aaa">${JSON.stringify(item.args)}</div>
+                <div><strong>${escapeHtml(item.tool)}</strong></div>
+                <div style="font-size:0.8rem;color:#aaa">${escapeHtml(JSON.stringify(item.args))}</div>
+                ${this.renderPreview(item.preview)}
+                ${item.required > 1 ? `<div class="pending-factors">needs ${item.required} confirmations; have: ${escapeHtml((item.factors || []).join(', ') || 'none')}</div>` : ''}
               </div>
               <div class="pending-actions">
+                ${item.required > 1 ? '<input class="pin" type="password" inputmode="numeric" placeholder="PIN" size="6">' : ''}
                 <button class="approve">Approve</button>
+                <button class="allow-15m" title="Allow similar calls for 15 minutes">15 min</button>
+                <button class="allow-session" title="Allow similar calls until core restarts">Session</button>
+                <button class="allow-always" title="Always allow similar calls">Always</button>
                 <button class="deny">Deny</button>
               </div>
             `;
             el.querySelector('.approve').onclick = () => this.confirmTool(item.id, true);
+            el.querySelector('.allow-15m').onclick = () => this.confirmTool(item.id, true, { minutes: 15 });
+            el.querySelector('.allow-session').onclick = () => this.confirmTool(item.id, true, 'session');
+            el.querySelector('.allow-always').onclick = () => this.confirmTool(item.id, true, 'always');
             el.querySelector('.deny').onclick = () => this.confirmTool(item.id, false);
             this.pendingList.appendChild(el);
         });
     }
 
-    async confirmTool(id, approve) {
+    renderPreview(preview) {
+        if (!preview) return '';
+        let body;
+        switch (preview.kind) {
+            case 'file_diff':
+                body = preview.diff
+                    ? preview.diff.split('\n').map(line => {
+                        const cls = line.startsWith('+') ? 'add' : line.startsWith('-') ? 'del' : '';
+                        return `<span class="${cls}">${escapeHtml(line)}</span>`;
+                    }).join('\n')
+                    : `${escapeHtml(preview.path)}: no changes`;
+                break;
+            case 'command':
+                body = [
+                    `$ ${preview.command_line}`,
+                    `program: ${preview.program || '(not found on PATH)'}`,
+                    `cwd: ${preview.cwd}`,
+                    ...preview.paths.map(p => `path: ${p.path} (${p.exists ? 'exists' : 'missing'})`)
+                ].map(escapeHtml).join('\n');
+                break;
+            case 'network':
+                body = escapeHtml(preview.error
+                    ? `${preview.url} -> unresolved (${preview.error})`
+                    : `${preview.url} -> ${preview.host}:${preview.port} [${preview.addresses.join(', ')}]`);
+                break;
+            default:
+                return '';
+        }
+        return `<pre class="pending-preview">${body}</pre>`;
+    }
+
+    async confirmTool(id, approve, remember = null) {
+        const pinInput = this.pendingList.querySelector(`[data-id="${id}"] .pin`);
+        const pin = pinInput && pinInput.value ? pinInput.value : null;
         try {
             const res = await fetch(`${this.coreApi}/v1/confirm`, {
                 method: 'POST',
                 headers: { 'Content-Type': 'application/json' },
-                body: JSON.stringify({ id, approve })
+                body: JSON.stringify({ id, approve, pin, remember })
             });
             const data = await res.json();
-            this.addToHistory(`tool ${approve ? 'approve' : 'deny'} ${id}`, JSON.stringify(data));
+            const item = this.addToHistory(`tool ${approve ? 'approve' : 'deny'} ${id}`, escapeHtml(JSON.stringify(data)));
+            const output = data.result && data.result.output;
+            this.offerUndo(item, output && output.rollback);
         } catch (e) {
             this.addToHistory('tool confirm failed', String(e));
         }
@@ -273,6 +380,14 @@ class MuninUI {
     }
 }
 
+function escapeHtml(text) {
+    return String(text)
+        .replace(/&/g, '&amp;')
+        .replace(/</g, '&lt;')
+        .replace(/>/g, '&gt;')
+        .replace(/"/g, '&quot;');
+}
+
 // Initialize UI when DOM is ready
 document.addEventListener('DOMContentLoaded', () => {
     window.muninUI = new MuninUI();